@@ -0,0 +1,177 @@
+// benches/benchmarks.rs
+
+// Timings for the slowest parts of a turn, to catch them getting slower.
+// Run with cargo bench. Fixed seeds keep each run measuring the same
+// levels, and the AI is measured in an arena so how a level happened to
+// be generated doesn't matter.
+
+use coord_2d::{Coord, Size};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rand::SeedableRng;
+use rand_isaac::Isaac64Rng;
+
+use chargrid_roguelike_tutorial_2020::difficulty::Difficulty;
+use chargrid_roguelike_tutorial_2020::game::{GameState, NewCharacter, PlayerAction};
+use chargrid_roguelike_tutorial_2020::terrain::{
+    self, GenerationConstraints, LevelTheme, SpawnTables,
+};
+use chargrid_roguelike_tutorial_2020::visibility::{VisibilityAlgorithm, VisibilityGrid};
+use chargrid_roguelike_tutorial_2020::world::{NpcType, StartingKit, World};
+
+const RNG_SEED: u64 = 0;
+const MAP_SIZES: [Size; 3] = [
+    Size::new_u16(40, 23),
+    Size::new_u16(80, 50),
+    Size::new_u16(160, 100),
+];
+// the first level of each theme, and the final level
+const DUNGEON_LEVELS: [u32; 5] = [1, 4, 7, 10, terrain::FINAL_DUNGEON_LEVEL];
+const NUM_PLAYER_POSITIONS: usize = 8;
+const AI_MAP_SIZE: Size = Size::new_u16(80, 50);
+// the usual crowd, a big one, and one mostly too far away to be active
+const AGENT_COUNTS: [usize; 3] = [30, 50, 200];
+
+fn generate_dungeon(c: &mut Criterion) {
+    let spawn_tables = SpawnTables::built_in();
+    let constraints = GenerationConstraints::default();
+    let mut group = c.benchmark_group("generate_dungeon");
+    for size in MAP_SIZES {
+        for level in DUNGEON_LEVELS {
+            let id = BenchmarkId::new(format!("{}x{}", size.width(), size.height()), level);
+            group.bench_function(id, |b| {
+                b.iter_batched(
+                    || Isaac64Rng::seed_from_u64(RNG_SEED),
+                    |mut rng| {
+                        terrain::generate_dungeon(
+                            size,
+                            level,
+                            &constraints,
+                            &spawn_tables,
+                            &mut rng,
+                        )
+                    },
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+fn visibility_update(c: &mut Criterion) {
+    let spawn_tables = SpawnTables::built_in();
+    let mut group = c.benchmark_group("visibility_update");
+    for level in DUNGEON_LEVELS {
+        let mut world = World::new(AI_MAP_SIZE);
+        let mut rng = Isaac64Rng::seed_from_u64(RNG_SEED);
+        world.populate(level, &spawn_tables, &StartingKit::default(), &mut rng);
+        let theme = LevelTheme::from_dungeon_level(level);
+        let player_coords = (0..NUM_PLAYER_POSITIONS)
+            .filter_map(|_| world.random_free_coord(&mut rng))
+            .collect::<Vec<Coord>>();
+        let mut shadowcast_context = shadowcast::Context::default();
+        let mut visibility_grid = VisibilityGrid::new(AI_MAP_SIZE);
+        group.bench_function(BenchmarkId::from_parameter(level), |b| {
+            b.iter(|| {
+                for &player_coord in &player_coords {
+                    visibility_grid.update(
+                        player_coord,
+                        theme.player_light_radius(),
+                        theme.sight_radius(),
+                        None,
+                        &world,
+                        &mut shadowcast_context,
+                        VisibilityAlgorithm::Shadowcast,
+                    );
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+// Updates with nothing changed since the last, as when the player waits
+// or an action fails, on the largest map
+fn visibility_unchanged(c: &mut Criterion) {
+    let size = MAP_SIZES[MAP_SIZES.len() - 1];
+    let mut world = World::new(size);
+    let mut rng = Isaac64Rng::seed_from_u64(RNG_SEED);
+    world.populate(
+        1,
+        &SpawnTables::built_in(),
+        &StartingKit::default(),
+        &mut rng,
+    );
+    let theme = LevelTheme::from_dungeon_level(1);
+    let player_coord = world
+        .random_free_coord(&mut rng)
+        .expect("level has no free cell");
+    let mut shadowcast_context = shadowcast::Context::default();
+    let mut visibility_grid = VisibilityGrid::new(size);
+    let mut update = || {
+        visibility_grid.update(
+            player_coord,
+            theme.player_light_radius(),
+            theme.sight_radius(),
+            None,
+            &world,
+            &mut shadowcast_context,
+            VisibilityAlgorithm::Shadowcast,
+        )
+    };
+    update();
+    c.bench_function("visibility_unchanged", |b| b.iter(&mut update));
+}
+
+// Everything drawn on the map in a frame, with the player at the start of
+// a generated level
+fn entities_to_render(c: &mut Criterion) {
+    let game_state = GameState::new(
+        AI_MAP_SIZE,
+        RNG_SEED,
+        VisibilityAlgorithm::Shadowcast,
+        SpawnTables::built_in(),
+        Difficulty::Normal,
+        NewCharacter::default(),
+    );
+    c.bench_function("entities_to_render", |b| {
+        b.iter(|| game_state.entities_to_render(None).count())
+    });
+}
+
+// A whole turn of the NPCs, which the player waiting lets take place
+fn ai_turn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ai_turn");
+    for num_agents in AGENT_COUNTS {
+        group.bench_function(BenchmarkId::from_parameter(num_agents), |b| {
+            b.iter_batched_ref(
+                || {
+                    let mut game_state = GameState::new_arena(
+                        AI_MAP_SIZE,
+                        RNG_SEED,
+                        VisibilityAlgorithm::Shadowcast,
+                    );
+                    let num_spawned = game_state.spawn_npcs(NpcType::Orc, num_agents);
+                    assert_eq!(
+                        num_spawned, num_agents,
+                        "the arena is too small for the agents"
+                    );
+                    game_state
+                },
+                |game_state| game_state.perform(PlayerAction::Wait),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    generate_dungeon,
+    visibility_update,
+    visibility_unchanged,
+    entities_to_render,
+    ai_turn
+);
+criterion_main!(benches);
@@ -5,14 +5,17 @@ use chargrid::{
     decorator::{
         AlignView, Alignment, BorderStyle, BorderView, BoundView, FillBackgroundView, MinSizeView,
     },
-    event_routine::{self, common_event::{CommonEvent, Delay},
-                    make_either, DataSelector, Decorate, EventOrPeek,
-                    EventRoutine, EventRoutineView, Handled, Loop,
-                    SideEffect, SideEffectThen, Value, ViewSelector},
+    event_routine::{
+        self,
+        common_event::{CommonEvent, Delay},
+        make_either, DataSelector, Decorate, EventOrPeek, EventRoutine, EventRoutineView, Handled,
+        Loop, SideEffect, SideEffectThen, Value, ViewSelector,
+    },
     input::{keys, Input, KeyboardInput, MouseButton, MouseInput},
-    menu::{self, ChooseSelector, MenuIndexFromScreenCoord, MenuInstanceBuilder,
-           MenuInstanceChoose, MenuInstanceChooseOrEscape, MenuInstanceMouseTracker,
-           MenuInstanceRoutine},
+    menu::{
+        self, ChooseSelector, MenuIndexFromScreenCoord, MenuInstanceBuilder, MenuInstanceChoose,
+        MenuInstanceChooseOrEscape, MenuInstanceMouseTracker, MenuInstanceRoutine,
+    },
     render::{blend_mode, ColModify, ColModifyMap, Frame, Style, View, ViewCell, ViewContext},
     text::{RichTextPart, RichTextViewSingleLine, StringViewSingleLine},
 };
@@ -21,246 +24,1801 @@ use direction::CardinalDirection;
 use general_storage_file::{format, FileStorage, IfDirectoryMissing, Storage};
 use maplit::hashmap;
 use rgb24::Rgb24;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::time::Duration;
 
-use crate::game::{GameState, LevelUp};
-use crate::ui::{StatsData, UiData, UiView};
-use crate::visibility::{CellVisibility, VisibilityAlgorithm};
-use crate::world::{ItemType, ItemUsage, Layer, NpcType, ProjectileType, Tile};
+use chargrid_roguelike_tutorial_2020::replay::{
+    Desync, Recorder, Replay, ReplayEntry, ReplayHeader,
+};
+use chargrid_roguelike_tutorial_2020::{log_debug, log_error, log_info};
 
+use crate::audio::Audio;
+use crate::config::{AudioConfig, GameConfig};
+use crate::crash::{self, RunProgress};
+use crate::daily;
+use crate::difficulty::Difficulty;
+use crate::game::{
+    AimPreview, Background, GameEvent, GameState, LevelUp, LogMessage, NewCharacter, PlayerAction,
+    StartOptions, TravelTarget, WizardCommand, NUM_QUICK_SLOTS,
+};
+use crate::i18n;
+use crate::keybindings::{Action, KeyBindings};
+use crate::scores::{HighScore, HighScoreOrder, HighScores};
+use crate::screenshot::{self, TextFrame};
+use crate::terrain::{LevelTheme, SpawnTables};
+use crate::ui::{
+    CharacterCreationData, CharacterCreationView, EquipmentData, HighScoresData, HighScoresView,
+    RunStatsData, RunStatsView, StatsData, StatusData, UiData, UiView, NUM_MESSAGES,
+};
+use crate::visibility::VisibilityAlgorithm;
+use crate::world::{
+    EquipmentClass, ItemType, ItemUsage, Layer, NpcType, ProjectileType, Tile, TrapType,
+    VisualEffect, VisualEffectType,
+};
+use colors::{ColorTheme, ThemeName};
 
-const UI_NUM_ROWS: u32 = 5;
 const BETWEEN_ANIMATION_TICKS: Duration = Duration::from_millis(33);
-const SAVE_DIR: &str = "save";
+const HIT_FLASH_DURATION: Duration = Duration::from_millis(150);
+const KILL_FLASH_DURATION: Duration = Duration::from_millis(100);
+const CURSOR_BLINK_PERIOD: Duration = Duration::from_millis(500);
+const LOW_HEALTH_PULSE_PERIOD: Duration = Duration::from_millis(1000);
+const BETWEEN_AUTO_ACT_STEPS: Duration = Duration::from_millis(60);
+// keys pressed while an animation plays are kept, up to this many
+const MAX_BUFFERED_INPUTS: usize = 3;
+pub const SAVE_DIR: &str = "save";
 const SAVE_FILE: &str = "save";
+// what was open over the game when it was saved, kept apart from the
+// save so that saves without it still load
+const SCREEN_FILE: &str = "screen";
+// a copy of the run saved while playing, for recovering after a crash
+const AUTOSAVE_FILE: &str = "autosave";
+const AUTOSAVE_PERIOD_TURNS: u64 = 100;
+const CRASH_REPORT_FILE: &str = "crash-report.txt";
 const SAVE_FORMAT: format::Compress<format::Json> = format::Compress(format::Json);
-
+// everyone plays the daily challenge at the same difficulty, so their
+// scores can be compared
+const DAILY_DIFFICULTY: Difficulty = Difficulty::Normal;
+// in characters, so the name fits in the UI's title row
+const MAX_CHARACTER_NAME_LEN: usize = 16;
 
 pub mod colors {
     use super::*;
     use rgb24::Rgb24;
-    pub const PLAYER: Rgb24 = Rgb24::new_grey(255);
-    pub const ORC: Rgb24 = Rgb24::new(0, 187, 0);
-    pub const TROLL: Rgb24 = Rgb24::new(187, 0, 0);
-    pub const HEALTH_POTION: Rgb24 = Rgb24::new(255, 0, 255);
-    pub const FIREBALL_SCROLL: Rgb24 = Rgb24::new(255, 127, 0);
-    pub const CONFUSION_SCROLL: Rgb24 = Rgb24::new(187, 0, 255);
-    pub const SWORD: Rgb24 = Rgb24::new(187, 187, 187);
-    pub const STAFF: Rgb24 = Rgb24::new(187, 127, 187);
-    pub const ARMOR: Rgb24 = Rgb24::new(127, 127, 127);
-    pub const ROBE: Rgb24 = Rgb24::new(127, 127, 187);
+    use std::str::FromStr;
+
+    #[derive(Clone, Copy)]
+    pub struct Palette {
+        pub floor_foreground: Rgb24,
+        pub floor_background: Rgb24,
+        pub wall_foreground: Rgb24,
+        pub wall_background: Rgb24,
+    }
+
+    // Every colour the game draws with, other than the greys of menus and
+    // plain text
+    pub struct ColorTheme {
+        pub player: Rgb24,
+        pub orc: Rgb24,
+        pub troll: Rgb24,
+        pub shopkeeper: Rgb24,
+        pub health_potion: Rgb24,
+        pub fireball_scroll: Rgb24,
+        pub confusion_scroll: Rgb24,
+        pub clairvoyance_scroll: Rgb24,
+        pub fog: Rgb24,
+        pub sensed: Rgb24,
+        pub sword: Rgb24,
+        pub staff: Rgb24,
+        pub armor: Rgb24,
+        pub robe: Rgb24,
+        pub backpack: Rgb24,
+        pub warhammer: Rgb24,
+        pub gold: Rgb24,
+        pub altar: Rgb24,
+        pub door: Rgb24,
+        pub stairs: Rgb24,
+        pub water: Rgb24,
+        pub water_background: Rgb24,
+        pub lava: Rgb24,
+        pub lava_background: Rgb24,
+        pub foliage: Rgb24,
+        pub chasm: Rgb24,
+        pub rune: Rgb24,
+        pub portal: Rgb24,
+        pub torch: Rgb24,
+        pub spike_trap: Rgb24,
+        pub teleport_trap: Rgb24,
+        pub alarm_trap: Rgb24,
+        pub remembered: Rgb24,
+        pub remembered_stairs: Rgb24,
+        // messages and status effects that are bad or good for the player
+        pub danger: Rgb24,
+        pub healing: Rgb24,
+        pub health_high: Rgb24,
+        pub health_mid: Rgb24,
+        pub health_low: Rgb24,
+        pub log_harm: Rgb24,
+        pub log_combat: Rgb24,
+        pub log_items: Rgb24,
+        pub dungeon: Palette,
+        pub caves: Palette,
+        pub crypt: Palette,
+        pub lair: Palette,
+    }
+
+    pub const CLASSIC: ColorTheme = ColorTheme {
+        player: Rgb24::new_grey(255),
+        orc: Rgb24::new(0, 187, 0),
+        troll: Rgb24::new(187, 0, 0),
+        shopkeeper: Rgb24::new(255, 255, 0),
+        health_potion: Rgb24::new(255, 0, 255),
+        fireball_scroll: Rgb24::new(255, 127, 0),
+        confusion_scroll: Rgb24::new(187, 0, 255),
+        clairvoyance_scroll: Rgb24::new(0, 187, 255),
+        fog: Rgb24::new_grey(187),
+        sensed: Rgb24::new(255, 0, 255),
+        sword: Rgb24::new(187, 187, 187),
+        staff: Rgb24::new(187, 127, 187),
+        armor: Rgb24::new(127, 127, 127),
+        robe: Rgb24::new(127, 127, 187),
+        backpack: Rgb24::new(159, 111, 63),
+        warhammer: Rgb24::new(159, 159, 127),
+        gold: Rgb24::new(255, 215, 0),
+        altar: Rgb24::new(187, 187, 255),
+        door: Rgb24::new(187, 127, 63),
+        stairs: Rgb24::new_grey(255),
+        water: Rgb24::new(63, 127, 255),
+        water_background: Rgb24::new(0, 0, 127),
+        lava: Rgb24::new(255, 127, 0),
+        lava_background: Rgb24::new(127, 31, 0),
+        foliage: Rgb24::new(0, 187, 0),
+        chasm: Rgb24::new(63, 31, 95),
+        rune: Rgb24::new(0, 255, 255),
+        portal: Rgb24::new(255, 0, 255),
+        torch: Rgb24::new(255, 187, 0),
+        spike_trap: Rgb24::new(187, 187, 187),
+        teleport_trap: Rgb24::new(0, 187, 255),
+        alarm_trap: Rgb24::new(255, 63, 63),
+        remembered: Rgb24::new_grey(63),
+        remembered_stairs: Rgb24::new_grey(187),
+        danger: Rgb24::new(255, 0, 0),
+        healing: Rgb24::new(0, 187, 0),
+        health_high: Rgb24::new(0, 127, 0),
+        health_mid: Rgb24::new(127, 127, 0),
+        health_low: Rgb24::new(160, 0, 0),
+        log_harm: Rgb24::new(255, 63, 63),
+        log_combat: Rgb24::new(255, 159, 63),
+        log_items: Rgb24::new(255, 255, 127),
+        dungeon: Palette {
+            floor_foreground: Rgb24::new_grey(63),
+            floor_background: Rgb24::new(0, 0, 63),
+            wall_foreground: Rgb24::new(0, 63, 63),
+            wall_background: Rgb24::new(63, 127, 127),
+        },
+        caves: Palette {
+            floor_foreground: Rgb24::new(95, 63, 31),
+            floor_background: Rgb24::new(31, 23, 15),
+            wall_foreground: Rgb24::new(63, 47, 31),
+            wall_background: Rgb24::new(127, 95, 63),
+        },
+        crypt: Palette {
+            floor_foreground: Rgb24::new_grey(95),
+            floor_background: Rgb24::new_grey(31),
+            wall_foreground: Rgb24::new_grey(63),
+            wall_background: Rgb24::new_grey(127),
+        },
+        lair: Palette {
+            floor_foreground: Rgb24::new(95, 0, 0),
+            floor_background: Rgb24::new(47, 0, 15),
+            wall_foreground: Rgb24::new(63, 0, 0),
+            wall_background: Rgb24::new(127, 31, 31),
+        },
+    };
+
+    // Black floors and bright walls on every level, with fully saturated
+    // colours for everything on them
+    pub const HIGH_CONTRAST: ColorTheme = ColorTheme {
+        orc: Rgb24::new(0, 255, 0),
+        troll: Rgb24::new(255, 0, 0),
+        staff: Rgb24::new(255, 127, 255),
+        armor: Rgb24::new(187, 187, 187),
+        robe: Rgb24::new(127, 127, 255),
+        backpack: Rgb24::new(223, 159, 63),
+        warhammer: Rgb24::new(223, 223, 159),
+        door: Rgb24::new(255, 159, 63),
+        water: Rgb24::new(127, 187, 255),
+        foliage: Rgb24::new(0, 255, 0),
+        chasm: Rgb24::new(127, 63, 187),
+        spike_trap: Rgb24::new_grey(255),
+        remembered: Rgb24::new_grey(127),
+        remembered_stairs: Rgb24::new_grey(255),
+        healing: Rgb24::new(0, 255, 0),
+        health_high: Rgb24::new(0, 187, 0),
+        health_mid: Rgb24::new(187, 187, 0),
+        health_low: Rgb24::new(255, 0, 0),
+        dungeon: Palette {
+            floor_foreground: Rgb24::new_grey(127),
+            floor_background: Rgb24::new_grey(0),
+            wall_foreground: Rgb24::new_grey(0),
+            wall_background: Rgb24::new_grey(255),
+        },
+        caves: Palette {
+            floor_foreground: Rgb24::new(187, 127, 63),
+            floor_background: Rgb24::new_grey(0),
+            wall_foreground: Rgb24::new_grey(0),
+            wall_background: Rgb24::new(255, 223, 187),
+        },
+        crypt: Palette {
+            floor_foreground: Rgb24::new_grey(127),
+            floor_background: Rgb24::new_grey(0),
+            wall_foreground: Rgb24::new_grey(0),
+            wall_background: Rgb24::new_grey(223),
+        },
+        lair: Palette {
+            floor_foreground: Rgb24::new(187, 63, 63),
+            floor_background: Rgb24::new_grey(0),
+            wall_foreground: Rgb24::new_grey(0),
+            wall_background: Rgb24::new(255, 187, 187),
+        },
+        ..CLASSIC
+    };
+
+    // Keeps things that must be told apart on the blue-orange axis rather
+    // than red-green, so orcs, trolls and the health bar stay distinct
+    pub const DEUTERANOPIA: ColorTheme = ColorTheme {
+        orc: Rgb24::new(86, 180, 233),
+        troll: Rgb24::new(213, 94, 0),
+        health_potion: Rgb24::new(204, 121, 167),
+        confusion_scroll: Rgb24::new(0, 114, 178),
+        sensed: Rgb24::new(204, 121, 167),
+        foliage: Rgb24::new(0, 158, 115),
+        portal: Rgb24::new(204, 121, 167),
+        teleport_trap: Rgb24::new(86, 180, 233),
+        alarm_trap: Rgb24::new(213, 94, 0),
+        danger: Rgb24::new(213, 94, 0),
+        healing: Rgb24::new(86, 180, 233),
+        health_high: Rgb24::new(0, 114, 178),
+        health_mid: Rgb24::new(160, 150, 40),
+        health_low: Rgb24::new(213, 94, 0),
+        log_harm: Rgb24::new(230, 159, 0),
+        log_combat: Rgb24::new(240, 228, 66),
+        log_items: Rgb24::new(86, 180, 233),
+        lair: Palette {
+            floor_foreground: Rgb24::new(95, 47, 0),
+            floor_background: Rgb24::new(47, 23, 0),
+            wall_foreground: Rgb24::new(63, 31, 0),
+            wall_background: Rgb24::new(127, 79, 31),
+        },
+        ..CLASSIC
+    };
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum ThemeName {
+        Classic,
+        HighContrast,
+        Deuteranopia,
+    }
+
+    impl ThemeName {
+        pub fn colors(self) -> &'static ColorTheme {
+            match self {
+                Self::Classic => &CLASSIC,
+                Self::HighContrast => &HIGH_CONTRAST,
+                Self::Deuteranopia => &DEUTERANOPIA,
+            }
+        }
+
+        pub fn name(self) -> &'static str {
+            match self {
+                Self::Classic => "classic",
+                Self::HighContrast => "high-contrast",
+                Self::Deuteranopia => "deuteranopia",
+            }
+        }
+
+        // The theme after this one in the options menu
+        pub fn next(self) -> Self {
+            match self {
+                Self::Classic => Self::HighContrast,
+                Self::HighContrast => Self::Deuteranopia,
+                Self::Deuteranopia => Self::Classic,
+            }
+        }
+    }
+
+    impl FromStr for ThemeName {
+        type Err = String;
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "classic" => Ok(Self::Classic),
+                "high-contrast" => Ok(Self::HighContrast),
+                "deuteranopia" => Ok(Self::Deuteranopia),
+                _ => Err(format!(
+                    "unknown theme {:?} (expected classic, high-contrast or deuteranopia)",
+                    s
+                )),
+            }
+        }
+    }
+
+    impl ColorTheme {
+        pub fn palette(&self, theme: LevelTheme) -> Palette {
+            match theme {
+                LevelTheme::Dungeon => self.dungeon,
+                LevelTheme::Caves => self.caves,
+                LevelTheme::Crypt => self.crypt,
+                LevelTheme::Lair => self.lair,
+            }
+        }
+
+        pub fn npc_color(&self, npc_type: NpcType) -> Rgb24 {
+            match npc_type {
+                NpcType::Orc => self.orc,
+                NpcType::Troll => self.troll,
+                NpcType::Shopkeeper => self.shopkeeper,
+            }
+        }
+
+        pub fn item_color(&self, item_type: ItemType) -> Rgb24 {
+            match item_type {
+                ItemType::HealthPotion => self.health_potion,
+                ItemType::FireballScroll => self.fireball_scroll,
+                ItemType::ConfusionScroll => self.confusion_scroll,
+                ItemType::ClairvoyanceScroll => self.clairvoyance_scroll,
+                ItemType::Torch => self.torch,
+                ItemType::SmokeBomb => self.fog,
+                ItemType::Sword => self.sword,
+                ItemType::Staff => self.staff,
+                ItemType::Armor => self.armor,
+                ItemType::Robe => self.robe,
+                ItemType::Backpack => self.backpack,
+                ItemType::Warhammer => self.warhammer,
+            }
+        }
+
+        pub fn trap_color(&self, trap_type: TrapType) -> Rgb24 {
+            match trap_type {
+                TrapType::Spike => self.spike_trap,
+                TrapType::Teleport => self.teleport_trap,
+                TrapType::Alarm => self.alarm_trap,
+            }
+        }
+
+        pub fn projectile_color(&self, projectile_type: ProjectileType) -> Rgb24 {
+            match projectile_type {
+                ProjectileType::Fireball { .. } => self.fireball_scroll,
+                ProjectileType::Confusion { .. } => self.confusion_scroll,
+            }
+        }
+    }
+}
 
-    pub fn npc_color(npc_type: NpcType) -> Rgb24 {
-        match npc_type {
-            NpcType::Orc => ORC,
-            NpcType::Troll => TROLL,
+// Short-lived visual feedback for things happening in the game
+#[derive(Default)]
+struct Effects {
+    until_hit_flash_ends: Duration,
+    shake_frames: u32,
+    kill_flashes: Vec<(Coord, Duration)>,
+    // how far through the current low health pulse
+    low_health_pulse_time: Duration,
+}
+
+impl Effects {
+    fn add(&mut self, event: GameEvent) {
+        match event {
+            GameEvent::PlayerDamaged => {
+                self.until_hit_flash_ends = HIT_FLASH_DURATION;
+                self.shake_frames = 1;
+            }
+            GameEvent::KillingBlow(coord) => self.kill_flashes.push((coord, KILL_FLASH_DURATION)),
+            _ => (),
         }
     }
 
-    pub fn item_color(item_type: ItemType) -> Rgb24 {
-        match item_type {
-            ItemType::HealthPotion => HEALTH_POTION,
-            ItemType::FireballScroll => FIREBALL_SCROLL,
-            ItemType::ConfusionScroll => CONFUSION_SCROLL,
-            ItemType::Sword => SWORD,
-            ItemType::Staff => STAFF,
-            ItemType::Armor => ARMOR,
-            ItemType::Robe => ROBE,
+    fn tick(&mut self, period: Duration) {
+        self.until_hit_flash_ends = self.until_hit_flash_ends.saturating_sub(period);
+        self.shake_frames = self.shake_frames.saturating_sub(1);
+        for (_, remaining) in self.kill_flashes.iter_mut() {
+            *remaining = remaining.saturating_sub(period);
         }
+        self.kill_flashes
+            .retain(|&(_, remaining)| remaining > Duration::from_millis(0));
+        self.low_health_pulse_time += period;
+        while self.low_health_pulse_time >= LOW_HEALTH_PULSE_PERIOD {
+            self.low_health_pulse_time -= LOW_HEALTH_PULSE_PERIOD;
+        }
+    }
+
+    // Rises from 0 to 255 and back over each pulse period
+    fn low_health_pulse(&self) -> u8 {
+        let period = LOW_HEALTH_PULSE_PERIOD.as_millis();
+        let time = self.low_health_pulse_time.as_millis();
+        let rising = if time * 2 < period {
+            time
+        } else {
+            period - time
+        };
+        (rising * 2 * 255 / period) as u8
+    }
+
+    fn is_hit_flashing(&self) -> bool {
+        self.until_hit_flash_ends > Duration::from_millis(0)
     }
 
-    pub fn projectile_color(projectile_type: ProjectileType) -> Rgb24 {
-        match projectile_type {
-            ProjectileType::Fireball { .. } => FIREBALL_SCROLL,
-            ProjectileType::Confusion { .. } => CONFUSION_SCROLL,
+    fn camera_offset(&self) -> Coord {
+        if self.shake_frames > 0 {
+            Coord::new(1, 0)
+        } else {
+            Coord::new(0, 0)
         }
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MovePrefix {
+    Run,
+    ForceAttack,
+}
+
+// Player actions the repeat key can do again. Aimed items are left out,
+// so a fireball is never thrown again without choosing where.
+#[derive(Clone, Copy)]
+enum RepeatableAction {
+    Move(CardinalDirection),
+    Wait,
+    UseItem(usize),
+}
+
+// What to do with replays: either record the player's actions, or play
+// back a recording instead of taking input
+pub enum ReplayMode {
+    Record(String),
+    Play {
+        replay: Replay,
+        // the time between played back actions
+        step_period: Duration,
+    },
+}
+
+// A recording being played back a step at a time. Escape stops it and
+// hands the game over to the player.
+struct Playback {
+    entries: Vec<ReplayEntry>,
+    next_index: usize,
+    step_period: Duration,
+    until_next_step: Duration,
+    // the turn of the last checksum which matched
+    last_matching_turn: u64,
+}
 
 struct AppData {
     game_state: GameState,
     inventory_slot_menu: MenuInstanceChooseOrEscape<InventorySlotMenuEntry>,
+    // which slots of the open inventory menu are worth choosing
+    inventory_slot_filter: InventorySlotFilter,
     visibility_algorithm: VisibilityAlgorithm,
     cursor: Option<Coord>,
+    // the targeting cursor blinks on and off
+    cursor_blink_on: bool,
+    until_cursor_blink: Duration,
     until_next_animation_tick: Duration,
     main_menu: MenuInstanceChooseOrEscape<MainMenuEntry>,
+    // None where today's date is unknown, so there's no daily challenge
+    daily_challenge_date: Option<String>,
     game_area_size: Size,
     rng_seed: u64,
     level_up_menu: MenuInstanceChooseOrEscape<LevelUp>,
+    spawn_tables: SpawnTables,
+    // set while waiting for the player to confirm jumping into a chasm
+    confirming_chasm_jump: bool,
+    effects: Effects,
+    theme_name: ThemeName,
+    // for the next new run, which may not be the current run's
+    difficulty: Difficulty,
+    // the last character made, who new runs are started as until another
+    // is made
+    new_character: NewCharacter,
+    // the length of the message log when the player's current action began
+    action_log_start: usize,
+    // while set, the message log is paused at this index showing --More--
+    message_page_start: Option<usize>,
+    // the diagnostics overlay can only be shown when enabled on the
+    // command line
+    diagnostics_enabled: bool,
+    show_diagnostics: bool,
+    // set by the screenshot key, and cleared by the next frame drawn,
+    // which draws itself a second time as text
+    screenshot_requested: Cell<bool>,
+    // the text of the screenshot, waiting to be saved between frames
+    screenshot_text: RefCell<Option<String>>,
+    key_bindings: KeyBindings,
+    // the time between the last two frames
+    frame_period: Duration,
+    // the way the player would walk to the cell under the mouse
+    travel_preview: Vec<Coord>,
+    until_auto_act_step: Duration,
+    // set after the run or force attack key, and changes what the next
+    // direction pressed does
+    move_prefix: Option<MovePrefix>,
+    // keys pressed during an animation, and the player's hit points when
+    // the first was pressed
+    input_buffer: Vec<Input>,
+    input_buffer_hit_points: u32,
+    // the last action which took a turn, and the dungeon level it was
+    // taken on, as repeating it on another level makes no sense
+    last_action: Option<(RepeatableAction, u32)>,
+    // rebuilt from the places the player remembers each time it's opened
+    travel_menu: Option<MenuInstanceChooseOrEscape<TravelMenuEntry>>,
+    recorder: Option<Recorder>,
+    playback: Option<Playback>,
+    // games watched from a replay aren't saved over the player's own
+    save_enabled: bool,
+    // the cheat keys only work when started with --wizard
+    wizard_enabled: bool,
+    wizard_menu: Option<MenuInstanceChooseOrEscape<WizardMenuEntry>>,
+    // wizard mode's overlay of how far NPCs would walk to the player
+    show_npc_distances: bool,
+    // every new run other than a daily challenge starts with this
+    start_options: Option<StartOptions>,
+    game_config: GameConfig,
+    // the dungeon level and turn of the last autosave
+    last_autosave: (u32, u64),
+    high_scores: HighScores,
+    high_score_order: HighScoreOrder,
+    audio: Audio,
+    // the screen open over the game, which is saved so it can be opened
+    // again when the game is loaded
+    open_screen: Option<OpenScreen>,
+    // opened as soon as the game starts, after loading a save which had it
+    // open
+    reopen_screen: Option<OpenScreen>,
+}
+
+// The screens which can be open over the game when it's saved
+#[derive(Clone, Copy, Serialize, Deserialize)]
+enum OpenScreen {
+    UseItem,
+    DropItem,
+    Equipment(EquipmentCommand),
+    AssignQuickSlot,
+    Examine,
+    AimItem(usize),
+}
+
+impl OpenScreen {
+    fn of_game_return(game_return: &GameReturn) -> Option<Self> {
+        match *game_return {
+            GameReturn::UseItem => Some(Self::UseItem),
+            GameReturn::DropItem => Some(Self::DropItem),
+            GameReturn::Equipment(command) => Some(Self::Equipment(command)),
+            GameReturn::AssignQuickSlot => Some(Self::AssignQuickSlot),
+            GameReturn::Examine => Some(Self::Examine),
+            GameReturn::AimItem(inventory_index) => Some(Self::AimItem(inventory_index)),
+            _ => None,
+        }
+    }
+
+    fn game_return(self) -> GameReturn {
+        match self {
+            Self::UseItem => GameReturn::UseItem,
+            Self::DropItem => GameReturn::DropItem,
+            Self::Equipment(command) => GameReturn::Equipment(command),
+            Self::AssignQuickSlot => GameReturn::AssignQuickSlot,
+            Self::Examine => GameReturn::Examine,
+            Self::AimItem(inventory_index) => GameReturn::AimItem(inventory_index),
+        }
+    }
+}
 
+// The parts of the app's state which are saved with the game, so that
+// quitting and loading again carries on just where the player left off
+#[derive(Serialize, Deserialize)]
+struct SavedScreen {
+    open_screen: Option<OpenScreen>,
+    inventory_slot_index: usize,
+    cursor: Option<Coord>,
+    message_page_start: Option<usize>,
+    show_diagnostics: bool,
+    show_npc_distances: bool,
 }
 
 impl AppData {
-    fn new(screen_size: Size,
-           rng_seed: u64,
-           visibility_algorithm: VisibilityAlgorithm) -> Self {
-        let game_area_size = screen_size.set_height(screen_size.height() - UI_NUM_ROWS);
-
-        let game_state = Self::load_game()
-            .unwrap_or_else(|| GameState::new(game_area_size, rng_seed, visibility_algorithm));
-        let player_inventory = game_state.player_inventory();
-        let inventory_slot_menu = {
-            let items = (0..player_inventory.slots().len())
-                .zip('a'..)
-                .map(|(index, key)| InventorySlotMenuEntry { index, key })
-                .collect::<Vec<_>>();
-            let hotkeys = items
-                .iter()
-                .map(|&entry| (entry.key, entry))
-                .collect::<HashMap<_, _>>();
-            MenuInstanceBuilder {
-                items,
-                hotkeys: Some(hotkeys),
-                selected_index: 0,
-            }.build()
-                .unwrap()
-                .into_choose_or_escape()
+    fn new(
+        game_area_size: Size,
+        rng_seed: u64,
+        visibility_algorithm: VisibilityAlgorithm,
+        spawn_tables: SpawnTables,
+        theme_name: ThemeName,
+        difficulty: Difficulty,
+        diagnostics_enabled: bool,
+        wizard_enabled: bool,
+        start_options: Option<StartOptions>,
+        game_config: GameConfig,
+        audio_config: AudioConfig,
+        daily_date: Option<String>,
+        key_bindings: KeyBindings,
+        replay_mode: Option<ReplayMode>,
+    ) -> Self {
+        let mut rng_seed = rng_seed;
+        let mut recorder = None;
+        let mut playback = None;
+        let mut saved_screen = None;
+        let game_state = match replay_mode {
+            Some(ReplayMode::Play {
+                replay,
+                step_period,
+            }) => {
+                log_info!(
+                    "Playing back a replay. Press escape to take over, or + and - to change speed."
+                );
+                rng_seed = replay.header.rng_seed;
+                playback = Some(Playback {
+                    entries: replay.entries,
+                    next_index: 0,
+                    step_period,
+                    until_next_step: step_period,
+                    last_matching_turn: 0,
+                });
+                replay.header.new_game_state()
+            }
+            // a recording has to start at the beginning of a run, so the
+            // save isn't loaded
+            Some(ReplayMode::Record(path)) => {
+                let header = ReplayHeader {
+                    rng_seed,
+                    map_size: game_area_size,
+                    visibility_algorithm,
+                    spawn_tables: spawn_tables.clone(),
+                    difficulty: if daily_date.is_some() {
+                        DAILY_DIFFICULTY
+                    } else {
+                        difficulty
+                    },
+                    new_character: NewCharacter::default(),
+                };
+                match Recorder::create(&path, &header) {
+                    Ok(new_recorder) => recorder = Some(new_recorder),
+                    Err(error) => log_error!("Failed to record to {}: {}", path, error),
+                }
+                let mut game_state = header.new_game_state();
+                if let Some(date) = daily_date {
+                    game_state.set_daily_date(date);
+                }
+                game_state
+            }
+            // a daily challenge starts from the beginning too
+            None => match daily_date {
+                Some(date) => {
+                    let mut game_state = GameState::new(
+                        game_area_size,
+                        rng_seed,
+                        visibility_algorithm,
+                        spawn_tables.clone(),
+                        DAILY_DIFFICULTY,
+                        NewCharacter::default(),
+                    );
+                    game_state.set_daily_date(date);
+                    game_state
+                }
+                // as does a head start
+                None => match start_options.as_ref() {
+                    Some(start_options) => {
+                        let mut game_state = GameState::new(
+                            game_area_size,
+                            rng_seed,
+                            visibility_algorithm,
+                            spawn_tables.clone(),
+                            difficulty,
+                            NewCharacter::default(),
+                        );
+                        game_state.apply_start_options(start_options, visibility_algorithm);
+                        game_state
+                    }
+                    None => match Self::load_game() {
+                        Some(game_state) => {
+                            saved_screen = Self::load_saved_screen();
+                            game_state
+                        }
+                        None => GameState::new(
+                            game_area_size,
+                            rng_seed,
+                            visibility_algorithm,
+                            spawn_tables.clone(),
+                            difficulty,
+                            NewCharacter::default(),
+                        ),
+                    },
+                },
+            },
         };
-        Self {
+        let save_enabled = playback.is_none();
+        // only offered when the player would otherwise carry on with their
+        // own save
+        let recoverable = save_enabled && recorder.is_none() && Self::is_crash_recoverable();
+        if recoverable {
+            log_info!("The last run crashed. It can be recovered from the menu.");
+        }
+        let daily_challenge_date = daily::today();
+        let last_autosave = (game_state.dungeon_level(), game_state.turn());
+        let action_log_start = game_state.message_log().end();
+        let inventory_slot_menu =
+            inventory_slot_menu_instance(game_state.player_inventory().slots().len());
+        let mut data = Self {
             game_state,
             inventory_slot_menu,
+            inventory_slot_filter: InventorySlotFilter::All,
             visibility_algorithm,
             cursor: None,
+            cursor_blink_on: true,
+            until_cursor_blink: CURSOR_BLINK_PERIOD,
             until_next_animation_tick: Duration::from_millis(0),
-            main_menu: main_menu_instance(),
+            main_menu: main_menu_instance(recoverable, daily_challenge_date.is_some()),
+            daily_challenge_date,
             game_area_size,
             rng_seed,
             level_up_menu: level_up_menu_instance(),
+            spawn_tables,
+            confirming_chasm_jump: false,
+            effects: Effects::default(),
+            theme_name,
+            difficulty,
+            new_character: NewCharacter::default(),
+            action_log_start,
+            message_page_start: None,
+            diagnostics_enabled,
+            show_diagnostics: false,
+            screenshot_requested: Cell::new(false),
+            screenshot_text: RefCell::new(None),
+            key_bindings,
+            frame_period: Duration::from_millis(0),
+            travel_preview: Vec::new(),
+            travel_menu: None,
+            move_prefix: None,
+            input_buffer: Vec::new(),
+            input_buffer_hit_points: 0,
+            last_action: None,
+            until_auto_act_step: Duration::from_millis(0),
+            recorder,
+            playback,
+            save_enabled,
+            wizard_enabled,
+            wizard_menu: None,
+            show_npc_distances: false,
+            start_options,
+            game_config,
+            last_autosave,
+            high_scores: HighScores::load(),
+            high_score_order: HighScoreOrder::Score,
+            audio: Audio::new(audio_config.volume),
+            open_screen: None,
+            reopen_screen: None,
+        };
+        if let Some(saved_screen) = saved_screen {
+            data.restore_screen(saved_screen);
+        }
+        data
+    }
+
+    fn colors(&self) -> &'static ColorTheme {
+        self.theme_name.colors()
+    }
+
+    // The menu has an entry for each slot, so it's built again if the
+    // player's inventory has grown or shrunk since it was last opened
+    fn update_inventory_slot_menu(&mut self) {
+        let num_slots = self.game_state.player_inventory().slots().len();
+        if self.inventory_slot_menu.menu_instance().enumerate().count() != num_slots {
+            self.inventory_slot_menu = inventory_slot_menu_instance(num_slots);
+        }
+    }
+
+    fn saved_screen(&self) -> SavedScreen {
+        SavedScreen {
+            open_screen: self.open_screen,
+            inventory_slot_index: self.inventory_slot_menu.menu_instance().index(),
+            cursor: self.cursor,
+            message_page_start: self.message_page_start,
+            show_diagnostics: self.show_diagnostics,
+            show_npc_distances: self.show_npc_distances,
+        }
+    }
+
+    fn restore_screen(&mut self, saved_screen: SavedScreen) {
+        self.update_inventory_slot_menu();
+        let num_slots = self.game_state.player_inventory().slots().len();
+        self.inventory_slot_menu.menu_instance_mut().set_index(
+            saved_screen
+                .inventory_slot_index
+                .min(num_slots.saturating_sub(1)),
+        );
+        self.cursor = saved_screen.cursor;
+        // paged from the start of the action which was being read
+        if let Some(message_page_start) = saved_screen.message_page_start {
+            self.message_page_start = Some(message_page_start);
+            self.action_log_start = message_page_start;
         }
+        self.show_diagnostics = saved_screen.show_diagnostics && self.diagnostics_enabled;
+        self.show_npc_distances = saved_screen.show_npc_distances && self.wizard_enabled;
+        self.reopen_screen = saved_screen.open_screen;
+    }
+
+    // Notes which screen, if any, the game is going to open
+    fn opening(&mut self, game_return: GameReturn) -> GameReturn {
+        self.open_screen = OpenScreen::of_game_return(&game_return);
+        game_return
     }
 
     fn handle_input(&mut self, input: Input) -> Option<GameReturn> {
-        if !self.game_state.is_player_alive() {
-            return Some(GameReturn::GameOver);
+        self.check_recording();
+        // a player who died mid-action still gets to read how it happened
+        if self.message_page_start.is_some() {
+            self.page_messages(input);
+            return None;
+        }
+        if self.playback.is_some() {
+            // a replay ends with the player's death
+            if !self.game_state.is_player_alive() {
+                return Some(GameReturn::GameOver);
+            }
+            if let Input::Keyboard(key) = input {
+                self.control_playback(key);
+            }
+            return None;
+        }
+        // escape is never kept waiting, and forgets anything pressed before
+        if let Input::Keyboard(key) = input {
+            if key == keys::ESCAPE {
+                self.input_buffer.clear();
+            } else if self.game_state.has_animations() {
+                if self.input_buffer.is_empty() {
+                    self.input_buffer_hit_points = self.game_state.player_hit_points().current();
+                }
+                if self.input_buffer.len() < MAX_BUFFERED_INPUTS {
+                    self.input_buffer.push(input);
+                }
+                return None;
+            }
+        }
+        if let Input::Keyboard(_) = input {
+            // any key interrupts travelling or resting
+            if self.game_state.is_auto_acting() {
+                self.perform(PlayerAction::StopAutoAct);
+            }
+            self.travel_preview.clear();
+            if !self.game_state.has_animations() {
+                self.action_log_start = self.game_state.message_log().end();
+            }
+        }
+        if self.confirming_chasm_jump {
+            if let Input::Keyboard(key) = input {
+                self.confirming_chasm_jump = false;
+                if key == KeyboardInput::Char('y') {
+                    self.perform(PlayerAction::JumpIntoChasm);
+                } else {
+                    self.game_state.cancel_jump_into_chasm();
+                }
+            }
+            self.check_for_more_messages();
+            return None;
         }
         match input {
             Input::Keyboard(key) => {
-                match key {
-                    KeyboardInput::Left => {
-                        self.game_state.maybe_move_player(CardinalDirection::West)
+                // a prefix key only applies to the key after it
+                let move_prefix = self.move_prefix.take();
+                // the wizard keys take priority over anything bound to them,
+                // except in the daily challenge where there's no cheating
+                let wizard_key = if self.wizard_enabled
+                    && self.game_state.daily_date().is_none()
+                    && self.game_state.is_player_alive()
+                {
+                    wizard_key(key)
+                } else {
+                    None
+                };
+                let action = match wizard_key {
+                    Some(WizardKey::Command(command)) => {
+                        self.wizard(command);
+                        None
+                    }
+                    Some(WizardKey::SpawnNpcMenu) => {
+                        self.open_wizard_menu(
+                            NpcType::ALL
+                                .iter()
+                                .map(|&npc_type| WizardCommand::SpawnNpc(npc_type)),
+                        );
+                        return Some(GameReturn::WizardMenu(i18n::text("menu.spawn")));
+                    }
+                    Some(WizardKey::GrantItemMenu) => {
+                        self.open_wizard_menu(
+                            ItemType::ALL
+                                .iter()
+                                .map(|&item_type| WizardCommand::GrantItem(item_type)),
+                        );
+                        return Some(GameReturn::WizardMenu(i18n::text("menu.grant")));
+                    }
+                    None => self.key_bindings.action(key),
+                };
+                // a dead player can still look around the level, and any
+                // other key ends the run
+                if !self.game_state.is_player_alive() && !action.map_or(false, Action::is_meta) {
+                    return Some(GameReturn::GameOver);
+                }
+                match action {
+                    Some(Action::ToggleDiagnostics) => {
+                        if self.diagnostics_enabled {
+                            self.show_diagnostics = !self.show_diagnostics;
+                        }
+                        return None;
+                    }
+                    Some(Action::Screenshot) => {
+                        self.screenshot_requested.set(true);
+                        return None;
+                    }
+                    Some(Action::MoveWest) => {
+                        if move_prefix.is_some() {
+                            self.move_player(CardinalDirection::West, move_prefix)
+                        } else {
+                            self.act(RepeatableAction::Move(CardinalDirection::West))
+                        }
+                    }
+                    Some(Action::MoveEast) => {
+                        if move_prefix.is_some() {
+                            self.move_player(CardinalDirection::East, move_prefix)
+                        } else {
+                            self.act(RepeatableAction::Move(CardinalDirection::East))
+                        }
+                    }
+                    Some(Action::MoveNorth) => {
+                        if move_prefix.is_some() {
+                            self.move_player(CardinalDirection::North, move_prefix)
+                        } else {
+                            self.act(RepeatableAction::Move(CardinalDirection::North))
+                        }
+                    }
+                    Some(Action::MoveSouth) => {
+                        if move_prefix.is_some() {
+                            self.move_player(CardinalDirection::South, move_prefix)
+                        } else {
+                            self.act(RepeatableAction::Move(CardinalDirection::South))
+                        }
+                    }
+                    Some(Action::Wait) => self.act(RepeatableAction::Wait),
+                    Some(Action::Repeat) => {
+                        let dungeon_level = self.game_state.dungeon_level();
+                        match self.last_action {
+                            Some((action, level)) if level == dungeon_level => self.act(action),
+                            _ => (),
+                        }
+                    }
+                    Some(Action::Run) => self.move_prefix = Some(MovePrefix::Run),
+                    Some(Action::ForceAttack) => self.move_prefix = Some(MovePrefix::ForceAttack),
+                    Some(Action::Rest) => {
+                        if self.perform(PlayerAction::Rest) {
+                            self.until_auto_act_step = Duration::from_millis(0);
+                        }
+                    }
+                    Some(Action::Get) => {
+                        self.perform(PlayerAction::Get);
                     }
-                    KeyboardInput::Right => {
-                        self.game_state.maybe_move_player(CardinalDirection::East)
+                    Some(Action::CloseDoor) => {
+                        self.perform(PlayerAction::CloseDoor);
                     }
-                    KeyboardInput::Up => {
-                        self.game_state.maybe_move_player(CardinalDirection::North)
+                    Some(Action::Search) => {
+                        self.perform(PlayerAction::Search);
                     }
-                    KeyboardInput::Down => {
-                        self.game_state.maybe_move_player(CardinalDirection::South)
+                    Some(Action::PrayOrPay) => {
+                        if self.game_state.is_player_on_altar() {
+                            self.perform(PlayerAction::Pray);
+                        } else {
+                            self.perform(PlayerAction::Pay);
+                        }
+                    }
+                    Some(Action::UseItem) => return Some(GameReturn::UseItem),
+                    Some(Action::DropItem) => return Some(GameReturn::DropItem),
+                    Some(Action::Wield) => {
+                        return Some(GameReturn::Equipment(EquipmentCommand::Wield))
+                    }
+                    Some(Action::Wear) => {
+                        return Some(GameReturn::Equipment(EquipmentCommand::Wear))
+                    }
+                    Some(Action::Unequip) => {
+                        return Some(GameReturn::Equipment(EquipmentCommand::Unequip))
                     }
-                    KeyboardInput::Char(' ') => self.game_state.wait_player(),
-                    KeyboardInput::Char('g') => self.game_state.maybe_player_get_item(),
-                    KeyboardInput::Char('i') => return Some(GameReturn::UseItem),
-                    KeyboardInput::Char('d') => return Some(GameReturn::DropItem),
-                    KeyboardInput::Char('x') => {
+                    Some(Action::AssignQuickSlot) => return Some(GameReturn::AssignQuickSlot),
+                    Some(
+                        action @ (Action::QuickUse1
+                        | Action::QuickUse2
+                        | Action::QuickUse3
+                        | Action::QuickUse4
+                        | Action::QuickUse5),
+                    ) => {
+                        let quick_slot = action.quick_slot().expect("not a quick use action");
+                        if let Some(game_return) = self.quick_use(quick_slot) {
+                            return Some(game_return);
+                        }
+                    }
+                    Some(Action::Statistics) => return Some(GameReturn::Statistics),
+                    Some(Action::Examine) => {
                         if self.cursor.is_none() {
                             self.cursor = Some(self.game_state.player_coord());
                         }
                         return Some(GameReturn::Examine);
                     }
-                    KeyboardInput::Char('>') => {
+                    Some(Action::Descend) => {
                         if self.game_state.is_player_on_stairs() {
-                            return Some(GameReturn::LevelUpAndDescend);
+                            // levelling up only happens on reaching a new level
+                            if self.game_state.is_next_level_visited() {
+                                // there's no levelling up on a visited level,
+                                // so this one is ignored
+                                self.perform(PlayerAction::Descend(LevelUp::Health));
+                            } else {
+                                return Some(GameReturn::LevelUpAndDescend);
+                            }
+                        } else if self.game_state.is_player_on_portal() {
+                            if self.perform(PlayerAction::EnterPortal) {
+                                return Some(GameReturn::Victory);
+                            }
+                        } else if self.game_state.start_travel_to_stairs().is_ok() {
+                            if let Some(stairs_coord) = self.game_state.known_stairs_coord() {
+                                self.record(PlayerAction::Travel(stairs_coord));
+                            }
+                            self.until_auto_act_step = Duration::from_millis(0);
+                        }
+                    }
+                    Some(Action::Ascend) => {
+                        if self.game_state.is_player_on_stairs_up() {
+                            self.perform(PlayerAction::Ascend);
+                        }
+                    }
+                    Some(Action::Travel) => {
+                        if self.open_travel_menu().is_ok() {
+                            return Some(GameReturn::Travel);
                         }
                     }
-                    keys::ESCAPE => return Some(GameReturn::Menu),
-                    _ => (),
+                    Some(Action::Menu) => return Some(GameReturn::Menu),
+                    None => (),
                 }
                 self.cursor = None;
             }
             Input::Mouse(mouse_input) => match mouse_input {
-                MouseInput::MouseMove { coord, .. } => self.cursor = Some(coord),
+                MouseInput::MouseMove { coord, .. } => {
+                    self.cursor = Some(coord);
+                    self.travel_preview = self.game_state.travel_path_to(coord).unwrap_or_default();
+                }
+                MouseInput::MousePress {
+                    button: MouseButton::Left,
+                    coord,
+                } => {
+                    if self.perform(PlayerAction::Travel(coord)) {
+                        self.travel_preview.clear();
+                        self.until_auto_act_step = Duration::from_millis(0);
+                    }
+                }
                 _ => (),
             },
         }
-        self.game_state.update_visibility(self.visibility_algorithm);
-        if !self.game_state.is_player_alive() {
-            return Some(GameReturn::GameOver);
-        }
+        // anything the player did has already updated visibility
+        self.check_for_more_messages();
         None
     }
 
-    fn player_level_up_and_descend(&mut self, level_up: LevelUp) {
-        self.game_state.player_level_up_and_descend(level_up);
-        self.game_state.update_visibility(self.visibility_algorithm);
+    fn open_travel_menu(&mut self) -> Result<(), ()> {
+        let items = self
+            .game_state
+            .travel_targets()
+            .into_iter()
+            .zip('a'..='z')
+            .map(|((coord, target), key)| TravelMenuEntry { coord, target, key })
+            .collect::<Vec<_>>();
+        if items.is_empty() {
+            return Err(());
+        }
+        let hotkeys = items
+            .iter()
+            .map(|&entry| (entry.key, entry))
+            .collect::<HashMap<_, _>>();
+        self.travel_menu = Some(
+            MenuInstanceBuilder {
+                items,
+                hotkeys: Some(hotkeys),
+                selected_index: 0,
+            }
+            .build()
+            .unwrap()
+            .into_choose_or_escape(),
+        );
+        Ok(())
     }
 
-    fn new_game(&mut self) {
-        self.rng_seed = self.rng_seed.wrapping_add(1);
-        self.game_state = GameState::new(
-            self.game_area_size,
-            self.rng_seed,
-            self.visibility_algorithm,
+    fn wizard(&mut self, command: WizardCommand) {
+        if command == WizardCommand::ToggleOmniscience {
+            self.visibility_algorithm = match self.visibility_algorithm {
+                VisibilityAlgorithm::Shadowcast => VisibilityAlgorithm::Omniscient,
+                VisibilityAlgorithm::Omniscient => VisibilityAlgorithm::Shadowcast,
+            };
+        }
+        if command == WizardCommand::ToggleNpcDistances {
+            self.show_npc_distances = !self.show_npc_distances;
+        }
+        self.perform(PlayerAction::Wizard(command));
+    }
+
+    fn open_wizard_menu(&mut self, commands: impl Iterator<Item = WizardCommand>) {
+        let items = commands
+            .zip('a'..='z')
+            .map(|(command, key)| WizardMenuEntry { command, key })
+            .collect::<Vec<_>>();
+        let hotkeys = items
+            .iter()
+            .map(|&entry| (entry.key, entry))
+            .collect::<HashMap<_, _>>();
+        self.wizard_menu = Some(
+            MenuInstanceBuilder {
+                items,
+                hotkeys: Some(hotkeys),
+                selected_index: 0,
+            }
+            .build()
+            .unwrap()
+            .into_choose_or_escape(),
         );
     }
 
-    fn save_game(&mut self) {
-        let mut file_storage = match FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create)
+    fn wizard_menu_choose(&mut self, entry: WizardMenuEntry) {
+        self.action_log_start = self.game_state.message_log().end();
+        self.wizard(entry.command);
+        self.check_for_more_messages();
+    }
+
+    fn travel_to(&mut self, entry: TravelMenuEntry) {
+        self.action_log_start = self.game_state.message_log().end();
+        if self.game_state.start_travel_to(entry.coord).is_ok() {
+            self.record(PlayerAction::Travel(entry.coord));
+            self.until_auto_act_step = Duration::from_millis(0);
+        }
+        self.check_for_more_messages();
+    }
+
+    // Handles the keys pressed during an animation once it's over, unless
+    // the player was hurt in the meantime and might want to think again
+    fn drain_input_buffer(&mut self) -> Option<GameReturn> {
+        if self.input_buffer.is_empty() || self.game_state.has_animations() {
+            return None;
+        }
+        if !self.game_state.is_player_alive()
+            || self.game_state.player_hit_points().current() < self.input_buffer_hit_points
         {
-            Ok(file_storage) => file_storage,
-            Err(error) => {
-                eprintln!("Failed to save game: {:?}", error);
-                return;
+            self.input_buffer.clear();
+            return None;
+        }
+        while !self.input_buffer.is_empty() {
+            let input = self.input_buffer.remove(0);
+            if let Some(game_return) = self.handle_input(input) {
+                self.input_buffer.clear();
+                return Some(game_return);
             }
-        };
-        println!("Saving to {:?}", file_storage.full_path(SAVE_FILE));
-        match file_storage.store(SAVE_FILE, &self.game_state, SAVE_FORMAT) {
-            Ok(()) => (),
-            Err(error) => {
-                eprintln!("Failed to save game: {:?}", error);
-                return;
+            if self.message_page_start.is_some() {
+                self.input_buffer.clear();
+            } else if self.game_state.has_animations() {
+                // the rest wait for this animation in turn
+                self.input_buffer_hit_points = self.game_state.player_hit_points().current();
+                break;
             }
         }
+        None
+    }
+
+    // Takes the turns of the player's travelling or resting one at a
+    // time, so they can see what's happening
+    fn tick_auto_act(&mut self, period: Duration) {
+        // a replay has the steps recorded
+        if !self.game_state.is_auto_acting()
+            || self.message_page_start.is_some()
+            || self.playback.is_some()
+        {
+            return;
+        }
+        if let Some(until_auto_act_step) = self.until_auto_act_step.checked_sub(period) {
+            self.until_auto_act_step = until_auto_act_step;
+            return;
+        }
+        self.until_auto_act_step = BETWEEN_AUTO_ACT_STEPS;
+        self.check_recording();
+        self.action_log_start = self.game_state.message_log().end();
+        self.perform(PlayerAction::AutoActStep);
     }
 
-    fn load_game() -> Option<GameState> {
-        let file_storage = match FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create) {
-            Ok(file_storage) => file_storage,
-            Err(error) => {
-                eprintln!("Failed to load game: {:?}", error);
-                return None;
-            }
+    // Takes the next recorded action once the last one's animations are
+    // over, comparing the game with the recording's checksums on the way
+    fn tick_playback(&mut self, period: Duration) {
+        let playback = match self.playback.as_mut() {
+            Some(playback) => playback,
+            None => return,
         };
-        if !file_storage.exists(SAVE_FILE) {
-            println!("No save file exists");
-            return None;
+        if self.game_state.has_animations() || self.message_page_start.is_some() {
+            return;
         }
-        println!("Loading from {:?}", file_storage.full_path(SAVE_FILE));
-        match file_storage.load(SAVE_FILE, SAVE_FORMAT) {
-            Ok(game_state) => Some(game_state),
-            Err(error) => {
-                eprintln!("Failed to load game: {:?}", error);
-                None
+        if let Some(until_next_step) = playback.until_next_step.checked_sub(period) {
+            playback.until_next_step = until_next_step;
+            return;
+        }
+        playback.until_next_step = playback.step_period;
+        // checksums are checked without waiting
+        while let Some(&entry) = playback.entries.get(playback.next_index) {
+            let index = playback.next_index;
+            playback.next_index += 1;
+            match entry {
+                ReplayEntry::Checksum(expected) => {
+                    let actual = self.game_state.checksum();
+                    if actual == expected {
+                        playback.last_matching_turn = self.game_state.turn();
+                    } else {
+                        log_error!(
+                            "{}",
+                            Desync {
+                                line: Replay::line_of(index),
+                                last_matching_turn: playback.last_matching_turn,
+                                turn: self.game_state.turn(),
+                                expected,
+                                actual,
+                            }
+                        );
+                    }
+                }
+                ReplayEntry::Action(action) => {
+                    self.action_log_start = self.game_state.message_log().end();
+                    self.game_state.perform(action);
+                    self.game_state.update_visibility(self.visibility_algorithm);
+                    break;
+                }
             }
         }
+        if playback.next_index >= playback.entries.len() {
+            log_info!("Replay finished");
+            self.playback = None;
+        }
     }
-}
 
+    // Escape stops a replay, and + and - speed it up and slow it down
+    fn control_playback(&mut self, key: KeyboardInput) {
+        const MIN_STEP_PERIOD: Duration = Duration::from_millis(10);
+        const MAX_STEP_PERIOD: Duration = Duration::from_millis(2000);
+        let playback = match self.playback.as_mut() {
+            Some(playback) => playback,
+            None => return,
+        };
+        match key {
+            keys::ESCAPE => {
+                log_info!("Replay stopped at turn {}", self.game_state.turn());
+                self.playback = None;
+            }
+            KeyboardInput::Char('+') => {
+                playback.step_period = (playback.step_period / 2).max(MIN_STEP_PERIOD);
+            }
+            KeyboardInput::Char('-') => {
+                playback.step_period = (playback.step_period * 2).min(MAX_STEP_PERIOD);
+            }
+            _ => (),
+        }
+    }
 
+    // Does something for the player, and adds it to the recording if
+    // there is one. False if it wasn't possible. Visibility is updated
+    // straight away, as it is when playing back.
+    // Every action which can take a turn goes through here, and none of
+    // them can be done once the player is dead
+    fn perform(&mut self, action: PlayerAction) -> bool {
+        if !self.game_state.is_player_alive() {
+            return false;
+        }
+        self.record(action);
+        let done = self.game_state.perform(action);
+        self.game_state.update_visibility(self.visibility_algorithm);
+        done
+    }
+
+    fn record(&mut self, action: PlayerAction) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(error) = recorder.record(action) {
+                log_error!("Failed to record replay: {}", error);
+                self.recorder = None;
+            }
+        }
+    }
+
+    fn save_screenshot(&mut self) {
+        let text = match self.screenshot_text.get_mut().take() {
+            Some(text) => text,
+            None => return,
+        };
+        match screenshot::save(&text) {
+            Ok(path) => {
+                log_info!("Saved a screenshot to {:?}", path);
+                self.game_state.push_message(LogMessage::ScreenshotSaved);
+            }
+            Err(error) => {
+                log_error!("Failed to save a screenshot: {}", error);
+                self.game_state.push_message(LogMessage::ScreenshotFailed);
+            }
+        }
+    }
+
+    // Adds a checksum to the recording once the actions so far have
+    // finished playing out
+    fn check_recording(&mut self) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(error) = recorder.check(&self.game_state) {
+                log_error!("Failed to record replay: {}", error);
+                self.recorder = None;
+            }
+        }
+    }
+
+    // Pauses the message log if the current action has added more messages
+    // than the UI can show at once
+    fn check_for_more_messages(&mut self) {
+        if self.message_page_start.is_none()
+            && self.game_state.message_log().end() > self.action_log_start + NUM_MESSAGES
+        {
+            self.message_page_start = Some(self.action_log_start);
+        }
+    }
+
+    // Space or return shows the next page of messages, and escape skips to
+    // the end. All other input is ignored while the log is paused.
+    fn page_messages(&mut self, input: Input) {
+        let page_start = match self.message_page_start {
+            Some(page_start) => page_start,
+            None => return,
+        };
+        let log_len = self.game_state.message_log().end();
+        let next_page_start = match input {
+            Input::Keyboard(keys::RETURN) | Input::Keyboard(KeyboardInput::Char(' ')) => {
+                page_start + NUM_MESSAGES
+            }
+            Input::Keyboard(keys::ESCAPE) => log_len,
+            _ => return,
+        };
+        if next_page_start + NUM_MESSAGES >= log_len {
+            // the rest fit in the normal view of the latest messages
+            self.message_page_start = None;
+            self.action_log_start = log_len;
+        } else {
+            self.message_page_start = Some(next_page_start);
+        }
+    }
+
+    fn tick_cursor_blink(&mut self, period: Duration) {
+        if let Some(until_cursor_blink) = self.until_cursor_blink.checked_sub(period) {
+            self.until_cursor_blink = until_cursor_blink;
+        } else {
+            self.until_cursor_blink = CURSOR_BLINK_PERIOD;
+            self.cursor_blink_on = !self.cursor_blink_on;
+        }
+    }
+
+    // The cursor stays lit while it's being moved
+    fn show_cursor(&mut self) {
+        self.cursor_blink_on = true;
+        self.until_cursor_blink = CURSOR_BLINK_PERIOD;
+    }
+
+    // Uses the item bound to a quick slot. Items which need aiming are
+    // aimed as if used from the inventory.
+    fn quick_use(&mut self, quick_slot: usize) -> Option<GameReturn> {
+        let inventory_index = self.game_state.quick_slot_inventory_index(quick_slot)?;
+        let turn = self.game_state.turn();
+        match self.game_state.maybe_player_use_item(inventory_index) {
+            Ok(ItemUsage::Aim) => Some(GameReturn::AimItem(inventory_index)),
+            Ok(ItemUsage::Immediate) => {
+                self.record(PlayerAction::Use(inventory_index));
+                self.game_state.update_visibility(self.visibility_algorithm);
+                self.remember_action_if_turn_taken(
+                    RepeatableAction::UseItem(inventory_index),
+                    turn,
+                );
+                None
+            }
+            Err(()) => None,
+        }
+    }
+
+    // Does something the repeat key can do again, and remembers it if it
+    // took a turn
+    fn act(&mut self, action: RepeatableAction) {
+        let turn = self.game_state.turn();
+        match action {
+            RepeatableAction::Move(direction) => self.move_player(direction, None),
+            RepeatableAction::Wait => {
+                self.perform(PlayerAction::Wait);
+            }
+            RepeatableAction::UseItem(index) => {
+                self.perform(PlayerAction::Use(index));
+            }
+        }
+        self.remember_action_if_turn_taken(action, turn);
+    }
+
+    fn remember_action_if_turn_taken(&mut self, action: RepeatableAction, turn_before: u64) {
+        if self.game_state.turn() != turn_before {
+            self.last_action = Some((action, self.game_state.dungeon_level()));
+        }
+    }
+
+    // Walking into a chasm asks for confirmation first
+    fn move_player(&mut self, direction: CardinalDirection, move_prefix: Option<MovePrefix>) {
+        if move_prefix == Some(MovePrefix::ForceAttack) {
+            self.perform(PlayerAction::ForceAttack(direction));
+        } else if self.game_state.is_chasm_in_direction(direction) {
+            self.confirming_chasm_jump = true;
+            self.game_state.prompt_jump_into_chasm();
+        } else if move_prefix == Some(MovePrefix::Run) {
+            self.perform(PlayerAction::Run(direction));
+            self.until_auto_act_step = BETWEEN_AUTO_ACT_STEPS;
+        } else {
+            let start = self.game_state.player_coord();
+            self.perform(PlayerAction::Move(direction));
+            if self.game_config.auto_pickup
+                && self.game_state.is_player_alive()
+                && self.game_state.player_coord() != start
+                && self.game_state.is_free_item_under_player()
+            {
+                self.perform(PlayerAction::Get);
+            }
+        }
+    }
+
+    fn player_level_up_and_descend(&mut self, level_up: LevelUp) {
+        self.perform(PlayerAction::Descend(level_up));
+    }
+
+    // A recording only ever holds the latest run
+    fn new_game(&mut self) {
+        self.rng_seed = self.rng_seed.wrapping_add(1);
+        self.start_run(None);
+    }
+
+    // Today's date is checked again in case the game has been running
+    // since before midnight
+    fn start_daily_challenge(&mut self) {
+        if let Some(date) = daily::today() {
+            log_info!("Starting the daily challenge for {}", date);
+            self.rng_seed = daily::seed(&date);
+            self.daily_challenge_date = Some(date.clone());
+            self.start_run(Some(date));
+        }
+    }
+
+    fn start_run(&mut self, daily_date: Option<String>) {
+        self.check_recording();
+        let difficulty = if daily_date.is_some() {
+            DAILY_DIFFICULTY
+        } else {
+            self.difficulty
+        };
+        // the daily challenge starts everyone with the same kit
+        let new_character = NewCharacter {
+            name: self.new_character.name.clone(),
+            background: if daily_date.is_some() {
+                None
+            } else {
+                self.new_character.background
+            },
+        };
+        self.game_state = GameState::new(
+            self.game_area_size,
+            self.rng_seed,
+            self.visibility_algorithm,
+            self.spawn_tables.clone(),
+            difficulty,
+            new_character.clone(),
+        );
+        match (daily_date, self.start_options.as_ref()) {
+            (Some(date), _) => self.game_state.set_daily_date(date),
+            (None, Some(start_options)) => self
+                .game_state
+                .apply_start_options(start_options, self.visibility_algorithm),
+            (None, None) => (),
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            let header = ReplayHeader {
+                rng_seed: self.rng_seed,
+                map_size: self.game_area_size,
+                visibility_algorithm: self.visibility_algorithm,
+                spawn_tables: self.spawn_tables.clone(),
+                difficulty,
+                new_character,
+            };
+            if let Err(error) = recorder.start_again(&header) {
+                log_error!("Failed to record replay: {}", error);
+                self.recorder = None;
+            }
+        }
+        self.playback = None;
+        self.action_log_start = self.game_state.message_log().end();
+        self.message_page_start = None;
+        self.last_action = None;
+        self.last_autosave = (self.game_state.dungeon_level(), self.game_state.turn());
+    }
+
+    // Saves a copy of the run on reaching a new level and every so often
+    // on the same one. This is only called between actions, so the copy
+    // is never of a game half way through changing.
+    fn maybe_autosave(&mut self) {
+        if self.game_state.has_animations() || !self.game_state.is_player_alive() {
+            return;
+        }
+        let dungeon_level = self.game_state.dungeon_level();
+        let turn = self.game_state.turn();
+        crash::set_run_progress(RunProgress {
+            rng_seed: self.game_state.run_seed(),
+            dungeon_level,
+            turn,
+        });
+        let (last_dungeon_level, last_turn) = self.last_autosave;
+        if !self.save_enabled
+            || (dungeon_level == last_dungeon_level && turn < last_turn + AUTOSAVE_PERIOD_TURNS)
+        {
+            return;
+        }
+        self.last_autosave = (dungeon_level, turn);
+        let mut file_storage = match FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create)
+        {
+            Ok(file_storage) => file_storage,
+            Err(error) => {
+                log_error!("Failed to autosave: {:?}", error);
+                return;
+            }
+        };
+        log_debug!("Autosaving to {:?}", file_storage.full_path(AUTOSAVE_FILE));
+        if let Err(error) = file_storage.store(AUTOSAVE_FILE, &self.game_state, SAVE_FORMAT) {
+            log_error!("Failed to autosave: {:?}", error);
+        }
+    }
+
+    // A run can be recovered if the game crashed since the autosave was
+    // made. Quitting normally deletes the autosave.
+    fn is_crash_recoverable() -> bool {
+        match FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create) {
+            Ok(file_storage) => {
+                file_storage.exists(CRASH_REPORT_FILE) && file_storage.exists(AUTOSAVE_FILE)
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn recover_last_run(&mut self) {
+        let file_storage = match FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create) {
+            Ok(file_storage) => file_storage,
+            Err(error) => {
+                log_error!("Failed to recover the last run: {:?}", error);
+                return;
+            }
+        };
+        log_info!(
+            "Recovering from {:?}",
+            file_storage.full_path(AUTOSAVE_FILE)
+        );
+        match file_storage.load(AUTOSAVE_FILE, SAVE_FORMAT) {
+            Ok(game_state) => {
+                self.game_state = game_state;
+                self.game_state.update_visibility(self.visibility_algorithm);
+                self.rng_seed = self.game_state.run_seed();
+                self.action_log_start = self.game_state.message_log().end();
+                self.message_page_start = None;
+                self.last_action = None;
+                self.last_autosave = (self.game_state.dungeon_level(), self.game_state.turn());
+                self.main_menu = main_menu_instance(false, self.daily_challenge_date.is_some());
+            }
+            Err(error) => log_error!("Failed to recover the last run: {:?}", error),
+        }
+    }
+
+    fn delete_autosave(&mut self) {
+        let mut file_storage = match FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create)
+        {
+            Ok(file_storage) => file_storage,
+            Err(error) => {
+                log_error!("Failed to delete autosave: {:?}", error);
+                return;
+            }
+        };
+        if file_storage.exists(AUTOSAVE_FILE) {
+            if let Err(error) = file_storage.remove(AUTOSAVE_FILE) {
+                log_error!("Failed to delete autosave: {:?}", error);
+            }
+        }
+    }
+
+    fn save_game(&mut self) {
+        if !self.save_enabled {
+            return;
+        }
+        // the game is quitting normally, so there's nothing to recover
+        self.delete_autosave();
+        let mut file_storage = match FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create)
+        {
+            Ok(file_storage) => file_storage,
+            Err(error) => {
+                log_error!("Failed to save game: {:?}", error);
+                return;
+            }
+        };
+        log_info!("Saving to {:?}", file_storage.full_path(SAVE_FILE));
+        match file_storage.store(SAVE_FILE, &self.game_state, SAVE_FORMAT) {
+            Ok(()) => (),
+            Err(error) => {
+                log_error!("Failed to save game: {:?}", error);
+                return;
+            }
+        }
+        if let Err(error) = file_storage.store(SCREEN_FILE, &self.saved_screen(), SAVE_FORMAT) {
+            log_error!("Failed to save the open screen: {:?}", error);
+        }
+    }
+
+    // Called once when the run ends in death or escape. Replays being
+    // watched aren't the player's own runs.
+    fn record_high_score(&mut self) {
+        if !self.save_enabled {
+            return;
+        }
+        self.high_scores.add(HighScore::new(&self.game_state));
+    }
+
+    // Used when the player gives up on a run, so it isn't loaded again
+    fn delete_save(&mut self) {
+        self.delete_autosave();
+        let mut file_storage = match FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create)
+        {
+            Ok(file_storage) => file_storage,
+            Err(error) => {
+                log_error!("Failed to delete save: {:?}", error);
+                return;
+            }
+        };
+        if !file_storage.exists(SAVE_FILE) {
+            return;
+        }
+        log_info!("Deleting {:?}", file_storage.full_path(SAVE_FILE));
+        if let Err(error) = file_storage.remove(SAVE_FILE) {
+            log_error!("Failed to delete save: {:?}", error);
+        }
+        if file_storage.exists(SCREEN_FILE) {
+            if let Err(error) = file_storage.remove(SCREEN_FILE) {
+                log_error!("Failed to delete save: {:?}", error);
+            }
+        }
+    }
+
+    fn load_game() -> Option<GameState> {
+        let file_storage = match FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create) {
+            Ok(file_storage) => file_storage,
+            Err(error) => {
+                log_error!("Failed to load game: {:?}", error);
+                return None;
+            }
+        };
+        if !file_storage.exists(SAVE_FILE) {
+            log_info!("No save file exists");
+            return None;
+        }
+        log_info!("Loading from {:?}", file_storage.full_path(SAVE_FILE));
+        match file_storage.load(SAVE_FILE, SAVE_FORMAT) {
+            Ok(game_state) => Some(game_state),
+            Err(error) => {
+                log_error!("Failed to load game: {:?}", error);
+                None
+            }
+        }
+    }
+
+    // Saves from before the open screen was saved don't have one, and the
+    // game is loaded without it
+    fn load_saved_screen() -> Option<SavedScreen> {
+        let file_storage = FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create).ok()?;
+        if !file_storage.exists(SCREEN_FILE) {
+            return None;
+        }
+        match file_storage.load(SCREEN_FILE, SAVE_FORMAT) {
+            Ok(saved_screen) => Some(saved_screen),
+            Err(error) => {
+                log_error!("Failed to load the open screen: {:?}", error);
+                None
+            }
+        }
+    }
+}
+
+// The window can be resized, but never laid out smaller than this
+const MIN_SCREEN_SIZE: Size = Size::new_u16(40, 20);
+
+// The map with the UI below it. Maps narrower than the UI are centred.
+pub fn screen_size(map_size: Size, ui_rows: u32) -> Size {
+    Size::new(
+        map_size.width().max(MIN_SCREEN_SIZE.width()),
+        map_size.height() + ui_rows,
+    )
+}
 
 struct AppView {
+    screen_size: Size,
+    // rows below the map, which the UI is drawn at the top of
+    ui_rows: u32,
     ui_y_offset: i32,
     game_view: GameView,
     inventory_slot_menu_view: InventorySlotMenuView,
+    travel_menu_view: TravelMenuView,
+    wizard_menu_view: WizardMenuView,
     ui_view: UiView,
     main_menu_view: MainMenuView,
     level_up_menu_view: LevelUpMenuView,
+    run_stats_view: RunStatsView,
+    high_scores_view: HighScoresView,
+    character_creation_view: CharacterCreationView,
 }
 
 impl AppView {
-    fn new(screen_size: Size) -> Self {
-        const UI_Y_PADDING: u32 = 0;
-        let ui_y_offset = (screen_size.height() - UI_NUM_ROWS + UI_Y_PADDING) as i32;
-        Self {
-            ui_y_offset,
+    fn new(screen_size: Size, map_size: Size, ui_rows: u32) -> Self {
+        let mut app_view = Self {
+            screen_size,
+            ui_rows,
+            ui_y_offset: 0,
             game_view: GameView::default(),
             inventory_slot_menu_view: InventorySlotMenuView::default(),
+            travel_menu_view: TravelMenuView::default(),
+            wizard_menu_view: WizardMenuView::default(),
             ui_view: UiView::default(),
             main_menu_view: MainMenuView::default(),
             level_up_menu_view: LevelUpMenuView::default(),
+            run_stats_view: RunStatsView::default(),
+            high_scores_view: HighScoresView::default(),
+            character_creation_view: CharacterCreationView::default(),
+        };
+        app_view.layout(screen_size, map_size);
+        app_view
+    }
+
+    // Sticks the UI to the bottom of the screen and centres the map in the
+    // space above it. Screens smaller than the minimum are laid out as if
+    // they were the minimum size, and get clipped.
+    fn layout(&mut self, screen_size: Size, map_size: Size) {
+        const UI_Y_PADDING: u32 = 0;
+        let screen_size = Size::new(
+            screen_size.width().max(MIN_SCREEN_SIZE.width()),
+            screen_size.height().max(MIN_SCREEN_SIZE.height()),
+        );
+        self.screen_size = screen_size;
+        let game_area_height = screen_size.height().saturating_sub(self.ui_rows);
+        self.ui_y_offset = (game_area_height + UI_Y_PADDING) as i32;
+        let centre = |space: u32, length: u32| (space.saturating_sub(length) / 2) as i32;
+        self.game_view.offset = Coord::new(
+            centre(screen_size.width(), map_size.width()),
+            centre(game_area_height, map_size.height()),
+        );
+    }
+
+    // Called every frame with the size of the screen, which changes when
+    // the window is resized
+    fn maybe_relayout(&mut self, screen_size: Size, map_size: Size) {
+        if screen_size != self.screen_size {
+            self.layout(screen_size, map_size);
+        }
+    }
+
+    // Mouse input arrives in screen coordinates, but the game works in
+    // map coordinates
+    fn screen_to_map_input(&self, mut input: Input) -> Input {
+        if let Input::Mouse(
+            MouseInput::MouseMove { coord, .. } | MouseInput::MousePress { coord, .. },
+        ) = &mut input
+        {
+            *coord = *coord - self.game_view.offset;
         }
+        input
     }
 
     fn render_ui<F: Frame, C: ColModify>(
@@ -273,48 +1831,175 @@ impl AppView {
         let player_hit_points = data.game_state.player_hit_points();
         let messages = data.game_state.message_log();
         let examine_cell = if let Some(cursor) = data.cursor {
-            frame.blend_cell_background_relative(
-                cursor,
-                1,
-                Rgb24::new_grey(255),
-                127,
-                blend_mode::LinearInterpolate,
-                context,
-            );
-            data.game_state.examine_cell(cursor)
+            if data.cursor_blink_on {
+                frame.blend_cell_background_relative(
+                    cursor,
+                    1,
+                    Rgb24::new_grey(255),
+                    127,
+                    blend_mode::LinearInterpolate,
+                    context.add_offset(self.game_view.offset),
+                );
+            }
+            Some((
+                data.game_state.examine_cell(cursor),
+                data.game_state.npc_awareness(cursor),
+                data.game_state.combat_forecast(cursor),
+            ))
         } else {
             None
         };
+        let (examine_cell, npc_awareness, combat_forecast) =
+            examine_cell.unwrap_or((None, None, None));
+        let equipped_indices = data.game_state.player_equipped_inventory_indices();
+        let inventory_slots = data.game_state.player_inventory().slots();
+        let equipped_item_type = |index: Option<usize>| {
+            index
+                .and_then(|index| inventory_slots.get(index).cloned().flatten())
+                .and_then(|entity| data.game_state.item_type(entity))
+        };
         self.ui_view.view(
             UiData {
+                colors: data.colors(),
                 player_hit_points,
+                player_hit_flash: data.effects.is_hit_flashing(),
+                player_low_health_pulse: if data.game_state.is_player_health_low() {
+                    Some(data.effects.low_health_pulse())
+                } else {
+                    None
+                },
                 messages,
+                message_page_start: data.message_page_start,
+                is_player_dead: !data.game_state.is_player_alive(),
+                is_resting: data.game_state.is_resting(),
+                quick_slots: data.game_state.quick_slots(),
                 name,
+                character_name: data.game_state.character_name(),
                 examine_cell,
+                npc_awareness,
+                combat_forecast,
                 stats_data: StatsData {
                     strength: data.game_state.player_strength(),
                     dexterity: data.game_state.player_dexterity(),
                     intelligence: data.game_state.player_intelligence(),
+                    gold: data.game_state.player_gold(),
+                    debt: data.game_state.player_debt(),
+                    runes: if data.game_state.is_final_level() {
+                        Some(data.game_state.player_runes())
+                    } else {
+                        None
+                    },
+                },
+                equipment_data: EquipmentData {
+                    main_hand: equipped_item_type(equipped_indices.main_hand),
+                    off_hand: equipped_item_type(equipped_indices.off_hand),
+                    body: equipped_item_type(equipped_indices.body),
+                    attack: data.game_state.player_attack_range(),
+                    defense: data.game_state.player_defense_range(),
+                },
+                status_data: StatusData {
+                    depth: data.game_state.dungeon_level(),
+                    turn: data.game_state.turn(),
+                    player_coord: data.game_state.player_coord(),
+                    status_effects: data.game_state.player_status_effects().collect(),
                 },
-                dungeon_level: data.game_state.dungeon_level(),
             },
             context.add_offset(Coord::new(0, self.ui_y_offset)),
             frame,
         );
+        if data.show_diagnostics {
+            self.render_diagnostics(data, context, frame);
+        }
+    }
+
+    // A small translucent box of timings and counts in the top-right
+    // corner, drawn over everything else
+    fn render_diagnostics<F: Frame, C: ColModify>(
+        &self,
+        data: &AppData,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        const WIDTH: u32 = 16;
+        const DEPTH: i8 = 20;
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let diagnostics = data.game_state.diagnostics();
+        let lines = [
+            format!("frame {:.1}ms", millis(data.frame_period)),
+            format!("vis   {:.2}ms", millis(diagnostics.visibility_update_time)),
+            format!("ai    {:.2}ms", millis(diagnostics.ai_turn_time)),
+            format!("ents  {}", diagnostics.entity_count),
+            format!("ai_st {}", diagnostics.ai_state_size),
+        ];
+        let context = context
+            .add_offset(Coord::new(
+                self.screen_size.width() as i32 - WIDTH as i32,
+                0,
+            ))
+            .add_depth(DEPTH);
+        let style = Style::new().with_foreground(Rgb24::new_grey(255));
+        for (y, line) in lines.iter().enumerate() {
+            for x in 0..WIDTH {
+                frame.blend_cell_background_relative(
+                    Coord::new(x as i32, y as i32),
+                    0,
+                    Rgb24::new_grey(0),
+                    191,
+                    blend_mode::LinearInterpolate,
+                    context,
+                );
+            }
+            StringViewSingleLine::new(style).view(
+                line,
+                context.add_offset(Coord::new(1, y as i32)).add_depth(1),
+                frame,
+            );
+        }
     }
 }
 
+// Lays out the screen again whenever the window changes size, before
+// anything else is drawn
+struct RelayoutDecorate;
+
+impl Decorate for RelayoutDecorate {
+    type View = AppView;
+    type Data = AppData;
+    fn view<E, F, C>(
+        &self,
+        data: &Self::Data,
+        mut event_routine_view: EventRoutineView<E>,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) where
+        E: EventRoutine<Data = Self::Data, View = Self::View>,
+        F: Frame,
+        C: ColModify,
+    {
+        event_routine_view
+            .view
+            .maybe_relayout(context.size, data.game_state.size());
+        event_routine_view.view(data, context, frame);
+        if data.screenshot_requested.take() {
+            let mut text_frame = TextFrame::new(context.size);
+            event_routine_view.view(data, context, &mut text_frame);
+            *data.screenshot_text.borrow_mut() = Some(text_frame.text());
+        }
+    }
+}
 
 fn game_loop() -> impl EventRoutine<Return = (), Data = AppData, View = AppView, Event = CommonEvent>
 {
-    make_either!(Ei = A | B | C | D | E | F);
+    make_either!(Ei = A | B | C | D | E | F | G | H | I | J | K | L);
     Loop::new(|| {
         GameEventRoutine.and_then(|game_return| match game_return {
             GameReturn::Menu => Ei::A(main_menu().and_then(|choice| {
-                make_either!(Ei = A | B | C);
+                make_either!(Ei = A | B | C | D | E);
                 match choice {
                     Err(menu::Escape) => Ei::A(Value::new(None)),
-                    Ok(MainMenuEntry::Resume) => Ei::A(Value::new(None)),
+                    Ok(MainMenuEntry::Resume)
+                    | Ok(MainMenuEntry::Theme)
+                    | Ok(MainMenuEntry::Difficulty) => Ei::A(Value::new(None)),
                     Ok(MainMenuEntry::SaveAndQuit) => {
                         Ei::C(SideEffect::new_with_view(|data: &mut AppData, _: &_| {
                             data.save_game();
@@ -327,18 +2012,68 @@ fn game_loop() -> impl EventRoutine<Return = (), Data = AppData, View = AppView,
                             None
                         }))
                     }
-                }
-            })),
-            GameReturn::GameOver => Ei::B(game_over().and_then(|()| {
-                SideEffect::new_with_view(|data: &mut AppData, _: &_| {
-                    data.new_game();
-                    None
-                })
-            })),
-            GameReturn::UseItem => Ei::C(use_item().map(|_| None)),
-            GameReturn::DropItem => Ei::D(drop_item().map(|_| None)),
-            GameReturn::Examine => Ei::E(TargetEventRoutine { name: "EXAMINE" }.map(|_| None)),
-            GameReturn::LevelUpAndDescend => Ei::F(level_up_menu().and_then(|maybe_level_up| {
+                    Ok(MainMenuEntry::RecoverLastRun) => {
+                        Ei::B(SideEffect::new_with_view(|data: &mut AppData, _: &_| {
+                            data.recover_last_run();
+                            None
+                        }))
+                    }
+                    Ok(MainMenuEntry::DailyChallenge) => {
+                        Ei::B(SideEffect::new_with_view(|data: &mut AppData, _: &_| {
+                            data.start_daily_challenge();
+                            None
+                        }))
+                    }
+                    Ok(MainMenuEntry::HighScores) => Ei::E(HighScoresEventRoutine.map(|()| None)),
+                    Ok(MainMenuEntry::AbandonRun) => Ei::D(
+                        KeyPromptEventRoutine {
+                            prompt: i18n::text("prompt.abandon-run"),
+                        }
+                        .and_then(|key| {
+                            let confirmed = key == KeyboardInput::Char('y');
+                            SideEffect::new_with_view(move |data: &mut AppData, _: &_| {
+                                if confirmed {
+                                    data.delete_save();
+                                    Some(())
+                                } else {
+                                    None
+                                }
+                            })
+                        }),
+                    ),
+                }
+            })),
+            GameReturn::GameOver | GameReturn::Victory => {
+                let (text, rgb24) = if matches!(game_return, GameReturn::Victory) {
+                    (i18n::text("run-stats.escaped"), Rgb24::new(255, 0, 255))
+                } else {
+                    (i18n::text("run-stats.died"), Rgb24::new(255, 0, 0))
+                };
+                // the score is recorded before anything else can happen to
+                // the finished run
+                Ei::B(
+                    SideEffect::new_with_view(|data: &mut AppData, _: &_| data.record_high_score())
+                        .and_then(move |()| game_over(text, rgb24))
+                        .and_then(|()| HighScoresEventRoutine)
+                        .and_then(|()| {
+                            SideEffect::new_with_view(|data: &mut AppData, _: &_| {
+                                data.new_game();
+                                None
+                            })
+                        }),
+                )
+            }
+            GameReturn::UseItem => Ei::C(use_item().map(|_| None)),
+            GameReturn::DropItem => Ei::D(drop_item().map(|_| None)),
+            GameReturn::Equipment(command) => Ei::L(equipment_item(command).map(|_| None)),
+            GameReturn::Examine => Ei::E(
+                TargetEventRoutine {
+                    name: i18n::text("menu.examine"),
+                    aim_item: None,
+                }
+                .map(|_| None),
+            ),
+            GameReturn::LevelUpAndDescend => Ei::F(level_up_menu().and_then(|maybe_level_up| {
                 SideEffect::new_with_view(move |data: &mut AppData, _: &_| {
                     match maybe_level_up {
                         Err(menu::Escape) => (),
@@ -347,137 +2082,502 @@ fn game_loop() -> impl EventRoutine<Return = (), Data = AppData, View = AppView,
                     None
                 })
             })),
+            GameReturn::Statistics => Ei::G(StatisticsEventRoutine.map(|()| None)),
+            GameReturn::AimItem(inventory_index) => Ei::I(aim_item(inventory_index).map(|_| None)),
+            GameReturn::AssignQuickSlot => Ei::J(assign_quick_slot().map(|()| None)),
+            GameReturn::Travel => Ei::H(travel_menu().and_then(|result| {
+                SideEffect::new_with_view(move |data: &mut AppData, _: &_| {
+                    if let Ok(entry) = result {
+                        data.travel_to(entry);
+                    }
+                    data.travel_menu = None;
+                    None
+                })
+            })),
+            GameReturn::WizardMenu(title) => Ei::K(wizard_menu(title).and_then(|result| {
+                SideEffect::new_with_view(move |data: &mut AppData, _: &_| {
+                    if let Ok(entry) = result {
+                        data.wizard_menu_choose(entry);
+                    }
+                    data.wizard_menu = None;
+                    None
+                })
+            })),
         })
-    }).return_on_exit(|data| data.save_game())
+    })
+    .return_on_exit(|data| data.save_game())
+}
+
+// Where the panic hook writes a crash report, next to the save
+pub fn crash_report_path() -> Option<PathBuf> {
+    FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create)
+        .ok()
+        .map(|file_storage| file_storage.full_path(CRASH_REPORT_FILE))
 }
 
 pub fn app(
-    screen_size: Size,
+    map_size: Size,
+    ui_rows: u32,
     rng_seed: u64,
     visibility_algorithm: VisibilityAlgorithm,
+    spawn_tables: SpawnTables,
+    theme_name: ThemeName,
+    difficulty: Difficulty,
+    diagnostics_enabled: bool,
+    wizard_enabled: bool,
+    start_options: Option<StartOptions>,
+    game_config: GameConfig,
+    audio_config: AudioConfig,
+    daily_date: Option<String>,
+    key_bindings: KeyBindings,
+    replay_mode: Option<ReplayMode>,
 ) -> impl ChargridApp {
-    let data = AppData::new(screen_size, rng_seed, visibility_algorithm);
-    let view = AppView::new(screen_size);
-    game_loop().app_one_shot_ignore_return(data, view)
+    let data = AppData::new(
+        map_size,
+        rng_seed,
+        visibility_algorithm,
+        spawn_tables,
+        theme_name,
+        difficulty,
+        diagnostics_enabled,
+        wizard_enabled,
+        start_options,
+        game_config,
+        audio_config,
+        daily_date,
+        key_bindings,
+        replay_mode,
+    );
+    let screen_size = screen_size(map_size, ui_rows);
+    let view = AppView::new(screen_size, data.game_state.size(), ui_rows);
+    game_loop()
+        .decorated(RelayoutDecorate)
+        .app_one_shot_ignore_return(data, view)
 }
 
-
-fn currently_visible_view_cell_of_tile(tile: Tile) -> ViewCell {
-    match tile {
-        Tile::Player => ViewCell::new()
-            .with_character('@')
-            .with_foreground(colors::PLAYER),
-        Tile::PlayerCorpse => ViewCell::new()
-            .with_character('%')
-            .with_foreground(colors::PLAYER),
+fn currently_visible_view_cell_of_tile(
+    tile: Tile,
+    theme: LevelTheme,
+    colors: &ColorTheme,
+) -> ViewCell {
+    let palette = colors.palette(theme);
+    let view_cell = match tile {
+        Tile::Player => ViewCell::new().with_foreground(colors.player),
+        Tile::PlayerCorpse => ViewCell::new().with_foreground(colors.player),
         Tile::Floor => ViewCell::new()
-            .with_character('.')
-            .with_foreground(Rgb24::new_grey(63))
-            .with_background(Rgb24::new(0, 0, 63)),
+            .with_foreground(palette.floor_foreground)
+            .with_background(palette.floor_background),
         Tile::Wall => ViewCell::new()
-            .with_character('#')
-            .with_foreground(Rgb24::new(0, 63, 63))
-            .with_background(Rgb24::new(63, 127, 127)),
-        Tile::Npc(NpcType::Orc) => ViewCell::new()
-            .with_character('o')
-            .with_bold(true)
-            .with_foreground(colors::ORC),
+            .with_foreground(palette.wall_foreground)
+            .with_background(palette.wall_background),
+        Tile::Npc(NpcType::Orc) => ViewCell::new().with_bold(true).with_foreground(colors.orc),
         Tile::Npc(NpcType::Troll) => ViewCell::new()
-            .with_character('T')
             .with_bold(true)
-            .with_foreground(colors::TROLL),
-        Tile::NpcCorpse(NpcType::Orc) => ViewCell::new()
-            .with_character('%')
+            .with_foreground(colors.troll),
+        Tile::Npc(NpcType::Shopkeeper) => ViewCell::new()
             .with_bold(true)
-            .with_foreground(colors::ORC),
+            .with_foreground(colors.shopkeeper),
+        Tile::NpcCorpse(NpcType::Orc) => {
+            ViewCell::new().with_bold(true).with_foreground(colors.orc)
+        }
         Tile::NpcCorpse(NpcType::Troll) => ViewCell::new()
-            .with_character('%')
             .with_bold(true)
-            .with_foreground(colors::TROLL),
-        Tile::Item(ItemType::HealthPotion) => ViewCell::new()
-            .with_character('!')
-            .with_foreground(colors::HEALTH_POTION),
-        Tile::Item(ItemType::FireballScroll) => ViewCell::new()
-            .with_character('?')
-            .with_foreground(colors::FIREBALL_SCROLL),
-        Tile::Item(ItemType::ConfusionScroll) => ViewCell::new()
-            .with_character('?')
-            .with_foreground(colors::CONFUSION_SCROLL),
-        Tile::Projectile(ProjectileType::Fireball { .. }) => ViewCell::new()
-            .with_character('*')
-            .with_foreground(colors::FIREBALL_SCROLL),
-        Tile::Projectile(ProjectileType::Confusion { .. }) => ViewCell::new()
-            .with_character('*')
-            .with_foreground(colors::CONFUSION_SCROLL),
+            .with_foreground(colors.troll),
+        Tile::NpcCorpse(NpcType::Shopkeeper) => ViewCell::new()
+            .with_bold(true)
+            .with_foreground(colors.shopkeeper),
+        Tile::Gold => ViewCell::new().with_bold(true).with_foreground(colors.gold),
+        Tile::Item(ItemType::HealthPotion) => ViewCell::new().with_foreground(colors.health_potion),
+        Tile::Item(ItemType::FireballScroll) => {
+            ViewCell::new().with_foreground(colors.fireball_scroll)
+        }
+        Tile::Item(ItemType::ConfusionScroll) => {
+            ViewCell::new().with_foreground(colors.confusion_scroll)
+        }
+        Tile::Item(ItemType::ClairvoyanceScroll) => {
+            ViewCell::new().with_foreground(colors.clairvoyance_scroll)
+        }
+        Tile::Item(ItemType::Torch) => ViewCell::new().with_foreground(colors.torch),
+        Tile::Item(ItemType::SmokeBomb) => ViewCell::new().with_foreground(colors.fog),
+        // fog is drawn over the floor without hiding its background
+        Tile::Fog => ViewCell::new().with_foreground(colors.fog),
+        Tile::Projectile(ProjectileType::Fireball { .. }) => {
+            ViewCell::new().with_foreground(colors.fireball_scroll)
+        }
+        Tile::Projectile(ProjectileType::Confusion { .. }) => {
+            ViewCell::new().with_foreground(colors.confusion_scroll)
+        }
         Tile::Stairs => ViewCell::new()
-            .with_character('>')
             .with_bold(true)
-            .with_foreground(Rgb24::new_grey(255))
-            .with_background(Rgb24::new(0, 0, 63)),
+            .with_foreground(colors.stairs)
+            .with_background(palette.floor_background),
+        Tile::DoorClosed => ViewCell::new()
+            .with_bold(true)
+            .with_foreground(colors.door)
+            .with_background(palette.wall_background),
+        Tile::DoorOpen => ViewCell::new()
+            .with_bold(true)
+            .with_foreground(colors.door)
+            .with_background(palette.floor_background),
+        Tile::Trap(trap_type) => ViewCell::new()
+            .with_bold(true)
+            .with_foreground(colors.trap_color(trap_type))
+            .with_background(palette.floor_background),
+        Tile::Altar => ViewCell::new()
+            .with_bold(true)
+            .with_foreground(colors.altar)
+            .with_background(palette.floor_background),
+        Tile::Water => ViewCell::new()
+            .with_foreground(colors.water)
+            .with_background(colors.water_background),
+        Tile::Lava => ViewCell::new()
+            .with_bold(true)
+            .with_foreground(colors.lava)
+            .with_background(colors.lava_background),
+        Tile::StairsUp => ViewCell::new()
+            .with_bold(true)
+            .with_foreground(colors.stairs)
+            .with_background(palette.floor_background),
+        Tile::Foliage => ViewCell::new()
+            .with_foreground(colors.foliage)
+            .with_background(palette.floor_background),
+        Tile::Chasm => ViewCell::new()
+            .with_foreground(colors.chasm)
+            .with_background(Rgb24::new_grey(0)),
+        Tile::Torch => ViewCell::new()
+            .with_bold(true)
+            .with_foreground(colors.torch)
+            .with_background(palette.wall_background),
+        Tile::Rune => ViewCell::new().with_bold(true).with_foreground(colors.rune),
+        Tile::PortalSealed => ViewCell::new()
+            .with_foreground(colors.portal.saturating_scalar_mul_div(1, 2))
+            .with_background(palette.floor_background),
+        Tile::PortalOpen => ViewCell::new()
+            .with_bold(true)
+            .with_foreground(colors.portal)
+            .with_background(palette.floor_background),
         Tile::Item(ItemType::Sword) => ViewCell::new()
             .with_bold(true)
-            .with_character('/')
-            .with_foreground(colors::SWORD),
+            .with_foreground(colors.sword),
         Tile::Item(ItemType::Staff) => ViewCell::new()
             .with_bold(true)
-            .with_character('\\')
-            .with_foreground(colors::STAFF),
+            .with_foreground(colors.staff),
         Tile::Item(ItemType::Armor) => ViewCell::new()
             .with_bold(true)
-            .with_character(']')
-            .with_foreground(colors::ARMOR),
-        Tile::Item(ItemType::Robe) => ViewCell::new()
+            .with_foreground(colors.armor),
+        Tile::Item(ItemType::Robe) => ViewCell::new().with_bold(true).with_foreground(colors.robe),
+        Tile::Item(ItemType::Backpack) => ViewCell::new()
             .with_bold(true)
-            .with_character('}')
-            .with_foreground(colors::ROBE),
-    }
+            .with_foreground(colors.backpack),
+        Tile::Item(ItemType::Warhammer) => ViewCell::new()
+            .with_bold(true)
+            .with_foreground(colors.warhammer),
+    };
+    view_cell.with_character(tile.character())
 }
 
-
-fn previously_visible_view_cell_of_tile(tile: Tile) -> ViewCell {
-    match tile {
+fn previously_visible_view_cell_of_tile(tile: Tile, colors: &ColorTheme) -> ViewCell {
+    let view_cell = match tile {
         Tile::Floor => ViewCell::new()
-            .with_character('.')
-            .with_foreground(Rgb24::new_grey(63))
+            .with_foreground(colors.remembered)
             .with_background(Rgb24::new_grey(0)),
-        Tile::Wall => ViewCell::new()
-            .with_character('#')
-            .with_foreground(Rgb24::new_grey(63))
+        Tile::Wall | Tile::Torch => ViewCell::new()
+            .with_foreground(colors.remembered)
             .with_background(Rgb24::new_grey(0)),
-        _ => ViewCell::new(),
-    }
+        Tile::DoorClosed => ViewCell::new()
+            .with_foreground(colors.remembered)
+            .with_background(Rgb24::new_grey(0)),
+        Tile::DoorOpen => ViewCell::new()
+            .with_foreground(colors.remembered)
+            .with_background(Rgb24::new_grey(0)),
+        Tile::Trap(_) => ViewCell::new()
+            .with_foreground(colors.remembered)
+            .with_background(Rgb24::new_grey(0)),
+        Tile::Water | Tile::Lava => ViewCell::new()
+            .with_foreground(colors.remembered)
+            .with_background(Rgb24::new_grey(0)),
+        Tile::Foliage => ViewCell::new()
+            .with_foreground(colors.remembered)
+            .with_background(Rgb24::new_grey(0)),
+        Tile::Chasm => ViewCell::new()
+            .with_foreground(colors.remembered.saturating_scalar_mul_div(1, 2))
+            .with_background(Rgb24::new_grey(0)),
+        Tile::PortalSealed | Tile::PortalOpen => ViewCell::new()
+            .with_foreground(colors.remembered)
+            .with_background(Rgb24::new_grey(0)),
+        // remembered items, corpses and the like are drawn over the
+        // remembered floor
+        _ => ViewCell::new().with_foreground(colors.remembered),
+    };
+    view_cell.with_character(tile.character())
+}
+
+// NPCs sensed through walls keep their glyph but are drawn in a single
+// colour so they can't be mistaken for ones the player can see
+fn sensed_view_cell_of_tile(tile: Tile, colors: &ColorTheme) -> ViewCell {
+    ViewCell::new()
+        .with_character(tile.character())
+        .with_bold(true)
+        .with_foreground(colors.sensed)
+}
+
+// Dims a visible cell according to how much light reaches it. Unlit
+// cells are still drawn, just darker.
+fn light_view_cell(view_cell: ViewCell, light: u8) -> ViewCell {
+    const MIN_BRIGHTNESS: u32 = 85;
+    let brightness = MIN_BRIGHTNESS + (255 - MIN_BRIGHTNESS) * light as u32 / 255;
+    dim_view_cell(view_cell, brightness as u8)
+}
+
+// Fades a visible cell towards the edge of the player's vision
+fn dim_view_cell(mut view_cell: ViewCell, brightness: u8) -> ViewCell {
+    let brightness = brightness as u32;
+    view_cell.style.foreground = view_cell
+        .style
+        .foreground
+        .map(|rgb24| rgb24.saturating_scalar_mul_div(brightness, 255));
+    view_cell.style.background = view_cell
+        .style
+        .background
+        .map(|rgb24| rgb24.saturating_scalar_mul_div(brightness, 255));
+    view_cell
 }
 
 #[derive(Default)]
-struct GameView {}
+struct GameView {
+    // where the top-left of the map is drawn on the screen
+    offset: Coord,
+}
+
+impl GameView {
+    // Draws the game with any feedback effects, such as the view being
+    // nudged when the player is hit
+    fn view_with_effects<F: Frame, C: ColModify>(
+        &mut self,
+        game_state: &GameState,
+        colors: &ColorTheme,
+        effects: &Effects,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        let context = context.add_offset(effects.camera_offset());
+        self.view((game_state, colors), context, frame);
+        let map_context = context.add_offset(self.offset);
+        for &(coord, _) in effects.kill_flashes.iter() {
+            let flash = ViewCell::new()
+                .with_character(' ')
+                .with_background(Rgb24::new_grey(255));
+            frame.set_cell_relative(coord, EFFECT_DEPTH, flash, map_context);
+        }
+    }
+
+    // Highlights the cells the player will walk through to where they
+    // clicked, or would walk through to the cell under the mouse
+    fn view_travel_path<F: Frame, C: ColModify>(
+        &mut self,
+        path: &[Coord],
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        let context = context.add_offset(self.offset);
+        for &coord in path {
+            frame.blend_cell_background_relative(
+                coord,
+                EFFECT_DEPTH,
+                Rgb24::new_grey(255),
+                47,
+                blend_mode::LinearInterpolate,
+                context,
+            );
+        }
+    }
+
+    // Tints each cell by how far NPCs would walk from it to reach the
+    // player, from red nearby to blue far away
+    fn view_npc_distances<F: Frame, C: ColModify>(
+        &mut self,
+        game_state: &GameState,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        const MAX_DISTANCE: u32 = 20;
+        let context = context.add_offset(self.offset);
+        for coord in game_state.size().coord_iter_row_major() {
+            if let Some(distance) = game_state.npc_distance_to_player(coord) {
+                let far = (distance.min(MAX_DISTANCE) * 255 / MAX_DISTANCE) as u8;
+                frame.blend_cell_background_relative(
+                    coord,
+                    EFFECT_DEPTH,
+                    Rgb24::new(255 - far, 0, far),
+                    95,
+                    blend_mode::LinearInterpolate,
+                    context,
+                );
+            }
+        }
+    }
+
+    // Highlights the cells an aimed projectile will pass through. The part
+    // of the line it won't reach is shown in the danger colour, so the
+    // real impact point is clear. Any area it bursts over is shaded more
+    // faintly.
+    fn view_aim_preview<F: Frame, C: ColModify>(
+        &mut self,
+        preview: &AimPreview,
+        colors: &ColorTheme,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        let context = context.add_offset(self.offset);
+        let highlights = preview
+            .path
+            .iter()
+            .map(|&coord| (coord, Rgb24::new_grey(255), 63u8))
+            .chain(
+                preview
+                    .area
+                    .iter()
+                    .map(|&coord| (coord, Rgb24::new_grey(255), 31)),
+            )
+            .chain(
+                preview
+                    .beyond
+                    .iter()
+                    .map(|&coord| (coord, colors.danger, 127)),
+            );
+        for (coord, rgb24, alpha) in highlights {
+            frame.blend_cell_background_relative(
+                coord,
+                EFFECT_DEPTH,
+                rgb24,
+                alpha,
+                blend_mode::LinearInterpolate,
+                context,
+            );
+        }
+    }
+}
 
-impl<'a> View<&'a GameState> for GameView {
+impl<'a> View<(&'a GameState, &'a ColorTheme)> for GameView {
     fn view<F: Frame, C: ColModify>(
         &mut self,
-        game_state: &'a GameState,
+        (game_state, colors): (&'a GameState, &'a ColorTheme),
         context: ViewContext<C>,
         frame: &mut F,
     ) {
-        for entity_to_render in game_state.entities_to_render() {
-            let view_cell = match entity_to_render.visibility {
-                CellVisibility::Currently => {
-                    currently_visible_view_cell_of_tile(entity_to_render.tile)
-                }
-                CellVisibility::Previously => {
-                    previously_visible_view_cell_of_tile(entity_to_render.tile)
+        let context = context.add_offset(self.offset);
+        let theme = game_state.level_theme();
+        for entity_to_render in game_state.entities_to_render(None) {
+            if entity_to_render.sensed {
+                let view_cell = sensed_view_cell_of_tile(entity_to_render.tile, colors);
+                let depth = render_depth(entity_to_render.tile);
+                frame.set_cell_relative(entity_to_render.location.coord, depth, view_cell, context);
+                continue;
+            }
+            let mut view_cell =
+                currently_visible_view_cell_of_tile(entity_to_render.tile, theme, colors);
+            if let Tile::Wall = entity_to_render.tile {
+                let coord = entity_to_render.location.coord;
+                view_cell.character = Some(wall_glyph(game_state.wall_connections(coord)));
+            }
+            let view_cell = light_view_cell(view_cell, entity_to_render.light);
+            let view_cell = dim_view_cell(view_cell, entity_to_render.distance_brightness);
+            let depth = render_depth(entity_to_render.tile);
+            frame.set_cell_relative(entity_to_render.location.coord, depth, view_cell, context);
+        }
+        let known_stairs_coord = game_state.known_stairs_coord();
+        for (coord, layer, tile) in game_state.remembered_tiles_to_render() {
+            // things that move are only ever drawn where they are now
+            if matches!(tile, Tile::Player | Tile::Npc(_) | Tile::Projectile(_)) {
+                continue;
+            }
+            // remembered stairs stand out so the player can find their way back
+            let view_cell = if Some(coord) == known_stairs_coord && matches!(layer, Layer::Floor) {
+                ViewCell::new()
+                    .with_character(Tile::Stairs.character())
+                    .with_bold(true)
+                    .with_foreground(colors.remembered_stairs)
+                    .with_background(Rgb24::new_grey(0))
+            } else {
+                let mut view_cell = previously_visible_view_cell_of_tile(tile, colors);
+                if let Tile::Wall = tile {
+                    view_cell.character = Some(wall_glyph(game_state.wall_connections(coord)));
                 }
-                CellVisibility::Never => ViewCell::new(),
+                view_cell
             };
-            let depth = match entity_to_render.location.layer {
-                None => -1,
-                Some(Layer::Floor) => 0,
-                Some(Layer::Feature) => 1,
-                Some(Layer::Object) => 2,
-                Some(Layer::Character) => 3,
-                Some(Layer::Projectile) => 4,
-            };
-            frame.set_cell_relative(entity_to_render.location.coord, depth, view_cell, context);
+            frame.set_cell_relative(coord, render_depth(tile), view_cell, context);
+        }
+        for (coord, visual_effect) in game_state.visual_effects_to_render() {
+            let view_cell = visual_effect_view_cell(visual_effect, colors);
+            frame.set_cell_relative(coord, EFFECT_DEPTH, view_cell, context);
+        }
+    }
+}
+
+// Visual effects fade out over their frames
+fn visual_effect_view_cell(visual_effect: VisualEffect, colors: &ColorTheme) -> ViewCell {
+    let num_frames = visual_effect.effect_type.num_frames();
+    let remaining = num_frames.saturating_sub(visual_effect.frame);
+    let (character, color) = match visual_effect.effect_type {
+        VisualEffectType::Blast => ('*', colors.fireball_scroll),
+        VisualEffectType::Sparkle => {
+            const SPARKLE: [char; 4] = ['+', '*', '+', '.'];
+            (
+                SPARKLE[visual_effect.frame as usize % SPARKLE.len()],
+                colors.healing,
+            )
+        }
+    };
+    ViewCell::new()
+        .with_character(character)
+        .with_bold(true)
+        .with_foreground(color.saturating_scalar_mul_div(remaining, num_frames))
+}
+
+// Picks a line-drawing character for a wall from the neighbouring walls
+// it joins up with. Isolated walls are drawn as '#'.
+fn wall_glyph(connections: Option<u8>) -> char {
+    // indexed by a bitmask of north = 1, east = 2, south = 4, west = 8
+    const WALL_GLYPHS: [char; 16] = [
+        '#', '│', '─', '└', '│', '│', '┌', '├', '─', '┘', '─', '┴', '┐', '┤', '┬', '┼',
+    ];
+    WALL_GLYPHS[connections.unwrap_or(0) as usize & 0xF]
+}
+
+// Drawing depths within a cell, from the floor up
+const FLOOR_DEPTH: i8 = 0;
+const FEATURE_DEPTH: i8 = 1;
+const CORPSE_DEPTH: i8 = 2;
+const OBJECT_DEPTH: i8 = 3;
+const GAS_DEPTH: i8 = 4;
+const CHARACTER_DEPTH: i8 = 5;
+const PROJECTILE_DEPTH: i8 = 6;
+// visual effects and flashes go over everything in the game
+const EFFECT_DEPTH: i8 = 7;
+
+// The depth a tile is drawn at. Corpses share the object layer with items
+// but are drawn beneath them. Every tile is listed, rather than going by
+// layer, so that a new tile can't be added without choosing its depth.
+fn render_depth(tile: Tile) -> i8 {
+    match tile {
+        Tile::Floor
+        | Tile::Stairs
+        | Tile::StairsUp
+        | Tile::Altar
+        | Tile::Trap(_)
+        | Tile::Water
+        | Tile::Lava
+        | Tile::Chasm
+        | Tile::PortalSealed
+        | Tile::PortalOpen => FLOOR_DEPTH,
+        Tile::Wall | Tile::Torch | Tile::DoorClosed | Tile::DoorOpen | Tile::Foliage => {
+            FEATURE_DEPTH
         }
+        Tile::PlayerCorpse | Tile::NpcCorpse(_) => CORPSE_DEPTH,
+        Tile::Item(_) | Tile::Gold | Tile::Rune => OBJECT_DEPTH,
+        Tile::Fog => GAS_DEPTH,
+        Tile::Player | Tile::Npc(_) => CHARACTER_DEPTH,
+        Tile::Projectile(_) => PROJECTILE_DEPTH,
     }
 }
 
@@ -514,15 +2614,25 @@ impl<'a> View<&'a AppData> for InventorySlotMenuView {
             .enumerate()
             .zip(player_inventory_slots.into_iter())
         {
-            let (name, name_color) = if let Some(item_entity) = slot {
-                let item_type = data
-                    .game_state
+            let item_type = slot.map(|item_entity| {
+                data.game_state
                     .item_type(item_entity)
-                    .expect("non-item in player inventory");
-                (item_type.name(), colors::item_color(item_type))
+                    .expect("non-item in player inventory")
+            });
+            let (name, name_color) = if let Some(item_type) = item_type {
+                (
+                    i18n::item_name(item_type),
+                    data.colors().item_color(item_type),
+                )
             } else {
                 ("-", Rgb24::new_grey(187))
             };
+            let equipped = equipped_indices.contains(i);
+            let name_color = if data.inventory_slot_filter.matches(item_type, equipped) {
+                name_color
+            } else {
+                name_color.saturating_scalar_mul_div(1, 3)
+            };
             let (selected_prefix, prefix_style, name_style) = if maybe_selected.is_some() {
                 (
                     ">",
@@ -539,10 +2649,12 @@ impl<'a> View<&'a AppData> for InventorySlotMenuView {
                 )
             };
             let prefix = format!("{} {}) ", selected_prefix, entry.key);
-            let equipment_suffix = if equipped_indices.held == Some(i) {
-                " (held)"
-            } else if equipped_indices.worn == Some(i) {
-                " (worn)"
+            let equipment_suffix = if equipped_indices.main_hand == Some(i) {
+                i18n::text("ui.held")
+            } else if equipped_indices.off_hand == Some(i) {
+                i18n::text("ui.off-hand")
+            } else if equipped_indices.body == Some(i) {
+                i18n::text("ui.worn")
             } else {
                 ""
             };
@@ -557,7 +2669,7 @@ impl<'a> View<&'a AppData> for InventorySlotMenuView {
                 },
                 RichTextPart {
                     text: equipment_suffix,
-                    style: name_style
+                    style: name_style,
                 },
             ];
             let size = RichTextViewSingleLine::new().view_size(
@@ -601,25 +2713,855 @@ impl ViewSelector for InventorySlotMenuSelect {
     }
 }
 
+struct InventorySlotMenuDecorate<'a> {
+    title: &'a str,
+}
+
+impl<'a> Decorate for InventorySlotMenuDecorate<'a> {
+    type View = AppView;
+    type Data = AppData;
+    fn view<E, F, C>(
+        &self,
+        data: &Self::Data,
+        mut event_routine_view: EventRoutineView<E>,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) where
+        E: EventRoutine<Data = Self::Data, View = Self::View>,
+        F: Frame,
+        C: ColModify,
+    {
+        let map_offset = event_routine_view.view.game_view.offset;
+        BoundView {
+            size: data.game_state.size(),
+            view: AlignView {
+                alignment: Alignment::centre(),
+                view: FillBackgroundView {
+                    rgb24: Rgb24::new_grey(0),
+                    view: BorderView {
+                        style: &BorderStyle {
+                            title: Some(self.title.to_string()),
+                            title_style: Style::new().with_foreground(Rgb24::new_grey(255)),
+                            ..Default::default()
+                        },
+                        view: MinSizeView {
+                            size: Size::new(12, 0),
+                            view: &mut event_routine_view,
+                        },
+                    },
+                },
+            },
+        }
+        .view(data, context.add_offset(map_offset).add_depth(10), frame);
+        event_routine_view.view.game_view.view(
+            (&data.game_state, data.colors()),
+            context.compose_col_modify(ColModifyMap(|c: Rgb24| c.saturating_scalar_mul_div(1, 2))),
+            frame,
+        );
+        event_routine_view
+            .view
+            .render_ui(None, &data, context, frame);
+    }
+}
+
+fn inventory_slot_menu_instance(
+    num_slots: usize,
+) -> MenuInstanceChooseOrEscape<InventorySlotMenuEntry> {
+    let items = (0..num_slots)
+        .zip('a'..)
+        .map(|(index, key)| InventorySlotMenuEntry { index, key })
+        .collect::<Vec<_>>();
+    let hotkeys = items
+        .iter()
+        .map(|&entry| (entry.key, entry))
+        .collect::<HashMap<_, _>>();
+    MenuInstanceBuilder {
+        items,
+        hotkeys: Some(hotkeys),
+        selected_index: 0,
+    }
+    .build()
+    .unwrap()
+    .into_choose_or_escape()
+}
+
+// Which inventory slots a menu is for. The others are still listed, but
+// dimmed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum InventorySlotFilter {
+    All,
+    Weapons,
+    BodyArmor,
+    Equipped,
+}
+
+impl InventorySlotFilter {
+    fn matches(self, item_type: Option<ItemType>, equipped: bool) -> bool {
+        let equipment_class = item_type.and_then(ItemType::equipment_class);
+        match self {
+            Self::All => true,
+            Self::Weapons => equipment_class.map_or(false, EquipmentClass::is_weapon),
+            Self::BodyArmor => equipment_class == Some(EquipmentClass::Body),
+            Self::Equipped => equipped,
+        }
+    }
+}
+
+fn inventory_slot_menu<'a>(
+    title: &'a str,
+    filter: InventorySlotFilter,
+) -> impl 'a
+       + EventRoutine<
+    Return = Result<InventorySlotMenuEntry, menu::Escape>,
+    Data = AppData,
+    View = AppView,
+    Event = CommonEvent,
+> {
+    SideEffectThen::new_with_view(move |data: &mut AppData, _: &_| {
+        data.update_inventory_slot_menu();
+        data.inventory_slot_filter = filter;
+        MenuInstanceRoutine::new(InventorySlotMenuSelect)
+            .convert_input_to_common_event()
+            .decorated(InventorySlotMenuDecorate { title })
+    })
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TravelMenuEntry {
+    coord: Coord,
+    target: TravelTarget,
+    key: char,
+}
+
+#[derive(Default)]
+struct TravelMenuView {
+    mouse_tracker: MenuInstanceMouseTracker,
+}
+
+impl MenuIndexFromScreenCoord for TravelMenuView {
+    fn menu_index_from_screen_coord(&self, len: usize, coord: Coord) -> Option<usize> {
+        self.mouse_tracker.menu_index_from_screen_coord(len, coord)
+    }
+}
+
+impl<'a> View<&'a AppData> for TravelMenuView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        data: &'a AppData,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        self.mouse_tracker.new_frame(context.offset);
+        let travel_menu = match data.travel_menu.as_ref() {
+            Some(travel_menu) => travel_menu,
+            None => return,
+        };
+        for (i, entry, maybe_selected) in travel_menu.menu_instance().enumerate() {
+            let name_color = match entry.target {
+                TravelTarget::Stairs | TravelTarget::StairsUp => data.colors().stairs,
+                TravelTarget::Item(item_type) => data.colors().item_color(item_type),
+            };
+            let (selected_prefix, prefix_style, name_style) = if maybe_selected.is_some() {
+                (
+                    ">",
+                    Style::new()
+                        .with_foreground(Rgb24::new_grey(255))
+                        .with_bold(true),
+                    Style::new().with_foreground(name_color).with_bold(true),
+                )
+            } else {
+                (
+                    " ",
+                    Style::new().with_foreground(Rgb24::new_grey(187)),
+                    Style::new().with_foreground(name_color.saturating_scalar_mul_div(2, 3)),
+                )
+            };
+            let prefix = format!("{} {}) ", selected_prefix, entry.key);
+            let text = &[
+                RichTextPart {
+                    text: &prefix,
+                    style: prefix_style,
+                },
+                RichTextPart {
+                    text: i18n::travel_target_name(entry.target),
+                    style: name_style,
+                },
+            ];
+            let size = RichTextViewSingleLine::new().view_size(
+                text.into_iter().cloned(),
+                context.add_offset(Coord::new(0, i as i32)),
+                frame,
+            );
+            self.mouse_tracker.on_entry_view_size(size);
+        }
+    }
+}
+
+struct TravelMenuSelect;
+
+impl ChooseSelector for TravelMenuSelect {
+    type ChooseOutput = MenuInstanceChooseOrEscape<TravelMenuEntry>;
+    fn choose_mut<'a>(&self, input: &'a mut Self::DataInput) -> &'a mut Self::ChooseOutput {
+        input.travel_menu.as_mut().expect("travel menu is not open")
+    }
+}
+
+impl DataSelector for TravelMenuSelect {
+    type DataInput = AppData;
+    type DataOutput = AppData;
+    fn data<'a>(&self, input: &'a Self::DataInput) -> &'a Self::DataOutput {
+        input
+    }
+    fn data_mut<'a>(&self, input: &'a mut Self::DataInput) -> &'a mut Self::DataOutput {
+        input
+    }
+}
+
+impl ViewSelector for TravelMenuSelect {
+    type ViewInput = AppView;
+    type ViewOutput = TravelMenuView;
+    fn view<'a>(&self, input: &'a Self::ViewInput) -> &'a Self::ViewOutput {
+        &input.travel_menu_view
+    }
+    fn view_mut<'a>(&self, input: &'a mut Self::ViewInput) -> &'a mut Self::ViewOutput {
+        &mut input.travel_menu_view
+    }
+}
+
+// Looks just like the inventory menus
+fn travel_menu() -> impl EventRoutine<
+    Return = Result<TravelMenuEntry, menu::Escape>,
+    Data = AppData,
+    View = AppView,
+    Event = CommonEvent,
+> {
+    MenuInstanceRoutine::new(TravelMenuSelect)
+        .convert_input_to_common_event()
+        .decorated(InventorySlotMenuDecorate {
+            title: i18n::text("menu.travel-to"),
+        })
+}
+
+// The keys of wizard mode's cheats
+enum WizardKey {
+    Command(WizardCommand),
+    SpawnNpcMenu,
+    GrantItemMenu,
+}
+
+fn wizard_key(key: KeyboardInput) -> Option<WizardKey> {
+    let command = match key {
+        KeyboardInput::Function(2) => WizardCommand::RevealMap,
+        KeyboardInput::Function(3) => WizardCommand::Heal,
+        KeyboardInput::Function(4) => return Some(WizardKey::SpawnNpcMenu),
+        KeyboardInput::Function(5) => return Some(WizardKey::GrantItemMenu),
+        KeyboardInput::Function(6) => WizardCommand::Descend,
+        KeyboardInput::Function(7) => WizardCommand::ToggleOmniscience,
+        KeyboardInput::Function(8) => WizardCommand::RegenerateLevel,
+        KeyboardInput::Function(9) => WizardCommand::ToggleNpcDistances,
+        _ => return None,
+    };
+    Some(WizardKey::Command(command))
+}
+
+#[derive(Clone, Copy, Debug)]
+struct WizardMenuEntry {
+    command: WizardCommand,
+    key: char,
+}
+
+#[derive(Default)]
+struct WizardMenuView {
+    mouse_tracker: MenuInstanceMouseTracker,
+}
+
+impl MenuIndexFromScreenCoord for WizardMenuView {
+    fn menu_index_from_screen_coord(&self, len: usize, coord: Coord) -> Option<usize> {
+        self.mouse_tracker.menu_index_from_screen_coord(len, coord)
+    }
+}
+
+impl<'a> View<&'a AppData> for WizardMenuView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        data: &'a AppData,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        self.mouse_tracker.new_frame(context.offset);
+        let wizard_menu = match data.wizard_menu.as_ref() {
+            Some(wizard_menu) => wizard_menu,
+            None => return,
+        };
+        for (i, entry, maybe_selected) in wizard_menu.menu_instance().enumerate() {
+            let (name, name_color) = match entry.command {
+                WizardCommand::SpawnNpc(npc_type) => {
+                    (i18n::npc_name(npc_type), data.colors().npc_color(npc_type))
+                }
+                WizardCommand::GrantItem(item_type) => (
+                    i18n::item_name(item_type),
+                    data.colors().item_color(item_type),
+                ),
+                _ => continue,
+            };
+            let (selected_prefix, prefix_style, name_style) = if maybe_selected.is_some() {
+                (
+                    ">",
+                    Style::new()
+                        .with_foreground(Rgb24::new_grey(255))
+                        .with_bold(true),
+                    Style::new().with_foreground(name_color).with_bold(true),
+                )
+            } else {
+                (
+                    " ",
+                    Style::new().with_foreground(Rgb24::new_grey(187)),
+                    Style::new().with_foreground(name_color.saturating_scalar_mul_div(2, 3)),
+                )
+            };
+            let prefix = format!("{} {}) ", selected_prefix, entry.key);
+            let text = &[
+                RichTextPart {
+                    text: &prefix,
+                    style: prefix_style,
+                },
+                RichTextPart {
+                    text: name,
+                    style: name_style,
+                },
+            ];
+            let size = RichTextViewSingleLine::new().view_size(
+                text.into_iter().cloned(),
+                context.add_offset(Coord::new(0, i as i32)),
+                frame,
+            );
+            self.mouse_tracker.on_entry_view_size(size);
+        }
+    }
+}
+
+struct WizardMenuSelect;
+
+impl ChooseSelector for WizardMenuSelect {
+    type ChooseOutput = MenuInstanceChooseOrEscape<WizardMenuEntry>;
+    fn choose_mut<'a>(&self, input: &'a mut Self::DataInput) -> &'a mut Self::ChooseOutput {
+        input.wizard_menu.as_mut().expect("wizard menu is not open")
+    }
+}
+
+impl DataSelector for WizardMenuSelect {
+    type DataInput = AppData;
+    type DataOutput = AppData;
+    fn data<'a>(&self, input: &'a Self::DataInput) -> &'a Self::DataOutput {
+        input
+    }
+    fn data_mut<'a>(&self, input: &'a mut Self::DataInput) -> &'a mut Self::DataOutput {
+        input
+    }
+}
+
+impl ViewSelector for WizardMenuSelect {
+    type ViewInput = AppView;
+    type ViewOutput = WizardMenuView;
+    fn view<'a>(&self, input: &'a Self::ViewInput) -> &'a Self::ViewOutput {
+        &input.wizard_menu_view
+    }
+    fn view_mut<'a>(&self, input: &'a mut Self::ViewInput) -> &'a mut Self::ViewOutput {
+        &mut input.wizard_menu_view
+    }
+}
+
+fn wizard_menu(
+    title: &'static str,
+) -> impl EventRoutine<
+    Return = Result<WizardMenuEntry, menu::Escape>,
+    Data = AppData,
+    View = AppView,
+    Event = CommonEvent,
+> {
+    MenuInstanceRoutine::new(WizardMenuSelect)
+        .convert_input_to_common_event()
+        .decorated(InventorySlotMenuDecorate { title })
+}
+
+struct GameEventRoutine;
+
+enum GameReturn {
+    UseItem,
+    DropItem,
+    Equipment(EquipmentCommand),
+    GameOver,
+    Victory,
+    Examine,
+    Menu,
+    LevelUpAndDescend,
+    Statistics,
+    Travel,
+    // a wizard command which needs something chosen from a menu, with
+    // the menu's title
+    WizardMenu(&'static str),
+    AssignQuickSlot,
+    // a quick slot's item needs aiming
+    AimItem(usize),
+}
+
+impl EventRoutine for GameEventRoutine {
+    type Return = GameReturn;
+    type Data = AppData;
+    type View = AppView;
+    type Event = CommonEvent;
+
+    fn handle<EP>(
+        self,
+        data: &mut Self::Data,
+        view: &Self::View,
+        event_or_peek: EP,
+    ) -> Handled<Self::Return, Self>
+    where
+        EP: EventOrPeek<Event = Self::Event>,
+    {
+        // nothing is open over the game while it's handling events
+        data.open_screen = None;
+        event_routine::event_or_peek_with_handled(event_or_peek, self, |s, event| match event {
+            CommonEvent::Input(input) => {
+                let input = view.screen_to_map_input(input);
+                if let Some(game_return) = data.handle_input(input) {
+                    Handled::Return(data.opening(game_return))
+                } else {
+                    Handled::Continue(s)
+                }
+            }
+            CommonEvent::Frame(period) => {
+                if let Some(open_screen) = data.reopen_screen.take() {
+                    return Handled::Return(data.opening(open_screen.game_return()));
+                }
+                data.frame_period = period;
+                if let Some(until_next_animation_tick) =
+                    data.until_next_animation_tick.checked_sub(period)
+                {
+                    data.until_next_animation_tick = until_next_animation_tick;
+                } else {
+                    data.until_next_animation_tick = BETWEEN_ANIMATION_TICKS;
+                    data.game_state.tick_animations();
+                    // the player sees what the animation uncovered
+                    if !data.game_state.has_animations() {
+                        data.game_state.update_visibility(data.visibility_algorithm);
+                    }
+                }
+                data.check_recording();
+                data.save_screenshot();
+                data.maybe_autosave();
+                data.tick_playback(period);
+                if let Some(game_return) = data.drain_input_buffer() {
+                    return Handled::Return(data.opening(game_return));
+                }
+                data.tick_auto_act(period);
+                data.effects.tick(period);
+                for event in data.game_state.take_events() {
+                    data.effects.add(event);
+                    data.audio.play(event);
+                }
+                data.check_for_more_messages();
+                // a dead player has the level to look at until they press
+                // a key, other than in a replay
+                if data.playback.is_some()
+                    && data.message_page_start.is_none()
+                    && !data.game_state.is_player_alive()
+                {
+                    return Handled::Return(GameReturn::GameOver);
+                }
+                Handled::Continue(s)
+            }
+        })
+    }
+
+    fn view<F, C>(
+        &self,
+        data: &Self::Data,
+        view: &mut Self::View,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) where
+        F: Frame,
+        C: ColModify,
+    {
+        view.game_view.view_with_effects(
+            &data.game_state,
+            data.colors(),
+            &data.effects,
+            context,
+            frame,
+        );
+        let travel_path = if data.game_state.is_auto_acting() {
+            data.game_state.travel_path()
+        } else {
+            &data.travel_preview
+        };
+        view.game_view.view_travel_path(travel_path, context, frame);
+        if data.show_npc_distances {
+            view.game_view
+                .view_npc_distances(&data.game_state, context, frame);
+        }
+        view.render_ui(None, &data, context, frame);
+    }
+}
+
+fn use_item() -> impl EventRoutine<Return = (), Data = AppData, View = AppView, Event = CommonEvent>
+{
+    make_either!(Ei = A | B);
+    Loop::new(|| {
+        let title = i18n::text("menu.use-item");
+        inventory_slot_menu(title, InventorySlotFilter::All).and_then(|result| match result {
+            Err(menu::Escape) => Ei::A(Value::new(Some(()))),
+            Ok(entry) => Ei::B(SideEffectThen::new_with_view(
+                move |data: &mut AppData, _: &_| {
+                    make_either!(Ei = A | B | C);
+                    let turn = data.game_state.turn();
+                    if let Ok(usage) = data.game_state.maybe_player_use_item(entry.index) {
+                        match usage {
+                            ItemUsage::Immediate => {
+                                data.record(PlayerAction::Use(entry.index));
+                                data.game_state.update_visibility(data.visibility_algorithm);
+                                data.remember_action_if_turn_taken(
+                                    RepeatableAction::UseItem(entry.index),
+                                    turn,
+                                );
+                                Ei::A(Value::new(Some(())))
+                            }
+                            ItemUsage::Aim => Ei::B(aim_item(entry.index)),
+                        }
+                    } else {
+                        Ei::C(Value::new(None))
+                    }
+                },
+            )),
+        })
+    })
+}
+
+// Chooses where to use the item in an inventory slot which needs aiming,
+// returning Some(()) if it was used
+fn aim_item(
+    inventory_index: usize,
+) -> impl EventRoutine<Return = Option<()>, Data = AppData, View = AppView, Event = CommonEvent> {
+    TargetEventRoutine {
+        name: i18n::text("menu.aim"),
+        aim_item: Some(inventory_index),
+    }
+    .and_then(move |maybe_coord| {
+        SideEffect::new_with_view(move |data: &mut AppData, _: &_| {
+            if let Some(coord) = maybe_coord {
+                if data
+                    .game_state
+                    .maybe_player_use_item_aim(inventory_index, coord)
+                    .is_ok()
+                {
+                    data.record(PlayerAction::UseAim(inventory_index, coord));
+                    data.game_state.update_visibility(data.visibility_algorithm);
+                    Some(())
+                } else {
+                    None
+                }
+            } else {
+                None
+            }
+        })
+    })
+}
+
+// Binds the chosen inventory slot to the quick slot whose number the
+// player presses next
+fn assign_quick_slot(
+) -> impl EventRoutine<Return = (), Data = AppData, View = AppView, Event = CommonEvent> {
+    make_either!(Ei = A | B);
+    let title = i18n::text("menu.assign-quick-slot");
+    inventory_slot_menu(title, InventorySlotFilter::All).and_then(|result| match result {
+        Err(menu::Escape) => Ei::A(Value::new(())),
+        Ok(entry) => Ei::B(
+            KeyPromptEventRoutine {
+                prompt: i18n::text("prompt.quick-slot"),
+            }
+            .and_then(move |key| {
+                SideEffect::new_with_view(move |data: &mut AppData, _: &_| {
+                    let quick_slot = match key {
+                        KeyboardInput::Char(c) => c.to_digit(10),
+                        _ => None,
+                    };
+                    match quick_slot {
+                        Some(n) if n >= 1 && n as usize <= NUM_QUICK_SLOTS => {
+                            let _ = data
+                                .game_state
+                                .assign_quick_slot(n as usize - 1, entry.index);
+                        }
+                        _ => (),
+                    }
+                })
+            }),
+        ),
+    })
+}
+
+fn drop_item() -> impl EventRoutine<Return = (), Data = AppData, View = AppView, Event = CommonEvent>
+{
+    make_either!(Ei = A | B);
+    Loop::new(|| {
+        let title = i18n::text("menu.drop-item");
+        inventory_slot_menu(title, InventorySlotFilter::All).and_then(|result| match result {
+            Err(menu::Escape) => Ei::A(Value::new(Some(()))),
+            Ok(entry) => Ei::B(SideEffect::new_with_view(
+                move |data: &mut AppData, _: &_| {
+                    if data.perform(PlayerAction::Drop(entry.index)) {
+                        Some(())
+                    } else {
+                        None
+                    }
+                },
+            )),
+        })
+    })
+}
+
+// The commands which put on and take off equipment, each choosing an
+// inventory slot from a menu
+#[derive(Clone, Copy, Serialize, Deserialize)]
+enum EquipmentCommand {
+    Wield,
+    Wear,
+    Unequip,
+}
+
+impl EquipmentCommand {
+    fn title(self) -> &'static str {
+        i18n::text(match self {
+            Self::Wield => "menu.wield",
+            Self::Wear => "menu.wear",
+            Self::Unequip => "menu.unequip",
+        })
+    }
+
+    fn filter(self) -> InventorySlotFilter {
+        match self {
+            Self::Wield => InventorySlotFilter::Weapons,
+            Self::Wear => InventorySlotFilter::BodyArmor,
+            Self::Unequip => InventorySlotFilter::Equipped,
+        }
+    }
+
+    fn player_action(self, inventory_index: usize) -> PlayerAction {
+        match self {
+            Self::Wield => PlayerAction::Wield(inventory_index),
+            Self::Wear => PlayerAction::Wear(inventory_index),
+            Self::Unequip => PlayerAction::Unequip(inventory_index),
+        }
+    }
+}
+
+fn equipment_item(
+    command: EquipmentCommand,
+) -> impl EventRoutine<Return = (), Data = AppData, View = AppView, Event = CommonEvent> {
+    make_either!(Ei = A | B);
+    Loop::new(move || {
+        inventory_slot_menu(command.title(), command.filter()).and_then(
+            move |result| match result {
+                Err(menu::Escape) => Ei::A(Value::new(Some(()))),
+                Ok(entry) => Ei::B(SideEffect::new_with_view(
+                    move |data: &mut AppData, _: &_| {
+                        if data.perform(command.player_action(entry.index)) {
+                            Some(())
+                        } else {
+                            None
+                        }
+                    },
+                )),
+            },
+        )
+    })
+}
+
+// Shown when the game ends, either in death or escape. The game is
+// tinted with a dark shade of `rgb24`.
+fn game_over(
+    text: &'static str,
+    rgb24: Rgb24,
+) -> impl EventRoutine<Return = (), Data = AppData, View = AppView, Event = CommonEvent> {
+    struct GameOverDecorate {
+        text: &'static str,
+        rgb24: Rgb24,
+    }
+    impl Decorate for GameOverDecorate {
+        type View = AppView;
+        type Data = AppData;
+        fn view<E, F, C>(
+            &self,
+            data: &Self::Data,
+            event_routine_view: EventRoutineView<E>,
+            context: ViewContext<C>,
+            frame: &mut F,
+        ) where
+            E: EventRoutine<Data = Self::Data, View = Self::View>,
+            F: Frame,
+            C: ColModify,
+        {
+            let tint = self.rgb24.saturating_scalar_mul_div(1, 8);
+            let map_offset = event_routine_view.view.game_view.offset;
+            BoundView {
+                size: data.game_state.size(),
+                view: AlignView {
+                    alignment: Alignment::centre(),
+                    view: &mut event_routine_view.view.run_stats_view,
+                },
+            }
+            .view(
+                RunStatsData {
+                    title: self.text,
+                    title_color: self.rgb24,
+                    run_stats: data.game_state.run_stats(),
+                    daily_date: data.game_state.daily_date(),
+                    difficulty: data.game_state.difficulty(),
+                    colors: data.colors(),
+                },
+                context.add_offset(map_offset).add_depth(10),
+                frame,
+            );
+            FillBackgroundView {
+                rgb24: tint,
+                view: &mut event_routine_view.view.game_view,
+            }
+            .view(
+                (&data.game_state, data.colors()),
+                context.compose_col_modify(ColModifyMap(|c: Rgb24| {
+                    c.saturating_scalar_mul_div(1, 3).saturating_add(tint)
+                })),
+                frame,
+            );
+            event_routine_view
+                .view
+                .render_ui(None, &data, context, frame);
+        }
+    }
+    Delay::new(Duration::from_millis(2000)).decorated(GameOverDecorate { text, rgb24 })
+}
+
+// Shows the run's statistics over the game until a key is pressed
+struct StatisticsEventRoutine;
+
+impl EventRoutine for StatisticsEventRoutine {
+    type Return = ();
+    type Data = AppData;
+    type View = AppView;
+    type Event = CommonEvent;
+
+    fn handle<EP>(
+        self,
+        _data: &mut Self::Data,
+        _view: &Self::View,
+        event_or_peek: EP,
+    ) -> Handled<Self::Return, Self>
+    where
+        EP: EventOrPeek<Event = Self::Event>,
+    {
+        event_routine::event_or_peek_with_handled(event_or_peek, self, |s, event| match event {
+            CommonEvent::Input(Input::Keyboard(_)) => Handled::Return(()),
+            _ => Handled::Continue(s),
+        })
+    }
+
+    fn view<F, C>(
+        &self,
+        data: &Self::Data,
+        view: &mut Self::View,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) where
+        F: Frame,
+        C: ColModify,
+    {
+        let map_offset = view.game_view.offset;
+        BoundView {
+            size: data.game_state.size(),
+            view: AlignView {
+                alignment: Alignment::centre(),
+                view: FillBackgroundView {
+                    rgb24: Rgb24::new_grey(0),
+                    view: BorderView {
+                        style: &BorderStyle::default(),
+                        view: &mut view.run_stats_view,
+                    },
+                },
+            },
+        }
+        .view(
+            RunStatsData {
+                title: i18n::text("run-stats.title"),
+                title_color: Rgb24::new_grey(255),
+                run_stats: data.game_state.run_stats(),
+                daily_date: data.game_state.daily_date(),
+                difficulty: data.game_state.difficulty(),
+                colors: data.colors(),
+            },
+            context.add_offset(map_offset).add_depth(10),
+            frame,
+        );
+        view.game_view.view(
+            (&data.game_state, data.colors()),
+            context.compose_col_modify(ColModifyMap(|c: Rgb24| c.saturating_scalar_mul_div(1, 2))),
+            frame,
+        );
+        view.render_ui(None, &data, context, frame);
+    }
+}
+
+// Shows the high score table over the game until a key other than one
+// which sorts the table is pressed
+struct HighScoresEventRoutine;
 
-struct InventorySlotMenuDecorate<'a> {
-    title: &'a str,
-}
-
-impl<'a> Decorate for InventorySlotMenuDecorate<'a> {
-    type View = AppView;
+impl EventRoutine for HighScoresEventRoutine {
+    type Return = ();
     type Data = AppData;
-    fn view<E, F, C>(
+    type View = AppView;
+    type Event = CommonEvent;
+
+    fn handle<EP>(
+        self,
+        data: &mut Self::Data,
+        _view: &Self::View,
+        event_or_peek: EP,
+    ) -> Handled<Self::Return, Self>
+    where
+        EP: EventOrPeek<Event = Self::Event>,
+    {
+        event_routine::event_or_peek_with_handled(event_or_peek, self, |s, event| match event {
+            CommonEvent::Input(Input::Keyboard(KeyboardInput::Char('s'))) => {
+                data.high_score_order = HighScoreOrder::Score;
+                Handled::Continue(s)
+            }
+            CommonEvent::Input(Input::Keyboard(KeyboardInput::Char('d'))) => {
+                data.high_score_order = HighScoreOrder::DungeonLevel;
+                Handled::Continue(s)
+            }
+            CommonEvent::Input(Input::Keyboard(_)) => Handled::Return(()),
+            _ => Handled::Continue(s),
+        })
+    }
+
+    fn view<F, C>(
         &self,
         data: &Self::Data,
-        mut event_routine_view: EventRoutineView<E>,
+        view: &mut Self::View,
         context: ViewContext<C>,
         frame: &mut F,
     ) where
-        E: EventRoutine<Data = Self::Data, View = Self::View>,
         F: Frame,
         C: ColModify,
     {
+        // the title, column headings, and help below the table
+        const NON_ENTRY_ROWS: u32 = 5;
+        let map_offset = view.game_view.offset;
+        let max_entries = data
+            .game_state
+            .size()
+            .height()
+            .saturating_sub(NON_ENTRY_ROWS) as usize;
         BoundView {
             size: data.game_state.size(),
             view: AlignView {
@@ -627,86 +3569,92 @@ impl<'a> Decorate for InventorySlotMenuDecorate<'a> {
                 view: FillBackgroundView {
                     rgb24: Rgb24::new_grey(0),
                     view: BorderView {
-                        style: &BorderStyle {
-                            title: Some(self.title.to_string()),
-                            title_style: Style::new().with_foreground(Rgb24::new_grey(255)),
-                            ..Default::default()
-                        },
-                        view: MinSizeView {
-                            size: Size::new(12, 0),
-                            view: &mut event_routine_view,
-                        },
+                        style: &BorderStyle::default(),
+                        view: &mut view.high_scores_view,
                     },
                 },
             },
-        }.view(data, context.add_depth(10), frame);
-        event_routine_view.view.game_view.view(
-            &data.game_state,
+        }
+        .view(
+            HighScoresData {
+                high_scores: &data.high_scores,
+                order: data.high_score_order,
+                max_entries,
+            },
+            context.add_offset(map_offset).add_depth(10),
+            frame,
+        );
+        view.game_view.view(
+            (&data.game_state, data.colors()),
             context.compose_col_modify(ColModifyMap(|c: Rgb24| c.saturating_scalar_mul_div(1, 2))),
             frame,
         );
-        event_routine_view.view.render_ui(None, &data, context, frame);
+        view.render_ui(None, &data, context, frame);
     }
 }
 
-fn inventory_slot_menu<'a>(title: &'a str,) -> impl 'a
-    + EventRoutine<
-            Return = Result<InventorySlotMenuEntry, menu::Escape>,
-            Data = AppData,
-            View = AppView,
-            Event = CommonEvent,
-        > {
-        MenuInstanceRoutine::new(InventorySlotMenuSelect)
-            .convert_input_to_common_event()
-            .decorated(InventorySlotMenuDecorate { title })
+// Makes the character for a new run over the dimmed game. Typing edits
+// the name, left and right choose the background, return starts the run
+// and escape gives up on it.
+struct CharacterCreationEventRoutine {
+    name: String,
+    background: Background,
 }
 
-
-struct GameEventRoutine;
-
-enum GameReturn {
-    UseItem,
-    DropItem,
-    GameOver,
-    Examine,
-    Menu,
-    LevelUpAndDescend,
+impl CharacterCreationEventRoutine {
+    // Starts from the last character made, if there was one
+    fn new(previous: NewCharacter) -> Self {
+        Self {
+            name: previous.name,
+            background: previous.background.unwrap_or(Background::Warrior),
+        }
+    }
 }
 
-impl EventRoutine for GameEventRoutine {
-    type Return = GameReturn;
+impl EventRoutine for CharacterCreationEventRoutine {
+    type Return = Option<NewCharacter>;
     type Data = AppData;
     type View = AppView;
     type Event = CommonEvent;
 
     fn handle<EP>(
         self,
-        data: &mut Self::Data,
+        _data: &mut Self::Data,
         _view: &Self::View,
         event_or_peek: EP,
     ) -> Handled<Self::Return, Self>
     where
         EP: EventOrPeek<Event = Self::Event>,
     {
-        event_routine::event_or_peek_with_handled(event_or_peek, self, |s, event| match event{
-            CommonEvent::Input(input) => {
-                if let Some(game_return) = data.handle_input(input) {
-                    Handled::Return(game_return)
-                } else {
+        event_routine::event_or_peek_with_handled(event_or_peek, self, |mut s, event| match event {
+            CommonEvent::Input(Input::Keyboard(key)) => match key {
+                keys::ESCAPE => Handled::Return(None),
+                // a character needs a name
+                keys::RETURN if !s.name.trim().is_empty() => Handled::Return(Some(NewCharacter {
+                    name: s.name.trim().to_string(),
+                    background: Some(s.background),
+                })),
+                keys::BACKSPACE => {
+                    s.name.pop();
                     Handled::Continue(s)
                 }
-            }
-            CommonEvent::Frame(period) => {
-                if let Some(until_next_animation_tick) =
-                    data.until_next_animation_tick.checked_sub(period)
-                {
-                    data.until_next_animation_tick = until_next_animation_tick;
-                } else {
-                    data.until_next_animation_tick = BETWEEN_ANIMATION_TICKS;
-                    data.game_state.tick_animations();
+                KeyboardInput::Left => {
+                    s.background = s.background.previous();
+                    Handled::Continue(s)
                 }
-                Handled::Continue(s)
-            }
+                KeyboardInput::Right => {
+                    s.background = s.background.next();
+                    Handled::Continue(s)
+                }
+                KeyboardInput::Char(c) if !c.is_control() => {
+                    if s.name.chars().count() < MAX_CHARACTER_NAME_LEN {
+                        s.name.push(c);
+                    }
+                    Handled::Continue(s)
+                }
+                _ => Handled::Continue(s),
+            },
+            _ => Handled::Continue(s),
         })
     }
 
@@ -720,120 +3668,107 @@ impl EventRoutine for GameEventRoutine {
         F: Frame,
         C: ColModify,
     {
-        view.game_view.view(&data.game_state, context, frame);
+        let map_offset = view.game_view.offset;
+        BoundView {
+            size: data.game_state.size(),
+            view: AlignView {
+                alignment: Alignment::centre(),
+                view: FillBackgroundView {
+                    rgb24: Rgb24::new_grey(0),
+                    view: BorderView {
+                        style: &BorderStyle::default(),
+                        view: &mut view.character_creation_view,
+                    },
+                },
+            },
+        }
+        .view(
+            CharacterCreationData {
+                name: &self.name,
+                background: self.background,
+            },
+            context.add_offset(map_offset).add_depth(10),
+            frame,
+        );
+        view.game_view.view(
+            (&data.game_state, data.colors()),
+            context.compose_col_modify(ColModifyMap(|c: Rgb24| c.saturating_scalar_mul_div(1, 2))),
+            frame,
+        );
         view.render_ui(None, &data, context, frame);
     }
 }
 
-
-fn use_item() -> impl EventRoutine<Return = (), Data = AppData, View = AppView, Event = CommonEvent>
-{
-    make_either!(Ei = A | B);
-    Loop::new(|| {
-        inventory_slot_menu("Use Item").and_then(|result| match result {
-            Err(menu::Escape) => Ei::A(Value::new(Some(()))),
-            Ok(entry) => Ei::B(SideEffectThen::new_with_view(
-                move |data: &mut AppData, _: &_| {
-                    make_either!(Ei = A | B | C);
-                    if let Ok(usage) = data.game_state.maybe_player_use_item(entry.index) {
-                        match usage {
-                            ItemUsage::Immediate => Ei::A(Value::new(Some(()))),
-                            ItemUsage::Aim => Ei::B(TargetEventRoutine { name: "AIM" }.and_then(
-                                move |maybe_coord| {
-                                    SideEffect::new_with_view(move |data: &mut AppData, _: &_| {
-                                        if let Some(coord) = maybe_coord {
-                                            if data
-                                                .game_state
-                                                .maybe_player_use_item_aim(entry.index, coord)
-                                                .is_ok()
-                                            {
-                                                Some(())
-                                            } else {
-                                                None
-                                            }
-                                        } else {
-                                            None
-                                        }
-                                    })
-                                },
-                            )),
-                        }
-                    } else {
-                        Ei::C(Value::new(None))
-                    }
-                },
-            )),
-        })
-    })
+// Asks the player a question over the dimmed game, answered with a key
+struct KeyPromptEventRoutine {
+    prompt: &'static str,
 }
 
+impl EventRoutine for KeyPromptEventRoutine {
+    type Return = KeyboardInput;
+    type Data = AppData;
+    type View = AppView;
+    type Event = CommonEvent;
 
-fn drop_item() -> impl EventRoutine<Return = (), Data = AppData, View = AppView, Event = CommonEvent>
-{
-    make_either!(Ei = A | B);
-    Loop::new(|| {
-        inventory_slot_menu("Drop Item").and_then(|result| match result {
-            Err(menu::Escape) => Ei::A(Value::new(Some(()))),
-            Ok(entry) => Ei::B(SideEffect::new_with_view(
-                move |data: &mut AppData, _: &_| {
-                    if data.game_state.maybe_player_drop_item(entry.index).is_ok() {
-                        Some(())
-                    } else {
-                        None
-                    }
-                },
-            )),
+    fn handle<EP>(
+        self,
+        _data: &mut Self::Data,
+        _view: &Self::View,
+        event_or_peek: EP,
+    ) -> Handled<Self::Return, Self>
+    where
+        EP: EventOrPeek<Event = Self::Event>,
+    {
+        event_routine::event_or_peek_with_handled(event_or_peek, self, |s, event| match event {
+            CommonEvent::Input(Input::Keyboard(key)) => Handled::Return(key),
+            _ => Handled::Continue(s),
         })
-    })
-}
+    }
 
-fn game_over() -> impl EventRoutine<Return = (), Data = AppData, View = AppView, Event = CommonEvent>
-{
-    struct GameOverDecorate;
-    impl Decorate for GameOverDecorate {
-        type View = AppView;
-        type Data = AppData;
-        fn view<E, F, C>(
-            &self,
-            data: &Self::Data,
-            event_routine_view: EventRoutineView<E>,
-            context: ViewContext<C>,
-            frame: &mut F,
-        ) where
-            E: EventRoutine<Data = Self::Data, View = Self::View>,
-            F: Frame,
-            C: ColModify,
-        {
-            AlignView {
+    fn view<F, C>(
+        &self,
+        data: &Self::Data,
+        view: &mut Self::View,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) where
+        F: Frame,
+        C: ColModify,
+    {
+        let map_offset = view.game_view.offset;
+        BoundView {
+            size: data.game_state.size(),
+            view: AlignView {
                 alignment: Alignment::centre(),
-                view: StringViewSingleLine::new(
-                    Style::new()
-                        .with_foreground(Rgb24::new(255, 0, 0))
-                        .with_bold(true),
-                ),
-            }
-            .view("YOU DIED", context.add_depth(10), frame);
-            FillBackgroundView {
-                rgb24: Rgb24::new(31, 0, 0),
-                view: &mut event_routine_view.view.game_view,
-            }
-            .view(
-                &data.game_state,
-                context.compose_col_modify(ColModifyMap(|c: Rgb24| {
-                    c.saturating_scalar_mul_div(1, 3)
-                        .saturating_add(Rgb24::new(31, 0, 0))
-                })),
-                frame,
-            );
-            event_routine_view.view.render_ui(None, &data, context, frame);
+                view: FillBackgroundView {
+                    rgb24: Rgb24::new_grey(0),
+                    view: BorderView {
+                        style: &BorderStyle::default(),
+                        view: StringViewSingleLine::new(
+                            Style::new().with_foreground(Rgb24::new_grey(255)),
+                        ),
+                    },
+                },
+            },
         }
+        .view(
+            self.prompt,
+            context.add_offset(map_offset).add_depth(10),
+            frame,
+        );
+        view.game_view.view(
+            (&data.game_state, data.colors()),
+            context.compose_col_modify(ColModifyMap(|c: Rgb24| c.saturating_scalar_mul_div(1, 2))),
+            frame,
+        );
+        view.render_ui(None, &data, context, frame);
     }
-    Delay::new(Duration::from_millis(2000)).decorated(GameOverDecorate)
 }
 
-
 struct TargetEventRoutine {
     name: &'static str,
+    // the inventory slot of the item being aimed, whose path is previewed
+    aim_item: Option<usize>,
 }
 
 impl EventRoutine for TargetEventRoutine {
@@ -845,7 +3780,7 @@ impl EventRoutine for TargetEventRoutine {
     fn handle<EP>(
         self,
         data: &mut Self::Data,
-        _view: &Self::View,
+        view: &Self::View,
         event_or_peek: EP,
     ) -> Handled<Self::Return, Self>
     where
@@ -853,8 +3788,9 @@ impl EventRoutine for TargetEventRoutine {
     {
         event_routine::event_or_peek_with_handled(event_or_peek, self, |s, event| {
             match event {
-                CommonEvent::Input(input) => match input {
+                CommonEvent::Input(input) => match view.screen_to_map_input(input) {
                     Input::Keyboard(key) => {
+                        data.show_cursor();
                         let delta = match key {
                             KeyboardInput::Left => Coord::new(-1, 0),
                             KeyboardInput::Right => Coord::new(1, 0),
@@ -863,7 +3799,7 @@ impl EventRoutine for TargetEventRoutine {
                             keys::RETURN => {
                                 let cursor = data.cursor;
                                 data.cursor = None;
-                                return Handled::Return(cursor)
+                                return Handled::Return(cursor);
                             }
                             keys::ESCAPE => {
                                 data.cursor = None;
@@ -878,7 +3814,10 @@ impl EventRoutine for TargetEventRoutine {
                         );
                     }
                     Input::Mouse(mouse_input) => match mouse_input {
-                        MouseInput::MouseMove { coord, .. } => data.cursor = Some(coord),
+                        MouseInput::MouseMove { coord, .. } => {
+                            data.show_cursor();
+                            data.cursor = Some(coord);
+                        }
                         MouseInput::MousePress {
                             button: MouseButton::Left,
                             coord,
@@ -889,7 +3828,7 @@ impl EventRoutine for TargetEventRoutine {
                         _ => (),
                     },
                 },
-                CommonEvent::Frame(_period) => (),
+                CommonEvent::Frame(period) => data.tick_cursor_blink(period),
             };
             Handled::Continue(s)
         })
@@ -905,28 +3844,74 @@ impl EventRoutine for TargetEventRoutine {
         F: Frame,
         C: ColModify,
     {
-        view.game_view.view(&data.game_state, context, frame);
+        view.game_view
+            .view((&data.game_state, data.colors()), context, frame);
+        if let (Some(aim_item), Some(cursor)) = (self.aim_item, data.cursor) {
+            if let Some(preview) = data.game_state.aim_preview(aim_item, cursor) {
+                view.game_view
+                    .view_aim_preview(&preview, data.colors(), context, frame);
+            }
+        }
         view.render_ui(Some(self.name), &data, context, frame);
     }
 }
 
-
 #[derive(Clone, Copy, Debug)]
 enum MainMenuEntry {
     NewGame,
     Resume,
+    Theme,
+    // of the next new run
+    Difficulty,
     SaveAndQuit,
+    AbandonRun,
+    // loads the autosave of a run which crashed
+    RecoverLastRun,
+    // starts a run from the seed for today's date
+    DailyChallenge,
+    HighScores,
 }
 
-fn main_menu_instance() -> MenuInstanceChooseOrEscape<MainMenuEntry> {
+fn main_menu_instance(
+    recoverable: bool,
+    daily_challenge: bool,
+) -> MenuInstanceChooseOrEscape<MainMenuEntry> {
     use MainMenuEntry::*;
+    // named in full as Difficulty and HighScores are also types
+    let mut items = vec![
+        Resume,
+        NewGame,
+        Theme,
+        MainMenuEntry::Difficulty,
+        MainMenuEntry::HighScores,
+        SaveAndQuit,
+        AbandonRun,
+    ];
+    let mut hotkeys = hashmap![
+        'r' => Resume,
+        'n' => NewGame,
+        't' => Theme,
+        'f' => MainMenuEntry::Difficulty,
+        'h' => MainMenuEntry::HighScores,
+        'q' => SaveAndQuit,
+        'a' => AbandonRun,
+    ];
+    if recoverable {
+        items.push(RecoverLastRun);
+        hotkeys.insert('l', RecoverLastRun);
+    }
+    if daily_challenge {
+        items.insert(2, DailyChallenge);
+        hotkeys.insert('d', DailyChallenge);
+    }
     MenuInstanceBuilder {
-        items: vec![Resume, NewGame, SaveAndQuit],
-        hotkeys: Some(hashmap!['r' => Resume, 'n' => NewGame, 'q' => SaveAndQuit]),
+        items,
+        hotkeys: Some(hotkeys),
         selected_index: 0,
-    }.build()
-        .unwrap()
-        .into_choose_or_escape()
+    }
+    .build()
+    .unwrap()
+    .into_choose_or_escape()
 }
 
 #[derive(Default)]
@@ -940,7 +3925,6 @@ impl MenuIndexFromScreenCoord for MainMenuView {
     }
 }
 
-
 impl<'a> View<&'a AppData> for MainMenuView {
     fn view<F: Frame, C: ColModify>(
         &mut self,
@@ -961,9 +3945,28 @@ impl<'a> View<&'a AppData> for MainMenuView {
                 (" ", Style::new().with_foreground(Rgb24::new_grey(187)))
             };
             let text = match entry {
-                MainMenuEntry::Resume => "(r) Resume",
-                MainMenuEntry::NewGame => "(n) New Game",
-                MainMenuEntry::SaveAndQuit => "(q) Save and Quit",
+                MainMenuEntry::Resume => i18n::text("menu.resume").to_string(),
+                MainMenuEntry::NewGame => i18n::text("menu.new-game").to_string(),
+                // theme names are also what's written in config.toml, so
+                // they aren't translated
+                MainMenuEntry::Theme => {
+                    i18n::format("menu.theme", &[("theme", &data.theme_name.name())])
+                }
+                MainMenuEntry::Difficulty => i18n::format(
+                    "menu.difficulty",
+                    &[("difficulty", &i18n::difficulty_name(data.difficulty))],
+                ),
+                MainMenuEntry::SaveAndQuit => i18n::text("menu.save-and-quit").to_string(),
+                MainMenuEntry::AbandonRun => i18n::text("menu.abandon-run").to_string(),
+                MainMenuEntry::RecoverLastRun => i18n::text("menu.recover-last-run").to_string(),
+                MainMenuEntry::HighScores => i18n::text("menu.high-scores").to_string(),
+                MainMenuEntry::DailyChallenge => i18n::format(
+                    "menu.daily-challenge",
+                    &[(
+                        "date",
+                        &data.daily_challenge_date.as_deref().unwrap_or_default(),
+                    )],
+                ),
             };
             let size = StringViewSingleLine::new(style).view_size(
                 format!("{} {}", prefix, text),
@@ -975,7 +3978,6 @@ impl<'a> View<&'a AppData> for MainMenuView {
     }
 }
 
-
 struct MainMenuSelect;
 
 impl ChooseSelector for MainMenuSelect {
@@ -1007,7 +4009,6 @@ impl ViewSelector for MainMenuSelect {
     }
 }
 
-
 struct MainMenuDecorate;
 
 impl Decorate for MainMenuDecorate {
@@ -1024,6 +4025,7 @@ impl Decorate for MainMenuDecorate {
         F: Frame,
         C: ColModify,
     {
+        let map_offset = event_routine_view.view.game_view.offset;
         BoundView {
             size: data.game_state.size(),
             view: AlignView {
@@ -1043,9 +4045,10 @@ impl Decorate for MainMenuDecorate {
                     },
                 },
             },
-        }.view(data, context.add_depth(10), frame);
+        }
+        .view(data, context.add_offset(map_offset).add_depth(10), frame);
         event_routine_view.view.game_view.view(
-            &data.game_state,
+            (&data.game_state, data.colors()),
             context.compose_col_modify(ColModifyMap(|c: Rgb24| c.saturating_scalar_mul_div(1, 2))),
             frame,
         );
@@ -1061,21 +4064,56 @@ fn main_menu() -> impl EventRoutine<
     View = AppView,
     Event = CommonEvent,
 > {
-    MenuInstanceRoutine::new(MainMenuSelect)
-        .convert_input_to_common_event()
-        .decorated(MainMenuDecorate)
+    make_either!(Ei = A | B | C | D);
+    // changing the theme takes effect straight away and keeps the menu
+    // open, as does changing the difficulty, which applies to the next run.
+    // A new game needs a character made first, and escaping from making
+    // one keeps the menu open too.
+    Loop::new(|| {
+        MenuInstanceRoutine::new(MainMenuSelect)
+            .convert_input_to_common_event()
+            .decorated(MainMenuDecorate)
+            .and_then(|choice| match choice {
+                Ok(MainMenuEntry::Theme) => {
+                    Ei::A(SideEffect::new_with_view(|data: &mut AppData, _: &_| {
+                        data.theme_name = data.theme_name.next();
+                        None
+                    }))
+                }
+                Ok(MainMenuEntry::Difficulty) => {
+                    Ei::C(SideEffect::new_with_view(|data: &mut AppData, _: &_| {
+                        data.difficulty = data.difficulty.next();
+                        None
+                    }))
+                }
+                Ok(MainMenuEntry::NewGame) => Ei::D(
+                    SideEffect::new_with_view(|data: &mut AppData, _: &_| {
+                        data.new_character.clone()
+                    })
+                    .and_then(CharacterCreationEventRoutine::new)
+                    .and_then(|new_character| {
+                        SideEffect::new_with_view(move |data: &mut AppData, _: &_| {
+                            new_character.map(|new_character| {
+                                data.new_character = new_character;
+                                Ok(MainMenuEntry::NewGame)
+                            })
+                        })
+                    }),
+                ),
+                choice => Ei::B(Value::new(Some(choice))),
+            })
+    })
 }
 
 fn level_up_menu_instance() -> MenuInstanceChooseOrEscape<LevelUp> {
-    use LevelUp::*;
     MenuInstanceBuilder {
-        items: vec![Strength, Dexterity, Intelligence, Health],
+        items: LevelUp::ALL.to_vec(),
         hotkeys: None,
         selected_index: 0,
     }
     .build()
-        .unwrap()
-        .into_choose_or_escape()
+    .unwrap()
+    .into_choose_or_escape()
 }
 
 #[derive(Default)]
@@ -1108,12 +4146,7 @@ impl<'a> View<&'a AppData> for LevelUpMenuView {
             } else {
                 (" ", Style::new().with_foreground(Rgb24::new_grey(187)))
             };
-            let text = match level_up {
-                LevelUp::Strength => "Strength",
-                LevelUp::Dexterity => "Dexterity",
-                LevelUp::Intelligence => "Intelligence",
-                LevelUp::Health => "Constitution",
-            };
+            let text = i18n::stat_name(level_up);
             let size = StringViewSingleLine::new(style).view_size(
                 format!("{} {}", prefix, text),
                 context.add_offset(Coord::new(0, i as i32)),
@@ -1124,7 +4157,6 @@ impl<'a> View<&'a AppData> for LevelUpMenuView {
     }
 }
 
-
 struct LevelUpMenuSelect;
 
 impl ChooseSelector for LevelUpMenuSelect {
@@ -1156,7 +4188,6 @@ impl ViewSelector for LevelUpMenuSelect {
     }
 }
 
-
 struct LevelUpMenuDecorate;
 
 impl Decorate for LevelUpMenuDecorate {
@@ -1173,6 +4204,7 @@ impl Decorate for LevelUpMenuDecorate {
         F: Frame,
         C: ColModify,
     {
+        let map_offset = event_routine_view.view.game_view.offset;
         BoundView {
             size: data.game_state.size(),
             view: AlignView {
@@ -1181,7 +4213,7 @@ impl Decorate for LevelUpMenuDecorate {
                     rgb24: Rgb24::new_grey(0),
                     view: BorderView {
                         style: &BorderStyle {
-                            title: Some("Level Up".to_string()),
+                            title: Some(i18n::text("menu.level-up").to_string()),
                             title_style: Style::new().with_foreground(Rgb24::new_grey(255)),
                             ..Default::default()
                         },
@@ -1192,9 +4224,10 @@ impl Decorate for LevelUpMenuDecorate {
                     },
                 },
             },
-        }.view(data, context.add_depth(10), frame);
+        }
+        .view(data, context.add_offset(map_offset).add_depth(10), frame);
         event_routine_view.view.game_view.view(
-            &data.game_state,
+            (&data.game_state, data.colors()),
             context.compose_col_modify(ColModifyMap(|c: Rgb24| c.saturating_scalar_mul_div(1, 2))),
             frame,
         );
@@ -1204,7 +4237,6 @@ impl Decorate for LevelUpMenuDecorate {
     }
 }
 
-
 fn level_up_menu() -> impl EventRoutine<
     Return = Result<LevelUp, menu::Escape>,
     Data = AppData,
@@ -1215,3 +4247,117 @@ fn level_up_menu() -> impl EventRoutine<
         .convert_input_to_common_event()
         .decorated(LevelUpMenuDecorate)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::{self, Dungeon};
+
+    const MAP_SIZE: Size = Size::new_u16(40, 23);
+    const RNG_SEED: u64 = 0;
+
+    // A new run with some items to choose from. Starting it from options
+    // means no save is loaded.
+    fn new_app_data() -> AppData {
+        new_app_data_on(None)
+    }
+
+    fn new_app_data_on(first_level: Option<Dungeon>) -> AppData {
+        AppData::new(
+            MAP_SIZE,
+            RNG_SEED,
+            VisibilityAlgorithm::Shadowcast,
+            SpawnTables::built_in(),
+            ThemeName::Classic,
+            Difficulty::Normal,
+            false,
+            false,
+            Some(StartOptions {
+                dungeon_level: 1,
+                level_ups: Vec::new(),
+                items: vec![
+                    ItemType::HealthPotion,
+                    ItemType::FireballScroll,
+                    ItemType::ConfusionScroll,
+                ],
+                first_level,
+            }),
+            GameConfig { auto_pickup: false },
+            AudioConfig { volume: 0.0 },
+            None,
+            KeyBindings::built_in(),
+            None,
+        )
+    }
+
+    #[test]
+    fn the_use_item_menu_is_open_again_after_loading() {
+        let mut data = new_app_data();
+        // entry 'c'
+        const SELECTED: usize = 2;
+        data.inventory_slot_menu
+            .menu_instance_mut()
+            .set_index(SELECTED);
+        data.opening(GameReturn::UseItem);
+        let saved_game = serde_json::to_string(&data.game_state).unwrap();
+        let saved_screen = serde_json::to_string(&data.saved_screen()).unwrap();
+
+        let mut loaded = new_app_data();
+        loaded.game_state = serde_json::from_str(&saved_game).unwrap();
+        loaded.restore_screen(serde_json::from_str(&saved_screen).unwrap());
+        assert_eq!(loaded.inventory_slot_menu.menu_instance().index(), SELECTED);
+        // what the first frame opens
+        let reopened = loaded.reopen_screen.take().map(OpenScreen::game_return);
+        assert!(matches!(reopened, Some(GameReturn::UseItem)));
+    }
+
+    // The player in a corner with trolls either side, and wall out to the
+    // size of the map
+    fn cornered_by_trolls() -> Dungeon {
+        let rows = ["#####", "#@T.#", "#T..#", "#####"];
+        let mut text = String::new();
+        for y in 0..MAP_SIZE.height() as usize {
+            let row = rows.get(y).copied().unwrap_or("");
+            text.push_str(row);
+            text.push_str(&"#".repeat(MAP_SIZE.width() as usize - row.len()));
+            text.push('\n');
+        }
+        terrain::parse_level(&text).unwrap()
+    }
+
+    #[test]
+    fn a_dead_player_can_look_around_and_leave_but_not_act() {
+        const MAX_TURNS: usize = 1000;
+        let mut data = new_app_data_on(Some(cornered_by_trolls()));
+        for _ in 0..MAX_TURNS {
+            if !data.game_state.is_player_alive() {
+                break;
+            }
+            data.perform(PlayerAction::Wait);
+        }
+        assert!(!data.game_state.is_player_alive());
+        // keys pressed while the last blows are still showing wait for them
+        for _ in 0..MAX_TURNS {
+            if !data.game_state.has_animations() {
+                break;
+            }
+            data.game_state.tick_animations();
+        }
+        let turn = data.game_state.turn();
+        assert!(!data.perform(PlayerAction::Move(CardinalDirection::East)));
+        assert_eq!(data.game_state.turn(), turn);
+        let key = |c| Input::Keyboard(KeyboardInput::Char(c));
+        assert!(matches!(
+            data.handle_input(key('x')),
+            Some(GameReturn::Examine)
+        ));
+        assert!(matches!(
+            data.handle_input(key('C')),
+            Some(GameReturn::Statistics)
+        ));
+        assert!(matches!(
+            data.handle_input(Input::Keyboard(keys::ESCAPE)),
+            Some(GameReturn::GameOver)
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+// audio.rs
+
+// Sound effects for game events. Built with the audio feature, each event
+// with a sound plays sounds/NAME.ogg from next to the executable. Without
+// the feature, or without an audio device, nothing is played. Any sound
+// file which is missing is skipped.
+
+use crate::game::GameEvent;
+
+#[cfg(feature = "audio")]
+use chargrid_roguelike_tutorial_2020::{log_debug, log_info};
+#[cfg(feature = "audio")]
+use rodio::{OutputStream, OutputStreamHandle};
+#[cfg(feature = "audio")]
+use std::collections::HashMap;
+#[cfg(feature = "audio")]
+use std::io::Cursor;
+#[cfg(feature = "audio")]
+use std::sync::Arc;
+
+#[cfg(feature = "audio")]
+use crate::world::ItemType;
+
+#[cfg(feature = "audio")]
+const SOUND_DIR: &str = "sounds";
+#[cfg(feature = "audio")]
+const SOUND_NAMES: [&str; 13] = [
+    "player-hurt",
+    "melee-hit",
+    "melee-miss",
+    "npc-death",
+    "player-death",
+    "pick-up",
+    "drink",
+    "read-scroll",
+    "explosion",
+    "door-open",
+    "door-close",
+    "stairs",
+    "fall",
+];
+
+// The sound for an event, as one of SOUND_NAMES
+#[cfg(feature = "audio")]
+fn sound_name(event: GameEvent) -> Option<&'static str> {
+    match event {
+        GameEvent::PlayerDamaged => Some("player-hurt"),
+        GameEvent::MeleeHit => Some("melee-hit"),
+        GameEvent::MeleeMiss => Some("melee-miss"),
+        GameEvent::NpcDied(_) => Some("npc-death"),
+        GameEvent::PlayerDied => Some("player-death"),
+        GameEvent::ItemPickedUp(_) => Some("pick-up"),
+        GameEvent::ItemUsed(ItemType::HealthPotion) => Some("drink"),
+        GameEvent::ItemUsed(ItemType::FireballScroll)
+        | GameEvent::ItemUsed(ItemType::ConfusionScroll)
+        | GameEvent::ItemUsed(ItemType::ClairvoyanceScroll) => Some("read-scroll"),
+        GameEvent::ExplosionAt(_) => Some("explosion"),
+        GameEvent::DoorOpened(_) => Some("door-open"),
+        GameEvent::DoorClosed(_) => Some("door-close"),
+        GameEvent::StairsDescended | GameEvent::StairsAscended => Some("stairs"),
+        GameEvent::PlayerFell => Some("fall"),
+        // the melee hit already has a sound
+        GameEvent::KillingBlow(_) | GameEvent::ItemUsed(_) => None,
+    }
+}
+
+#[cfg(feature = "audio")]
+struct Output {
+    // sounds stop when this is dropped
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
+    sounds: HashMap<&'static str, Arc<[u8]>>,
+}
+
+#[cfg(feature = "audio")]
+pub struct Audio {
+    output: Option<Output>,
+    // from 0 to 1
+    volume: f32,
+}
+
+#[cfg(feature = "audio")]
+impl Audio {
+    pub fn new(volume: f32) -> Self {
+        let output = if volume > 0.0 {
+            Self::open_output()
+        } else {
+            None
+        };
+        Self { output, volume }
+    }
+
+    fn open_output() -> Option<Output> {
+        let (stream, handle) = match OutputStream::try_default() {
+            Ok(output) => output,
+            Err(error) => {
+                log_info!("Playing without sound: {}", error);
+                return None;
+            }
+        };
+        let sound_dir = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.join(SOUND_DIR)))?;
+        let mut sounds = HashMap::new();
+        for name in SOUND_NAMES {
+            let path = sound_dir.join(format!("{}.ogg", name));
+            match std::fs::read(&path) {
+                Ok(bytes) => {
+                    sounds.insert(name, Arc::from(bytes));
+                }
+                Err(error) => log_debug!("No sound {:?}: {}", path, error),
+            }
+        }
+        Some(Output {
+            _stream: stream,
+            handle,
+            sounds,
+        })
+    }
+
+    pub fn play(&mut self, event: GameEvent) {
+        let output = match self.output.as_ref() {
+            Some(output) => output,
+            None => return,
+        };
+        let bytes = match sound_name(event).and_then(|name| output.sounds.get(name)) {
+            Some(bytes) => bytes.clone(),
+            None => return,
+        };
+        // the sound plays on after the sink is detached
+        match output.handle.play_once(Cursor::new(bytes)) {
+            Ok(sink) => {
+                sink.set_volume(self.volume);
+                sink.detach();
+            }
+            Err(error) => log_debug!("Failed to play a sound for {:?}: {}", event, error),
+        }
+    }
+}
+
+#[cfg(not(feature = "audio"))]
+pub struct Audio;
+
+#[cfg(not(feature = "audio"))]
+impl Audio {
+    pub fn new(_volume: f32) -> Self {
+        Self
+    }
+
+    pub fn play(&mut self, _event: GameEvent) {}
+}
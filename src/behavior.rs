@@ -1,59 +1,324 @@
 // behavior.rs
 
+use crate::visibility::{CellVisibility, VisibilityGrid};
 use crate::world::World;
 use coord_2d::{Coord, Size};
 use direction::CardinalDirection;
 use entity_table::Entity;
+use grid_2d::Grid;
 use grid_search_cardinal::{
     distance_map::{
-        DistanceMap,
-        PopulateContext as DistanceMapPopulateContext,
+        DistanceMap, PopulateContext as DistanceMapPopulateContext,
         SearchContext as DistanceMapSearchContext,
     },
     CanEnter,
 };
 use line_2d::LineSegment;
-use serde::{Serialize, Deserialize};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use shadowcast::{vision_distance, VisionDistance};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet, VecDeque};
 
+// The cost of walking from each cell to the nearest of a set of goals,
+// where each cell costs what `step_cost` says to enter. Walking downhill
+// approaches the goals and walking uphill flees them. Cells further than
+// the maximum cost, or which can't be entered, have no distance.
+#[derive(Serialize, Deserialize)]
+pub struct DistanceField {
+    distances: Grid<Option<u32>>,
+}
+
+impl DistanceField {
+    pub fn new(size: Size) -> Self {
+        Self {
+            distances: Grid::new_copy(size, None),
+        }
+    }
+
+    pub fn populate<F: Fn(Coord) -> Option<u32>>(
+        &mut self,
+        goals: &[Coord],
+        step_cost: F,
+        max_cost: u32,
+    ) {
+        for distance in self.distances.iter_mut() {
+            *distance = None;
+        }
+        let mut queue = BinaryHeap::new();
+        for &goal in goals {
+            if let Some(distance) = self.distances.get_mut(goal) {
+                *distance = Some(0);
+                queue.push(Reverse((0, goal.x, goal.y)));
+            }
+        }
+        while let Some(Reverse((cost, x, y))) = queue.pop() {
+            let coord = Coord::new(x, y);
+            // a cheaper way here was found after this entry was queued
+            if self.distance(coord) != Some(cost) {
+                continue;
+            }
+            for direction in CardinalDirection::all() {
+                let neighbour = coord + direction.coord();
+                if !neighbour.is_valid(self.distances.size()) {
+                    continue;
+                }
+                let neighbour_cost = match step_cost(neighbour) {
+                    Some(step_cost) => cost + step_cost,
+                    None => continue,
+                };
+                let distance = self.distances.get_checked_mut(neighbour);
+                if neighbour_cost <= max_cost
+                    && distance.map_or(true, |distance| neighbour_cost < distance)
+                {
+                    *distance = Some(neighbour_cost);
+                    queue.push(Reverse((neighbour_cost, neighbour.x, neighbour.y)));
+                }
+            }
+        }
+    }
+
+    pub fn distance(&self, coord: Coord) -> Option<u32> {
+        self.distances.get(coord).cloned().flatten()
+    }
+
+    // A step from `coord` into the enterable neighbour nearest the goals,
+    // if it's nearer than `coord` is. Equally good steps are chosen
+    // between at random. When every nearer neighbour is occupied, the
+    // step is the first of a short detour to somewhere nearer, so an NPC
+    // steps around another in its way rather than waiting behind it.
+    pub fn step_towards<R: Rng>(
+        &self,
+        coord: Coord,
+        can_enter: impl Fn(Coord) -> bool,
+        rng: &mut R,
+    ) -> Option<CardinalDirection> {
+        let current = self.distance(coord)?;
+        self.best_step(coord, &can_enter, rng, |distance| {
+            (distance < current).then(|| distance)
+        })
+        .or_else(|| self.detour(coord, current, &can_enter))
+    }
+
+    // The first step of the shortest walk through enterable cells to a
+    // cell nearer than `current`, if there's one within MAX_DETOUR_STEPS
+    fn detour(
+        &self,
+        start: Coord,
+        current: u32,
+        can_enter: impl Fn(Coord) -> bool,
+    ) -> Option<CardinalDirection> {
+        const MAX_DETOUR_STEPS: u32 = 5;
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        visited.insert(start);
+        queue.push_back((start, 0, None));
+        while let Some((coord, steps, first_step)) = queue.pop_front() {
+            if first_step.is_some() && self.distance(coord).map_or(false, |d| d < current) {
+                return first_step;
+            }
+            if steps == MAX_DETOUR_STEPS {
+                continue;
+            }
+            for direction in CardinalDirection::all() {
+                let neighbour = coord + direction.coord();
+                if can_enter(neighbour) && visited.insert(neighbour) {
+                    queue.push_back((neighbour, steps + 1, first_step.or(Some(direction))));
+                }
+            }
+        }
+        None
+    }
+
+    // A step from `coord` into the enterable neighbour furthest from the
+    // goals, if it's further than `coord` is. Cells out of reach of the
+    // goals count as furthest of all.
+    pub fn step_away<R: Rng>(
+        &self,
+        coord: Coord,
+        can_enter: impl Fn(Coord) -> bool,
+        rng: &mut R,
+    ) -> Option<CardinalDirection> {
+        let current = self.distance(coord).unwrap_or(u32::MAX);
+        self.best_step(coord, can_enter, rng, |distance| {
+            (distance > current).then(|| u32::MAX - distance)
+        })
+    }
+
+    // The step with the lowest score, where `score` turns a neighbour's
+    // distance into None for steps not worth taking
+    fn best_step<R: Rng>(
+        &self,
+        coord: Coord,
+        can_enter: impl Fn(Coord) -> bool,
+        rng: &mut R,
+        score: impl Fn(u32) -> Option<u32>,
+    ) -> Option<CardinalDirection> {
+        let scored = CardinalDirection::all()
+            .filter(|direction| can_enter(coord + direction.coord()))
+            .filter_map(|direction| {
+                let distance = self.distance(coord + direction.coord()).unwrap_or(u32::MAX);
+                score(distance).map(|score| (direction, score))
+            })
+            .collect::<Vec<_>>();
+        let best = scored.iter().map(|&(_, score)| score).min()?;
+        scored
+            .iter()
+            .filter(|&&(_, score)| score == best)
+            .map(|&(direction, _)| direction)
+            .collect::<Vec<_>>()
+            .choose(rng)
+            .cloned()
+    }
+}
 
+// Worked out once per turn and shared by every NPC
 #[derive(Serialize, Deserialize)]
 pub struct BehaviorContext {
-    distance_map_to_player: DistanceMap,
+    distances_to_player: DistanceField,
+}
+
+impl BehaviorContext {
+    pub fn new(size: Size) -> Self {
+        Self {
+            distances_to_player: DistanceField::new(size),
+        }
+    }
+
+    pub fn update(&mut self, player: Entity, world: &World) {
+        let player_coord = world.entity_coord(player).expect("player has no coord");
+        const MAX_APPROACH_COST: u32 = 20;
+        self.distances_to_player.populate(
+            &[player_coord],
+            |coord| world.npc_step_cost(coord),
+            MAX_APPROACH_COST,
+        );
+    }
+
+    // How far an NPC would have to walk to reach the player, as of the
+    // start of the NPCs' turn
+    pub fn distances_to_player(&self) -> &DistanceField {
+        &self.distances_to_player
+    }
+}
+
+// Cells the player knows of and could walk through, ignoring NPCs
+struct PlayerCanTravel<'a> {
+    world: &'a World,
+    visibility_grid: &'a VisibilityGrid,
+}
+
+impl<'a> CanEnter for PlayerCanTravel<'a> {
+    fn can_enter(&self, coord: Coord) -> bool {
+        !matches!(
+            self.visibility_grid.cell_visibility(coord),
+            CellVisibility::Never
+        ) && self.world.can_npc_enter_ignoring_other_npcs(coord)
+    }
+}
+
+const MAX_TRAVEL_DISTANCE: u32 = 100;
+
+// Finds routes for the player to travel along when they click on the
+// map, using the same distance maps as NPCs. Only cells the player has
+// seen are considered.
+#[derive(Serialize, Deserialize)]
+pub struct TravelContext {
+    distance_map_to_goal: DistanceMap,
     distance_map_populate_context: DistanceMapPopulateContext,
     distance_map_search_context: DistanceMapSearchContext,
 }
 
-impl BehaviorContext {
+impl TravelContext {
     pub fn new(size: Size) -> Self {
         Self {
-            distance_map_to_player: DistanceMap::new(size),
+            distance_map_to_goal: DistanceMap::new(size),
             distance_map_populate_context: DistanceMapPopulateContext::default(),
             distance_map_search_context: DistanceMapSearchContext::new(size),
         }
     }
 
-    pub fn update(&mut self, player: Entity, world: &World) {
-        struct NpcCanEnterIgnoringOtherNpcs<'a> {
-            world: &'a World,
+    // The cells the player would step through to reach `goal`, ending
+    // with the goal itself. None if they know of no way there.
+    pub fn path(
+        &mut self,
+        start: Coord,
+        goal: Coord,
+        world: &World,
+        visibility_grid: &VisibilityGrid,
+    ) -> Option<Vec<Coord>> {
+        let can_travel = PlayerCanTravel {
+            world,
+            visibility_grid,
+        };
+        if start == goal || !can_travel.can_enter(goal) {
+            return None;
         }
-        impl<'a> CanEnter for NpcCanEnterIgnoringOtherNpcs<'a> {
-            fn can_enter(&self, coord: Coord) -> bool {
-                self.world.can_npc_enter_ignoring_other_npcs(coord)
-            }
+        self.distance_map_populate_context.add(goal);
+        self.distance_map_populate_context.populate_approach(
+            &can_travel,
+            MAX_TRAVEL_DISTANCE,
+            &mut self.distance_map_to_goal,
+        );
+        let mut path = Vec::new();
+        let mut coord = start;
+        while coord != goal && path.len() < MAX_TRAVEL_DISTANCE as usize {
+            let direction = self.distance_map_search_context.search_first(
+                &can_travel,
+                coord,
+                1,
+                &self.distance_map_to_goal,
+            )?;
+            coord = coord + direction.coord();
+            path.push(coord);
+        }
+        if coord == goal {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    // The first step along the shortest known route to whichever of
+    // `goals` is nearest
+    pub fn first_step(
+        &mut self,
+        start: Coord,
+        goals: &[Coord],
+        world: &World,
+        visibility_grid: &VisibilityGrid,
+    ) -> Option<CardinalDirection> {
+        let can_travel = PlayerCanTravel {
+            world,
+            visibility_grid,
+        };
+        let goals = goals
+            .iter()
+            .cloned()
+            .filter(|&goal| goal != start && can_travel.can_enter(goal))
+            .collect::<Vec<_>>();
+        if goals.is_empty() {
+            return None;
+        }
+        for goal in goals {
+            self.distance_map_populate_context.add(goal);
         }
-        let player_coord = world.entity_coord(player).expect("player has no coord");
-        const MAX_APPROACH_DISTANCE: u32 = 20;
-        self.distance_map_populate_context.add(player_coord);
         self.distance_map_populate_context.populate_approach(
-            &NpcCanEnterIgnoringOtherNpcs { world },
-            MAX_APPROACH_DISTANCE,
-            &mut self.distance_map_to_player,
+            &can_travel,
+            MAX_TRAVEL_DISTANCE,
+            &mut self.distance_map_to_goal,
         );
+        self.distance_map_search_context.search_first(
+            &can_travel,
+            start,
+            1,
+            &self.distance_map_to_goal,
+        )
     }
 }
-        
 
+#[derive(Debug)]
 pub enum NpcAction {
     Wait,
     Move(CardinalDirection),
@@ -62,33 +327,65 @@ pub enum NpcAction {
 #[derive(Serialize, Deserialize)]
 pub struct Agent {
     turns_since_last_saw_player: u32,
+    // the direction the agent last moved in, which is the way it's looking
+    facing: CardinalDirection,
 }
 
 impl Agent {
-    pub fn new() -> Self {
+    pub fn new(facing: CardinalDirection) -> Self {
         Self {
             turns_since_last_saw_player: u32::MAX,
+            facing,
         }
     }
 
-    pub fn act(
+    // True if the agent saw the player on its most recent turn
+    pub fn has_noticed_player(&self) -> bool {
+        self.turns_since_last_saw_player == 0
+    }
+
+    // Makes the agent chase the player as though it had just seen them.
+    pub fn alert(&mut self) {
+        self.turns_since_last_saw_player = 0;
+    }
+
+    // Agents too far from the player to walk to them are inactive. They
+    // don't look for the player, and only wander a step every
+    // WANDER_PERIOD turns, until the player comes within reach.
+    pub fn act<R: Rng>(
         &mut self,
         entity: Entity,
         player: Entity,
         world: &World,
-        behavior_context: &mut BehaviorContext,
+        behavior_context: &BehaviorContext,
+        turn: u64,
+        rng: &mut R,
     ) -> NpcAction {
-        struct NpcCanEnter<'a> {
-            world: &'a World,
-        }
-        impl<'a> CanEnter for NpcCanEnter<'a> {
-            fn can_enter(&self, coord: Coord) -> bool {
-                self.world.can_npc_enter(coord)
+        const WANDER_PERIOD: u64 = 8;
+        let npc_coord = world.entity_coord(entity).expect("npc has no coord");
+        if behavior_context
+            .distances_to_player
+            .distance(npc_coord)
+            .is_none()
+        {
+            self.turns_since_last_saw_player = self.turns_since_last_saw_player.saturating_add(1);
+            if turn % WANDER_PERIOD != 0 {
+                return NpcAction::Wait;
             }
+            let directions = CardinalDirection::all()
+                .filter(|direction| world.can_npc_enter(npc_coord + direction.coord()))
+                .collect::<Vec<_>>();
+            return match directions.choose(rng) {
+                None => NpcAction::Wait,
+                Some(&direction) => {
+                    self.facing = direction;
+                    NpcAction::Move(direction)
+                }
+            };
         }
-        let npc_coord = world.entity_coord(entity).expect("npc has no coord");
         let player_coord = world.entity_coord(player).expect("player has no coord");
-        if npc_has_line_of_sight(npc_coord, player_coord, world) {
+        let detection_range = world.npc_detection_range(entity, player);
+        if npc_can_perceive(npc_coord, self.facing, player_coord, detection_range, world) {
             self.turns_since_last_saw_player = 0;
         } else {
             self.turns_since_last_saw_player = self.turns_since_last_saw_player.saturating_add(1);
@@ -96,39 +393,252 @@ impl Agent {
         const MAX_TURNS_TO_CHASE_PLAYER_AFTER_LOSING_SIGHT: u32 = 3;
         if self.turns_since_last_saw_player > MAX_TURNS_TO_CHASE_PLAYER_AFTER_LOSING_SIGHT {
             return NpcAction::Wait;
-        }        
-        const SEARCH_DISTANCE: u32 = 5;
-        match behavior_context.distance_map_search_context.search_first(
-            &NpcCanEnter { world },
+        }
+        match behavior_context.distances_to_player.step_towards(
             npc_coord,
-            SEARCH_DISTANCE,
-            &behavior_context.distance_map_to_player,
+            |coord| world.can_npc_enter(coord),
+            rng,
         ) {
             None => NpcAction::Wait,
-            Some(direction) => NpcAction::Move(direction),
+            Some(direction) => {
+                self.facing = direction;
+                NpcAction::Move(direction)
+            }
         }
     }
 }
 
+// NPCs always notice an adjacent player, and otherwise only see within a
+// 90 degree cone in front of them
+fn npc_can_perceive(
+    src: Coord,
+    facing: CardinalDirection,
+    dst: Coord,
+    range: u32,
+    world: &World,
+) -> bool {
+    let delta = dst - src;
+    if delta.x.abs() <= 1 && delta.y.abs() <= 1 {
+        return true;
+    }
+    if world.coord_contains_fog(dst) {
+        return false;
+    }
+    let forward = facing.coord();
+    let ahead = delta.x * forward.x + delta.y * forward.y;
+    let across = (delta.x * forward.y - delta.y * forward.x).abs();
+    ahead >= across && npc_has_line_of_sight(src, dst, range, world)
+}
 
-fn npc_has_line_of_sight(src: Coord, dst: Coord, world: &World) -> bool {
-    const NPC_VISION_DISTANCE_SQUARED: u32 = 100;
-    const NPC_VISION_DISTANCE: vision_distance::Circle =
-        vision_distance::Circle::new_squared(NPC_VISION_DISTANCE_SQUARED);
+fn npc_has_line_of_sight(src: Coord, dst: Coord, range: u32, world: &World) -> bool {
+    let npc_vision_distance = vision_distance::Circle::new_squared(range * range);
     if src == dst {
         return true;
     }
 
     for coord in LineSegment::new(src, dst).iter() {
         let src_to_coord = coord - src;
-        if !NPC_VISION_DISTANCE.in_range(src_to_coord) {
+        if !npc_vision_distance.in_range(src_to_coord) {
             return false;
         }
-        if !world.can_npc_see_through_cell(coord) {
+        // Like the player's view, opaque cells are themselves visible
+        // but block sight beyond them
+        if coord != src && coord != dst && !world.can_npc_see_through_cell(coord) {
             return false;
         }
     }
     true
 }
 
-        
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::{self, TerrainTile};
+    use crate::world::{Populate, StartingKit};
+    use rand::SeedableRng;
+    use rand_isaac::Isaac64Rng;
+
+    // A map where G is the goal, # is wall, ~ costs two steps to enter,
+    // o is an NPC in the way and A is the NPC being moved
+    fn char_grid(map: &str) -> Grid<char> {
+        let rows = map.lines().collect::<Vec<_>>();
+        let size = Size::new(rows[0].len() as u32, rows.len() as u32);
+        Grid::new_fn(size, |coord| {
+            rows[coord.y as usize]
+                .chars()
+                .nth(coord.x as usize)
+                .unwrap()
+        })
+    }
+
+    fn distance_field(grid: &Grid<char>, max_cost: u32) -> DistanceField {
+        let goals = grid
+            .enumerate()
+            .filter(|&(_, &c)| c == 'G')
+            .map(|(coord, _)| coord)
+            .collect::<Vec<_>>();
+        let mut distance_field = DistanceField::new(grid.size());
+        distance_field.populate(
+            &goals,
+            |coord| match *grid.get_checked(coord) {
+                '#' => None,
+                '~' => Some(2),
+                _ => Some(1),
+            },
+            max_cost,
+        );
+        distance_field
+    }
+
+    // Where A steps, trying every seed for the tie-breaking RNG to
+    // check it always steps the same way
+    fn step(map: &str) -> Option<CardinalDirection> {
+        let grid = char_grid(map);
+        let distance_field = distance_field(&grid, u32::MAX);
+        let start = grid
+            .enumerate()
+            .find(|&(_, &c)| c == 'A')
+            .map(|(coord, _)| coord)
+            .unwrap();
+        let can_enter = |coord: Coord| matches!(grid.get(coord), Some(&c) if c != '#' && c != 'o');
+        let steps = (0..8)
+            .map(|seed| {
+                let mut rng = Isaac64Rng::seed_from_u64(seed);
+                distance_field.step_towards(start, can_enter, &mut rng)
+            })
+            .collect::<Vec<_>>();
+        assert!(steps.iter().all(|&step| step == steps[0]));
+        steps[0]
+    }
+
+    #[test]
+    fn distances_add_up_the_cost_of_each_step() {
+        let distance_field = distance_field(&char_grid("G.~.#."), u32::MAX);
+        let distances = (0..6)
+            .map(|x| distance_field.distance(Coord::new(x, 0)))
+            .collect::<Vec<_>>();
+        assert_eq!(distances, [Some(0), Some(1), Some(3), Some(4), None, None]);
+    }
+
+    #[test]
+    fn distances_stop_at_the_maximum_cost() {
+        let distance_field = distance_field(&char_grid("G...."), 2);
+        assert_eq!(distance_field.distance(Coord::new(2, 0)), Some(2));
+        assert_eq!(distance_field.distance(Coord::new(3, 0)), None);
+    }
+
+    #[test]
+    fn npcs_walk_around_walls() {
+        let map = "\
+G...
+###.
+A...";
+        assert_eq!(step(map), Some(CardinalDirection::East));
+    }
+
+    #[test]
+    fn npcs_take_another_step_downhill_when_the_best_is_occupied() {
+        let map = "\
+G...
+..oA";
+        assert_eq!(step(map), Some(CardinalDirection::North));
+    }
+
+    #[test]
+    fn npcs_step_around_another_in_a_wide_corridor() {
+        let map = "\
+G...oA
+......";
+        assert_eq!(step(map), Some(CardinalDirection::South));
+    }
+
+    #[test]
+    fn npcs_wait_behind_another_in_a_narrow_corridor() {
+        assert_eq!(step("G..oA"), None);
+    }
+
+    // An orc on the far side of a door from the player, with the distance
+    // field the NPCs' turn would use
+    fn orc_behind(door: TerrainTile) -> (World, BehaviorContext, Coord) {
+        let mut dungeon = terrain::parse_level(
+            "\
+#######
+#@.+o.#
+#######",
+        )
+        .unwrap();
+        let door_coord = Coord::new(3, 1);
+        *dungeon.grid.get_checked_mut(door_coord) = door;
+        let mut world = World::new(dungeon.grid.size());
+        let Populate { player_entity, .. } = world.populate_from(
+            dungeon,
+            1,
+            &StartingKit::default(),
+            &mut Isaac64Rng::seed_from_u64(0),
+        );
+        let mut behavior_context = BehaviorContext::new(world.size());
+        behavior_context.update(player_entity, &world);
+        (world, behavior_context, Coord::new(4, 1))
+    }
+
+    #[test]
+    fn npcs_path_through_closed_doors() {
+        let (world, behavior_context, orc_coord) = orc_behind(TerrainTile::DoorClosed);
+        let distances = behavior_context.distances_to_player();
+        // the door takes a turn to open
+        assert_eq!(distances.distance(orc_coord), Some(4));
+        let step = distances.step_towards(
+            orc_coord,
+            |coord| world.can_npc_enter(coord),
+            &mut Isaac64Rng::seed_from_u64(0),
+        );
+        assert_eq!(step, Some(CardinalDirection::West));
+    }
+
+    #[test]
+    fn npcs_never_path_through_secret_doors() {
+        let (world, behavior_context, orc_coord) = orc_behind(TerrainTile::SecretDoor);
+        let distances = behavior_context.distances_to_player();
+        assert_eq!(distances.distance(Coord::new(3, 1)), None);
+        assert_eq!(distances.distance(orc_coord), None);
+        let step = distances.step_towards(
+            orc_coord,
+            |coord| world.can_npc_enter(coord),
+            &mut Isaac64Rng::seed_from_u64(0),
+        );
+        assert_eq!(step, None);
+    }
+
+    #[test]
+    fn an_inactive_agent_activates_when_the_player_comes_within_reach() {
+        // the orc starts further along the corridor than NPCs look for
+        // the player
+        let corridor = format!("#@{}o#", ".".repeat(28));
+        let wall = "#".repeat(corridor.len());
+        let level = format!("{}\n{}\n{}", wall, corridor, wall);
+        let mut world = World::new(Size::new(corridor.len() as u32, 3));
+        let Populate {
+            player_entity,
+            mut ai_state,
+            ..
+        } = world.populate_from(
+            terrain::parse_level(&level).unwrap(),
+            1,
+            &StartingKit::default(),
+            &mut Isaac64Rng::seed_from_u64(0),
+        );
+        let orc = ai_state.entities().next().unwrap();
+        let agent = ai_state.get_mut(orc).unwrap();
+        // even a noise doesn't wake it while it's out of reach
+        agent.alert();
+        let mut behavior_context = BehaviorContext::new(world.size());
+        behavior_context.update(player_entity, &world);
+        let mut rng = Isaac64Rng::seed_from_u64(0);
+        let action = agent.act(orc, player_entity, &world, &behavior_context, 1, &mut rng);
+        assert!(matches!(action, NpcAction::Wait));
+        world.move_character_to(player_entity, Coord::new(20, 1));
+        behavior_context.update(player_entity, &world);
+        let action = agent.act(orc, player_entity, &world, &behavior_context, 2, &mut rng);
+        assert!(matches!(action, NpcAction::Move(CardinalDirection::West)));
+    }
+}
@@ -0,0 +1,138 @@
+// bot.rs
+
+// A simple player for soak testing: it fights whatever is next to it,
+// drinks a potion when badly hurt, picks things up, explores, and takes
+// the stairs down when it finds them. It makes no random choices, so a
+// seed always plays out the same way.
+
+use crate::difficulty::Difficulty;
+use crate::game::{ActionResult, GameState, LevelUp, NewCharacter, PlayerAction};
+use crate::terrain::SpawnTables;
+use crate::visibility::VisibilityAlgorithm;
+use crate::world::ItemType;
+use coord_2d::Size;
+use std::fmt;
+
+// The bot drinks a potion when its hit points fall below this
+// percentage of the maximum
+const DRINK_POTION_PERCENT: u32 = 30;
+
+// Opening doors and descending don't take a turn, so give up on a
+// choice which keeps not taking one
+const MAX_FREE_ACTIONS_IN_A_ROW: u32 = 10;
+
+pub struct BotSummary {
+    pub rng_seed: u64,
+    pub dungeon_level: u32,
+    pub turn: u64,
+    pub kills: u32,
+    pub survived: bool,
+    // GameState::checksum at the end, to compare the same seed between
+    // platforms and builds
+    pub checksum: u64,
+}
+
+impl fmt::Display for BotSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "seed {}: {} on level {} after {} turns with {} kills (checksum {:016x})",
+            self.rng_seed,
+            if self.survived { "survived" } else { "died" },
+            self.dungeon_level,
+            self.turn,
+            self.kills,
+            self.checksum,
+        )
+    }
+}
+
+// Plays a game until the player dies or `max_turns` have passed
+pub fn play(
+    rng_seed: u64,
+    max_turns: u64,
+    map_size: Size,
+    spawn_tables: SpawnTables,
+) -> BotSummary {
+    let visibility_algorithm = VisibilityAlgorithm::Shadowcast;
+    let mut game_state = GameState::new(
+        map_size,
+        rng_seed,
+        visibility_algorithm,
+        spawn_tables,
+        Difficulty::Normal,
+        NewCharacter::default(),
+    );
+    let mut free_actions = 0;
+    while game_state.turn() < max_turns {
+        let turn = game_state.turn();
+        let mut result = ActionResult::Refused;
+        if free_actions < MAX_FREE_ACTIONS_IN_A_ROW {
+            // try each choice in order until one of them works
+            for action in choose_actions(&mut game_state) {
+                result = game_state.act(action, visibility_algorithm);
+                if result != ActionResult::Refused {
+                    break;
+                }
+            }
+        }
+        if result == ActionResult::Refused {
+            result = game_state.act(PlayerAction::Wait, visibility_algorithm);
+        }
+        if result == ActionResult::PlayerDied {
+            break;
+        }
+        if game_state.turn() == turn {
+            free_actions += 1;
+        } else {
+            free_actions = 0;
+        }
+    }
+    BotSummary {
+        rng_seed,
+        dungeon_level: game_state.dungeon_level(),
+        turn: game_state.turn(),
+        kills: game_state.run_stats().total_kills(),
+        survived: game_state.is_player_alive(),
+        checksum: game_state.checksum(),
+    }
+}
+
+// The things the bot would like to do this turn, most important first
+fn choose_actions(game_state: &mut GameState) -> Vec<PlayerAction> {
+    let mut actions = Vec::new();
+    let hit_points = game_state.player_hit_points();
+    if hit_points.current() * 100 < hit_points.max() * DRINK_POTION_PERCENT {
+        if let Some(inventory_index) = potion_inventory_index(game_state) {
+            actions.push(PlayerAction::Use(inventory_index));
+        }
+    }
+    if let Some(direction) = game_state.adjacent_hostile_npc_direction() {
+        actions.push(PlayerAction::Move(direction));
+    }
+    if game_state.is_player_on_stairs() {
+        actions.push(PlayerAction::Descend(LevelUp::Health));
+    }
+    if game_state.is_item_under_player() {
+        actions.push(PlayerAction::Get);
+    }
+    if let Some(direction) = game_state.explore_direction() {
+        actions.push(PlayerAction::Move(direction));
+    }
+    if let Some(stairs_coord) = game_state.known_stairs_coord() {
+        if let Some(direction) = game_state.first_step_towards(&[stairs_coord]) {
+            actions.push(PlayerAction::Move(direction));
+        }
+    }
+    actions
+}
+
+fn potion_inventory_index(game_state: &GameState) -> Option<usize> {
+    game_state
+        .player_inventory()
+        .slots()
+        .iter()
+        .position(|slot| {
+            slot.and_then(|entity| game_state.item_type(entity)) == Some(ItemType::HealthPotion)
+        })
+}
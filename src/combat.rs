@@ -0,0 +1,129 @@
+// combat.rs
+
+// How much damage a bump attack does, worked out from the attacker's
+// damage range and the victim's defense range alone. Real attacks roll
+// within the ranges, and the examine forecast counts every possible
+// roll, so the forecast can't disagree with what actually happens.
+
+// The damage done when the attacker and victim roll these far above the
+// least of their ranges. No damage means the victim dodged.
+pub fn net_damage(
+    attack: (i32, i32),
+    defense: (i32, i32),
+    attack_roll: i32,
+    defense_roll: i32,
+) -> u32 {
+    let gross_damage = attack.0 + attack_roll;
+    let damage_reduction = defense.0 + defense_roll;
+    gross_damage.saturating_sub(damage_reduction).max(0) as u32
+}
+
+// How many different rolls there are in a range, each equally likely
+pub fn num_rolls(range: (i32, i32)) -> i32 {
+    range.1 - range.0 + 1
+}
+
+// What one character attacking another can expect
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CombatForecast {
+    // rounded to the nearest percent
+    pub hit_percent: u32,
+    // the least and most damage done by a hit which isn't dodged
+    pub min_damage: u32,
+    pub max_damage: u32,
+}
+
+pub fn forecast(attack: (i32, i32), defense: (i32, i32)) -> CombatForecast {
+    let num_outcomes = num_rolls(attack).max(0) as u32 * num_rolls(defense).max(0) as u32;
+    let mut num_hits = 0;
+    let mut min_damage = u32::MAX;
+    let mut max_damage = 0;
+    for attack_roll in 0..num_rolls(attack) {
+        for defense_roll in 0..num_rolls(defense) {
+            let damage = net_damage(attack, defense, attack_roll, defense_roll);
+            if damage > 0 {
+                num_hits += 1;
+                min_damage = min_damage.min(damage);
+                max_damage = max_damage.max(damage);
+            }
+        }
+    }
+    if num_hits == 0 {
+        return CombatForecast {
+            hit_percent: 0,
+            min_damage: 0,
+            max_damage: 0,
+        };
+    }
+    CombatForecast {
+        hit_percent: (num_hits * 100 + num_outcomes / 2) / num_outcomes,
+        min_damage,
+        max_damage,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn damage_is_what_gets_past_the_defense() {
+        assert_eq!(net_damage((1, 3), (0, 2), 0, 0), 1);
+        assert_eq!(net_damage((1, 3), (0, 2), 2, 0), 3);
+        assert_eq!(net_damage((1, 3), (0, 2), 2, 1), 2);
+        // never negative
+        assert_eq!(net_damage((1, 3), (0, 2), 0, 2), 0);
+        assert_eq!(net_damage((0, 0), (5, 5), 0, 0), 0);
+    }
+
+    #[test]
+    fn each_whole_number_in_a_range_is_a_roll() {
+        assert_eq!(num_rolls((0, 0)), 1);
+        assert_eq!(num_rolls((2, 5)), 4);
+    }
+
+    #[test]
+    fn an_attack_which_always_gets_through_always_hits() {
+        assert_eq!(
+            forecast((2, 4), (0, 1)),
+            CombatForecast {
+                hit_percent: 100,
+                min_damage: 1,
+                max_damage: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn the_hit_chance_counts_the_rolls_which_get_through() {
+        // 3 of the 6 rolls get through
+        assert_eq!(
+            forecast((1, 2), (0, 2)),
+            CombatForecast {
+                hit_percent: 50,
+                min_damage: 1,
+                max_damage: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn the_hit_chance_is_rounded_to_the_nearest_percent() {
+        // 1 in 3
+        assert_eq!(forecast((1, 1), (0, 2)).hit_percent, 33);
+        // 2 in 3
+        assert_eq!(forecast((2, 2), (0, 2)).hit_percent, 67);
+    }
+
+    #[test]
+    fn an_attack_which_never_gets_through_does_no_damage() {
+        assert_eq!(
+            forecast((0, 0), (1, 3)),
+            CombatForecast {
+                hit_percent: 0,
+                min_damage: 0,
+                max_damage: 0,
+            }
+        );
+    }
+}
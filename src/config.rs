@@ -0,0 +1,206 @@
+// config.rs
+
+use coord_2d::Size;
+use serde::Deserialize;
+use std::str::FromStr;
+
+use chargrid_roguelike_tutorial_2020::{log_error, log_info};
+
+use crate::app::colors::ThemeName;
+use crate::difficulty::Difficulty;
+use crate::ui::UI_NUM_ROWS;
+
+// Looked for next to the executable when no other file is given
+const CONFIG_FILE: &str = "config.toml";
+const DEFAULT_CONFIG: &str = include_str!("data/config.toml");
+
+// Cells smaller than this can't be read, and bigger ones don't fit a
+// window on most screens
+const MIN_CELL_SIZE_PX: u32 = 8;
+const MAX_CELL_SIZE_PX: u32 = 64;
+
+// The size of each level, written as WxH. Maps narrower than the UI are
+// centred on the screen.
+const MIN_MAP_SIZE: Size = Size::new_u16(20, 15);
+// Bigger levels than this are slow to generate and to search for paths
+const MAX_MAP_SIZE: Size = Size::new_u16(200, 100);
+
+#[derive(Clone, Copy)]
+pub struct MapSize(pub Size);
+
+impl FromStr for MapSize {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid map size {:?} (expected WxH, such as 60x30)", s);
+        let (width, height) = s.split_once('x').ok_or_else(error)?;
+        let width = width.parse::<u32>().map_err(|_| error())?;
+        let height = height.parse::<u32>().map_err(|_| error())?;
+        Self::new(Size::new(width, height))
+    }
+}
+
+impl MapSize {
+    pub fn new(size: Size) -> Result<Self, String> {
+        if !(MIN_MAP_SIZE.width()..=MAX_MAP_SIZE.width()).contains(&size.width())
+            || !(MIN_MAP_SIZE.height()..=MAX_MAP_SIZE.height()).contains(&size.height())
+        {
+            return Err(format!(
+                "map size {}x{} is out of range (from {}x{} to {}x{})",
+                size.width(),
+                size.height(),
+                MIN_MAP_SIZE.width(),
+                MIN_MAP_SIZE.height(),
+                MAX_MAP_SIZE.width(),
+                MAX_MAP_SIZE.height(),
+            ));
+        }
+        Ok(Self(size))
+    }
+}
+
+// The file as written, before its values are checked. Anything left out
+// takes its value from the built-in file.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    cell_size_px: u32,
+    window_title: String,
+    font_normal: Option<String>,
+    font_bold: Option<String>,
+    map_size: String,
+    ui_rows: u32,
+    theme: String,
+    difficulty: String,
+    keys: Option<String>,
+    auto_pickup: bool,
+    volume: f32,
+    lang: String,
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        toml::from_str(DEFAULT_CONFIG).expect("built-in config is invalid")
+    }
+}
+
+// How the window looks, for the graphical frontend
+pub struct DisplayConfig {
+    pub cell_size_px: u32,
+    pub window_title: String,
+    // None for the built-in fonts
+    pub font_normal: Option<String>,
+    pub font_bold: Option<String>,
+}
+
+// Choices about how the game plays which aren't part of its rules
+#[derive(Clone, Copy)]
+pub struct GameConfig {
+    pub auto_pickup: bool,
+}
+
+// Sound, for builds with the audio feature
+#[derive(Clone, Copy)]
+pub struct AudioConfig {
+    // from 0 for silence to 1
+    pub volume: f32,
+}
+
+pub struct Config {
+    pub display: DisplayConfig,
+    pub map_size: Size,
+    pub ui_rows: u32,
+    pub theme_name: ThemeName,
+    // for new runs, which can also be chosen from the main menu
+    pub difficulty: Difficulty,
+    pub keys_path: Option<String>,
+    pub game: GameConfig,
+    pub audio: AudioConfig,
+    // the code of the language to show text in
+    pub lang: String,
+}
+
+impl Config {
+    pub fn built_in() -> Self {
+        Self::parse(DEFAULT_CONFIG).expect("built-in config is invalid")
+    }
+
+    // Loads the config from next to the executable, writing the built-in
+    // one there first if there isn't one yet
+    pub fn load_default() -> Result<Self, String> {
+        let path = match std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.join(CONFIG_FILE)))
+        {
+            Some(path) => path,
+            None => return Ok(Self::built_in()),
+        };
+        if path.exists() {
+            return Self::load(&path.to_string_lossy());
+        }
+        log_info!("Writing the default config to {:?}", path);
+        if let Err(error) = std::fs::write(&path, DEFAULT_CONFIG) {
+            log_error!("Failed to write the default config: {}", error);
+        }
+        Ok(Self::built_in())
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+        Self::parse(&text)
+    }
+
+    // Errors name the key with the bad value
+    fn parse(text: &str) -> Result<Self, String> {
+        let file: ConfigFile = toml::from_str(text).map_err(|error| error.to_string())?;
+        if !(MIN_CELL_SIZE_PX..=MAX_CELL_SIZE_PX).contains(&file.cell_size_px) {
+            return Err(format!(
+                "cell_size_px: {} is out of range (from {} to {})",
+                file.cell_size_px, MIN_CELL_SIZE_PX, MAX_CELL_SIZE_PX
+            ));
+        }
+        let MapSize(map_size) = file
+            .map_size
+            .parse()
+            .map_err(|error| format!("map_size: {}", error))?;
+        if file.ui_rows < UI_NUM_ROWS {
+            return Err(format!(
+                "ui_rows: the UI needs at least {} rows",
+                UI_NUM_ROWS
+            ));
+        }
+        if !(0.0..=1.0).contains(&file.volume) {
+            return Err(format!(
+                "volume: {} is out of range (from 0 to 1)",
+                file.volume
+            ));
+        }
+        let theme_name = file
+            .theme
+            .parse()
+            .map_err(|error| format!("theme: {}", error))?;
+        let difficulty = file
+            .difficulty
+            .parse()
+            .map_err(|error| format!("difficulty: {}", error))?;
+        Ok(Self {
+            display: DisplayConfig {
+                cell_size_px: file.cell_size_px,
+                window_title: file.window_title,
+                font_normal: file.font_normal,
+                font_bold: file.font_bold,
+            },
+            map_size,
+            ui_rows: file.ui_rows,
+            theme_name,
+            difficulty,
+            keys_path: file.keys,
+            game: GameConfig {
+                auto_pickup: file.auto_pickup,
+            },
+            audio: AudioConfig {
+                volume: file.volume,
+            },
+            lang: file.lang,
+        })
+    }
+}
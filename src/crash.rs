@@ -0,0 +1,74 @@
+// crash.rs
+
+// When the game panics a report is written next to the save, with the
+// panic, a backtrace, and how far the run had got. The game state may be
+// half way through changing when it panics, so it isn't saved then.
+// Instead the app autosaves between actions, and offers to recover the
+// last autosave on the next launch.
+
+use chargrid_roguelike_tutorial_2020::log_error;
+#[cfg(not(feature = "web"))]
+use std::backtrace::Backtrace;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+#[derive(Clone, Copy)]
+pub struct RunProgress {
+    pub rng_seed: u64,
+    pub dungeon_level: u32,
+    pub turn: u64,
+}
+
+// Kept up to date by the app between actions, for the panic hook
+static RUN_PROGRESS: Mutex<Option<RunProgress>> = Mutex::new(None);
+
+pub fn set_run_progress(run_progress: RunProgress) {
+    if let Ok(mut current) = RUN_PROGRESS.lock() {
+        *current = Some(run_progress);
+    }
+}
+
+// Logs the seed and writes a report to `report_path` before the default
+// hook prints the panic. The seed given is the one the game started with,
+// in case it panics before a run begins.
+pub fn install_panic_hook(rng_seed: u64, report_path: Option<PathBuf>) {
+    #[cfg(feature = "web")]
+    let _ = report_path;
+    let default_panic_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic_info| {
+        // the lock is never held while anything can panic, but a hook
+        // mustn't wait on it to find out
+        let run_progress = RUN_PROGRESS
+            .try_lock()
+            .ok()
+            .and_then(|run_progress| *run_progress);
+        match run_progress {
+            Some(run_progress) => log_error!(
+                "Crashed on level {} at turn {} of a run with RNG seed {}",
+                run_progress.dungeon_level,
+                run_progress.turn,
+                run_progress.rng_seed,
+            ),
+            None => log_error!("Crashed before a run began, with RNG seed {}", rng_seed),
+        }
+        // there's nowhere to write a report in the browser
+        #[cfg(not(feature = "web"))]
+        if let Some(report_path) = report_path.as_ref() {
+            let mut report = format!("{}\n\n", panic_info);
+            match run_progress {
+                Some(run_progress) => {
+                    report.push_str(&format!("rng seed: {}\n", run_progress.rng_seed));
+                    report.push_str(&format!("dungeon level: {}\n", run_progress.dungeon_level));
+                    report.push_str(&format!("turn: {}\n", run_progress.turn));
+                }
+                None => report.push_str(&format!("rng seed: {}\n", rng_seed)),
+            }
+            report.push_str(&format!("\nbacktrace:\n{}\n", Backtrace::force_capture()));
+            match std::fs::write(report_path, report) {
+                Ok(()) => log_error!("Wrote a crash report to {:?}", report_path),
+                Err(error) => log_error!("Failed to write a crash report: {}", error),
+            }
+        }
+        default_panic_hook(panic_info);
+    }));
+}
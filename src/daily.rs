@@ -0,0 +1,52 @@
+// daily.rs
+
+// Everyone playing the daily challenge on the same day gets the same
+// dungeon, generated from a seed made from the date in UTC.
+
+// Today's date in UTC as YYYY-MM-DD. std's clock isn't available in the
+// browser, so there's no daily challenge there.
+#[cfg(not(feature = "web"))]
+pub fn today() -> Option<String> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    const SECONDS_PER_DAY: u64 = 86400;
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let (year, month, day) = civil_from_days((seconds / SECONDS_PER_DAY) as i64);
+    Some(format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+#[cfg(feature = "web")]
+pub fn today() -> Option<String> {
+    None
+}
+
+// The year, month and day of a number of days since 1970-01-01, by
+// Howard Hinnant's algorithm. Years start in March so the leap day comes
+// last.
+#[cfg(not(feature = "web"))]
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+    let day_of_era = (z - era * 146097) as u64;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let march_based_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * march_based_month + 2) / 5 + 1) as u32;
+    let month = if march_based_month < 10 {
+        march_based_month + 3
+    } else {
+        march_based_month - 9
+    } as u32;
+    let year = year_of_era as i64 + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+// FNV-1a of the date, which unlike std's hasher gives the same seed on
+// every platform and Rust version
+pub fn seed(date: &str) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+    date.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
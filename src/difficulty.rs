@@ -0,0 +1,97 @@
+// difficulty.rs
+
+// How hard a run is, chosen before it starts. Everything a difficulty
+// changes is in its DifficultyProfile, so balancing them is a matter of
+// changing the numbers in Difficulty::profile.
+
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DifficultyProfile {
+    // each entry of the spawn tables' npcs_per_room is scaled by this
+    pub npcs_per_room_percent: u32,
+    // how quickly the tougher NPCs become more common with depth
+    pub npc_per_level_percent: u32,
+    pub npc_hit_points_percent: u32,
+    pub npc_extra_damage: u32,
+    // put in the player's inventory at the start of a run
+    pub extra_health_potions: u32,
+    pub score_percent: u64,
+}
+
+impl Difficulty {
+    pub fn profile(self) -> DifficultyProfile {
+        match self {
+            Self::Easy => DifficultyProfile {
+                npcs_per_room_percent: 75,
+                npc_per_level_percent: 50,
+                npc_hit_points_percent: 75,
+                npc_extra_damage: 0,
+                extra_health_potions: 2,
+                score_percent: 50,
+            },
+            Self::Normal => DifficultyProfile {
+                npcs_per_room_percent: 100,
+                npc_per_level_percent: 100,
+                npc_hit_points_percent: 100,
+                npc_extra_damage: 0,
+                extra_health_potions: 0,
+                score_percent: 100,
+            },
+            Self::Hard => DifficultyProfile {
+                npcs_per_room_percent: 125,
+                npc_per_level_percent: 150,
+                npc_hit_points_percent: 150,
+                npc_extra_damage: 1,
+                extra_health_potions: 0,
+                score_percent: 150,
+            },
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Easy => "easy",
+            Self::Normal => "normal",
+            Self::Hard => "hard",
+        }
+    }
+
+    // The difficulty after this one in the main menu
+    pub fn next(self) -> Self {
+        match self {
+            Self::Easy => Self::Normal,
+            Self::Normal => Self::Hard,
+            Self::Hard => Self::Easy,
+        }
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "easy" => Ok(Self::Easy),
+            "normal" => Ok(Self::Normal),
+            "hard" => Ok(Self::Hard),
+            _ => Err(format!(
+                "unknown difficulty {:?} (expected easy, normal or hard)",
+                s
+            )),
+        }
+    }
+}
+
+// Scales `value` by `percent`, rounding to the nearest whole number
+pub fn scale(value: u32, percent: u32) -> u32 {
+    (value * percent + 50) / 100
+}
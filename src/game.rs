@@ -1,23 +1,80 @@
 // game.rs
 
 use coord_2d::{Coord, Size};
-use direction::CardinalDirection;
+use direction::{CardinalDirection, Direction};
 use entity_table::{ComponentTable, Entity};
-use rand::SeedableRng;
+use line_2d::CardinalStepIter;
+use rand::{Rng, SeedableRng};
 use rand_isaac::Isaac64Rng;
 use serde::{Deserialize, Serialize};
+use std::collections::{vec_deque, HashMap, VecDeque};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::str::FromStr;
+use std::time::Duration;
+#[cfg(not(feature = "web"))]
+use std::time::Instant;
 
-use crate::behavior::{Agent, BehaviorContext, NpcAction};
+use crate::behavior::{Agent, BehaviorContext, NpcAction, TravelContext};
+use crate::combat::CombatForecast;
+use crate::difficulty::Difficulty;
+use crate::terrain::{
+    self, Dungeon, DungeonStats, LevelFeeling, LevelStats, LevelTheme, SpawnTables,
+};
 use crate::visibility::{CellVisibility, VisibilityAlgorithm, VisibilityGrid};
-use crate::world::{EquippedInventoryIndices, HitPoints, Inventory,
-                   ItemType, ItemUsage, Location, NpcType, Populate,
-                   ProjectileType, Tile, World};
-
+use crate::world::{
+    CharacterData, DamageSource, EquippedInventoryIndices, FloorThing, FloorThings, HitPoints,
+    Inventory, ItemType, ItemUsage, Layer, Liquid, Location, NpcType, Populate, ProjectileStop,
+    ProjectileType, StartingKit, StatEvent, StatusEffect, Tile, TrapType, VisualEffect,
+    VisualEffectType, World,
+};
 
 pub struct EntityToRender {
     pub tile: Tile,
     pub location: Location,
-    pub visibility: CellVisibility,
+    pub light: u8,
+    pub distance_brightness: u8,
+    // an NPC the player can sense but not see
+    pub sensed: bool,
+}
+
+// Things that happened in the game which the frontend may want to give
+// some feedback for, such as a flash or a sound. They pile up until taken
+// with GameState::take_events, in the order they happened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameEvent {
+    PlayerDamaged,
+    // where the player killed an NPC in melee
+    KillingBlow(Coord),
+    // a melee attack between the player and an NPC, either way round
+    MeleeHit,
+    MeleeMiss,
+    NpcDied(NpcType),
+    PlayerDied,
+    ItemPickedUp(ItemType),
+    ItemUsed(ItemType),
+    ExplosionAt(Coord),
+    DoorOpened(Coord),
+    DoorClosed(Coord),
+    StairsDescended,
+    StairsAscended,
+    PlayerFell,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Arrival {
+    Stairs,
+    Fall,
+}
+
+// A level the player has left. Nothing on a stored level moves
+// until the player returns to it.
+#[derive(Serialize, Deserialize)]
+struct StoredLevel {
+    world: World,
+    visibility_grid: VisibilityGrid,
+    ai_state: ComponentTable<Agent>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -28,9 +85,172 @@ pub struct GameState {
     visibility_grid: VisibilityGrid,
     ai_state: ComponentTable<Agent>,
     behavior_context: BehaviorContext,
-    message_log: Vec<LogMessage>,
-    rng: Isaac64Rng,
+    travel_context: TravelContext,
+    // what the player is doing over several turns, if anything
+    auto_act: Option<AutoAct>,
+    quick_slots: [Option<QuickSlot>; NUM_QUICK_SLOTS],
+    message_log: MessageLog,
+    // saves from before the RNG was split have a single one under "rng"
+    #[serde(alias = "rng")]
+    rngs: Rngs,
     dungeon_level: u32,
+    stored_levels: HashMap<u32, StoredLevel>,
+    spawn_tables: SpawnTables,
+    run_seed: u64,
+    // the number of turns the player has taken
+    turn: u64,
+    run_stats: RunStats,
+    // set once the player has been warned about being badly wounded,
+    // until they heal
+    low_health_warned: bool,
+    // set once a wizard command has been used, as the run no longer counts
+    #[serde(default)]
+    cheated: bool,
+    // the date of the daily challenge this run is, if it's one
+    #[serde(default)]
+    daily_date: Option<String>,
+    #[serde(default)]
+    difficulty: Difficulty,
+    // empty for runs from before characters had names
+    #[serde(default)]
+    character_name: String,
+    #[serde(default)]
+    background: Option<Background>,
+    // how long the last visibility update and AI turn took
+    #[serde(skip)]
+    visibility_update_time: Duration,
+    #[serde(skip)]
+    ai_turn_time: Duration,
+    // taken from each level's world as it's left, as they'd be lost
+    #[serde(skip)]
+    events: Vec<GameEvent>,
+}
+
+pub const NUM_QUICK_SLOTS: usize = 5;
+
+// An inventory slot bound to a quick-use key. The item type is kept to
+// catch the slot's item having been swapped for something else.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct QuickSlot {
+    inventory_index: usize,
+    item_type: ItemType,
+}
+
+// Everything the player can do, for playing the game through
+// GameState::act and for recording replays
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum PlayerAction {
+    Move(CardinalDirection),
+    ForceAttack(CardinalDirection),
+    Wait,
+    Get,
+    CloseDoor,
+    Search,
+    Pray,
+    Pay,
+    Use(usize),
+    UseAim(usize, Coord),
+    Drop(usize),
+    Wield(usize),
+    Wear(usize),
+    Unequip(usize),
+    // the level up is only used on reaching a level for the first time
+    Descend(LevelUp),
+    Ascend,
+    EnterPortal,
+    JumpIntoChasm,
+    Travel(Coord),
+    Rest,
+    Run(CardinalDirection),
+    // takes the next turn of a travel, rest or run
+    AutoActStep,
+    StopAutoAct,
+    Wizard(WizardCommand),
+}
+
+// Cheats for testing things deep in the dungeon, only available when
+// started with --wizard. None of them take a turn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WizardCommand {
+    RevealMap,
+    Heal,
+    SpawnNpc(NpcType),
+    GrantItem(ItemType),
+    Descend,
+    // the app changes the visibility algorithm, and the game only notes it
+    ToggleOmniscience,
+    RegenerateLevel,
+    // the app shows or hides the distances, and the game only notes it
+    ToggleNpcDistances,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActionResult {
+    Done,
+    // the action wasn't possible, and nothing happened
+    Refused,
+    PlayerDied,
+}
+
+// Times work for the diagnostics overlay. std's Instant panics in the
+// browser, so there everything takes no time at all.
+struct Stopwatch {
+    #[cfg(not(feature = "web"))]
+    start: Instant,
+}
+
+impl Stopwatch {
+    fn start() -> Self {
+        Self {
+            #[cfg(not(feature = "web"))]
+            start: Instant::now(),
+        }
+    }
+
+    #[cfg(not(feature = "web"))]
+    fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    #[cfg(feature = "web")]
+    fn elapsed(&self) -> Duration {
+        Duration::from_millis(0)
+    }
+}
+
+// the longest the player will rest for in one go
+const MAX_REST_TURNS: u32 = 100;
+
+// Something the player keeps doing a turn at a time until it's finished
+// or interrupted
+#[derive(Serialize, Deserialize)]
+enum AutoAct {
+    // the cells still to walk through
+    Travel(Vec<Coord>),
+    // the number of turns rested so far
+    Rest(u32),
+    Run {
+        direction: CardinalDirection,
+        // the number of steps taken so far
+        steps: u32,
+    },
+}
+
+// Somewhere the player remembers which they can choose to travel to
+#[derive(Clone, Copy, Debug)]
+pub enum TravelTarget {
+    Stairs,
+    StairsUp,
+    Item(ItemType),
+}
+
+// Measurements for profiling, shown in the diagnostics overlay
+#[derive(Clone, Copy, Debug)]
+pub struct Diagnostics {
+    pub visibility_update_time: Duration,
+    pub ai_turn_time: Duration,
+    pub entity_count: usize,
+    pub ai_state_size: usize,
 }
 
 impl GameState {
@@ -38,18 +258,39 @@ impl GameState {
         screen_size: Size,
         rng_seed: u64,
         initial_visibility_algorithm: VisibilityAlgorithm,
+        spawn_tables: SpawnTables,
+        difficulty: Difficulty,
+        new_character: NewCharacter,
     ) -> Self {
         let mut world = World::new(screen_size);
-        let mut rng = Isaac64Rng::seed_from_u64(rng_seed);
-        println!("RNG: {:?}", rng);
+        let rngs = Rngs::new(rng_seed);
         let dungeon_level = 1;
+        let profile = difficulty.profile();
+        let spawn_tables = spawn_tables.with_difficulty(&profile);
+        let mut starting_kit = new_character
+            .background
+            .map(Background::starting_kit)
+            .unwrap_or_default();
+        for _ in 0..profile.extra_health_potions {
+            starting_kit.items.push(ItemType::HealthPotion);
+        }
         let Populate {
             player_entity,
             ai_state,
-        } = world.populate(dungeon_level, &mut rng);
+            stats,
+            level_stats,
+        } = world.populate(
+            dungeon_level,
+            &spawn_tables,
+            &starting_kit,
+            &mut level_rng(rng_seed, dungeon_level),
+        );
+        world.apply_difficulty(&profile);
+        log_generated_level(dungeon_level, &stats);
         let shadowcast_context = shadowcast::Context::default();
         let visibility_grid = VisibilityGrid::new(screen_size);
         let behavior_context = BehaviorContext::new(screen_size);
+        let travel_context = TravelContext::new(screen_size);
         let mut game_state = Self {
             world,
             player_entity,
@@ -57,48 +298,866 @@ impl GameState {
             visibility_grid,
             ai_state,
             behavior_context,
-            message_log: Vec::new(),
-            rng,
+            travel_context,
+            auto_act: None,
+            quick_slots: [None; NUM_QUICK_SLOTS],
+            message_log: MessageLog::default(),
+            rngs,
             dungeon_level,
+            stored_levels: HashMap::new(),
+            spawn_tables,
+            run_seed: rng_seed,
+            turn: 0,
+            run_stats: RunStats::default(),
+            low_health_warned: false,
+            cheated: false,
+            daily_date: None,
+            difficulty,
+            character_name: new_character.name,
+            background: new_character.background,
+            visibility_update_time: Duration::default(),
+            ai_turn_time: Duration::default(),
+            events: Vec::new(),
         };
         game_state.update_visibility(initial_visibility_algorithm);
+        let theme = game_state.level_theme();
+        game_state
+            .message_log
+            .push(LogMessage::PlayerEntersLevel(theme));
+        game_state.push_level_feelings(&level_stats);
+        game_state
+    }
+
+    // A run whose first level is an arena (see terrain::arena)
+    // instead of a generated one, for benchmarks
+    pub fn new_arena(
+        screen_size: Size,
+        rng_seed: u64,
+        visibility_algorithm: VisibilityAlgorithm,
+    ) -> Self {
+        let mut game_state = Self::new(
+            screen_size,
+            rng_seed,
+            visibility_algorithm,
+            SpawnTables::built_in(),
+            Difficulty::Normal,
+            NewCharacter::default(),
+        );
+        game_state.replace_level(terrain::arena(screen_size));
+        game_state.update_visibility(visibility_algorithm);
         game_state
     }
 
+    // Adds up to `count` NPCs in free cells around the level, each with
+    // its own agent, for benchmarks. Returns how many there was room for.
+    pub fn spawn_npcs(&mut self, npc_type: NpcType, count: usize) -> usize {
+        (0..count)
+            .take_while(|_| {
+                match self
+                    .world
+                    .spawn_npc_anywhere(npc_type, &mut self.rngs.terrain)
+                {
+                    Ok(entity) => {
+                        self.ai_state.insert(entity, Agent::new(self.rngs.ai.gen()));
+                        true
+                    }
+                    Err(()) => false,
+                }
+            })
+            .count()
+    }
+
+    // Carries out a player action in full, for driving the game without a
+    // frontend. Projectiles fly until they land, and visibility is brought
+    // up to date afterwards.
+    pub fn act(
+        &mut self,
+        action: PlayerAction,
+        visibility_algorithm: VisibilityAlgorithm,
+    ) -> ActionResult {
+        while self.has_animations() {
+            self.tick_animations();
+        }
+        let done = self.perform(action);
+        // the app looks again as soon as the player acts, and again once
+        // the animations are over
+        self.update_visibility(visibility_algorithm);
+        if self.has_animations() {
+            while self.has_animations() {
+                self.tick_animations();
+            }
+            self.update_visibility(visibility_algorithm);
+        }
+        // nothing is drawing them
+        self.take_events();
+        if !self.is_player_alive() {
+            ActionResult::PlayerDied
+        } else if done {
+            ActionResult::Done
+        } else {
+            ActionResult::Refused
+        }
+    }
+
+    // Starts a player action, leaving any animations it causes to the
+    // caller. False if it wasn't possible.
+    pub fn perform(&mut self, action: PlayerAction) -> bool {
+        let turn = self.turn;
+        let dungeon_level = self.dungeon_level;
+        match action {
+            PlayerAction::Move(direction) => {
+                let start = self.player_coord();
+                let opens_door = self.world.is_closed_door_at(start + direction.coord());
+                self.maybe_move_player(direction);
+                opens_door || self.turn != turn || self.player_coord() != start
+            }
+            PlayerAction::ForceAttack(direction) => {
+                self.player_force_attack(direction);
+                self.turn != turn
+            }
+            PlayerAction::Wait => {
+                self.wait_player();
+                true
+            }
+            PlayerAction::Get => {
+                self.maybe_player_get_item();
+                self.turn != turn
+            }
+            PlayerAction::CloseDoor => {
+                self.maybe_player_close_door();
+                self.turn != turn
+            }
+            PlayerAction::Search => {
+                self.player_search();
+                self.turn != turn
+            }
+            PlayerAction::Pray => {
+                self.maybe_player_pray();
+                self.turn != turn
+            }
+            PlayerAction::Pay => {
+                self.maybe_player_pay();
+                self.turn != turn
+            }
+            PlayerAction::Use(inventory_index) => matches!(
+                self.maybe_player_use_item(inventory_index),
+                Ok(ItemUsage::Immediate)
+            ),
+            PlayerAction::UseAim(inventory_index, target) => {
+                match self.maybe_player_use_item(inventory_index) {
+                    Ok(ItemUsage::Aim) => self
+                        .maybe_player_use_item_aim(inventory_index, target)
+                        .is_ok(),
+                    Ok(ItemUsage::Immediate) => true,
+                    Err(()) => false,
+                }
+            }
+            PlayerAction::Drop(inventory_index) => {
+                self.maybe_player_drop_item(inventory_index).is_ok()
+            }
+            PlayerAction::Wield(inventory_index) => {
+                self.maybe_player_wield_item(inventory_index).is_ok()
+            }
+            PlayerAction::Wear(inventory_index) => {
+                self.maybe_player_wear_item(inventory_index).is_ok()
+            }
+            PlayerAction::Unequip(inventory_index) => {
+                self.maybe_player_unequip_item(inventory_index).is_ok()
+            }
+            PlayerAction::Descend(level_up) => {
+                if self.is_player_on_stairs() {
+                    if self.is_next_level_visited() {
+                        self.player_descend();
+                    } else {
+                        self.player_level_up_and_descend(level_up);
+                    }
+                }
+                self.dungeon_level != dungeon_level
+            }
+            PlayerAction::Ascend => {
+                if self.is_player_on_stairs_up() {
+                    self.player_ascend();
+                }
+                self.dungeon_level != dungeon_level
+            }
+            PlayerAction::EnterPortal => self.maybe_player_enter_portal().is_ok(),
+            PlayerAction::JumpIntoChasm => {
+                self.player_jump_into_chasm();
+                self.dungeon_level != dungeon_level
+            }
+            PlayerAction::Travel(goal) => self.start_travel(goal).is_ok(),
+            PlayerAction::Rest => self.start_rest().is_ok(),
+            PlayerAction::Run(direction) => {
+                self.start_run(direction);
+                self.turn != turn
+            }
+            PlayerAction::AutoActStep => {
+                self.auto_act_step();
+                self.turn != turn
+            }
+            PlayerAction::StopAutoAct => {
+                let was_auto_acting = self.is_auto_acting();
+                self.stop_auto_act();
+                was_auto_acting
+            }
+            PlayerAction::Wizard(command) => self.wizard(command).is_ok(),
+        }
+    }
+
+    fn wizard(&mut self, command: WizardCommand) -> Result<(), ()> {
+        // daily challenges are for comparing fair runs
+        if self.has_animations() || self.daily_date.is_some() {
+            return Err(());
+        }
+        match command {
+            WizardCommand::RevealMap => self.visibility_grid.reveal_all(&self.world),
+            WizardCommand::Heal => self.world.heal_fully(self.player_entity),
+            WizardCommand::SpawnNpc(npc_type) => {
+                let entity = self
+                    .world
+                    .spawn_npc_next_to(self.player_coord(), npc_type)?;
+                self.ai_state.insert(entity, Agent::new(self.rngs.ai.gen()));
+            }
+            WizardCommand::GrantItem(item_type) => {
+                if self
+                    .world
+                    .grant_item(self.player_entity, item_type)
+                    .is_err()
+                {
+                    self.message_log.push(LogMessage::PlayerInventoryIsFull);
+                    return Err(());
+                }
+            }
+            WizardCommand::Descend => {
+                if self.is_final_level() {
+                    return Err(());
+                }
+                let player_data = self.store_current_level();
+                self.enter_level(self.dungeon_level + 1, player_data, Arrival::Stairs);
+            }
+            WizardCommand::ToggleOmniscience | WizardCommand::ToggleNpcDistances => (),
+            WizardCommand::RegenerateLevel => self.regenerate_level(),
+        }
+        self.cheated = true;
+        self.message_log.push(LogMessage::Wizard(command));
+        Ok(())
+    }
+
+    // Swaps the first level for the hand-made one if there is one, skips
+    // down to the start level as though the player had taken the stairs
+    // on each level, then hands over the items. Items which don't fit in
+    // the inventory are left out.
+    pub fn apply_start_options(
+        &mut self,
+        options: &StartOptions,
+        visibility_algorithm: VisibilityAlgorithm,
+    ) {
+        if let Some(first_level) = options.first_level.as_ref() {
+            self.replace_level(first_level.clone());
+        }
+        let level_ups = if options.level_ups.is_empty() {
+            &LevelUp::ALL[..]
+        } else {
+            &options.level_ups[..]
+        };
+        let num_levels_skipped = options.dungeon_level.saturating_sub(self.dungeon_level) as usize;
+        for &level_up in level_ups.iter().cycle().take(num_levels_skipped) {
+            if self.is_final_level() {
+                break;
+            }
+            self.level_up_and_descend_from_anywhere(level_up);
+        }
+        for &item_type in &options.items {
+            if self
+                .world
+                .grant_item(self.player_entity, item_type)
+                .is_err()
+            {
+                self.message_log.push(LogMessage::PlayerInventoryIsFull);
+                break;
+            }
+        }
+        self.cheated = true;
+        self.update_visibility(visibility_algorithm);
+    }
+
+    // True once any wizard command or head start has been used this run
+    pub fn is_cheated(&self) -> bool {
+        self.cheated
+    }
+
+    // Marks the run as the daily challenge for `date`, which is where its
+    // seed came from
+    pub fn set_daily_date(&mut self, date: String) {
+        self.daily_date = Some(date);
+    }
+
+    pub fn daily_date(&self) -> Option<&str> {
+        self.daily_date.as_deref()
+    }
+
+    pub fn difficulty(&self) -> Difficulty {
+        self.difficulty
+    }
+
+    pub fn character_name(&self) -> &str {
+        &self.character_name
+    }
+
+    // For telling the player about something the app did, such as saving
+    // a screenshot
+    pub fn push_message(&mut self, message: LogMessage) {
+        self.message_log.push(message);
+    }
+
+    // Everything that has happened since this was last called, for the
+    // app's visual effects, sounds and anything else that wants to know
+    pub fn take_events(&mut self) -> Vec<GameEvent> {
+        self.events.extend(self.world.take_events());
+        mem::take(&mut self.events)
+    }
+
     pub fn tick_animations(&mut self) {
-        self.world.move_projectiles(&mut self.message_log)
+        // effects started by this tick's projectiles get to show their
+        // first frame
+        self.world.tick_visual_effects();
+        self.world.move_projectiles(&mut self.message_log);
+        self.record_stats();
+        self.check_low_health();
+    }
+
+    // Adds what the player has done since this was last called to the
+    // run's statistics
+    fn record_stats(&mut self) {
+        for stat_event in self.world.take_stat_events() {
+            self.run_stats.record(stat_event);
+        }
+    }
+
+    pub fn run_stats(&self) -> &RunStats {
+        &self.run_stats
+    }
+
+    // True when the player is below a quarter of their hit points
+    pub fn is_player_health_low(&self) -> bool {
+        let hit_points = self.player_hit_points();
+        hit_points.current() * 4 < hit_points.max()
+    }
+
+    // Warns the player once when their health drops below the low health
+    // threshold. The warning is re-armed when they heal back above it, so
+    // hovering at low health doesn't repeat it every turn.
+    fn check_low_health(&mut self) {
+        if !self.is_player_alive() {
+            return;
+        }
+        let low_health = self.is_player_health_low();
+        if low_health && !self.low_health_warned {
+            self.message_log.push(LogMessage::PlayerIsBadlyWounded);
+        }
+        self.low_health_warned = low_health;
     }
 
-    fn has_animations(&self) -> bool {
-        self.world.has_projectiles()
+    pub fn has_animations(&self) -> bool {
+        self.world.has_projectiles() || self.world.has_visual_effects()
     }
 
     pub fn update_visibility(&mut self, visibility_algorithm: VisibilityAlgorithm) {
+        let start = Stopwatch::start();
         let player_coord = self
             .world
             .spatial_table
             .coord_of(self.player_entity)
             .unwrap();
-        self.visibility_grid
-            .update(
-                player_coord,
-                &self.world,
-                &mut self.shadowcast_context,
-                visibility_algorithm,
-            );
+        // a lit torch lets the player see further and lights their way
+        const TORCH_RADIUS: u32 = 9;
+        let theme = self.level_theme();
+        let (player_light_radius, sight_radius) = if self.is_player_carrying_lit_torch() {
+            (TORCH_RADIUS, TORCH_RADIUS)
+        } else {
+            (theme.player_light_radius(), theme.sight_radius())
+        };
+        // the blind can only make out what's right next to them
+        let max_radius = if self.is_player_blind() {
+            Some(1)
+        } else {
+            None
+        };
+        self.visibility_grid.update(
+            player_coord,
+            player_light_radius,
+            sight_radius,
+            max_radius,
+            &self.world,
+            &mut self.shadowcast_context,
+            visibility_algorithm,
+        );
+        self.visibility_update_time = start.elapsed();
+    }
+
+    // A summary of the run so far, for noticing when playing back the same
+    // actions has gone differently. It's the same on every platform, so a
+    // run can be compared between them.
+    pub fn checksum(&self) -> u64 {
+        let mut hasher = StableHasher::new();
+        self.turn.hash(&mut hasher);
+        self.dungeon_level.hash(&mut hasher);
+        // the next number each RNG would give catches any difference in
+        // how many have been drawn
+        self.rngs.next_values().hash(&mut hasher);
+        for entity in self.ai_state.entities().chain(Some(self.player_entity)) {
+            if let Some(coord) = self.world.entity_coord(entity) {
+                (coord.x, coord.y).hash(&mut hasher);
+            }
+            if let Some(hit_points) = self.world.hit_points(entity) {
+                (hit_points.current(), hit_points.max()).hash(&mut hasher);
+            }
+        }
+        for slot in self.player_inventory().slots() {
+            slot.and_then(|entity| self.item_type(entity))
+                .map(|item_type| item_type.name())
+                .hash(&mut hasher);
+        }
+        self.player_gold().hash(&mut hasher);
+        self.run_stats.total_kills().hash(&mut hasher);
+        self.message_log.end().hash(&mut hasher);
+        // sorted, as the order of a HashMap differs from run to run
+        let mut stored_levels = self.stored_levels.keys().collect::<Vec<_>>();
+        stored_levels.sort();
+        stored_levels.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    pub fn diagnostics(&self) -> Diagnostics {
+        Diagnostics {
+            visibility_update_time: self.visibility_update_time,
+            ai_turn_time: self.ai_turn_time,
+            entity_count: self.world.components.tile.entities().count(),
+            ai_state_size: self.ai_state.entities().count(),
+        }
     }
 
     pub fn maybe_move_player(&mut self, direction: CardinalDirection) {
+        self.player_step(direction, false);
+    }
+
+    // Attacks the cell in `direction` even if there's a neutral NPC or
+    // nothing at all there, taking the player's turn either way
+    pub fn player_force_attack(&mut self, direction: CardinalDirection) {
+        self.player_step(direction, true);
+    }
+
+    fn player_step(&mut self, direction: CardinalDirection, force_attack: bool) {
         if self.has_animations() {
             return;
         }
-        self.world
-            .maybe_move_character(
-                self.player_entity,
-                direction,
-                &mut self.message_log,
-                &mut self.rng);
+        // opening a door is a free action
+        let start = self.player_coord();
+        if !force_attack
+            && self
+                .world
+                .maybe_open_door(start + direction.coord())
+                .is_ok()
+        {
+            return;
+        }
+        let direction = self.world.stumble_direction(
+            self.player_entity,
+            direction,
+            &mut self.message_log,
+            &mut self.rngs.combat,
+        );
+        self.world.maybe_move_character(
+            self.player_entity,
+            direction,
+            force_attack,
+            &mut self.message_log,
+            &mut self.rngs.combat,
+        );
+        let end = self.player_coord();
+        // say what's here, but only on arriving somewhere new
+        if end != start {
+            if let Some(floor_things) = FloorThings::new(&self.world.floor_things_at(end)) {
+                self.message_log
+                    .push(LogMessage::PlayerSeesHere(floor_things));
+            }
+        }
         self.ai_turn();
+        // wading into water is slow, giving NPCs an extra move
+        if end != start
+            && self.world.liquid_at(end) == Some(Liquid::Water)
+            && self.is_player_alive()
+        {
+            self.ai_turn();
+        }
+    }
+
+    // The way the player would walk to reach `goal`, through cells they
+    // have seen
+    pub fn travel_path_to(&mut self, goal: Coord) -> Option<Vec<Coord>> {
+        let start = self.player_coord();
+        self.travel_context
+            .path(start, goal, &self.world, &self.visibility_grid)
+    }
+
+    // The way the player would step first to reach the nearest of `goals`,
+    // through cells they have seen
+    pub fn first_step_towards(&mut self, goals: &[Coord]) -> Option<CardinalDirection> {
+        let start = self.player_coord();
+        self.travel_context
+            .first_step(start, goals, &self.world, &self.visibility_grid)
+    }
+
+    // The way the player would step first to reach somewhere next to a
+    // cell they've never seen. None once there's nowhere left to explore.
+    pub fn explore_direction(&mut self) -> Option<CardinalDirection> {
+        let size = self.world.size();
+        let frontier = size
+            .coord_iter_row_major()
+            .filter(|&coord| {
+                !matches!(
+                    self.visibility_grid.cell_visibility(coord),
+                    CellVisibility::Never
+                ) && CardinalDirection::all().any(|direction| {
+                    let neighbour = coord + direction.coord();
+                    neighbour.is_valid(size)
+                        && matches!(
+                            self.visibility_grid.cell_visibility(neighbour),
+                            CellVisibility::Never
+                        )
+                })
+            })
+            .collect::<Vec<_>>();
+        self.first_step_towards(&frontier)
+    }
+
+    // A direction in which a hostile NPC is standing next to the player
+    pub fn adjacent_hostile_npc_direction(&self) -> Option<CardinalDirection> {
+        let player_coord = self.player_coord();
+        CardinalDirection::all().find(|direction| {
+            self.world
+                .npc_at(player_coord + direction.coord())
+                .map(|entity| {
+                    self.world.is_living_character(entity) && !self.world.is_neutral(entity)
+                })
+                .unwrap_or(false)
+        })
+    }
+
+    pub fn is_item_under_player(&self) -> bool {
+        self.world
+            .floor_things_at(self.player_coord())
+            .iter()
+            .any(|thing| matches!(thing, FloorThing::Item(_) | FloorThing::Rune))
+    }
+
+    // Anything the player could pick up without owing a shopkeeper for it
+    pub fn is_free_item_under_player(&self) -> bool {
+        let player_coord = self.player_coord();
+        !self.world.is_for_sale_at(player_coord)
+            && self
+                .world
+                .floor_things_at(player_coord)
+                .iter()
+                .any(|thing| {
+                    matches!(
+                        thing,
+                        FloorThing::Item(_) | FloorThing::Rune | FloorThing::Gold
+                    )
+                })
+    }
+
+    pub fn start_travel(&mut self, goal: Coord) -> Result<(), ()> {
+        let path = self.travel_path_to(goal).ok_or(())?;
+        self.auto_act = Some(AutoAct::Travel(path));
+        Ok(())
+    }
+
+    // Waits a turn at a time until the player is healed. There's no point
+    // resting at full health or with enemies in view.
+    pub fn start_rest(&mut self) -> Result<(), ()> {
+        if self.has_animations() {
+            return Err(());
+        }
+        let hit_points = self.player_hit_points();
+        if hit_points.is_full() {
+            self.message_log.push(LogMessage::NoNeedToRest);
+            return Err(());
+        }
+        if self.is_hostile_npc_visible() {
+            self.message_log.push(LogMessage::CannotRestNearEnemies);
+            return Err(());
+        }
+        self.auto_act = Some(AutoAct::Rest(0));
+        Ok(())
+    }
+
+    // Travels to the stairs down, if the player knows where they are
+    pub fn start_travel_to_stairs(&mut self) -> Result<(), ()> {
+        match self.known_stairs_coord() {
+            Some(stairs_coord) => self.start_travel_to(stairs_coord),
+            None => {
+                self.message_log.push(LogMessage::StairsNotKnown);
+                Err(())
+            }
+        }
+    }
+
+    // Like start_travel, but tells the player when there's no way there
+    pub fn start_travel_to(&mut self, goal: Coord) -> Result<(), ()> {
+        let result = self.start_travel(goal);
+        if result.is_err() {
+            self.message_log.push(LogMessage::NoKnownPath);
+        }
+        result
+    }
+
+    // The places the player remembers which are worth travelling to: the
+    // stairs, then items they've seen lying around, nearest first. Tells
+    // the player if there aren't any.
+    pub fn travel_targets(&mut self) -> Vec<(Coord, TravelTarget)> {
+        let player_coord = self.player_coord();
+        let mut targets = self
+            .visibility_grid
+            .known_tiles()
+            .filter(|&(coord, _, _)| coord != player_coord)
+            .filter_map(|(coord, _, tile)| {
+                let target = match tile {
+                    Tile::Stairs => TravelTarget::Stairs,
+                    Tile::StairsUp => TravelTarget::StairsUp,
+                    Tile::Item(item_type) => TravelTarget::Item(item_type),
+                    _ => return None,
+                };
+                Some((coord, target))
+            })
+            .collect::<Vec<_>>();
+        targets.sort_by_key(|&(coord, target)| {
+            let rank = match target {
+                TravelTarget::Stairs => 0,
+                TravelTarget::StairsUp => 1,
+                TravelTarget::Item(_) => 2,
+            };
+            (rank, (coord - player_coord).magnitude2())
+        });
+        if targets.is_empty() {
+            self.message_log.push(LogMessage::NoTravelTargets);
+        }
+        targets
+    }
+
+    // Moves in `direction` a turn at a time until something interesting
+    // happens. The first step is taken straight away like any other move.
+    pub fn start_run(&mut self, direction: CardinalDirection) {
+        if self.has_animations() {
+            return;
+        }
+        self.auto_act = Some(AutoAct::Run {
+            direction,
+            steps: 0,
+        });
+        self.run_step();
+    }
+
+    // Stopping a rest says how long the player rested for
+    pub fn stop_auto_act(&mut self) {
+        if let Some(AutoAct::Rest(turns)) = self.auto_act.take() {
+            if turns > 0 {
+                self.message_log.push(LogMessage::PlayerRests(turns));
+            }
+        }
+    }
+
+    pub fn is_auto_acting(&self) -> bool {
+        self.auto_act.is_some()
+    }
+
+    pub fn is_resting(&self) -> bool {
+        matches!(self.auto_act, Some(AutoAct::Rest(_)))
+    }
+
+    // the rest of the way to where the player is travelling
+    pub fn travel_path(&self) -> &[Coord] {
+        match self.auto_act {
+            Some(AutoAct::Travel(ref path)) => path,
+            _ => &[],
+        }
+    }
+
+    // Takes the next turn of whatever the player is doing over several
+    // turns. Each stops when done, and as soon as a hostile NPC is in
+    // view, the player is hurt or anything is added to the message log.
+    pub fn auto_act_step(&mut self) {
+        if self.has_animations() {
+            return;
+        }
+        match self.auto_act {
+            Some(AutoAct::Travel(_)) => self.travel_step(),
+            Some(AutoAct::Rest(_)) => self.rest_step(),
+            Some(AutoAct::Run { .. }) => self.run_step(),
+            None => (),
+        }
+    }
+
+    fn travel_step(&mut self) {
+        let next = match self.travel_path().first() {
+            Some(&next) => next,
+            None => {
+                self.stop_auto_act();
+                return;
+            }
+        };
+        if self.is_hostile_npc_visible() || self.world.npc_at(next).is_some() {
+            self.stop_auto_act();
+            return;
+        }
+        // opening a door is a free action, and the next step goes through
+        if self.world.maybe_open_door(next).is_ok() {
+            return;
+        }
+        let start = self.player_coord();
+        let direction = match CardinalDirection::all().find(|d| start + d.coord() == next) {
+            Some(direction) => direction,
+            None => {
+                self.stop_auto_act();
+                return;
+            }
+        };
+        let hit_points = self.player_hit_points().current();
+        let message_log_len = self.message_log.end();
+        self.maybe_move_player(direction);
+        if self.player_coord() != next {
+            self.stop_auto_act();
+        } else if let Some(AutoAct::Travel(ref mut path)) = self.auto_act {
+            path.remove(0);
+            if path.is_empty() {
+                self.stop_auto_act();
+            }
+        }
+        if !self.is_player_alive()
+            || self.player_hit_points().current() < hit_points
+            || self.message_log.end() != message_log_len
+        {
+            self.stop_auto_act();
+        }
+    }
+
+    fn rest_step(&mut self) {
+        if self.is_hostile_npc_visible() {
+            self.stop_auto_act();
+            return;
+        }
+        let hit_points = self.player_hit_points().current();
+        let message_log_len = self.message_log.end();
+        self.wait_player();
+        let turns = match self.auto_act {
+            Some(AutoAct::Rest(ref mut turns)) => {
+                *turns += 1;
+                *turns
+            }
+            _ => return,
+        };
+        let now = self.player_hit_points();
+        if !self.is_player_alive()
+            || now.current() < hit_points
+            || now.is_full()
+            || turns >= MAX_REST_TURNS
+            || self.message_log.end() != message_log_len
+        {
+            self.stop_auto_act();
+        }
+    }
+
+    fn run_step(&mut self) {
+        let (direction, steps) = match self.auto_act {
+            Some(AutoAct::Run { direction, steps }) => (direction, steps),
+            _ => return,
+        };
+        let start = self.player_coord();
+        let next = start + direction.coord();
+        if steps > 0
+            && (self.is_any_npc_visible()
+                || self.world.npc_at(next).is_some()
+                || !self.world.can_npc_enter_ignoring_other_npcs(next))
+        {
+            self.stop_auto_act();
+            return;
+        }
+        let open_neighbours = self.open_neighbour_count(start);
+        let interesting_neighbours = self.interesting_neighbours(start);
+        let hit_points = self.player_hit_points().current();
+        let message_log_len = self.message_log.end();
+        self.maybe_move_player(direction);
+        if let Some(AutoAct::Run { ref mut steps, .. }) = self.auto_act {
+            *steps += 1;
+        }
+        let end = self.player_coord();
+        // the first step may be out of a junction, so the count of open
+        // neighbours only matters after that
+        if end != next
+            || !self.is_player_alive()
+            || self.player_hit_points().current() != hit_points
+            || self.message_log.end() != message_log_len
+            || (steps > 0 && self.open_neighbour_count(end) != open_neighbours)
+            || self
+                .interesting_neighbours(end)
+                .iter()
+                .any(|coord| !interesting_neighbours.contains(coord))
+        {
+            self.stop_auto_act();
+        }
+    }
+
+    // How many of a cell's orthogonal neighbours can be walked into. This
+    // changes when a corridor meets another or opens into a room.
+    fn open_neighbour_count(&self, coord: Coord) -> usize {
+        CardinalDirection::all()
+            .filter(|direction| {
+                self.world
+                    .can_npc_enter_ignoring_other_npcs(coord + direction.coord())
+            })
+            .count()
+    }
+
+    // The cells next to `coord` which the player would want to stop
+    // running beside: doors, stairs, items and other features
+    fn interesting_neighbours(&self, coord: Coord) -> Vec<Coord> {
+        Direction::all()
+            .map(|direction| coord + direction.coord())
+            .filter(|&neighbour| {
+                self.world.is_door_at(neighbour)
+                    || self
+                        .world
+                        .floor_things_at(neighbour)
+                        .iter()
+                        .any(|thing| !matches!(thing, FloorThing::Corpse(_)))
+            })
+            .collect()
+    }
+
+    fn is_hostile_npc_visible(&self) -> bool {
+        self.ai_state
+            .entities()
+            .any(|entity| !self.world.is_neutral(entity) && self.is_living_npc_visible(entity))
+    }
+
+    fn is_any_npc_visible(&self) -> bool {
+        self.ai_state
+            .entities()
+            .any(|entity| self.is_living_npc_visible(entity))
+    }
+
+    fn is_living_npc_visible(&self, entity: Entity) -> bool {
+        self.world.is_living_character(entity)
+            && self
+                .world
+                .entity_coord(entity)
+                .map(|coord| {
+                    matches!(
+                        self.visibility_grid.cell_visibility(coord),
+                        CellVisibility::Currently
+                    )
+                })
+                .unwrap_or(false)
     }
 
     pub fn maybe_player_get_item(&mut self) {
@@ -114,17 +1173,80 @@ impl GameState {
         }
     }
 
+    pub fn player_search(&mut self) {
+        if self.has_animations() {
+            return;
+        }
+        self.world.active_search(
+            self.player_entity,
+            &mut self.message_log,
+            &mut self.rngs.terrain,
+        );
+        self.ai_turn();
+    }
+
+    pub fn maybe_player_close_door(&mut self) {
+        if self.has_animations() {
+            return;
+        }
+        if self
+            .world
+            .maybe_close_door(self.player_entity, &mut self.message_log)
+            .is_ok()
+        {
+            self.ai_turn();
+        }
+    }
+
+    pub fn maybe_player_pray(&mut self) {
+        if self.has_animations() {
+            return;
+        }
+        if let Ok(summoned) = self.world.maybe_pray(
+            self.player_entity,
+            &mut self.message_log,
+            &mut self.rngs.items,
+        ) {
+            for entity in summoned {
+                // summoned NPCs know exactly where the player is
+                let mut agent = Agent::new(self.rngs.ai.gen());
+                agent.alert();
+                self.ai_state.insert(entity, agent);
+            }
+            self.ai_turn();
+        }
+    }
+
+    pub fn is_player_on_altar(&self) -> bool {
+        self.world.coord_contains_altar(self.player_coord())
+    }
+
+    pub fn maybe_player_pay(&mut self) {
+        if self.has_animations() {
+            return;
+        }
+        if self
+            .world
+            .maybe_pay(self.player_entity, &mut self.message_log)
+            .is_ok()
+        {
+            self.ai_turn();
+        }
+    }
+
     pub fn maybe_player_use_item(&mut self, inventory_index: usize) -> Result<ItemUsage, ()> {
         if self.has_animations() {
             return Err(());
         }
         let result =
-            self
-              .world
-              .maybe_use_item(self.player_entity, inventory_index, &mut self.message_log);
+            self.world
+                .maybe_use_item(self.player_entity, inventory_index, &mut self.message_log);
         if let Ok(usage) = result {
             match usage {
-                ItemUsage::Immediate => self.ai_turn(),
+                ItemUsage::Immediate => {
+                    self.clear_used_quick_slots();
+                    self.ai_turn();
+                }
                 ItemUsage::Aim => (),
             }
         }
@@ -136,18 +1258,132 @@ impl GameState {
         inventory_index: usize,
         target: Coord,
     ) -> Result<(), ()> {
-        self.world.maybe_use_item_aim(
+        if self.is_player_blind()
+            && !matches!(
+                self.visibility_grid.cell_visibility(target),
+                CellVisibility::Currently
+            )
+        {
+            self.message_log.push(LogMessage::CannotSeeTarget);
+            return Err(());
+        }
+        let result = self.world.maybe_use_item_aim(
             self.player_entity,
             inventory_index,
             target,
             &mut self.message_log,
-        )
+        );
+        if result.is_ok() {
+            self.clear_used_quick_slots();
+        }
+        result
+    }
+
+    // Binds a quick slot to the item in an inventory slot. Each inventory
+    // slot has at most one quick slot.
+    pub fn assign_quick_slot(
+        &mut self,
+        quick_slot: usize,
+        inventory_index: usize,
+    ) -> Result<(), ()> {
+        let item_type = self.player_inventory_item_type(inventory_index).ok_or(())?;
+        for bound in self.quick_slots.iter_mut() {
+            if bound.map(|bound| bound.inventory_index) == Some(inventory_index) {
+                *bound = None;
+            }
+        }
+        self.quick_slots[quick_slot] = Some(QuickSlot {
+            inventory_index,
+            item_type,
+        });
+        self.message_log.push(LogMessage::PlayerAssignsQuickSlot(
+            quick_slot as u32 + 1,
+            item_type,
+        ));
+        Ok(())
+    }
+
+    // The inventory slot a quick slot is bound to. A binding whose item
+    // has gone is forgotten, so it can never use something else.
+    pub fn quick_slot_inventory_index(&mut self, quick_slot: usize) -> Option<usize> {
+        match self.quick_slots[quick_slot] {
+            Some(bound) if self.is_quick_slot_current(bound) => Some(bound.inventory_index),
+            _ => {
+                self.quick_slots[quick_slot] = None;
+                self.message_log
+                    .push(LogMessage::NothingBoundToQuickSlot(quick_slot as u32 + 1));
+                None
+            }
+        }
+    }
+
+    // the type of item bound to each quick slot, for the quickbar
+    pub fn quick_slots(&self) -> [Option<ItemType>; NUM_QUICK_SLOTS] {
+        let mut item_types = [None; NUM_QUICK_SLOTS];
+        for (item_type, bound) in item_types.iter_mut().zip(self.quick_slots.iter()) {
+            *item_type = bound.map(|bound| bound.item_type);
+        }
+        item_types
+    }
+
+    fn is_quick_slot_current(&self, quick_slot: QuickSlot) -> bool {
+        self.player_inventory_item_type(quick_slot.inventory_index) == Some(quick_slot.item_type)
+    }
+
+    // Forgets bindings to items used up by the player
+    fn clear_used_quick_slots(&mut self) {
+        for i in 0..NUM_QUICK_SLOTS {
+            if let Some(bound) = self.quick_slots[i] {
+                if !self.is_quick_slot_current(bound) {
+                    self.quick_slots[i] = None;
+                }
+            }
+        }
+    }
+
+    fn player_inventory_item_type(&self, inventory_index: usize) -> Option<ItemType> {
+        self.player_inventory()
+            .get(inventory_index)
+            .ok()
+            .and_then(|entity| self.world.item_type(entity))
     }
 
     pub fn maybe_player_drop_item(&mut self, inventory_index: usize) -> Result<(), ()> {
         let result =
             self.world
-              .maybe_drop_item(self.player_entity, inventory_index, &mut self.message_log);
+                .maybe_drop_item(self.player_entity, inventory_index, &mut self.message_log);
+        if result.is_ok() {
+            self.ai_turn();
+        }
+        result
+    }
+
+    pub fn maybe_player_wield_item(&mut self, inventory_index: usize) -> Result<(), ()> {
+        let result =
+            self.world
+                .maybe_wield_item(self.player_entity, inventory_index, &mut self.message_log);
+        if result.is_ok() {
+            self.ai_turn();
+        }
+        result
+    }
+
+    pub fn maybe_player_wear_item(&mut self, inventory_index: usize) -> Result<(), ()> {
+        let result =
+            self.world
+                .maybe_wear_item(self.player_entity, inventory_index, &mut self.message_log);
+        if result.is_ok() {
+            self.ai_turn();
+        }
+        result
+    }
+
+    pub fn maybe_player_unequip_item(&mut self, inventory_index: usize) -> Result<(), ()> {
+        let result = self.world.maybe_unequip_item(
+            self.player_entity,
+            inventory_index,
+            &mut self.message_log,
+        );
         if result.is_ok() {
             self.ai_turn();
         }
@@ -155,25 +1391,261 @@ impl GameState {
     }
 
     pub fn player_level_up_and_descend(&mut self, level_up: LevelUp) {
-        assert!(self.is_player_on_stairs());
         self.world.level_up_character(self.player_entity, level_up);
+        self.run_stats.levels_gained += 1;
+        self.player_descend();
+    }
+
+    // Leaves the level without the stairs, arriving on the next one as if
+    // by them, for skipping levels
+    fn level_up_and_descend_from_anywhere(&mut self, level_up: LevelUp) {
+        self.world.level_up_character(self.player_entity, level_up);
+        self.run_stats.levels_gained += 1;
+        let player_data = self.store_current_level();
+        self.enter_level(self.dungeon_level + 1, player_data, Arrival::Stairs);
+    }
+
+    pub fn player_descend(&mut self) {
+        assert!(self.is_player_on_stairs());
+        let player_data = self.store_current_level();
+        self.events.push(GameEvent::StairsDescended);
+        self.enter_level(self.dungeon_level + 1, player_data, Arrival::Stairs);
+    }
+
+    pub fn player_ascend(&mut self) {
+        assert!(self.is_player_on_stairs_up());
+        let player_data = self.store_current_level();
+        self.events.push(GameEvent::StairsAscended);
+        self.enter_level(self.dungeon_level - 1, player_data, Arrival::Stairs);
+    }
+
+    pub fn is_chasm_in_direction(&self, direction: CardinalDirection) -> bool {
+        self.world
+            .coord_contains_chasm(self.player_coord() + direction.coord())
+    }
+
+    pub fn prompt_jump_into_chasm(&mut self) {
+        self.message_log.push(LogMessage::ConfirmJumpIntoChasm);
+    }
+
+    pub fn cancel_jump_into_chasm(&mut self) {
+        self.message_log.push(LogMessage::PlayerStepsBackFromChasm);
+    }
+
+    // Falling is a quicker but more dangerous way down than the stairs.
+    // The player lands somewhere random and doesn't get to level up.
+    pub fn player_jump_into_chasm(&mut self) {
+        if self.has_animations() {
+            return;
+        }
+        self.message_log.push(LogMessage::PlayerFallsIntoChasm);
+        self.player_fall();
+    }
+
+    fn player_fall(&mut self) {
+        self.world.damage_character_by_fall(self.player_entity);
+        self.record_stats();
+        self.check_low_health();
+        if !self.is_player_alive() {
+            return;
+        }
+        let player_data = self.store_current_level();
+        self.events.push(GameEvent::PlayerFell);
+        self.enter_level(self.dungeon_level + 1, player_data, Arrival::Fall);
+    }
+
+    // Removes the player from the current level, and sets the level
+    // aside so it can be restored if the player comes back.
+    fn store_current_level(&mut self) -> CharacterData {
+        self.record_stats();
+        self.events.extend(self.world.take_events());
         let player_data = self.world.remove_character(self.player_entity);
-        self.world.clear();
-        self.visibility_grid.clear();
-        self.dungeon_level += 1;
+        let size = self.world.size();
+        let stored_level = StoredLevel {
+            world: mem::replace(&mut self.world, World::new(size)),
+            visibility_grid: mem::replace(&mut self.visibility_grid, VisibilityGrid::new(size)),
+            ai_state: mem::take(&mut self.ai_state),
+        };
+        self.stored_levels.insert(self.dungeon_level, stored_level);
+        player_data
+    }
+
+    // Restores a previously visited level, or generates a new one. On
+    // a restored level the player is placed on the stairs leading back
+    // to the level they came from. A player who fell lands anywhere.
+    fn enter_level(&mut self, dungeon_level: u32, player_data: CharacterData, arrival: Arrival) {
+        self.stop_auto_act();
+        let arriving_from_above = dungeon_level > self.dungeon_level;
+        self.dungeon_level = dungeon_level;
+        let mut new_level_stats = None;
+        if let Some(StoredLevel {
+            world,
+            visibility_grid,
+            ai_state,
+        }) = self.stored_levels.remove(&dungeon_level)
+        {
+            self.world = world;
+            self.visibility_grid = visibility_grid;
+            self.ai_state = ai_state;
+            let arrival_coord = match arrival {
+                Arrival::Fall => self.world.random_free_coord(&mut self.rngs.terrain),
+                Arrival::Stairs if arriving_from_above => self.world.stairs_up_coord(),
+                Arrival::Stairs => self.world.stairs_coord(),
+            }
+            .expect("stored level has no arrival point");
+            self.player_entity = self.world.insert_character_near(arrival_coord, player_data);
+        } else {
+            let Populate {
+                player_entity,
+                ai_state,
+                stats,
+                level_stats,
+            } = self.world.populate(
+                self.dungeon_level,
+                &self.spawn_tables,
+                // the player brings everything with them
+                &StartingKit::default(),
+                &mut level_rng(self.run_seed, self.dungeon_level),
+            );
+            self.world.apply_difficulty(&self.difficulty.profile());
+            log_generated_level(self.dungeon_level, &stats);
+
+            self.world.replace_character(player_entity, player_data);
+            self.player_entity = player_entity;
+            self.ai_state = ai_state;
+            new_level_stats = Some(level_stats);
+            if arrival == Arrival::Fall {
+                if let Some(landing_coord) = self.world.random_free_coord(&mut self.rngs.terrain) {
+                    self.world.move_character_to(player_entity, landing_coord);
+                }
+            }
+        }
+        let theme = self.level_theme();
+        self.message_log.push(LogMessage::PlayerEntersLevel(theme));
+        if let Some(level_stats) = new_level_stats {
+            self.push_level_feelings(&level_stats);
+        }
+    }
+
+    // Hints at what was generated on a level the player has just arrived
+    // on for the first time
+    fn push_level_feelings(&mut self, level_stats: &LevelStats) {
+        if level_stats.has_danger_room {
+            self.message_log.push(LogMessage::HeavyBreathing);
+        }
+        for feeling in level_stats.feelings() {
+            self.message_log.push(LogMessage::LevelFeeling(feeling));
+        }
+    }
+
+    // Swaps the current level for one which has already been laid out,
+    // keeping the player as they are
+    fn replace_level(&mut self, terrain: Dungeon) {
+        let player_data = self.world.remove_character(self.player_entity);
+        let size = self.world.size();
+        self.world = World::new(size);
+        self.visibility_grid = VisibilityGrid::new(size);
+        let Populate {
+            player_entity,
+            ai_state,
+            stats,
+            ..
+        } = self.world.populate_from(
+            terrain,
+            self.dungeon_level,
+            &StartingKit::default(),
+            &mut level_rng(self.run_seed, self.dungeon_level),
+        );
+        self.world.apply_difficulty(&self.difficulty.profile());
+        log_generated_level(self.dungeon_level, &stats);
+        self.world.replace_character(player_entity, player_data);
+        self.player_entity = player_entity;
+        self.ai_state = ai_state;
+    }
+
+    // Replaces the current level with a new one from a seed taken from
+    // the run's RNG, so each regeneration is different
+    fn regenerate_level(&mut self) {
+        self.stop_auto_act();
+        self.record_stats();
+        self.events.extend(self.world.take_events());
+        let player_data = self.world.remove_character(self.player_entity);
+        let size = self.world.size();
+        self.world = World::new(size);
+        self.visibility_grid = VisibilityGrid::new(size);
+        let Populate {
+            player_entity,
+            ai_state,
+            stats,
+            ..
+        } = self.world.populate(
+            self.dungeon_level,
+            &self.spawn_tables,
+            &StartingKit::default(),
+            &mut Isaac64Rng::seed_from_u64(self.rngs.terrain.gen()),
+        );
+        self.world.apply_difficulty(&self.difficulty.profile());
+        log_generated_level(self.dungeon_level, &stats);
+        self.world.replace_character(player_entity, player_data);
+        self.player_entity = player_entity;
+        self.ai_state = ai_state;
+    }
+
+    pub fn is_next_level_visited(&self) -> bool {
+        self.stored_levels.contains_key(&(self.dungeon_level + 1))
+    }
+
+    pub fn is_player_on_stairs(&self) -> bool {
+        self.world.coord_contains_stairs(self.player_coord())
+    }
+
+    pub fn is_player_on_portal(&self) -> bool {
+        self.world.coord_contains_portal(self.player_coord())
+    }
+
+    // Succeeds if the player has every rune, at which point they
+    // escape the dungeon
+    pub fn maybe_player_enter_portal(&mut self) -> Result<(), ()> {
+        let runes = self.player_runes();
+        if runes >= terrain::NUM_RUNES {
+            Ok(())
+        } else {
+            self.message_log
+                .push(LogMessage::PortalIsSealed(terrain::NUM_RUNES - runes));
+            Err(())
+        }
+    }
+
+    pub fn player_runes(&self) -> u32 {
+        self.world
+            .runes(self.player_entity)
+            .expect("player missing runes")
+    }
+
+    pub fn is_player_blind(&self) -> bool {
+        self.world.is_blind(self.player_entity)
+    }
+
+    pub fn is_player_carrying_lit_torch(&self) -> bool {
+        self.world.carried_torch(self.player_entity).is_some()
+    }
+
+    pub fn is_player_clairvoyant(&self) -> bool {
+        self.world.is_clairvoyant(self.player_entity)
+    }
 
-        let Populate {
-            player_entity,
-            ai_state,
-        } = self.world.populate(self.dungeon_level, &mut self.rng);
+    // each status the player is under with the turns it has left, most
+    // dangerous first
+    pub fn player_status_effects(&self) -> impl '_ + Iterator<Item = (StatusEffect, u32)> {
+        self.world.status_effects(self.player_entity)
+    }
 
-        self.world.replace_character(player_entity, player_data);
-        self.player_entity = player_entity;
-        self.ai_state = ai_state;
+    pub fn is_final_level(&self) -> bool {
+        self.dungeon_level == terrain::FINAL_DUNGEON_LEVEL
     }
 
-    pub fn is_player_on_stairs(&self) -> bool {
-        self.world.coord_contains_stairs(self.player_coord())
+    pub fn is_player_on_stairs_up(&self) -> bool {
+        self.world.coord_contains_stairs_up(self.player_coord())
     }
 
     pub fn player_strength(&self) -> i32 {
@@ -194,6 +1666,30 @@ impl GameState {
             .expect("player missing strength")
     }
 
+    pub fn player_attack_range(&self) -> (i32, i32) {
+        self.world
+            .attack_range(self.player_entity)
+            .expect("player missing attack stats")
+    }
+
+    pub fn player_defense_range(&self) -> (i32, i32) {
+        self.world
+            .defense_range(self.player_entity)
+            .expect("player missing defense stats")
+    }
+
+    pub fn player_gold(&self) -> u32 {
+        self.world
+            .gold(self.player_entity)
+            .expect("player missing gold")
+    }
+
+    pub fn player_debt(&self) -> u32 {
+        self.world
+            .debt(self.player_entity)
+            .expect("player missing debt")
+    }
+
     pub fn player_inventory(&self) -> &Inventory {
         self.world
             .inventory(self.player_entity)
@@ -204,6 +1700,10 @@ impl GameState {
         self.dungeon_level
     }
 
+    pub fn level_theme(&self) -> LevelTheme {
+        LevelTheme::from_dungeon_level(self.dungeon_level)
+    }
+
     pub fn item_type(&self, entity: Entity) -> Option<ItemType> {
         self.world.item_type(entity)
     }
@@ -212,7 +1712,35 @@ impl GameState {
         self.world.size()
     }
 
+    pub fn run_seed(&self) -> u64 {
+        self.run_seed
+    }
+
+    pub fn turn(&self) -> u64 {
+        self.turn
+    }
+
     fn ai_turn(&mut self) {
+        let start = Stopwatch::start();
+        self.turn += 1;
+        self.world
+            .tick_blindness(self.player_entity, &mut self.message_log);
+        self.world
+            .tick_clairvoyance(self.player_entity, &mut self.message_log);
+        self.world
+            .burn_torch(self.player_entity, &mut self.message_log);
+        self.world.burn_characters_in_lava(&mut self.message_log);
+        self.world.update_fog();
+        self.world.passive_search(
+            self.player_entity,
+            &mut self.message_log,
+            &mut self.rngs.terrain,
+        );
+        for entity in self.world.take_alarmed() {
+            if let Some(agent) = self.ai_state.get_mut(entity) {
+                agent.alert();
+            }
+        }
         self.behavior_context
             .update(self.player_entity, &self.world);
         let dead_entities = self
@@ -224,42 +1752,160 @@ impl GameState {
             self.ai_state.remove(dead_entity);
         }
         for (entity, agent) in self.ai_state.iter_mut() {
+            if self.world.is_neutral(entity) {
+                continue;
+            }
             let npc_action = agent.act(
                 entity,
                 self.player_entity,
                 &self.world,
-                &mut self.behavior_context);
+                &self.behavior_context,
+                self.turn,
+                &mut self.rngs.ai,
+            );
+            #[cfg(feature = "ai-trace")]
+            log_trace!(
+                "turn {}: NPC at {:?} chooses {:?}",
+                self.turn,
+                self.world.entity_coord(entity),
+                npc_action,
+            );
             match npc_action {
                 NpcAction::Wait => (),
-                NpcAction::Move(direction) => self.world.maybe_move_character(
-                    entity,
-                    direction,
-                    &mut self.message_log,
-                    &mut self.rng,
-                ),
+                NpcAction::Move(direction) => {
+                    // a confused NPC's wandering is one of its decisions
+                    let direction = self.world.stumble_direction(
+                        entity,
+                        direction,
+                        &mut self.message_log,
+                        &mut self.rngs.ai,
+                    );
+                    self.world.maybe_move_character(
+                        entity,
+                        direction,
+                        false,
+                        &mut self.message_log,
+                        &mut self.rngs.combat,
+                    );
+                }
             }
         }
+        self.record_stats();
+        self.check_low_health();
+        // an NPC can knock the player into a chasm
+        if self.is_player_alive() && self.world.coord_contains_chasm(self.player_coord()) {
+            self.player_fall();
+        }
+        self.ai_turn_time = start.elapsed();
+    }
+
+    // How far NPCs would walk to reach the player, as they planned on
+    // their last turn
+    pub fn npc_distance_to_player(&self, coord: Coord) -> Option<u32> {
+        self.behavior_context.distances_to_player().distance(coord)
     }
 
-    pub fn message_log(&self) -> &[LogMessage] {
+    pub fn message_log(&self) -> &MessageLog {
         &self.message_log
     }
 
-    pub fn entities_to_render<'a>(&'a self) -> impl 'a + Iterator<Item = EntityToRender> {
+    // Only entities the player can see or sense are included, so nothing
+    // comes back for cells they have never seen. Given the top-left and
+    // size of the part of the map on screen, entities outside it are left
+    // out too.
+    pub fn entities_to_render<'a>(
+        &'a self,
+        on_screen: Option<(Coord, Size)>,
+    ) -> impl 'a + Iterator<Item = EntityToRender> {
         let tile_component = &self.world.components.tile;
         let spatial_table = &self.world.spatial_table;
         let visibility_grid = &self.visibility_grid;
+        let world = &self.world;
+        let clairvoyant = self.is_player_clairvoyant();
         tile_component.iter().filter_map(move |(entity, &tile)| {
             let &location = spatial_table.location_of(entity)?;
+            if let Some((top_left, size)) = on_screen {
+                if !(location.coord - top_left).is_valid(size) {
+                    return None;
+                }
+            }
             let visibility = visibility_grid.cell_visibility(location.coord);
+            // cells which aren't currently visible are drawn from memory
+            if !matches!(visibility, CellVisibility::Currently) {
+                return match tile {
+                    Tile::Npc(_) if clairvoyant => Some(EntityToRender {
+                        tile,
+                        location,
+                        light: 0,
+                        distance_brightness: 0,
+                        sensed: true,
+                    }),
+                    _ => None,
+                };
+            }
+            // fog hides everything in it other than the player
+            let hidden_by_fog = matches!(location.layer, Some(Layer::Character | Layer::Object))
+                && !matches!(tile, Tile::Player)
+                && world.coord_contains_fog(location.coord);
+            if hidden_by_fog {
+                return None;
+            }
+            let light = visibility_grid.light(location.coord);
+            let distance_brightness = visibility_grid.distance_brightness(location.coord);
             Some(EntityToRender {
                 tile,
                 location,
-                visibility,
+                light,
+                distance_brightness,
+                sensed: false,
             })
         })
     }
 
+    // The cells covered by each frame of a visual effect, limited to those
+    // the player can see
+    pub fn visual_effects_to_render(&self) -> Vec<(Coord, VisualEffect)> {
+        let mut to_render = Vec::new();
+        for visual_effect in self.world.visual_effects() {
+            match visual_effect.effect_type {
+                VisualEffectType::Blast => {
+                    // a ring which grows by a cell each frame
+                    let radius = visual_effect.frame as i32;
+                    for y in -radius..=radius {
+                        for x in -radius..=radius {
+                            if x.abs().max(y.abs()) == radius {
+                                let coord = visual_effect.coord + Coord::new(x, y);
+                                to_render.push((coord, visual_effect));
+                            }
+                        }
+                    }
+                }
+                VisualEffectType::Sparkle => to_render.push((visual_effect.coord, visual_effect)),
+            }
+        }
+        to_render.retain(|&(coord, _)| {
+            matches!(
+                self.visibility_grid.cell_visibility(coord),
+                CellVisibility::Currently
+            )
+        });
+        to_render
+    }
+
+    pub fn wall_connections(&self, coord: Coord) -> Option<u8> {
+        self.world.wall_connections(coord)
+    }
+
+    pub fn known_stairs_coord(&self) -> Option<Coord> {
+        self.visibility_grid.known_stairs_coord()
+    }
+
+    pub fn remembered_tiles_to_render<'a>(
+        &'a self,
+    ) -> impl 'a + Iterator<Item = (Coord, Layer, Tile)> {
+        self.visibility_grid.remembered_tiles()
+    }
+
     pub fn wait_player(&mut self) {
         if self.has_animations() {
             return;
@@ -287,16 +1933,399 @@ impl GameState {
     pub fn examine_cell(&self, coord: Coord) -> Option<ExamineCell> {
         match self.visibility_grid.cell_visibility(coord) {
             CellVisibility::Currently => self.world.examine_cell(coord),
+            _ if self.is_player_clairvoyant() => self.world.examine_npc_at(coord),
             _ => None,
         }
     }
 
+    // The cells a projectile from an item in the player's inventory would
+    // pass through on its way to `target`
+    pub fn aim_preview(&self, inventory_index: usize, target: Coord) -> Option<AimPreview> {
+        let item_entity = self.player_inventory().get(inventory_index).ok()?;
+        let item_type = self.world.item_type(item_entity)?;
+        let projectile = self.world.aimed_projectile(self.player_entity, item_type)?;
+        let from = self.player_coord();
+        let stop = self
+            .world
+            .first_blocking_cell_on_line(from, target, projectile);
+        let mut preview = AimPreview {
+            path: Vec::new(),
+            beyond: Vec::new(),
+            area: Vec::new(),
+        };
+        let mut stopped = false;
+        let mut coord = from;
+        for direction in CardinalStepIter::new(target - from) {
+            coord = coord + direction.coord();
+            if stopped {
+                preview.beyond.push(coord);
+                continue;
+            }
+            match stop {
+                Some((stop_coord, stop)) if stop_coord == coord => {
+                    stopped = true;
+                    // walls stop the projectile before it enters their cell
+                    if stop == ProjectileStop::Blocked {
+                        preview.beyond.push(coord);
+                    } else {
+                        preview.path.push(coord);
+                    }
+                }
+                _ => preview.path.push(coord),
+            }
+        }
+        let centre = preview.path.last().copied().unwrap_or(from);
+        if let Some((top_left, size)) = World::projectile_area(centre, projectile) {
+            preview.area = size
+                .coord_iter_row_major()
+                .map(|offset| top_left + offset)
+                .collect();
+        }
+        Some(preview)
+    }
+
+    // Whether the NPC in a cell the player can see has noticed them, and
+    // how close the player has to get before it does
+    pub fn npc_awareness(&self, coord: Coord) -> Option<NpcAwareness> {
+        if !matches!(
+            self.visibility_grid.cell_visibility(coord),
+            CellVisibility::Currently
+        ) {
+            return None;
+        }
+        if self.world.coord_contains_fog(coord) {
+            return None;
+        }
+        let npc = self.world.npc_at(coord)?;
+        if self.world.is_neutral(npc) {
+            return None;
+        }
+        let agent = self.ai_state.get(npc)?;
+        Some(NpcAwareness {
+            noticed: agent.has_noticed_player(),
+            detection_range: self.world.npc_detection_range(npc, self.player_entity),
+        })
+    }
+
+    // What to expect of the player and the NPC at the coord attacking each
+    // other, in that order
+    pub fn combat_forecast(&self, coord: Coord) -> Option<(CombatForecast, CombatForecast)> {
+        if !matches!(
+            self.visibility_grid.cell_visibility(coord),
+            CellVisibility::Currently
+        ) {
+            return None;
+        }
+        if self.world.coord_contains_fog(coord) {
+            return None;
+        }
+        let npc = self.world.npc_at(coord)?;
+        Some((
+            self.world.combat_forecast(self.player_entity, npc)?,
+            self.world.combat_forecast(npc, self.player_entity)?,
+        ))
+    }
+
     pub fn player_equipped_inventory_indices(&self) -> EquippedInventoryIndices {
         self.world.equipped_inventory_indices(self.player_entity)
     }
 }
 
+fn log_generated_level(dungeon_level: u32, stats: &DungeonStats) {
+    log_debug!(
+        "Generated level {}: {} rooms, {} floor tiles, {} NPCs, {} items",
+        dungeon_level,
+        stats.rooms,
+        stats.floor_tiles,
+        stats.npcs,
+        stats.items,
+    );
+}
+
+// An RNG for each part of the game which makes random choices during
+// play, so that a change to how many numbers one part draws doesn't
+// change what the others get. Levels are generated from level_rng.
+#[derive(Serialize, Deserialize)]
+#[serde(from = "SavedRngs")]
+struct Rngs {
+    // placing and searching the level, and regenerating it
+    terrain: Isaac64Rng,
+    // attacks and what happens on entering a cell
+    combat: Isaac64Rng,
+    // NPCs' decisions
+    ai: Isaac64Rng,
+    // the effects of items and altars
+    items: Isaac64Rng,
+}
+
+impl Rngs {
+    fn new(run_seed: u64) -> Self {
+        Self::from_rng(Isaac64Rng::seed_from_u64(run_seed))
+    }
+
+    fn from_rng(mut rng: Isaac64Rng) -> Self {
+        Self {
+            terrain: Isaac64Rng::seed_from_u64(rng.gen()),
+            combat: Isaac64Rng::seed_from_u64(rng.gen()),
+            ai: Isaac64Rng::seed_from_u64(rng.gen()),
+            items: Isaac64Rng::seed_from_u64(rng.gen()),
+        }
+    }
+
+    // The next number each would give, without drawing it
+    fn next_values(&self) -> [u64; 4] {
+        [
+            self.terrain.clone().gen(),
+            self.combat.clone().gen(),
+            self.ai.clone().gen(),
+            self.items.clone().gen(),
+        ]
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedRngs {
+    Split {
+        terrain: Isaac64Rng,
+        combat: Isaac64Rng,
+        ai: Isaac64Rng,
+        items: Isaac64Rng,
+    },
+    // the single RNG of an older save, which the others are seeded from
+    Single(Isaac64Rng),
+}
+
+impl From<SavedRngs> for Rngs {
+    fn from(saved: SavedRngs) -> Self {
+        match saved {
+            SavedRngs::Split {
+                terrain,
+                combat,
+                ai,
+                items,
+            } => Self {
+                terrain,
+                combat,
+                ai,
+                items,
+            },
+            SavedRngs::Single(rng) => Self::from_rng(rng),
+        }
+    }
+}
+
+// FNV-1a, for hashes which have to be the same on every platform and
+// build, unlike those of DefaultHasher. Integers are hashed as
+// little-endian, with usize and isize widened to 64 bits, so 32-bit
+// targets such as wasm agree with the rest.
+struct StableHasher(u64);
+
+impl StableHasher {
+    const OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01B3;
+
+    fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_i64(i as i64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// Each level is generated with its own rng derived from the run's
+// seed and the level's depth, so a level's layout doesn't depend on
+// what the player did before reaching it.
+fn level_rng(run_seed: u64, dungeon_level: u32) -> Isaac64Rng {
+    const LEVEL_SEED_MULTIPLIER: u64 = 0x2545_F491_4F6C_DD1D;
+    Isaac64Rng::seed_from_u64(run_seed ^ (dungeon_level as u64).wrapping_mul(LEVEL_SEED_MULTIPLIER))
+}
+
+// A level generated just as it would be on first arriving there in a run
+// with the given seed, without the rest of the game. The map dump tool
+// uses it to show the effects of changes to level generation.
+pub struct LevelPreview {
+    world: World,
+    stats: DungeonStats,
+}
+
+impl LevelPreview {
+    pub fn generate(
+        size: Size,
+        rng_seed: u64,
+        dungeon_level: u32,
+        spawn_tables: &SpawnTables,
+    ) -> Self {
+        let mut world = World::new(size);
+        let Populate { stats, .. } = world.populate(
+            dungeon_level,
+            spawn_tables,
+            &StartingKit::default(),
+            &mut level_rng(rng_seed, dungeon_level),
+        );
+        Self { world, stats }
+    }
+
+    pub fn size(&self) -> Size {
+        self.world.size()
+    }
+
+    pub fn top_tile_at(&self, coord: Coord) -> Option<Tile> {
+        self.world.top_tile_at(coord)
+    }
+
+    pub fn stats(&self) -> DungeonStats {
+        self.stats
+    }
+}
+
+// the most entries the message log keeps
+const MAX_LOG_ENTRIES: usize = 1000;
+
+// Consecutive identical messages are stored as a single entry with a
+// count, so repeated events don't flood the log. Only the latest entries
+// are kept, so the log doesn't grow with the length of the run. The run's
+// totals are kept in RunStats rather than counted from the log.
+#[derive(Default, Serialize, Deserialize)]
+pub struct MessageLog {
+    entries: VecDeque<LogEntry>,
+    // entries dropped from the front, so that positions stay the same
+    #[serde(default)]
+    num_dropped: usize,
+}
+
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub message: LogMessage,
+    pub count: u32,
+}
+
+impl MessageLog {
+    pub fn push(&mut self, message: LogMessage) {
+        if let Some(last) = self.entries.last_mut() {
+            if last.message == message {
+                last.count += 1;
+                return;
+            }
+        }
+        self.entries.push_back(LogEntry { message, count: 1 });
+        // a save from before the log was capped may hold more
+        while self.entries.len() > MAX_LOG_ENTRIES {
+            self.entries.pop_front();
+            self.num_dropped += 1;
+        }
+    }
+
+    // The position after the latest entry. Positions count the entries
+    // dropped to keep the log short, so this only ever increases.
+    pub fn end(&self) -> usize {
+        self.num_dropped + self.entries.len()
+    }
+
+    // The entries from the position to the latest, skipping any which have
+    // been dropped
+    pub fn entries_from(&self, start: usize) -> vec_deque::Iter<LogEntry> {
+        let index = start
+            .saturating_sub(self.num_dropped)
+            .min(self.entries.len());
+        self.entries.range(index..)
+    }
+}
+
+// Totals of what the player has done over the whole run, shown on the
+// statistics screen and when the game ends
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct RunStats {
+    // in the order each type of NPC was first killed
+    kills: Vec<(NpcType, u32)>,
+    pub damage_dealt: u32,
+    pub damage_taken: u32,
+    pub potions_drunk: u32,
+    pub scrolls_read: u32,
+    pub items_picked_up: u32,
+    // one for each level up on the stairs down
+    #[serde(default)]
+    pub levels_gained: u32,
+    // None while the player is alive, and for a player who escaped
+    #[serde(default)]
+    pub killed_by: Option<DamageSource>,
+}
+
+impl RunStats {
+    fn record(&mut self, stat_event: StatEvent) {
+        match stat_event {
+            StatEvent::PlayerKills(npc_type) => {
+                match self
+                    .kills
+                    .iter_mut()
+                    .find(|(killed, _)| *killed == npc_type)
+                {
+                    Some((_, count)) => *count += 1,
+                    None => self.kills.push((npc_type, 1)),
+                }
+            }
+            StatEvent::PlayerDealsDamage(damage) => self.damage_dealt += damage,
+            StatEvent::PlayerTakesDamage(damage) => self.damage_taken += damage,
+            StatEvent::PlayerUses(item_type) => match item_type {
+                ItemType::HealthPotion => self.potions_drunk += 1,
+                ItemType::FireballScroll
+                | ItemType::ConfusionScroll
+                | ItemType::ClairvoyanceScroll => self.scrolls_read += 1,
+                _ => (),
+            },
+            StatEvent::PlayerPicksUp(_) => self.items_picked_up += 1,
+            StatEvent::PlayerDies(source) => self.killed_by = Some(source),
+        }
+    }
+
+    pub fn kills(&self) -> &[(NpcType, u32)] {
+        &self.kills
+    }
+
+    pub fn total_kills(&self) -> u32 {
+        self.kills.iter().map(|&(_, count)| count).sum()
+    }
+
+    // the player starts at level 1
+    pub fn character_level(&self) -> u32 {
+        1 + self.levels_gained
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LogMessage {
     PlayerAttacksNpc(NpcType),
     NpcAttacksPlayer(NpcType),
@@ -304,9 +2333,19 @@ pub enum LogMessage {
     NpcKillsPlayer(NpcType),
     PlayerGets(ItemType),
     PlayerInventoryIsFull,
+    // with the number of slots the inventory now has
+    PlayerPutsOnBackpack(u32),
+    InventoryCannotGrow,
+    ScreenshotSaved,
+    ScreenshotFailed,
     NoItemUnderPlayer,
     NoItemInInventorySlot,
     PlayerHeals,
+    PlayerReadsClairvoyance,
+    TorchGoesOut,
+    PlayerThrowsSmokeBomb,
+    TorchIsAlreadyLit,
+    PlayerClairvoyanceEnds,
     PlayerDrops(ItemType),
     NoSpaceToDropItem,
     PlayerLaunchesProjectile(ProjectileType),
@@ -316,20 +2355,566 @@ pub enum LogMessage {
     PlayerDodges(NpcType),
     NpcDodges(NpcType),
     PlayerEquips(ItemType),
+    PlayerUnequips(ItemType),
+    CannotWield(ItemType),
+    CannotWear(ItemType),
+    ItemIsAlreadyEquipped(ItemType),
+    ItemIsNotEquipped(ItemType),
+    PlayerGetsGold(u32),
+    PlayerOwes(u32),
+    PlayerPays(u32),
+    CannotAffordDebt(u32),
+    NothingToPayFor,
+    NoShopkeeperNearby,
+    PlayerSwapsWith(NpcType),
+    PlayerSwingsAtNothing,
+    PlayerAssignsQuickSlot(u32, ItemType),
+    NothingBoundToQuickSlot(u32),
+    NpcBecomesHostile(NpcType),
+    PrayerHeals,
+    PrayerIncreasesStat(LevelUp),
+    PrayerIdentifiesInventory,
+    PrayerAngersGods,
+    PlayerClosesDoor,
+    NoDoorToClose,
+    DoorIsBlocked,
+    TrapTriggered(Option<NpcType>, TrapType),
+    PlayerNoticesTrap(TrapType),
+    LavaBurns(Option<NpcType>),
+    // None for the player
+    SlamsIntoWall(Option<NpcType>),
+    SlamsInto(Option<NpcType>, NpcType),
+    KnockedIntoChasm(Option<NpcType>),
+    PlayerFindsSecretDoor,
+    PlayerEntersLevel(LevelTheme),
+    FireballFizzles,
+    HeavyBreathing,
+    LevelFeeling(LevelFeeling),
+    PlayerGetsRune(u32),
+    PortalOpens,
+    PortalIsSealed(u32),
+    PlayerIsBlinded(NpcType),
+    PlayerCanSeeAgain,
+    PlayerIsBadlyWounded,
+    StairsNotKnown,
+    NoKnownPath,
+    NoTravelTargets,
+    PlayerRests(u32),
+    NoNeedToRest,
+    CannotRestNearEnemies,
+    CannotSeeTarget,
+    ConfirmJumpIntoChasm,
+    PlayerStepsBackFromChasm,
+    PlayerFallsIntoChasm,
+    PlayerSeesHere(FloorThings),
+    Wizard(WizardCommand),
 }
 
 #[derive(Clone, Copy, Debug)]
 pub enum ExamineCell {
-    Npc(NpcType),
+    Npc {
+        npc_type: NpcType,
+        hit_points: HitPoints,
+    },
     NpcCorpse(NpcType),
     Item(ItemType),
+    Gold,
+    Altar,
+    Trap(TrapType),
+    Liquid(Liquid),
+    Chasm,
+    Rune,
+    Portal,
+    Fog,
     Player,
 }
 
+pub struct AimPreview {
+    // the cells a projectile passes through, ending where it lands
+    pub path: Vec<Coord>,
+    // the cells between where it lands and the target
+    pub beyond: Vec<Coord>,
+    // the cells around where it lands which it also affects
+    pub area: Vec<Coord>,
+}
+
 #[derive(Clone, Copy, Debug)]
+pub struct NpcAwareness {
+    pub noticed: bool,
+    pub detection_range: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LevelUp {
     Strength,
     Dexterity,
     Intelligence,
     Health,
 }
+
+impl LevelUp {
+    pub const ALL: [LevelUp; 4] = [
+        LevelUp::Strength,
+        LevelUp::Dexterity,
+        LevelUp::Intelligence,
+        LevelUp::Health,
+    ];
+
+    fn id(self) -> &'static str {
+        match self {
+            Self::Strength => "strength",
+            Self::Dexterity => "dexterity",
+            Self::Intelligence => "intelligence",
+            Self::Health => "health",
+        }
+    }
+}
+
+impl fmt::Display for LevelUp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+// Either the whole name or its first letter, as in "s" for strength
+impl FromStr for LevelUp {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .cloned()
+            .find(|level_up| level_up.id() == s || (s.len() == 1 && level_up.id().starts_with(s)))
+            .ok_or_else(|| {
+                format!(
+                    "unknown stat {:?} (expected strength, dexterity, intelligence or health, or their first letters)",
+                    s
+                )
+            })
+    }
+}
+
+// What the player chose to start with, which decides their starting kit
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Background {
+    Warrior,
+    Scholar,
+    Scavenger,
+}
+
+impl Background {
+    pub fn starting_kit(self) -> StartingKit {
+        match self {
+            Self::Warrior => StartingKit {
+                held: Some(ItemType::Sword),
+                worn: Some(ItemType::Armor),
+                ..Default::default()
+            },
+            Self::Scholar => StartingKit {
+                held: Some(ItemType::Staff),
+                items: vec![ItemType::FireballScroll, ItemType::ConfusionScroll],
+                ..Default::default()
+            },
+            Self::Scavenger => StartingKit {
+                items: vec![ItemType::HealthPotion, ItemType::HealthPotion],
+                extra_dexterity: 1,
+                ..Default::default()
+            },
+        }
+    }
+
+    // The background after this one on the character creation screen
+    pub fn next(self) -> Self {
+        match self {
+            Self::Warrior => Self::Scholar,
+            Self::Scholar => Self::Scavenger,
+            Self::Scavenger => Self::Warrior,
+        }
+    }
+
+    pub fn previous(self) -> Self {
+        self.next().next()
+    }
+}
+
+// The character made on the character creation screen. Runs started
+// without it, such as the daily challenge and the bot's, have no
+// background and begin with nothing.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewCharacter {
+    pub name: String,
+    pub background: Option<Background>,
+}
+
+// A head start for testing the deeper levels, given on the command line.
+// A run started with one counts as cheated.
+#[derive(Clone, Debug)]
+pub struct StartOptions {
+    // the level to begin on, counting from 1
+    pub dungeon_level: u32,
+    // the stat raised for each level skipped, taken in turn and repeated
+    // as needed. All four are taken in turn if this is empty.
+    pub level_ups: Vec<LevelUp>,
+    // put straight into the player's inventory
+    pub items: Vec<ItemType>,
+    // a hand-made level to play in place of the first generated one
+    pub first_level: Option<Dungeon>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RNG_SEED: u64 = 0;
+    const ARENA_SIZE: Size = Size::new_u16(80, 50);
+    const MAP_SIZE: Size = Size::new_u16(40, 23);
+    // more than any test needs, so a run that never stops fails instead
+    // of hanging
+    const MAX_AUTO_ACT_STEPS: usize = 100;
+
+    fn new_game() -> GameState {
+        GameState::new(
+            MAP_SIZE,
+            RNG_SEED,
+            VisibilityAlgorithm::Shadowcast,
+            SpawnTables::built_in(),
+            Difficulty::Normal,
+            NewCharacter::default(),
+        )
+    }
+
+    // A game on a level drawn in text, filled out with wall to the size
+    // of the map
+    fn game_on_level(level: &str) -> GameState {
+        let rows = level.lines().collect::<Vec<_>>();
+        let mut text = String::new();
+        for y in 0..MAP_SIZE.height() as usize {
+            let row = rows.get(y).copied().unwrap_or("");
+            text.push_str(row);
+            text.push_str(&"#".repeat(MAP_SIZE.width() as usize - row.chars().count()));
+            text.push('\n');
+        }
+        let mut game_state = new_game();
+        game_state.replace_level(terrain::parse_level(&text).unwrap());
+        game_state.update_visibility(VisibilityAlgorithm::Shadowcast);
+        game_state
+    }
+
+    // Runs until the run stops, returning where the player ends up
+    fn run(game_state: &mut GameState, direction: CardinalDirection) -> Coord {
+        game_state.perform(PlayerAction::Run(direction));
+        for _ in 0..MAX_AUTO_ACT_STEPS {
+            if !game_state.is_auto_acting() {
+                return game_state.player_coord();
+            }
+            game_state.perform(PlayerAction::AutoActStep);
+            game_state.update_visibility(VisibilityAlgorithm::Shadowcast);
+        }
+        panic!("the run didn't stop");
+    }
+
+    // The worst case for the NPCs' turn, with every agent in the open and
+    // in view of the player, whatever the seed
+    fn arena_with_orcs(num_orcs: usize) -> GameState {
+        let mut game_state =
+            GameState::new_arena(ARENA_SIZE, RNG_SEED, VisibilityAlgorithm::Shadowcast);
+        assert_eq!(game_state.spawn_npcs(NpcType::Orc, num_orcs), num_orcs);
+        game_state
+    }
+
+    #[test]
+    fn arena_holds_thirty_agents_for_a_turn() {
+        let mut game_state = arena_with_orcs(30);
+        assert_eq!(game_state.ai_state.entities().count(), 30);
+        assert!(game_state.perform(PlayerAction::Wait));
+        assert_eq!(game_state.turn(), 1);
+    }
+
+    #[test]
+    fn run_stops_at_a_corridor_junction() {
+        let mut game_state = game_on_level(
+            "\
+###########
+#@........#
+######.####
+######.####",
+        );
+        let end = run(&mut game_state, CardinalDirection::East);
+        assert_eq!(end, Coord::new(6, 1));
+    }
+
+    #[test]
+    fn run_stops_where_a_corridor_opens_into_a_room() {
+        let mut game_state = game_on_level(
+            "\
+############
+#######....#
+#@.........#
+#######....#
+############",
+        );
+        let end = run(&mut game_state, CardinalDirection::East);
+        assert_eq!(end, Coord::new(7, 2));
+    }
+
+    #[test]
+    fn run_stops_before_the_end_of_a_corridor() {
+        let mut game_state = game_on_level(
+            "\
+#######
+#@....#
+#######",
+        );
+        let end = run(&mut game_state, CardinalDirection::East);
+        assert_eq!(end, Coord::new(5, 1));
+    }
+
+    #[test]
+    fn run_out_of_a_junction_carries_on_down_the_corridor() {
+        // the first step leaves the junction, so its open neighbours
+        // don't stop the run
+        let mut game_state = game_on_level(
+            "\
+#######
+#..@..#
+###.###
+###.###
+###.###
+###.###
+#######",
+        );
+        let end = run(&mut game_state, CardinalDirection::South);
+        assert_eq!(end, Coord::new(3, 5));
+    }
+
+    // The repeat key does the last move again with the same PlayerAction,
+    // so whatever is in the way now decides what happens
+    #[test]
+    fn repeating_a_move_into_an_npc_attacks_it() {
+        let mut game_state = game_on_level(
+            "\
+#####
+#@.o#
+#####",
+        );
+        let action = PlayerAction::Move(CardinalDirection::East);
+        assert!(game_state.perform(action));
+        assert_eq!(game_state.player_coord(), Coord::new(2, 1));
+        let start = game_state.message_log.end();
+        assert!(game_state.perform(action));
+        assert_eq!(game_state.player_coord(), Coord::new(2, 1));
+        assert_eq!(game_state.turn(), 2);
+        assert!(game_state
+            .message_log
+            .entries_from(start)
+            .any(|entry| matches!(
+                entry.message,
+                LogMessage::PlayerAttacksNpc(NpcType::Orc)
+                    | LogMessage::PlayerKillsNpc(NpcType::Orc)
+                    | LogMessage::NpcDodges(NpcType::Orc)
+            )));
+    }
+
+    #[test]
+    fn repeating_a_move_into_a_wall_stays_put() {
+        // bumping a wall spends the turn, as pressing the key again would
+        let mut game_state = game_on_level(
+            "\
+####
+#@.#
+####",
+        );
+        let action = PlayerAction::Move(CardinalDirection::East);
+        assert!(game_state.perform(action));
+        game_state.perform(action);
+        assert_eq!(game_state.player_coord(), Coord::new(2, 1));
+        assert_eq!(game_state.turn(), 2);
+    }
+
+    #[test]
+    fn each_level_is_populated_once() {
+        let num_populated =
+            || crate::world::NUM_POPULATED.with(|num_populated| num_populated.get());
+        let start = num_populated();
+        let mut game_state = new_game();
+        assert_eq!(num_populated() - start, 1);
+        game_state.level_up_and_descend_from_anywhere(LevelUp::Health);
+        assert_eq!(num_populated() - start, 2);
+        // going back to a level finds it as it was left
+        let stairs_up = game_state.world.stairs_up_coord().unwrap();
+        game_state
+            .world
+            .move_character_to(game_state.player_entity, stairs_up);
+        game_state.player_ascend();
+        assert_eq!(game_state.dungeon_level(), 1);
+        let stairs = game_state.world.stairs_coord().unwrap();
+        game_state
+            .world
+            .move_character_to(game_state.player_entity, stairs);
+        game_state.player_descend();
+        assert_eq!(game_state.dungeon_level(), 2);
+        assert_eq!(num_populated() - start, 2);
+    }
+
+    #[test]
+    fn the_view_is_worked_out_once_per_move_and_not_for_redraws() {
+        let num_full_updates =
+            || crate::visibility::NUM_FULL_UPDATES.with(|num_full_updates| num_full_updates.get());
+        let mut game_state = game_on_level(
+            "\
+######
+#@...#
+######",
+        );
+        let start = num_full_updates();
+        let result = game_state.act(
+            PlayerAction::Move(CardinalDirection::East),
+            VisibilityAlgorithm::Shadowcast,
+        );
+        assert_eq!(result, ActionResult::Done);
+        assert_eq!(num_full_updates() - start, 1);
+        // what the app does for a keystroke in a menu, which changes
+        // nothing in the game
+        for _ in 0..3 {
+            game_state.update_visibility(VisibilityAlgorithm::Shadowcast);
+        }
+        assert_eq!(num_full_updates() - start, 1);
+    }
+
+    #[test]
+    fn the_message_log_stays_short_on_a_long_run() {
+        const NUM_MESSAGES: u32 = 10_000;
+        let mut message_log = MessageLog::default();
+        for turns in 0..NUM_MESSAGES {
+            message_log.push(LogMessage::PlayerRests(turns));
+        }
+        assert_eq!(message_log.end(), NUM_MESSAGES as usize);
+        assert_eq!(message_log.entries_from(0).count(), MAX_LOG_ENTRIES);
+        // the newest messages are still there to be shown
+        let newest = message_log
+            .entries_from(message_log.end() - 2)
+            .map(|entry| entry.message)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            newest,
+            vec![
+                LogMessage::PlayerRests(NUM_MESSAGES - 2),
+                LogMessage::PlayerRests(NUM_MESSAGES - 1),
+            ]
+        );
+        // and the save holds no more than the capped log
+        let mut capped_log = MessageLog::default();
+        for turns in NUM_MESSAGES - MAX_LOG_ENTRIES as u32..NUM_MESSAGES {
+            capped_log.push(LogMessage::PlayerRests(turns));
+        }
+        let saved = serde_json::to_string(&message_log).unwrap();
+        let capped = serde_json::to_string(&capped_log).unwrap();
+        assert!(saved.len() <= capped.len() + 16);
+    }
+
+    #[test]
+    fn only_entities_in_cells_the_player_has_seen_are_rendered() {
+        let mut game_state = GameState::new(
+            Size::new_u16(80, 50),
+            RNG_SEED,
+            VisibilityAlgorithm::Shadowcast,
+            SpawnTables::built_in(),
+            Difficulty::Normal,
+            NewCharacter::default(),
+        );
+        // looking around a little, so some cells are only remembered
+        for direction in CardinalDirection::all() {
+            game_state.act(
+                PlayerAction::Move(direction),
+                VisibilityAlgorithm::Shadowcast,
+            );
+        }
+        let never_seen = |coord| {
+            matches!(
+                game_state.visibility_grid.cell_visibility(coord),
+                CellVisibility::Never
+            )
+        };
+        // most of a new level hasn't been seen, so there's plenty to skip
+        let num_never_seen = game_state
+            .world
+            .components
+            .tile
+            .entities()
+            .filter_map(|entity| game_state.world.spatial_table.coord_of(entity))
+            .filter(|&coord| never_seen(coord))
+            .count();
+        assert!(num_never_seen > 0);
+        let to_render = game_state.entities_to_render(None).collect::<Vec<_>>();
+        assert!(!to_render.is_empty());
+        for entity in &to_render {
+            assert!(!never_seen(entity.location.coord));
+        }
+        // and only what's in the rectangle on screen
+        let top_left = game_state.player_coord() - Coord::new(2, 2);
+        let size = Size::new(5, 5);
+        let on_screen = game_state
+            .entities_to_render(Some((top_left, size)))
+            .collect::<Vec<_>>();
+        assert!(!on_screen.is_empty());
+        for entity in &on_screen {
+            assert!((entity.location.coord - top_left).is_valid(size));
+        }
+    }
+
+    #[test]
+    fn drawing_from_the_terrain_rng_leaves_combat_the_same() {
+        let fight = |extra_terrain_roll: bool| {
+            let mut game_state = game_on_level(
+                "\
+####
+#@o#
+####",
+            );
+            if extra_terrain_roll {
+                let _: u64 = game_state.rngs.terrain.gen();
+            }
+            for _ in 0..3 {
+                game_state.act(
+                    PlayerAction::Move(CardinalDirection::East),
+                    VisibilityAlgorithm::Shadowcast,
+                );
+            }
+            let messages = game_state
+                .message_log
+                .entries_from(0)
+                .map(|entry| (entry.message, entry.count))
+                .collect::<Vec<_>>();
+            (
+                messages,
+                game_state.player_hit_points().current(),
+                game_state.rngs.combat.gen::<u64>(),
+            )
+        };
+        assert!(!fight(false).0.is_empty());
+        assert_eq!(fight(false), fight(true));
+    }
+
+    #[test]
+    fn a_confused_npc_wanders_without_drawing_from_the_combat_rng() {
+        let mut game_state = game_on_level(
+            "\
+#########
+#@.....o#
+#########",
+        );
+        let orc = game_state.ai_state.entities().next().unwrap();
+        game_state
+            .world
+            .components
+            .confusion_countdown
+            .insert(orc, 3);
+        let next_combat_roll = game_state.rngs.combat.clone().gen::<u64>();
+        game_state.act(PlayerAction::Wait, VisibilityAlgorithm::Shadowcast);
+        // it stumbled
+        assert_eq!(
+            game_state.world.components.confusion_countdown.get(orc),
+            Some(&2)
+        );
+        assert_eq!(game_state.rngs.combat.gen::<u64>(), next_combat_roll);
+    }
+}
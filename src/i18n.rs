@@ -0,0 +1,314 @@
+// i18n.rs
+
+// The text shown to the player, looked up by key so it can be translated.
+// English is built in, and another language is loaded from lang/CODE.toml
+// next to the executable. See data/lang/en.toml for the format.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Write};
+use std::sync::OnceLock;
+
+use chargrid_roguelike_tutorial_2020::{log_debug, log_info};
+
+use crate::difficulty::Difficulty;
+use crate::game::{Background, LevelUp, TravelTarget};
+use crate::terrain::LevelTheme;
+use crate::world::{FloorThing, ItemType, Liquid, NpcType, ProjectileType, StatusEffect, TrapType};
+
+const ENGLISH: &str = include_str!("data/lang/en.toml");
+const LANG_DIR: &str = "lang";
+// shown in place of text missing even from the English catalog
+pub const MISSING_TEXT: &str = "???";
+
+// Keys of nested tables are joined with dots, as in "message.npc-dies"
+type Catalog = HashMap<String, String>;
+
+static CATALOG: OnceLock<Catalog> = OnceLock::new();
+
+fn parse(text: &str) -> Result<Catalog, String> {
+    let value = text
+        .parse::<toml::Value>()
+        .map_err(|error| error.to_string())?;
+    let mut catalog = Catalog::new();
+    flatten("", value, &mut catalog)?;
+    Ok(catalog)
+}
+
+fn flatten(key: &str, value: toml::Value, catalog: &mut Catalog) -> Result<(), String> {
+    match value {
+        toml::Value::String(text) => {
+            catalog.insert(key.to_string(), text);
+        }
+        toml::Value::Table(table) => {
+            for (name, value) in table {
+                let key = if key.is_empty() {
+                    name
+                } else {
+                    format!("{}.{}", key, name)
+                };
+                flatten(&key, value, catalog)?;
+            }
+        }
+        _ => return Err(format!("{}: expected text", key)),
+    }
+    Ok(())
+}
+
+fn english() -> Catalog {
+    parse(ENGLISH).expect("built-in English text is invalid")
+}
+
+fn load(lang: &str) -> Result<Catalog, String> {
+    // the code becomes part of a path
+    if lang.is_empty()
+        || !lang
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "invalid language {:?} (expected a code such as fr or pt-BR)",
+            lang
+        ));
+    }
+    let path = std::env::current_exe()
+        .ok()
+        .and_then(|exe| {
+            exe.parent()
+                .map(|dir| dir.join(LANG_DIR).join(format!("{}.toml", lang)))
+        })
+        .ok_or_else(|| "the executable's directory is unknown".to_string())?;
+    let text = std::fs::read_to_string(&path).map_err(|error| format!("{:?}: {}", path, error))?;
+    parse(&text).map_err(|error| format!("{:?}: {}", path, error))
+}
+
+// Chooses the language by its code, falling back to English for anything
+// the translation leaves out. Until this is called, everything is English.
+pub fn init(lang: &str) -> Result<(), String> {
+    let mut catalog = english();
+    if lang != "en" {
+        let translation = load(lang)?;
+        let mut unknown = translation
+            .keys()
+            .filter(|key| !catalog.contains_key(*key))
+            .collect::<Vec<_>>();
+        unknown.sort();
+        for key in unknown {
+            log_info!("Ignoring unknown text {} in the {} translation", key, lang);
+        }
+        let num_missing = catalog
+            .keys()
+            .filter(|key| !translation.contains_key(*key))
+            .count();
+        if num_missing > 0 {
+            log_info!(
+                "{} texts missing from the {} translation are shown in English",
+                num_missing,
+                lang
+            );
+        }
+        for (key, text) in translation {
+            if let Some(english_text) = catalog.get_mut(&key) {
+                *english_text = text;
+            }
+        }
+        log_info!("Language: {}", lang);
+    }
+    CATALOG
+        .set(catalog)
+        .map_err(|_| "the language has already been chosen".to_string())
+}
+
+// The text under the key in the chosen language
+pub fn text(key: &str) -> &'static str {
+    match CATALOG.get_or_init(english).get(key) {
+        Some(text) => text,
+        None => {
+            log_debug!("No text for {}", key);
+            MISSING_TEXT
+        }
+    }
+}
+
+// Replaces each {name} in the text with the argument of that name. Any
+// {name} without an argument is left as it is.
+pub fn format(key: &str, args: &[(&str, &dyn Display)]) -> String {
+    let mut formatted = String::new();
+    let mut rest = text(key);
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        formatted.push_str(&rest[..start]);
+        let name = &rest[start + 1..end];
+        match args.iter().find(|&&(arg_name, _)| arg_name == name) {
+            Some((_, value)) => write!(&mut formatted, "{}", value).unwrap(),
+            None => formatted.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    formatted.push_str(rest);
+    formatted
+}
+
+// The text under KEY.one for a count of exactly one, and under KEY.other
+// for any other count
+pub fn format_count(key: &str, count: u32, args: &[(&str, &dyn Display)]) -> String {
+    let form = if count == 1 { "one" } else { "other" };
+    format(&format!("{}.{}", key, form), args)
+}
+
+// Joins the parts as in "a, b and c", ending with "and more" if there
+// were more than could be listed
+pub fn list(parts: &[String], more: bool) -> String {
+    let mut joined = String::new();
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            let last = i + 1 == parts.len() && !more;
+            joined.push_str(text(if last { "list.and" } else { "list.separator" }));
+        }
+        joined.push_str(part);
+    }
+    if more {
+        joined.push_str(text("list.and-more"));
+    }
+    joined
+}
+
+pub fn npc_name(npc_type: NpcType) -> &'static str {
+    text(match npc_type {
+        NpcType::Orc => "npc.orc",
+        NpcType::Troll => "npc.troll",
+        NpcType::Shopkeeper => "npc.shopkeeper",
+    })
+}
+
+// With an indefinite article, as in "an orc"
+pub fn npc_name_a(npc_type: NpcType) -> &'static str {
+    text(match npc_type {
+        NpcType::Orc => "npc-a.orc",
+        NpcType::Troll => "npc-a.troll",
+        NpcType::Shopkeeper => "npc-a.shopkeeper",
+    })
+}
+
+fn item_key(item_type: ItemType) -> &'static str {
+    match item_type {
+        ItemType::HealthPotion => "health-potion",
+        ItemType::FireballScroll => "fireball-scroll",
+        ItemType::ConfusionScroll => "confusion-scroll",
+        ItemType::ClairvoyanceScroll => "clairvoyance-scroll",
+        ItemType::Torch => "torch",
+        ItemType::SmokeBomb => "smoke-bomb",
+        ItemType::Sword => "sword",
+        ItemType::Staff => "staff",
+        ItemType::Armor => "armor",
+        ItemType::Robe => "robe",
+        ItemType::Backpack => "backpack",
+        ItemType::Warhammer => "warhammer",
+    }
+}
+
+pub fn item_name(item_type: ItemType) -> &'static str {
+    text(&format!("item.{}", item_key(item_type)))
+}
+
+// With an indefinite article, as in "a torch"
+pub fn item_name_a(item_type: ItemType) -> &'static str {
+    text(&format!("item-a.{}", item_key(item_type)))
+}
+
+pub fn trap_name(trap_type: TrapType) -> &'static str {
+    text(match trap_type {
+        TrapType::Spike => "trap.spike",
+        TrapType::Teleport => "trap.teleport",
+        TrapType::Alarm => "trap.alarm",
+    })
+}
+
+pub fn projectile_name(projectile: ProjectileType) -> &'static str {
+    text(match projectile {
+        ProjectileType::Fireball { .. } => "projectile.fireball",
+        ProjectileType::Confusion { .. } => "projectile.confusion",
+    })
+}
+
+pub fn theme_name(theme: LevelTheme) -> &'static str {
+    text(match theme {
+        LevelTheme::Dungeon => "theme.dungeon",
+        LevelTheme::Caves => "theme.caves",
+        LevelTheme::Crypt => "theme.crypt",
+        LevelTheme::Lair => "theme.lair",
+    })
+}
+
+pub fn liquid_name(liquid: Liquid) -> &'static str {
+    text(match liquid {
+        Liquid::Water => "liquid.water",
+        Liquid::Lava => "liquid.lava",
+    })
+}
+
+pub fn difficulty_name(difficulty: Difficulty) -> &'static str {
+    text(match difficulty {
+        Difficulty::Easy => "difficulty.easy",
+        Difficulty::Normal => "difficulty.normal",
+        Difficulty::Hard => "difficulty.hard",
+    })
+}
+
+pub fn background_name(background: Background) -> &'static str {
+    text(match background {
+        Background::Warrior => "background.warrior",
+        Background::Scholar => "background.scholar",
+        Background::Scavenger => "background.scavenger",
+    })
+}
+
+// What a background starts a run with
+pub fn background_description(background: Background) -> &'static str {
+    text(match background {
+        Background::Warrior => "background-kit.warrior",
+        Background::Scholar => "background-kit.scholar",
+        Background::Scavenger => "background-kit.scavenger",
+    })
+}
+
+pub fn stat_name(level_up: LevelUp) -> &'static str {
+    text(match level_up {
+        LevelUp::Strength => "stat.strength",
+        LevelUp::Dexterity => "stat.dexterity",
+        LevelUp::Intelligence => "stat.intelligence",
+        LevelUp::Health => "stat.health",
+    })
+}
+
+// A short tag for the status line
+pub fn status_tag(status_effect: StatusEffect) -> &'static str {
+    text(match status_effect {
+        StatusEffect::Blind => "status.blind",
+        StatusEffect::Confused => "status.confused",
+        StatusEffect::Clairvoyant => "status.clairvoyant",
+    })
+}
+
+pub fn travel_target_name(target: TravelTarget) -> &'static str {
+    match target {
+        TravelTarget::Stairs => text("travel.stairs"),
+        TravelTarget::StairsUp => text("travel.stairs-up"),
+        TravelTarget::Item(item_type) => item_name(item_type),
+    }
+}
+
+pub fn floor_thing_description(floor_thing: FloorThing) -> String {
+    match floor_thing {
+        FloorThing::Stairs => text("floor.stairs").to_string(),
+        FloorThing::StairsUp => text("floor.stairs-up").to_string(),
+        FloorThing::Altar => text("floor.altar").to_string(),
+        FloorThing::Portal => text("floor.portal").to_string(),
+        FloorThing::Item(item_type) => item_name_a(item_type).to_string(),
+        FloorThing::Gold => text("floor.gold").to_string(),
+        FloorThing::Corpse(npc_type) => format("floor.corpse", &[("npc_a", &npc_name_a(npc_type))]),
+        FloorThing::Rune => text("floor.rune").to_string(),
+    }
+}
@@ -0,0 +1,271 @@
+// keybindings.rs
+
+use chargrid::input::{keys, KeyboardInput};
+use std::collections::BTreeMap;
+
+// Looked for next to the executable when no other file is given
+const KEY_BINDINGS_FILE: &str = "keybindings.toml";
+
+// Things the player can do from the game screen with a single key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    MoveNorth,
+    MoveSouth,
+    MoveWest,
+    MoveEast,
+    Wait,
+    Rest,
+    Run,
+    ForceAttack,
+    Repeat,
+    Get,
+    CloseDoor,
+    Search,
+    PrayOrPay,
+    UseItem,
+    DropItem,
+    Wield,
+    Wear,
+    Unequip,
+    AssignQuickSlot,
+    QuickUse1,
+    QuickUse2,
+    QuickUse3,
+    QuickUse4,
+    QuickUse5,
+    Examine,
+    Descend,
+    Ascend,
+    Travel,
+    Statistics,
+    Menu,
+    ToggleDiagnostics,
+    Screenshot,
+}
+
+impl Action {
+    const ALL: [Action; 32] = [
+        Action::MoveNorth,
+        Action::MoveSouth,
+        Action::MoveWest,
+        Action::MoveEast,
+        Action::Wait,
+        Action::Rest,
+        Action::Run,
+        Action::ForceAttack,
+        Action::Repeat,
+        Action::Get,
+        Action::CloseDoor,
+        Action::Search,
+        Action::PrayOrPay,
+        Action::UseItem,
+        Action::DropItem,
+        Action::Wield,
+        Action::Wear,
+        Action::Unequip,
+        Action::AssignQuickSlot,
+        Action::QuickUse1,
+        Action::QuickUse2,
+        Action::QuickUse3,
+        Action::QuickUse4,
+        Action::QuickUse5,
+        Action::Examine,
+        Action::Descend,
+        Action::Ascend,
+        Action::Travel,
+        Action::Statistics,
+        Action::Menu,
+        Action::ToggleDiagnostics,
+        Action::Screenshot,
+    ];
+
+    // the action's name in a key bindings file
+    fn name(self) -> &'static str {
+        match self {
+            Self::MoveNorth => "move_north",
+            Self::MoveSouth => "move_south",
+            Self::MoveWest => "move_west",
+            Self::MoveEast => "move_east",
+            Self::Wait => "wait",
+            Self::Rest => "rest",
+            Self::Run => "run",
+            Self::ForceAttack => "force_attack",
+            Self::Repeat => "repeat",
+            Self::Get => "get",
+            Self::CloseDoor => "close_door",
+            Self::Search => "search",
+            Self::PrayOrPay => "pray_or_pay",
+            Self::UseItem => "use_item",
+            Self::DropItem => "drop_item",
+            Self::Wield => "wield",
+            Self::Wear => "wear",
+            Self::Unequip => "unequip",
+            Self::AssignQuickSlot => "assign_quick_slot",
+            Self::QuickUse1 => "quick_use_1",
+            Self::QuickUse2 => "quick_use_2",
+            Self::QuickUse3 => "quick_use_3",
+            Self::QuickUse4 => "quick_use_4",
+            Self::QuickUse5 => "quick_use_5",
+            Self::Examine => "examine",
+            Self::Descend => "descend",
+            Self::Ascend => "ascend",
+            Self::Travel => "travel",
+            Self::Statistics => "statistics",
+            Self::Menu => "menu",
+            Self::ToggleDiagnostics => "toggle_diagnostics",
+            Self::Screenshot => "screenshot",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .cloned()
+            .find(|action| action.name() == name)
+    }
+
+    // the index of the quick slot a quick use action uses
+    pub fn quick_slot(self) -> Option<usize> {
+        match self {
+            Self::QuickUse1 => Some(0),
+            Self::QuickUse2 => Some(1),
+            Self::QuickUse3 => Some(2),
+            Self::QuickUse4 => Some(3),
+            Self::QuickUse5 => Some(4),
+            _ => None,
+        }
+    }
+
+    // Actions which only look at the game, and still work after the
+    // player has died
+    pub fn is_meta(self) -> bool {
+        matches!(
+            self,
+            Self::Examine | Self::Statistics | Self::ToggleDiagnostics | Self::Screenshot
+        )
+    }
+}
+
+// Keys are written as a single character, or by name for keys which
+// don't type one
+fn parse_key(name: &str) -> Option<KeyboardInput> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(KeyboardInput::Char(c));
+    }
+    let key = match name {
+        "space" => KeyboardInput::Char(' '),
+        "up" => KeyboardInput::Up,
+        "down" => KeyboardInput::Down,
+        "left" => KeyboardInput::Left,
+        "right" => KeyboardInput::Right,
+        "escape" => keys::ESCAPE,
+        "return" => keys::RETURN,
+        _ => {
+            let number = name.strip_prefix('f')?.parse::<u8>().ok()?;
+            if !(1..=12).contains(&number) {
+                return None;
+            }
+            KeyboardInput::Function(number)
+        }
+    };
+    Some(key)
+}
+
+// The line of a key bindings file on which an action is bound, for
+// pointing at it in error messages
+fn line_of(text: &str, action_name: &str) -> usize {
+    text.lines()
+        .position(|line| {
+            line.trim_start()
+                .strip_prefix(action_name)
+                .map(|rest| rest.trim_start().starts_with('='))
+                .unwrap_or(false)
+        })
+        .map(|index| index + 1)
+        .unwrap_or(0)
+}
+
+pub struct KeyBindings {
+    bindings: Vec<(KeyboardInput, Action)>,
+}
+
+impl KeyBindings {
+    pub fn built_in() -> Self {
+        Self::parse(include_str!("data/keybindings.toml"))
+            .expect("built-in key bindings are invalid")
+    }
+
+    // Loads the bindings from next to the executable if the player has
+    // put a file there, and uses the built-in ones otherwise
+    pub fn load_default() -> Result<Self, String> {
+        let path = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.join(KEY_BINDINGS_FILE)))
+            .filter(|path| path.exists());
+        match path {
+            Some(path) => Self::load(&path.to_string_lossy()),
+            None => Ok(Self::built_in()),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+        Self::parse(&text)
+    }
+
+    // Every problem with the file is reported at once, each with the line
+    // it's on
+    fn parse(text: &str) -> Result<Self, String> {
+        let table: BTreeMap<String, Vec<String>> =
+            toml::from_str(text).map_err(|error| error.to_string())?;
+        let mut errors = Vec::new();
+        let mut bindings: Vec<(KeyboardInput, Action)> = Vec::new();
+        for (action_name, key_names) in table.iter() {
+            let line = line_of(text, action_name);
+            let action = match Action::from_name(action_name) {
+                Some(action) => action,
+                None => {
+                    errors.push(format!("line {}: unknown action \"{}\"", line, action_name));
+                    continue;
+                }
+            };
+            for key_name in key_names {
+                let key = match parse_key(key_name) {
+                    Some(key) => key,
+                    None => {
+                        errors.push(format!("line {}: unknown key \"{}\"", line, key_name));
+                        continue;
+                    }
+                };
+                match bindings.iter().find(|&&(bound_key, _)| bound_key == key) {
+                    Some(&(_, other_action)) => errors.push(format!(
+                        "line {}: key \"{}\" is already bound to {} on line {}",
+                        line,
+                        key_name,
+                        other_action.name(),
+                        line_of(text, other_action.name()),
+                    )),
+                    None => bindings.push((key, action)),
+                }
+            }
+        }
+        for action in Action::ALL.iter() {
+            if !table.contains_key(action.name()) {
+                errors.push(format!("no keys bound to {}", action.name()));
+            }
+        }
+        if errors.is_empty() {
+            Ok(Self { bindings })
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    pub fn action(&self, key: KeyboardInput) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|&&(bound_key, _)| bound_key == key)
+            .map(|&(_, action)| action)
+    }
+}
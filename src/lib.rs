@@ -0,0 +1,17 @@
+// src/lib.rs
+
+// The game's rules, without any way of drawing them. The binary adds the
+// chargrid frontend on top. GameState::act lets tests and bots play
+// whole games through this library alone.
+
+#[macro_use]
+pub mod logging;
+pub mod behavior;
+pub mod bot;
+pub mod combat;
+pub mod difficulty;
+pub mod game;
+pub mod replay;
+pub mod terrain;
+pub mod visibility;
+pub mod world;
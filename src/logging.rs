@@ -0,0 +1,127 @@
+// logging.rs
+
+// Messages about the game itself rather than for the player, such as
+// where it's saving, through the log crate. On the desktop they go to
+// stderr, and to a file as well if one is given. In the browser they go
+// to the console. Until init is called they go nowhere.
+
+pub use log;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+#[cfg(not(feature = "web"))]
+use std::fs::File;
+#[cfg(not(feature = "web"))]
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(not(feature = "web"))]
+use std::sync::Mutex;
+
+// Cleared while something else is drawing to the terminal
+static CONSOLE_ENABLED: AtomicBool = AtomicBool::new(true);
+
+struct Logger {
+    #[cfg(not(feature = "web"))]
+    file: Option<Mutex<File>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!("[{}] {}", record.level(), record.args());
+        if CONSOLE_ENABLED.load(Ordering::Relaxed) {
+            write_to_console(record.level(), &line);
+        }
+        #[cfg(not(feature = "web"))]
+        if let Some(file) = self.file.as_ref() {
+            if let Ok(mut file) = file.lock() {
+                // there's nowhere left to report a failure to log
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        #[cfg(not(feature = "web"))]
+        if let Some(file) = self.file.as_ref() {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.flush();
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "web"))]
+fn write_to_console(_level: Level, line: &str) {
+    eprintln!("{}", line);
+}
+
+#[cfg(feature = "web")]
+fn write_to_console(level: Level, line: &str) {
+    match level {
+        Level::Error => web_sys::console::error_1(&line.into()),
+        Level::Warn => web_sys::console::warn_1(&line.into()),
+        Level::Info | Level::Debug | Level::Trace => web_sys::console::log_1(&line.into()),
+    }
+}
+
+// Starts logging messages at `level` and above, also appending them to
+// `log_file` if given
+#[cfg(not(feature = "web"))]
+pub fn init(level: LevelFilter, log_file: Option<&str>) -> Result<(), String> {
+    let file = match log_file {
+        Some(path) => Some(Mutex::new(
+            File::create(path).map_err(|error| error.to_string())?,
+        )),
+        None => None,
+    };
+    log::set_boxed_logger(Box::new(Logger { file })).map_err(|error| error.to_string())?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+// There's no file system to write a log file to in the browser
+#[cfg(feature = "web")]
+pub fn init(level: LevelFilter, _log_file: Option<&str>) -> Result<(), String> {
+    log::set_boxed_logger(Box::new(Logger {})).map_err(|error| error.to_string())?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+// The terminal frontend turns this off while it owns the terminal, as
+// anything printed would be drawn over the game
+pub fn set_console_enabled(enabled: bool) {
+    CONSOLE_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        $crate::logging::log::error!($($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        $crate::logging::log::info!($($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        $crate::logging::log::debug!($($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! log_trace {
+    ($($arg:tt)*) => {
+        $crate::logging::log::trace!($($arg)*)
+    };
+}
@@ -1,76 +1,688 @@
 // src/main.rs
 
-use app::app;
-use chargrid_graphical::{Config, Context, Dimensions, FontBytes};
-use coord_2d::Size;
+use app::{app, ReplayMode};
+use chargrid::app::App as ChargridApp;
+// app and ui refer to these as crate::game and so on
+use chargrid_roguelike_tutorial_2020::{
+    bot, combat, difficulty, game, log_error, log_info, logging, replay, terrain, visibility, world,
+};
+use coord_2d::{Coord, Size};
+use log::LevelFilter;
+#[cfg(not(feature = "web"))]
 use meap;
 use rand::Rng;
+use std::ops::Range;
+use std::str::FromStr;
+use std::time::Duration;
 
+use crate::app::colors::ThemeName;
+use crate::config::{Config, DisplayConfig, MapSize};
+use crate::difficulty::Difficulty;
+use crate::game::{LevelPreview, LevelUp, StartOptions};
+use crate::keybindings::KeyBindings;
+use crate::replay::Replay;
+use crate::terrain::SpawnTables;
+use crate::ui::UI_NUM_ROWS;
 use crate::visibility::VisibilityAlgorithm;
+use crate::world::{ItemType, Tile};
 
 mod app;
-mod behavior;
-mod game;
-mod terrain;
+mod audio;
+mod config;
+mod crash;
+mod daily;
+mod i18n;
+mod keybindings;
+mod scores;
+mod screenshot;
 mod ui;
-mod visibility;
-mod world;
 
 fn main() {
-    use meap::Parser;
     let Args {
         rng_seed,
         visibility_algorithm,
-    } = Args::parser().with_help_default().parse_env_or_exit();
-    println!("RNG Seed: {}", rng_seed);
-    
-    const CELL_SIZE_PX: f64 = 24.0;
-    let context = Context::new(Config {
+        data_path,
+        theme_name,
+        difficulty,
+        diagnostics,
+        wizard,
+        daily,
+        start_level,
+        level_ups,
+        give,
+        level_file,
+        keys_path,
+        bot_turns,
+        bot_seeds,
+        record_path,
+        replay_path,
+        verify_replay,
+        replay_step_ms,
+        dump_map,
+        dump_level,
+        dump_count,
+        dump_dir,
+        map_size,
+        ui_rows,
+        log_level,
+        log_file,
+        config_path,
+        lang,
+    } = Args::get();
+    if let Err(error) = logging::init(log_level, log_file.as_deref()) {
+        eprintln!("Failed to start logging: {}", error);
+        std::process::exit(1);
+    }
+    let daily_date = if daily {
+        if rng_seed.is_some() {
+            log_error!("The daily challenge has its own RNG seed");
+            std::process::exit(1);
+        }
+        if wizard {
+            log_error!("Can't use wizard mode in the daily challenge");
+            std::process::exit(1);
+        }
+        match daily::today() {
+            Some(date) => Some(date),
+            None => {
+                log_error!("Today's date is unknown, so there's no daily challenge");
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+    let rng_seed = match daily_date.as_ref() {
+        Some(date) => daily::seed(date),
+        None => rng_seed.unwrap_or_else(|| rand::thread_rng().gen()),
+    };
+    if let Some(date) = daily_date.as_ref() {
+        log_info!("Daily challenge for {}", date);
+    }
+    if !(1..=terrain::FINAL_DUNGEON_LEVEL).contains(&start_level) {
+        log_error!(
+            "There are only levels 1 to {}",
+            terrain::FINAL_DUNGEON_LEVEL
+        );
+        std::process::exit(1);
+    }
+    let first_level = level_file.map(|level_file| {
+        terrain::load_level(&level_file).unwrap_or_else(|error| {
+            log_error!("Failed to load level from {}: {}", level_file, error);
+            std::process::exit(1);
+        })
+    });
+    let start_options = if start_level > 1 || give.is_some() || first_level.is_some() {
+        if daily_date.is_some() {
+            log_error!("The daily challenge can't be given a head start");
+            std::process::exit(1);
+        }
+        // replays start from the beginning of a run
+        if record_path.is_some() || replay_path.is_some() {
+            log_error!(
+                "Can't record or play back a replay with --start-level, --give or --level-file"
+            );
+            std::process::exit(1);
+        }
+        log_info!("Starting on level {}", start_level);
+        Some(StartOptions {
+            dungeon_level: start_level,
+            level_ups: level_ups
+                .map(|LevelUpList(level_ups)| level_ups)
+                .unwrap_or_default(),
+            items: give.map(|ItemList(items)| items).unwrap_or_default(),
+            first_level,
+        })
+    } else {
+        None
+    };
+    log_info!("RNG Seed: {}", rng_seed);
+    // the seed is logged again on a crash, in case it's scrolled away
+    crash::install_panic_hook(rng_seed, app::crash_report_path());
+    let config = match config_path {
+        Some(config_path) => Config::load(&config_path),
+        None => Config::load_default(),
+    }
+    .unwrap_or_else(|error| {
+        log_error!("Failed to load config:\n{}", error);
+        std::process::exit(1);
+    });
+    // --lang overrides the config
+    let lang = lang.unwrap_or_else(|| config.lang.clone());
+    if let Err(error) = i18n::init(&lang) {
+        log_error!("Failed to load the {} translation: {}", lang, error);
+        std::process::exit(1);
+    }
+    let replay = replay_path.map(|replay_path| {
+        Replay::load(&replay_path).unwrap_or_else(|error| {
+            log_error!("Failed to load replay from {}: {}", replay_path, error);
+            std::process::exit(1);
+        })
+    });
+    if verify_replay {
+        let replay = replay.unwrap_or_else(|| {
+            log_error!("Give the replay to verify with --replay");
+            std::process::exit(1);
+        });
+        match replay.verify() {
+            Ok(game_state) => {
+                log_info!(
+                    "Replay verified: {} entries, ending on level {} at turn {}",
+                    replay.entries.len(),
+                    game_state.dungeon_level(),
+                    game_state.turn(),
+                );
+                std::process::exit(0);
+            }
+            Err(desync) => {
+                log_error!("{}", desync);
+                std::process::exit(1);
+            }
+        }
+    }
+    let replay_mode = match (replay, record_path) {
+        (Some(_), Some(_)) => {
+            log_error!("Can't record while playing back a replay");
+            std::process::exit(1);
+        }
+        (Some(replay), None) => Some(ReplayMode::Play {
+            replay,
+            step_period: Duration::from_millis(replay_step_ms),
+        }),
+        (None, Some(record_path)) => Some(ReplayMode::Record(record_path)),
+        (None, None) => None,
+    };
+    let spawn_tables = match data_path {
+        Some(data_path) => SpawnTables::load(&data_path).unwrap_or_else(|error| {
+            log_error!("Failed to load spawn tables from {}: {}", data_path, error);
+            std::process::exit(1);
+        }),
+        None => SpawnTables::built_in(),
+    };
+    // the command line overrides the config, and a level file overrides both
+    let map_size = map_size.map_or(config.map_size, |MapSize(map_size)| map_size);
+    let map_size = match start_options
+        .as_ref()
+        .and_then(|options| options.first_level.as_ref())
+    {
+        Some(first_level) => {
+            let MapSize(level_size) =
+                MapSize::new(first_level.grid.size()).unwrap_or_else(|error| {
+                    log_error!("The level file doesn't fit: {}", error);
+                    std::process::exit(1);
+                });
+            if level_size != map_size {
+                log_info!(
+                    "Using the level file's map size of {}x{}",
+                    level_size.width(),
+                    level_size.height()
+                );
+            }
+            level_size
+        }
+        None => map_size,
+    };
+    let ui_rows = ui_rows.unwrap_or(config.ui_rows);
+    let theme_name = theme_name.unwrap_or(config.theme_name);
+    let difficulty = difficulty.unwrap_or(config.difficulty);
+    if ui_rows < UI_NUM_ROWS {
+        log_error!("The UI needs at least {} rows", UI_NUM_ROWS);
+        std::process::exit(1);
+    }
+    // the app lays itself out again to fit the window or terminal
+    let screen_size = app::screen_size(map_size, ui_rows);
+    if dump_map {
+        if !(1..=terrain::FINAL_DUNGEON_LEVEL).contains(&dump_level) {
+            log_error!(
+                "There are only levels 1 to {}",
+                terrain::FINAL_DUNGEON_LEVEL
+            );
+            std::process::exit(1);
+        }
+        let all_reachable = dump_maps(
+            map_size,
+            rng_seed,
+            dump_level,
+            dump_count,
+            &spawn_tables,
+            dump_dir,
+        );
+        std::process::exit(if all_reachable { 0 } else { 1 });
+    }
+    if let Some(max_turns) = bot_turns {
+        let seeds = bot_seeds
+            .map(|SeedRange(seeds)| seeds)
+            .unwrap_or(rng_seed..rng_seed + 1);
+        let mut all_survived = true;
+        for seed in seeds {
+            let summary = bot::play(seed, max_turns, map_size, spawn_tables.clone());
+            log_info!("{}", summary);
+            all_survived &= summary.survived;
+        }
+        std::process::exit(if all_survived { 0 } else { 1 });
+    }
+    let key_bindings = match keys_path.or(config.keys_path) {
+        Some(keys_path) => KeyBindings::load(&keys_path),
+        None => KeyBindings::load_default(),
+    }
+    .unwrap_or_else(|error| {
+        log_error!("Failed to load key bindings:\n{}", error);
+        std::process::exit(1);
+    });
+    let app = app(
+        map_size,
+        ui_rows,
+        rng_seed,
+        visibility_algorithm,
+        spawn_tables,
+        theme_name,
+        difficulty,
+        diagnostics,
+        wizard,
+        start_options,
+        config.game,
+        config.audio,
+        daily_date,
+        key_bindings,
+        replay_mode,
+    );
+    run(app, screen_size, config.display);
+}
+
+// Prints levels for `count` seeds in a row as text, or writes each to a
+// file in `dump_dir`, so changes to level generation can be reviewed by
+// comparing dumps. False if any level's exit can't be reached.
+fn dump_maps(
+    map_size: Size,
+    first_rng_seed: u64,
+    dungeon_level: u32,
+    count: u64,
+    spawn_tables: &SpawnTables,
+    dump_dir: Option<String>,
+) -> bool {
+    let mut all_reachable = true;
+    for rng_seed in (0..count).map(|i| first_rng_seed.wrapping_add(i)) {
+        let preview = LevelPreview::generate(map_size, rng_seed, dungeon_level, spawn_tables);
+        all_reachable &= preview.stats().exit_distance.is_some();
+        let text = map_dump_text(&preview, rng_seed, dungeon_level);
+        match dump_dir {
+            Some(ref dump_dir) => {
+                let path = format!("{}/seed-{}-level-{}.txt", dump_dir, rng_seed, dungeon_level);
+                if let Err(error) = std::fs::write(&path, text) {
+                    log_error!("Failed to write {}: {}", path, error);
+                    std::process::exit(1);
+                }
+            }
+            None => println!("{}", text),
+        }
+    }
+    all_reachable
+}
+
+fn map_dump_text(preview: &LevelPreview, rng_seed: u64, dungeon_level: u32) -> String {
+    let mut text = format!("seed {} level {}\n", rng_seed, dungeon_level);
+    let size = preview.size();
+    for y in 0..size.height() {
+        for x in 0..size.width() {
+            let coord = Coord::new(x as i32, y as i32);
+            text.push(
+                preview
+                    .top_tile_at(coord)
+                    .map(Tile::character)
+                    .unwrap_or(' '),
+            );
+        }
+        text.push('\n');
+    }
+    let stats = preview.stats();
+    text.push_str(&format!("rooms: {}\n", stats.rooms));
+    text.push_str(&format!("floor tiles: {}\n", stats.floor_tiles));
+    text.push_str(&format!("npcs: {}\n", stats.npcs));
+    text.push_str(&format!("items: {}\n", stats.items));
+    match stats.exit_distance {
+        Some(distance) => text.push_str(&format!("exit: {} steps from arrival\n", distance)),
+        None => text.push_str("exit: unreachable\n"),
+    }
+    text
+}
+
+#[cfg(not(any(feature = "graphical", feature = "terminal", feature = "web")))]
+compile_error!("enable the \"graphical\", \"terminal\" or \"web\" feature to choose a frontend");
+
+#[cfg(all(feature = "graphical", not(feature = "web")))]
+fn run(app: impl ChargridApp + 'static, screen_size: Size, display: DisplayConfig) {
+    use chargrid_graphical::{Config as GraphicalConfig, Context, Dimensions, FontBytes};
+    // a font from the config replaces the built-in one
+    fn font(path: Option<String>, key: &str, built_in: &[u8]) -> Vec<u8> {
+        match path {
+            Some(path) => std::fs::read(&path).unwrap_or_else(|error| {
+                log_error!("Failed to load {} from {}: {}", key, path, error);
+                std::process::exit(1);
+            }),
+            None => built_in.to_vec(),
+        }
+    }
+    let cell_size_px = display.cell_size_px as f64;
+    let context = Context::new(GraphicalConfig {
         font_bytes: FontBytes {
-            normal: include_bytes!("./fonts/PxPlus_IBM_CGAthin.ttf").to_vec(),
-            bold: include_bytes!("./fonts/PxPlus_IBM_CGA.ttf").to_vec(),
+            normal: font(
+                display.font_normal,
+                "font_normal",
+                include_bytes!("./fonts/PxPlus_IBM_CGAthin.ttf"),
+            ),
+            bold: font(
+                display.font_bold,
+                "font_bold",
+                include_bytes!("./fonts/PxPlus_IBM_CGA.ttf"),
+            ),
         },
-        title: "Chargrid Tutorial".to_string(),
+        title: display.window_title,
         window_dimensions_px: Dimensions {
-            width: 960.0,
-            height: 720.0,
+            width: screen_size.width() as f64 * cell_size_px,
+            height: screen_size.height() as f64 * cell_size_px,
         },
         cell_dimensions_px: Dimensions {
-            width: CELL_SIZE_PX,
-            height: CELL_SIZE_PX,
+            width: cell_size_px,
+            height: cell_size_px,
         },
         font_scale: Dimensions {
-            width: CELL_SIZE_PX,
-            height: CELL_SIZE_PX,
+            width: cell_size_px,
+            height: cell_size_px,
         },
         underline_width_cell_ratio: 0.1,
         underline_top_offset_cell_ratio: 0.8,
-        resizable: false,
+        resizable: true,
+    });
+    context.run_app(app);
+}
+
+// The terminal is put back how it was when the context is dropped, which
+// also happens while unwinding from a panic. The graphical frontend wins
+// if both features are enabled. The terminal is whatever size it is.
+#[cfg(all(feature = "terminal", not(any(feature = "graphical", feature = "web"))))]
+fn run(app: impl ChargridApp, _screen_size: Size, _display: DisplayConfig) {
+    use chargrid_ansi_terminal::{col_encode, Context};
+    let context = Context::new().unwrap_or_else(|error| {
+        log_error!("Failed to start the terminal: {:?}", error);
+        std::process::exit(1);
     });
-    let screen_size = Size::new(40, 30);
-    let app = app(screen_size, rng_seed, visibility_algorithm);
-    context.run_app(app);        
+    logging::set_console_enabled(false);
+    context.run_app(app, col_encode::FromTermInfoRgb);
+    logging::set_console_enabled(true);
+}
+
+// Draws into the element with id "content" on the page. See web/index.html.
+#[cfg(feature = "web")]
+fn run(app: impl ChargridApp + 'static, screen_size: Size, _display: DisplayConfig) {
+    let context = chargrid_web::Context::new(screen_size, "content");
+    context.run_app(app);
 }
 
 struct Args {
-    rng_seed: u64,
+    rng_seed: Option<u64>,
     visibility_algorithm: VisibilityAlgorithm,
+    data_path: Option<String>,
+    theme_name: Option<ThemeName>,
+    difficulty: Option<Difficulty>,
+    diagnostics: bool,
+    wizard: bool,
+    daily: bool,
+    start_level: u32,
+    level_ups: Option<LevelUpList>,
+    give: Option<ItemList>,
+    level_file: Option<String>,
+    keys_path: Option<String>,
+    bot_turns: Option<u64>,
+    bot_seeds: Option<SeedRange>,
+    record_path: Option<String>,
+    replay_path: Option<String>,
+    verify_replay: bool,
+    replay_step_ms: u64,
+    dump_map: bool,
+    dump_level: u32,
+    dump_count: u64,
+    dump_dir: Option<String>,
+    map_size: Option<MapSize>,
+    ui_rows: Option<u32>,
+    log_level: LevelFilter,
+    log_file: Option<String>,
+    config_path: Option<String>,
+    lang: Option<String>,
+}
+
+// A range of seeds written as START..END, for playing many games with the
+// bot
+struct SeedRange(Range<u64>);
+
+impl FromStr for SeedRange {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid seed range {:?} (expected START..END)", s);
+        let (start, end) = s.split_once("..").ok_or_else(error)?;
+        let start = start.parse::<u64>().map_err(|_| error())?;
+        let end = end.parse::<u64>().map_err(|_| error())?;
+        Ok(Self(start..end))
+    }
+}
+
+// Stats separated by commas, for --level-ups
+struct LevelUpList(Vec<LevelUp>);
+
+impl FromStr for LevelUpList {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|level_up| level_up.trim().parse())
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+// Items separated by commas, for --give
+struct ItemList(Vec<ItemType>);
+
+impl FromStr for ItemList {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|item_type| item_type.trim().parse())
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
 }
 
 impl Args {
+    #[cfg(not(feature = "web"))]
+    fn get() -> Self {
+        use meap::Parser;
+        Self::parser().with_help_default().parse_env_or_exit()
+    }
+
+    // In the browser the options come from the page's query string, as in
+    // index.html?seed=42&theme=deuteranopia&map-size=60x30&diagnostics.
+    // There are no files to load data or key bindings from.
+    #[cfg(feature = "web")]
+    fn get() -> Self {
+        let query = web_sys::window()
+            .and_then(|window| window.location().search().ok())
+            .unwrap_or_default();
+        let mut args = Self {
+            rng_seed: None,
+            visibility_algorithm: VisibilityAlgorithm::Shadowcast,
+            data_path: None,
+            theme_name: None,
+            difficulty: None,
+            diagnostics: false,
+            wizard: false,
+            daily: false,
+            start_level: 1,
+            level_ups: None,
+            give: None,
+            level_file: None,
+            keys_path: None,
+            bot_turns: None,
+            bot_seeds: None,
+            record_path: None,
+            replay_path: None,
+            verify_replay: false,
+            replay_step_ms: 0,
+            dump_map: false,
+            dump_level: 1,
+            dump_count: 1,
+            dump_dir: None,
+            map_size: None,
+            ui_rows: None,
+            log_level: LevelFilter::Info,
+            log_file: None,
+            config_path: None,
+            lang: None,
+        };
+        for param in query
+            .trim_start_matches('?')
+            .split('&')
+            .filter(|param| !param.is_empty())
+        {
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (param, None),
+            };
+            match (name, value) {
+                ("seed", Some(value)) if value.parse::<u64>().is_ok() => {
+                    args.rng_seed = Some(value.parse().unwrap());
+                }
+                ("theme", Some(value)) if value.parse::<ThemeName>().is_ok() => {
+                    args.theme_name = Some(value.parse().unwrap());
+                }
+                ("difficulty", Some(value)) if value.parse::<Difficulty>().is_ok() => {
+                    args.difficulty = Some(value.parse().unwrap());
+                }
+                ("debug-omniscient", None) => {
+                    args.visibility_algorithm = VisibilityAlgorithm::Omniscient;
+                }
+                ("map-size", Some(value)) if value.parse::<MapSize>().is_ok() => {
+                    args.map_size = Some(value.parse().unwrap());
+                }
+                ("diagnostics", None) => args.diagnostics = true,
+                ("wizard", None) => args.wizard = true,
+                _ => log_error!("Ignoring query parameter {}", param),
+            }
+        }
+        args
+    }
+
+    #[cfg(not(feature = "web"))]
     fn parser() -> impl meap::Parser<Item = Self> {
         meap::let_map! {
             let {
                 rng_seed = opt_opt::<u64, _>("INT", "r")
                     .name("rng-seed")
-                    .desc("seed for random number generator")
-                    .with_default_lazy("randomly chosen seed", || rand::thread_rng().gen());
+                    .desc("seed for random number generator (randomly chosen if not given)");
                 visibility_algorithm = flag("debug-omniscient").some_if(VisibilityAlgorithm::Omniscient)
                     .with_default_general(VisibilityAlgorithm::Shadowcast);
+                data_path = opt_opt::<String, _>("PATH", "d")
+                    .name("data")
+                    .desc("load spawn tables from this file instead of the built-in ones");
+                theme_name = opt_opt::<ThemeName, _>("NAME", "t")
+                    .name("theme")
+                    .desc("colour theme: classic, high-contrast or deuteranopia");
+                difficulty = opt_opt::<Difficulty, _>("NAME", "difficulty")
+                    .desc("the difficulty of new runs: easy, normal or hard (the daily challenge is always normal)");
+                diagnostics = flag("diagnostics")
+                    .desc("allow toggling the diagnostics overlay with F3");
+                wizard = flag("wizard")
+                    .desc("enable cheats on F2 to F9 for testing, which take the place of F3's diagnostics overlay");
+                daily = flag("daily")
+                    .desc("play today's daily challenge, the same dungeon for everyone playing today");
+                start_level = opt_opt::<u32, _>("N", "start-level")
+                    .desc("start each new run on this level, levelling up for each one skipped, which counts as cheating")
+                    .with_default(1);
+                level_ups = opt_opt::<LevelUpList, _>("STATS", "level-ups")
+                    .desc("with --start-level, the stats to raise in turn, such as s,d,i,h (all four in turn if not given)");
+                give = opt_opt::<ItemList, _>("ITEMS", "give")
+                    .desc("start each new run with these items, such as torch,health-potion, which counts as cheating");
+                level_file = opt_opt::<String, _>("PATH", "level-file")
+                    .desc("play this hand-made level, drawn in the characters --dump-map prints, as the first level, which counts as cheating");
+                keys_path = opt_opt::<String, _>("PATH", "k")
+                    .name("keys")
+                    .desc("load key bindings from this file");
+                bot_turns = opt_opt::<u64, _>("TURNS", "b")
+                    .name("bot")
+                    .desc("let a bot play for up to this many turns without a window, then print how it did");
+                bot_seeds = opt_opt::<SeedRange, _>("RANGE", "s")
+                    .name("bot-seeds")
+                    .desc("with --bot, play a game for each seed in START..END");
+                record_path = opt_opt::<String, _>("PATH", "R")
+                    .name("record")
+                    .desc("start a new game, recording every action to this file");
+                replay_path = opt_opt::<String, _>("PATH", "p")
+                    .name("replay")
+                    .desc("watch a recording made with --record");
+                verify_replay = flag("verify")
+                    .desc("with --replay, play the recording without a window and check it goes the same way");
+                replay_step_ms = opt_opt::<u64, _>("MS", "m")
+                    .name("replay-speed")
+                    .desc("with --replay, the time between actions in milliseconds")
+                    .with_default(100);
+                dump_map = flag("dump-map")
+                    .desc("print a generated level as text instead of playing");
+                dump_level = opt_opt::<u32, _>("N", "l")
+                    .name("level")
+                    .desc("with --dump-map, the level to generate")
+                    .with_default(1);
+                dump_count = opt_opt::<u64, _>("M", "c")
+                    .name("count")
+                    .desc("with --dump-map, the number of seeds to generate the level for, counting up from the RNG seed")
+                    .with_default(1);
+                dump_dir = opt_opt::<String, _>("PATH", "o")
+                    .name("dump-dir")
+                    .desc("with --dump-map, write each level to a file in this directory instead of printing it");
+                map_size = opt_opt::<MapSize, _>("WxH", "M")
+                    .name("map-size")
+                    .desc("the size of each level in cells");
+                ui_rows = opt_opt::<u32, _>("N", "u")
+                    .name("ui-rows")
+                    .desc("the number of rows below the map for the UI");
+                log_level = opt_opt::<LevelFilter, _>("LEVEL", "log-level")
+                    .desc("the least important messages to log: error, warn, info, debug or trace")
+                    .with_default_general(LevelFilter::Info);
+                log_file = opt_opt::<String, _>("PATH", "log-file")
+                    .desc("also write log messages to this file, to attach to bug reports");
+                config_path = opt_opt::<String, _>("PATH", "config")
+                    .desc("load settings from this file instead of config.toml next to the executable");
+                lang = opt_opt::<String, _>("CODE", "lang")
+                    .desc("show text in this language, from lang/CODE.toml next to the executable");
             } in {
-                Self { rng_seed, visibility_algorithm }
+                Self {
+                    rng_seed,
+                    visibility_algorithm,
+                    data_path,
+                    theme_name,
+                    difficulty,
+                    diagnostics,
+                    wizard,
+                    daily,
+                    start_level,
+                    level_ups,
+                    give,
+                    keys_path,
+                    bot_turns,
+                    bot_seeds,
+                    record_path,
+                    replay_path,
+                    verify_replay,
+                    replay_step_ms,
+                    dump_map,
+                    dump_level,
+                    dump_count,
+                    dump_dir,
+                    map_size,
+                    ui_rows,
+                    log_level,
+                    log_file,
+                    config_path,
+                    lang,
+                }
             }
         }
     }
 }
-
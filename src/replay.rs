@@ -0,0 +1,184 @@
+// replay.rs
+
+// Recordings of runs which can be played back action by action. A
+// recording is a JSON value per line: first how the run began, then each
+// action the player took. A checksum of the game's state follows the
+// actions whenever their animations have finished, so a playback which
+// goes differently is caught close to where it went wrong.
+
+use crate::difficulty::Difficulty;
+use crate::game::{GameState, NewCharacter, PlayerAction};
+use crate::terrain::SpawnTables;
+use crate::visibility::VisibilityAlgorithm;
+use coord_2d::Size;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+// Everything needed to generate the same first level again
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub rng_seed: u64,
+    pub map_size: Size,
+    pub visibility_algorithm: VisibilityAlgorithm,
+    pub spawn_tables: SpawnTables,
+    // recordings made before there were difficulties are at Normal
+    #[serde(default)]
+    pub difficulty: Difficulty,
+    #[serde(default)]
+    pub new_character: NewCharacter,
+}
+
+impl ReplayHeader {
+    pub fn new_game_state(&self) -> GameState {
+        GameState::new(
+            self.map_size,
+            self.rng_seed,
+            self.visibility_algorithm,
+            self.spawn_tables.clone(),
+            self.difficulty,
+            self.new_character.clone(),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum ReplayEntry {
+    Action(PlayerAction),
+    // GameState::checksum once every action before it had finished
+    Checksum(u64),
+}
+
+pub struct Recorder {
+    path: String,
+    writer: BufWriter<File>,
+    // set when actions have been written since the last checksum
+    unchecked: bool,
+}
+
+impl Recorder {
+    pub fn create(path: &str, header: &ReplayHeader) -> Result<Self, String> {
+        let file = File::create(path).map_err(|error| error.to_string())?;
+        let mut recorder = Self {
+            path: path.to_string(),
+            writer: BufWriter::new(file),
+            unchecked: false,
+        };
+        recorder.write_line(header)?;
+        Ok(recorder)
+    }
+
+    // Replaces the recording with one of a new run
+    pub fn start_again(&mut self, header: &ReplayHeader) -> Result<(), String> {
+        *self = Self::create(&self.path, header)?;
+        Ok(())
+    }
+
+    pub fn record(&mut self, action: PlayerAction) -> Result<(), String> {
+        self.unchecked = true;
+        self.write_line(&ReplayEntry::Action(action))
+    }
+
+    // Writes a checksum if there have been actions since the last one and
+    // their animations are over
+    pub fn check(&mut self, game_state: &GameState) -> Result<(), String> {
+        if !self.unchecked || game_state.has_animations() {
+            return Ok(());
+        }
+        self.unchecked = false;
+        self.write_line(&ReplayEntry::Checksum(game_state.checksum()))
+    }
+
+    // Each line is flushed straight away so a crash doesn't lose the
+    // actions leading up to it
+    fn write_line<T: Serialize>(&mut self, value: &T) -> Result<(), String> {
+        let line = serde_json::to_string(value).map_err(|error| error.to_string())?;
+        writeln!(self.writer, "{}", line).map_err(|error| error.to_string())?;
+        self.writer.flush().map_err(|error| error.to_string())
+    }
+}
+
+// Where a playback first stopped matching its recording
+#[derive(Clone, Copy, Debug)]
+pub struct Desync {
+    pub line: usize,
+    // the turn of the last checksum which matched
+    pub last_matching_turn: u64,
+    pub turn: u64,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "replay diverged between turns {} and {} (line {}): recorded checksum {:016x}, got {:016x}",
+            self.last_matching_turn, self.turn, self.line, self.expected, self.actual,
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub entries: Vec<ReplayEntry>,
+}
+
+impl Replay {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|error| error.to_string())?;
+        let mut lines = BufReader::new(file).lines();
+        let header = match lines.next() {
+            Some(line) => {
+                let line = line.map_err(|error| error.to_string())?;
+                serde_json::from_str(&line).map_err(|error| format!("line 1: {}", error))?
+            }
+            None => return Err("the file is empty".to_string()),
+        };
+        let entries = lines
+            .enumerate()
+            .map(|(index, line)| {
+                let line = line.map_err(|error| error.to_string())?;
+                serde_json::from_str(&line)
+                    .map_err(|error| format!("line {}: {}", Self::line_of(index), error))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self { header, entries })
+    }
+
+    // The line of the file an entry is on, after the header
+    pub fn line_of(entry_index: usize) -> usize {
+        entry_index + 2
+    }
+
+    // Plays the whole recording without drawing anything, stopping at the
+    // first checksum which doesn't match. Returns the game as it was left.
+    pub fn verify(&self) -> Result<GameState, Desync> {
+        let visibility_algorithm = self.header.visibility_algorithm;
+        let mut game_state = self.header.new_game_state();
+        let mut last_matching_turn = 0;
+        for (index, &entry) in self.entries.iter().enumerate() {
+            match entry {
+                ReplayEntry::Action(action) => {
+                    game_state.act(action, visibility_algorithm);
+                }
+                ReplayEntry::Checksum(expected) => {
+                    let actual = game_state.checksum();
+                    if actual != expected {
+                        return Err(Desync {
+                            line: Self::line_of(index),
+                            last_matching_turn,
+                            turn: game_state.turn(),
+                            expected,
+                            actual,
+                        });
+                    }
+                    last_matching_turn = game_state.turn();
+                }
+            }
+        }
+        Ok(game_state)
+    }
+}
@@ -0,0 +1,187 @@
+// scores.rs
+
+// A table of the best runs played on this computer, kept next to the save.
+// An entry is added whenever a run ends in death or escape.
+
+use general_storage_file::{format, FileStorage, IfDirectoryMissing, Storage};
+use serde::{Deserialize, Serialize};
+
+use chargrid_roguelike_tutorial_2020::{log_error, log_info};
+
+use crate::app::SAVE_DIR;
+use crate::daily;
+use crate::difficulty::Difficulty;
+use crate::game::GameState;
+use crate::i18n;
+use crate::world::DamageSource;
+
+const SCORES_FILE: &str = "scores";
+// kept as plain JSON so players can look at it
+const SCORES_FORMAT: format::Json = format::Json;
+const MAX_HIGH_SCORES: usize = 100;
+
+// Points for each part of a run. Going deeper counts for the most, and
+// every turn taken costs a little so faster runs score higher. The total is
+// then scaled by the run's difficulty.
+const POINTS_PER_DUNGEON_LEVEL: u64 = 1000;
+const POINTS_PER_KILL: u64 = 50;
+const POINTS_FOR_ESCAPING: u64 = 5000;
+const TURNS_PER_POINT_LOST: u64 = 10;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HighScore {
+    pub score: u64,
+    // the UTC date the run ended, where it's known
+    pub date: Option<String>,
+    pub rng_seed: u64,
+    pub daily: bool,
+    pub character_level: u32,
+    pub dungeon_level: u32,
+    pub turns: u64,
+    pub kills: u32,
+    pub escaped: bool,
+    pub killed_by: Option<DamageSource>,
+    pub cheated: bool,
+    #[serde(default)]
+    pub difficulty: Difficulty,
+    // the character's name, empty for runs from before characters had
+    // names
+    #[serde(default)]
+    pub name: String,
+}
+
+impl HighScore {
+    pub fn new(game_state: &GameState) -> Self {
+        let run_stats = game_state.run_stats();
+        let escaped = game_state.is_player_alive();
+        let score = (game_state.dungeon_level() as u64 * POINTS_PER_DUNGEON_LEVEL
+            + run_stats.total_kills() as u64 * POINTS_PER_KILL
+            + if escaped { POINTS_FOR_ESCAPING } else { 0 })
+        .saturating_sub(game_state.turn() / TURNS_PER_POINT_LOST)
+            * game_state.difficulty().profile().score_percent
+            / 100;
+        Self {
+            score,
+            date: daily::today(),
+            rng_seed: game_state.run_seed(),
+            daily: game_state.daily_date().is_some(),
+            character_level: run_stats.character_level(),
+            dungeon_level: game_state.dungeon_level(),
+            turns: game_state.turn(),
+            kills: run_stats.total_kills(),
+            escaped,
+            killed_by: run_stats.killed_by,
+            cheated: game_state.is_cheated(),
+            difficulty: game_state.difficulty(),
+            name: game_state.character_name().to_string(),
+        }
+    }
+
+    // Short enough to fit on a line of the table
+    pub fn outcome(&self) -> &'static str {
+        match (self.escaped, self.killed_by) {
+            (true, _) => i18n::text("high-scores.escaped"),
+            (false, Some(DamageSource::Player)) => i18n::text("high-scores.own-fireball"),
+            (false, Some(DamageSource::Npc(npc_type))) => i18n::npc_name(npc_type),
+            (false, Some(DamageSource::Trap(trap_type))) => i18n::trap_name(trap_type),
+            (false, Some(DamageSource::Lava)) => i18n::text("high-scores.lava"),
+            (false, Some(DamageSource::Fall)) => i18n::text("high-scores.fall"),
+            (false, None) => i18n::text("high-scores.died"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HighScoreOrder {
+    Score,
+    DungeonLevel,
+}
+
+impl HighScoreOrder {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Score => i18n::text("high-scores.by-score"),
+            Self::DungeonLevel => i18n::text("high-scores.by-depth"),
+        }
+    }
+}
+
+// Kept in order of score, best first
+#[derive(Default)]
+pub struct HighScores {
+    entries: Vec<HighScore>,
+    // the entry for the run which just ended, which is highlighted
+    latest: Option<HighScore>,
+}
+
+impl HighScores {
+    // A missing or unreadable scores file gives an empty table, which
+    // replaces it the next time a run ends
+    pub fn load() -> Self {
+        let file_storage = match FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create) {
+            Ok(file_storage) => file_storage,
+            Err(error) => {
+                log_error!("Failed to load high scores: {:?}", error);
+                return Self::default();
+            }
+        };
+        if !file_storage.exists(SCORES_FILE) {
+            return Self::default();
+        }
+        let entries: Result<Vec<HighScore>, _> = file_storage.load(SCORES_FILE, SCORES_FORMAT);
+        match entries {
+            Ok(mut entries) => {
+                entries.sort_by(|a, b| b.score.cmp(&a.score));
+                entries.truncate(MAX_HIGH_SCORES);
+                Self {
+                    entries,
+                    latest: None,
+                }
+            }
+            Err(error) => {
+                log_error!("Ignoring unreadable high scores: {:?}", error);
+                Self::default()
+            }
+        }
+    }
+
+    fn store(&self) {
+        let mut file_storage = match FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create)
+        {
+            Ok(file_storage) => file_storage,
+            Err(error) => {
+                log_error!("Failed to save high scores: {:?}", error);
+                return;
+            }
+        };
+        if let Err(error) = file_storage.store(SCORES_FILE, &self.entries, SCORES_FORMAT) {
+            log_error!("Failed to save high scores: {:?}", error);
+        }
+    }
+
+    // Adds the entry and saves the table. Only the best MAX_HIGH_SCORES
+    // are kept.
+    pub fn add(&mut self, high_score: HighScore) {
+        log_info!("Run ended with a score of {}", high_score.score);
+        let index = self
+            .entries
+            .partition_point(|entry| entry.score >= high_score.score);
+        self.entries.insert(index, high_score.clone());
+        self.entries.truncate(MAX_HIGH_SCORES);
+        self.latest = Some(high_score);
+        self.store();
+    }
+
+    pub fn is_latest(&self, high_score: &HighScore) -> bool {
+        self.latest.as_ref() == Some(high_score)
+    }
+
+    pub fn sorted(&self, order: HighScoreOrder) -> Vec<&HighScore> {
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+        if order == HighScoreOrder::DungeonLevel {
+            // stable, so runs as deep as each other stay in order of score
+            entries.sort_by(|a, b| b.dungeon_level.cmp(&a.dungeon_level));
+        }
+        entries
+    }
+}
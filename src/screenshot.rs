@@ -0,0 +1,144 @@
+// screenshot.rs
+
+// Saving what's on screen as text. The screen is drawn a second time
+// into a TextFrame rather than the window, which keeps the character on
+// top in each cell, so anything which can be drawn can be captured.
+
+use chargrid::render::{Blend, Frame, ViewCell};
+use coord_2d::{Coord, Size};
+#[cfg(not(feature = "web"))]
+use general_storage_file::{FileStorage, IfDirectoryMissing, Storage};
+use rgb24::Rgb24;
+use std::path::PathBuf;
+
+#[cfg(not(feature = "web"))]
+use crate::daily;
+
+#[cfg(not(feature = "web"))]
+const SCREENSHOT_DIR: &str = "screenshots";
+
+pub struct TextFrame {
+    size: Size,
+    // the depth each cell's character was drawn at
+    cells: Vec<(i8, char)>,
+}
+
+impl TextFrame {
+    pub fn new(size: Size) -> Self {
+        Self {
+            size,
+            cells: vec![(i8::MIN, ' '); size.count()],
+        }
+    }
+
+    // One line per row, without trailing spaces
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for row in self.cells.chunks(self.size.width() as usize) {
+            let line = row.iter().map(|&(_, c)| c).collect::<String>();
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        text
+    }
+}
+
+impl Frame for TextFrame {
+    fn set_cell_absolute(
+        &mut self,
+        absolute_coord: Coord,
+        absolute_depth: i8,
+        absolute_cell: ViewCell,
+    ) {
+        if !absolute_coord.is_valid(self.size) {
+            return;
+        }
+        let width = self.size.width() as usize;
+        let index = absolute_coord.y as usize * width + absolute_coord.x as usize;
+        let (depth, character) = &mut self.cells[index];
+        if absolute_depth >= *depth {
+            *depth = absolute_depth;
+            if let Some(c) = absolute_cell.character {
+                *character = c;
+            }
+        }
+    }
+
+    // only the characters are kept
+    fn blend_cell_background_absolute<B: Blend>(
+        &mut self,
+        _absolute_coord: Coord,
+        _absolute_depth: i8,
+        _rgb24: Rgb24,
+        _alpha: u8,
+        _blend: B,
+    ) {
+    }
+}
+
+// Writes the text to a file named after the time in UTC, such as
+// screenshot-2024-05-01-093000.txt, in the screenshots directory next to
+// the executable, returning where it went
+#[cfg(not(feature = "web"))]
+pub fn save(text: &str) -> Result<PathBuf, String> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    const SECONDS_PER_DAY: u64 = 86400;
+    let mut file_storage = FileStorage::next_to_exe(SCREENSHOT_DIR, IfDirectoryMissing::Create)
+        .map_err(|error| format!("{:?}", error))?;
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|error| error.to_string())?
+        .as_secs();
+    let (year, month, day) = daily::civil_from_days((seconds / SECONDS_PER_DAY) as i64);
+    let time_of_day = seconds % SECONDS_PER_DAY;
+    let file_name = format!(
+        "screenshot-{:04}-{:02}-{:02}-{:02}{:02}{:02}.txt",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60,
+    );
+    file_storage
+        .store_raw(&file_name, text)
+        .map_err(|error| format!("{:?}", error))?;
+    Ok(file_storage.full_path(&file_name))
+}
+
+// there's nowhere to write files in the browser
+#[cfg(feature = "web")]
+pub fn save(_text: &str) -> Result<PathBuf, String> {
+    Err("screenshots can't be saved in the browser".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(frame: &mut TextFrame, x: i32, y: i32, depth: i8, character: char) {
+        frame.set_cell_absolute(
+            Coord::new(x, y),
+            depth,
+            ViewCell::new().with_character(character),
+        );
+    }
+
+    #[test]
+    fn a_small_scene_as_text() {
+        let mut frame = TextFrame::new(Size::new(5, 3));
+        draw(&mut frame, 0, 0, 0, '#');
+        // the player on the floor, drawn either way round
+        draw(&mut frame, 1, 0, 0, '.');
+        draw(&mut frame, 1, 0, 1, '@');
+        draw(&mut frame, 2, 0, 2, 'o');
+        draw(&mut frame, 2, 0, 0, '.');
+        // a colour drawn over the top leaves the character
+        frame.set_cell_absolute(Coord::new(0, 0), 3, ViewCell::new());
+        draw(&mut frame, 0, 2, 0, 'x');
+        // off the edge
+        draw(&mut frame, 5, 0, 0, '!');
+        draw(&mut frame, 0, -1, 0, '!');
+        assert_eq!(frame.text(), "#@o\n\nx\n");
+    }
+}
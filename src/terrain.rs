@@ -1,11 +1,17 @@
 // terrain.rs
 
+use direction::Direction;
 use grid_2d::{Coord, Grid, Size};
-use rand::{seq::IteratorRandom, seq::SliceRandom, Rng};
+use rand::{seq::IteratorRandom, seq::SliceRandom, Rng, SeedableRng};
+use rand_isaac::Isaac64Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::ops::Range;
 
-use crate::world::{ItemType, NpcType};
+use crate::difficulty::{self, DifficultyProfile};
+use crate::world::{ItemType, NpcType, TrapType};
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TerrainTile {
     Player,
     Floor,
@@ -13,72 +19,1353 @@ pub enum TerrainTile {
     Npc(NpcType),
     Item(ItemType),
     Stairs,
+    Gold,
+    ShopItem(ItemType),
+    ShopExit,
+    Altar,
+    DoorClosed,
+    Trap(TrapType),
+    StairsUp,
+    Water,
+    Lava,
+    SecretDoor,
+    Foliage,
+    Chasm,
+    Rune,
+    Portal,
+    Torch,
 }
 
-pub fn generate_dungeon<R: Rng>(size: Size, level: u32, rng: &mut R) -> Grid<TerrainTile> {
+// Each band of levels has its own look, room layout and population
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LevelTheme {
+    Dungeon,
+    Caves,
+    Crypt,
+    Lair,
+}
+
+impl LevelTheme {
+    pub fn from_dungeon_level(level: u32) -> Self {
+        match level {
+            0..=3 => Self::Dungeon,
+            4..=6 => Self::Caves,
+            7..=9 => Self::Crypt,
+            _ => Self::Lair,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Dungeon => "dungeon",
+            Self::Caves => "caves",
+            Self::Crypt => "crypt",
+            Self::Lair => "lair",
+        }
+    }
+
+    fn room_size_ranges(self) -> (Range<u32>, Range<u32>) {
+        match self {
+            Self::Dungeon | Self::Caves => (5..11, 5..9),
+            Self::Crypt => (5..8, 5..7),
+            Self::Lair => (8..14, 7..11),
+        }
+    }
+
+    // The attempts are tuned for maps of up to ROOM_ATTEMPTS_AREA cells,
+    // and larger maps get more so they fill up as much
+    fn num_room_attempts(self, size: Size) -> usize {
+        const ROOM_ATTEMPTS_AREA: u32 = 80 * 50;
+        let num_attempts = match self {
+            Self::Dungeon | Self::Caves => 100,
+            Self::Crypt => 200,
+            Self::Lair => 50,
+        };
+        num_attempts * (size.count() / ROOM_ATTEMPTS_AREA as usize).max(1)
+    }
+
+    fn has_rough_rooms(self) -> bool {
+        self == Self::Caves
+    }
+
+    // The crypt is dark, so the light the player carries reaches less
+    // far there
+    pub fn player_light_radius(self) -> u32 {
+        match self {
+            Self::Crypt => 3,
+            _ => 6,
+        }
+    }
+
+    // How far the player can see without carrying a torch. Deeper levels
+    // are darker.
+    pub fn sight_radius(self) -> u32 {
+        match self {
+            Self::Dungeon | Self::Caves => 5,
+            Self::Lair => 4,
+            Self::Crypt => 3,
+        }
+    }
+
+    fn torch_per_room_chance(self) -> f64 {
+        match self {
+            Self::Dungeon => 0.5,
+            Self::Caves => 0.2,
+            Self::Crypt => 0.1,
+            Self::Lair => 0.4,
+        }
+    }
+
+    fn foliage_per_room_chance(self) -> f64 {
+        match self {
+            Self::Dungeon => 0.1,
+            Self::Caves => 0.4,
+            Self::Crypt => 0.0,
+            Self::Lair => 0.2,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Dungeon {
+    pub grid: Grid<TerrainTile>,
+    pub room_count: usize,
+    pub level_stats: LevelStats,
+}
+
+// What generation rolled for a level next to what it rolls on average,
+// which the player is given a feeling for when they arrive. Levels which
+// aren't rolled, such as hand-made ones, leave everything at zero.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LevelStats {
+    pub npcs: usize,
+    pub expected_npcs: f64,
+    pub items: usize,
+    pub expected_items: f64,
+    // NPCs chosen from the spawn tables of a deeper level
+    pub out_of_depth_npcs: usize,
+    pub has_danger_room: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LevelFeeling {
+    Danger,
+    Treasure,
+    Quiet,
+}
+
+impl LevelStats {
+    // How the level feels, in the order to tell the player. Only counts
+    // well away from what's expected are worth a feeling.
+    pub fn feelings(&self) -> Vec<LevelFeeling> {
+        const CROWDED_RATIO: f64 = 1.5;
+        const SPARSE_RATIO: f64 = 0.5;
+        // the count over the average, if anything was rolled at all
+        let ratio = |count: usize, expected: f64| (expected > 0.0).then(|| count as f64 / expected);
+        let npc_ratio = ratio(self.npcs, self.expected_npcs);
+        let item_ratio = ratio(self.items, self.expected_items);
+        let mut feelings = Vec::new();
+        let dangerous =
+            self.out_of_depth_npcs > 0 || npc_ratio.map_or(false, |ratio| ratio >= CROWDED_RATIO);
+        if dangerous {
+            feelings.push(LevelFeeling::Danger);
+        }
+        if self.has_danger_room || item_ratio.map_or(false, |ratio| ratio >= CROWDED_RATIO) {
+            feelings.push(LevelFeeling::Treasure);
+        }
+        if !dangerous && npc_ratio.map_or(false, |ratio| ratio <= SPARSE_RATIO) {
+            feelings.push(LevelFeeling::Quiet);
+        }
+        feelings
+    }
+}
+
+// Figures describing a generated level, for reviewing changes to level
+// generation
+#[derive(Clone, Copy, Debug)]
+pub struct DungeonStats {
+    pub rooms: usize,
+    // cells which can be walked on
+    pub floor_tiles: usize,
+    pub npcs: usize,
+    // including those for sale in shops
+    pub items: usize,
+    // the number of steps from the arrival point to the stairs down, or
+    // to the portal on the final level. None if there's no way there.
+    pub exit_distance: Option<u32>,
+}
+
+impl Dungeon {
+    pub fn stats(&self) -> DungeonStats {
+        let count = |f: fn(TerrainTile) -> bool| self.grid.iter().filter(|&&tile| f(tile)).count();
+        let find = |f: fn(TerrainTile) -> bool| {
+            self.grid
+                .enumerate()
+                .find(|&(_, &tile)| f(tile))
+                .map(|(coord, _)| coord)
+        };
+        let arrival = find(|tile| matches!(tile, TerrainTile::Player | TerrainTile::StairsUp));
+        let exit = find(|tile| matches!(tile, TerrainTile::Stairs | TerrainTile::Portal));
+        let exit_distance = match (arrival, exit) {
+            (Some(arrival), Some(exit)) => walking_distance(&self.grid, arrival, exit),
+            _ => None,
+        };
+        DungeonStats {
+            rooms: self.room_count,
+            floor_tiles: count(TerrainTile::is_passable),
+            npcs: count(|tile| matches!(tile, TerrainTile::Npc(_))),
+            items: count(|tile| matches!(tile, TerrainTile::Item(_) | TerrainTile::ShopItem(_))),
+            exit_distance,
+        }
+    }
+}
+
+// What each character of a hand-made level stands for. These are the
+// characters --dump-map prints, and where one stands for several things
+// it means the first of them.
+fn terrain_tile_from_char(c: char) -> Option<TerrainTile> {
+    let terrain_tile = match c {
+        '@' => TerrainTile::Player,
+        '.' => TerrainTile::Floor,
+        '#' => TerrainTile::Wall,
+        '>' => TerrainTile::Stairs,
+        'o' => TerrainTile::Npc(NpcType::Orc),
+        'T' => TerrainTile::Npc(NpcType::Troll),
+        '$' => TerrainTile::Gold,
+        '!' => TerrainTile::Item(ItemType::HealthPotion),
+        '?' => TerrainTile::Item(ItemType::FireballScroll),
+        '/' => TerrainTile::Item(ItemType::Sword),
+        '\\' => TerrainTile::Item(ItemType::Staff),
+        '[' | ']' => TerrainTile::Item(ItemType::Armor),
+        '}' => TerrainTile::Item(ItemType::Robe),
+        '(' => TerrainTile::Item(ItemType::Backpack),
+        '&' => TerrainTile::Item(ItemType::Warhammer),
+        '+' => TerrainTile::DoorClosed,
+        '^' => TerrainTile::Trap(TrapType::Spike),
+        '_' => TerrainTile::Altar,
+        '~' => TerrainTile::Water,
+        '"' => TerrainTile::Foliage,
+        ':' => TerrainTile::Chasm,
+        _ => return None,
+    };
+    Some(terrain_tile)
+}
+
+// Reads a hand-made level drawn in text, one line per row of the map.
+// Errors give the line and column of the problem.
+pub fn parse_level(text: &str) -> Result<Dungeon, String> {
+    let lines = text
+        .trim_end_matches(&['\r', '\n'][..])
+        .lines()
+        .collect::<Vec<_>>();
+    let width = lines.first().map_or(0, |line| line.chars().count());
+    if width == 0 {
+        return Err("the level is empty".to_string());
+    }
+    let size = Size::new(width as u32, lines.len() as u32);
+    let mut grid = Grid::new_copy(size, TerrainTile::Wall);
+    let mut player_coord = None;
+    for (y, line) in lines.iter().enumerate() {
+        let line_width = line.chars().count();
+        if line_width != width {
+            return Err(format!(
+                "line {}: {} characters long, where line 1 is {}",
+                y + 1,
+                line_width,
+                width
+            ));
+        }
+        for (x, c) in line.chars().enumerate() {
+            let terrain_tile = terrain_tile_from_char(c).ok_or_else(|| {
+                format!(
+                    "line {}, column {}: unknown character {:?}",
+                    y + 1,
+                    x + 1,
+                    c
+                )
+            })?;
+            let coord = Coord::new(x as i32, y as i32);
+            if terrain_tile == TerrainTile::Player {
+                if let Some(Coord {
+                    x: first_x,
+                    y: first_y,
+                }) = player_coord
+                {
+                    return Err(format!(
+                        "line {}, column {}: a second player start, after the one at line {}, column {}",
+                        y + 1,
+                        x + 1,
+                        first_y + 1,
+                        first_x + 1
+                    ));
+                }
+                player_coord = Some(coord);
+            }
+            *grid.get_checked_mut(coord) = terrain_tile;
+        }
+    }
+    if player_coord.is_none() {
+        return Err("there is no player start (@)".to_string());
+    }
+    Ok(Dungeon {
+        grid,
+        room_count: 0,
+        level_stats: LevelStats::default(),
+    })
+}
+
+pub fn load_level(path: &str) -> Result<Dungeon, String> {
+    let text = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+    parse_level(&text)
+}
+
+// An open floor walled around the edge, with the player in the middle and
+// nothing else. Everything is in view from everywhere and every path is
+// short, making it the worst case for visibility and for NPCs, which
+// benchmarks measure without depending on how a generated level turned
+// out.
+pub fn arena(size: Size) -> Dungeon {
+    let mut grid = Grid::new_copy(size, TerrainTile::Floor);
+    for (coord, terrain_tile) in grid.enumerate_mut() {
+        if coord.x == 0
+            || coord.y == 0
+            || coord.x == size.width() as i32 - 1
+            || coord.y == size.height() as i32 - 1
+        {
+            *terrain_tile = TerrainTile::Wall;
+        }
+    }
+    *grid.get_checked_mut(Coord::new(
+        size.width() as i32 / 2,
+        size.height() as i32 / 2,
+    )) = TerrainTile::Player;
+    Dungeon {
+        grid,
+        room_count: 1,
+        level_stats: LevelStats::default(),
+    }
+}
+
+// The NPCs and items which can appear on each level, read from a
+// data file so they can be tweaked without recompiling
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SpawnTables {
+    npcs_per_room: Vec<usize>,
+    items_per_room: Vec<usize>,
+    levels: Vec<LevelSpawnTable>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct LevelSpawnTable {
+    min_level: u32,
+    #[serde(default)]
+    max_level: Option<u32>,
+    npcs: Vec<NpcWeight>,
+    items: Vec<ItemWeight>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct NpcWeight {
+    npc: NpcType,
+    weight: u32,
+    #[serde(default)]
+    per_level: u32,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct ItemWeight {
+    item: ItemType,
+    weight: u32,
+}
+
+impl SpawnTables {
+    pub fn built_in() -> Self {
+        Self::parse(include_str!("data/spawn_tables.toml"))
+            .expect("built-in spawn tables are invalid")
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+        Self::parse(&text)
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let spawn_tables: Self = toml::from_str(text).map_err(|error| error.to_string())?;
+        spawn_tables.validate()?;
+        Ok(spawn_tables)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.npcs_per_room.is_empty() {
+            return Err("npcs_per_room is empty".to_string());
+        }
+        if self.items_per_room.is_empty() {
+            return Err("items_per_room is empty".to_string());
+        }
+        if self.levels.is_empty() {
+            return Err("levels is empty".to_string());
+        }
+        let mut expected_min_level = 1;
+        for (i, table) in self.levels.iter().enumerate() {
+            if table.min_level != expected_min_level {
+                return Err(format!(
+                    "levels[{}] starts at level {} but should start at level {}",
+                    i, table.min_level, expected_min_level
+                ));
+            }
+            match table.max_level {
+                Some(max_level) if max_level < table.min_level => {
+                    return Err(format!(
+                        "levels[{}] has max_level {} less than min_level {}",
+                        i, max_level, table.min_level
+                    ));
+                }
+                Some(max_level) => expected_min_level = max_level + 1,
+                None if i + 1 < self.levels.len() => {
+                    return Err(format!(
+                        "levels[{}] has no max_level but is not the last band",
+                        i
+                    ));
+                }
+                None => (),
+            }
+            if table
+                .npcs
+                .iter()
+                .all(|npc| npc.weight + npc.per_level * table.min_level == 0)
+            {
+                return Err(format!(
+                    "levels[{}].npcs has no NPC with a non-zero weight",
+                    i
+                ));
+            }
+            if table.items.iter().all(|item| item.weight == 0) {
+                return Err(format!(
+                    "levels[{}].items has no item with a non-zero weight",
+                    i
+                ));
+            }
+        }
+        if let Some(max_level) = self.levels.last().unwrap().max_level {
+            return Err(format!(
+                "levels[{}] has max_level {} but the last band must not have a max_level",
+                self.levels.len() - 1,
+                max_level
+            ));
+        }
+        Ok(())
+    }
+
+    // The tables with the number of NPCs in each room and how quickly
+    // tougher NPCs become more common scaled by the profile
+    pub fn with_difficulty(&self, profile: &DifficultyProfile) -> Self {
+        let mut spawn_tables = self.clone();
+        for num_npcs in spawn_tables.npcs_per_room.iter_mut() {
+            *num_npcs = difficulty::scale(*num_npcs as u32, profile.npcs_per_room_percent) as usize;
+        }
+        // the base weights are raised instead of the increases being
+        // lowered, which would round away the small ones
+        for table in spawn_tables.levels.iter_mut() {
+            for npc in table.npcs.iter_mut() {
+                npc.weight *= 100;
+                npc.per_level *= profile.npc_per_level_percent;
+            }
+        }
+        spawn_tables
+    }
+
+    fn level_table(&self, level: u32) -> &LevelSpawnTable {
+        self.levels
+            .iter()
+            .find(|table| {
+                table.min_level <= level && !matches!(table.max_level, Some(max) if level > max)
+            })
+            .expect("spawn tables cover every level")
+    }
+
+    fn npc_probability_distribution(&self, level: u32) -> Vec<(NpcType, u32)> {
+        self.level_table(level)
+            .npcs
+            .iter()
+            .map(|npc| (npc.npc, npc.weight + npc.per_level * level))
+            .collect()
+    }
+
+    fn item_probability_distribution(&self, level: u32) -> Vec<(ItemType, u32)> {
+        self.level_table(level)
+            .items
+            .iter()
+            .map(|item| (item.item, item.weight))
+            .collect()
+    }
+}
+
+// Requirements a generated level must meet. Levels which fail them
+// are thrown away and generated again.
+#[derive(Clone, Copy, Debug)]
+pub struct GenerationConstraints {
+    pub min_rooms: usize,
+    // the number of steps it takes to walk from the player's arrival
+    // point to the stairs down
+    pub min_stairs_distance: u32,
+    pub max_attempts: u64,
+}
+
+impl Default for GenerationConstraints {
+    fn default() -> Self {
+        Self {
+            min_rooms: 4,
+            min_stairs_distance: 20,
+            max_attempts: 100,
+        }
+    }
+}
+
+// Each attempt uses its own rng seeded from a single seed taken from
+// `rng` and the attempt number, so the number of failed attempts
+// doesn't affect how much randomness is consumed from `rng`.
+pub fn generate_dungeon<R: Rng>(
+    size: Size,
+    level: u32,
+    constraints: &GenerationConstraints,
+    spawn_tables: &SpawnTables,
+    rng: &mut R,
+) -> Dungeon {
+    if level == FINAL_DUNGEON_LEVEL {
+        return generate_final_level(size, level, spawn_tables, rng);
+    }
+    let seed = rng.gen::<u64>();
+    for attempt in 0..constraints.max_attempts {
+        let attempt_seed = seed ^ attempt.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        let mut attempt_rng = Isaac64Rng::seed_from_u64(attempt_seed);
+        if let Some(dungeon) =
+            try_generate_dungeon(size, level, constraints, spawn_tables, &mut attempt_rng)
+        {
+            return dungeon;
+        }
+    }
+    // Small maps may have no room for enough rooms far enough apart, so
+    // rather than give up, settle for any level with stairs to take
+    let relaxed_constraints = GenerationConstraints {
+        min_rooms: 2,
+        min_stairs_distance: 0,
+        ..*constraints
+    };
+    log_info!(
+        "Relaxing the constraints on level {} of size {}x{}, which couldn't meet {:?}",
+        level,
+        size.width(),
+        size.height(),
+        constraints
+    );
+    for attempt in 0..constraints.max_attempts {
+        let attempt_seed = seed ^ attempt.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        let mut attempt_rng = Isaac64Rng::seed_from_u64(attempt_seed);
+        if let Some(dungeon) = try_generate_dungeon(
+            size,
+            level,
+            &relaxed_constraints,
+            spawn_tables,
+            &mut attempt_rng,
+        ) {
+            return dungeon;
+        }
+    }
+    panic!(
+        "failed to generate level {} of size {:?} meeting {:?} after {} attempts",
+        level, size, relaxed_constraints, constraints.max_attempts
+    );
+}
+
+pub const FINAL_DUNGEON_LEVEL: u32 = 12;
+pub const NUM_RUNES: u32 = 4;
+
+// The last level is a large arena with a sealed portal at its centre,
+// surrounded by four small chambers which each hold one of the runes
+// needed to open the portal.
+fn generate_final_level<R: Rng>(
+    size: Size,
+    level: u32,
+    spawn_tables: &SpawnTables,
+    rng: &mut R,
+) -> Dungeon {
+    const MAX_CHAMBER_SIZE: u32 = 5;
+    const NPCS_IN_ARENA: usize = 6;
+    const ITEMS_IN_ARENA: usize = 3;
     let mut grid = Grid::new_copy(size, None);
-    let mut room_centers = Vec::new();
+    let arena_size = Size::new(size.width() / 2, size.height() / 2);
+    let arena = Room {
+        top_left: (size.to_coord().unwrap() - arena_size.to_coord().unwrap()) / 2,
+        size: arena_size,
+    };
+    arena.carve_out(&mut grid);
 
-    const NPCS_PER_ROOM_DISTRIBUTION: &[usize] =
-        &[0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 3, 3, 4];
+    // chambers shrink to fit between the arena and the edge of small maps
+    let chamber_size = MAX_CHAMBER_SIZE
+        .min((arena.top_left.x as u32).saturating_sub(1))
+        .min((arena.top_left.y as u32).saturating_sub(1));
+    assert!(
+        chamber_size >= Room::MIN_SIZE,
+        "level of size {:?} is too small for the final level layout",
+        size
+    );
+    let chamber_size = Size::new(chamber_size, chamber_size);
+    let far_edge = size.to_coord().unwrap() - chamber_size.to_coord().unwrap() - Coord::new(1, 1);
+    let middle = (size.to_coord().unwrap() - chamber_size.to_coord().unwrap()) / 2;
+    let chambers = [
+        Coord::new(middle.x, 1),
+        Coord::new(middle.x, far_edge.y),
+        Coord::new(1, middle.y),
+        Coord::new(far_edge.x, middle.y),
+    ]
+    .iter()
+    .map(|&top_left| Room {
+        top_left,
+        size: chamber_size,
+    })
+    .collect::<Vec<_>>();
 
-    const ITEMS_PER_ROOM_DISTRIBUTION: &[usize] =
-        &[0, 0, 1, 1, 1, 1, 1, 2, 2];
+    let mut npc_probability_distribution = spawn_tables.npc_probability_distribution(level);
+    apply_npc_theme_weights(
+        LevelTheme::from_dungeon_level(level),
+        &mut npc_probability_distribution,
+    );
+    let item_probability_distribution = spawn_tables.item_probability_distribution(level);
 
-    let npc_probability_distribution = make_npc_probability_distribution(level);
-    let item_probability_distribution = make_item_probability_distribution(level);
+    let mut corridor_cells = Vec::new();
+    for chamber in &chambers {
+        assert!(
+            chamber.only_intersects_empty(&grid),
+            "level of size {:?} is too small for the final level layout",
+            size
+        );
+        chamber.carve_out(&mut grid);
+        *grid.get_checked_mut(chamber.center()) = Some(TerrainTile::Rune);
+        chamber.place_npcs(1, &npc_probability_distribution, &mut grid, rng);
+        corridor_cells.extend(carve_corridor(chamber.center(), arena.center(), &mut grid));
+    }
+    place_doors(&corridor_cells, &mut grid);
 
-    // attempt to add a room a constant number of times
-    const NUM_ATTEMPTS: usize = 100;
-    for _ in 0..NUM_ATTEMPTS {
-        let room = Room::choose(size, rng);
+    let arena_center = arena.center();
+    *grid.get_checked_mut(arena_center) = Some(TerrainTile::Portal);
+    let arrival = Coord::new(arena.top_left.x + 2, arena.top_left.y + 2);
+    *grid.get_checked_mut(arrival) = Some(TerrainTile::StairsUp);
+    arena.place_npcs(NPCS_IN_ARENA, &npc_probability_distribution, &mut grid, rng);
+    arena.place_items(
+        ITEMS_IN_ARENA,
+        &item_probability_distribution,
+        &mut grid,
+        rng,
+    );
+
+    let grid = grid.map(|t| t.unwrap_or(TerrainTile::Wall));
+    debug_assert!(
+        unreachable_cells(&grid).is_empty(),
+        "final level is not connected"
+    );
+    // the same number of everything every time
+    Dungeon {
+        grid,
+        room_count: chambers.len() + 1,
+        level_stats: LevelStats::default(),
+    }
+}
+
+fn try_generate_dungeon<R: Rng>(
+    size: Size,
+    level: u32,
+    constraints: &GenerationConstraints,
+    spawn_tables: &SpawnTables,
+    rng: &mut R,
+) -> Option<Dungeon> {
+    let theme = LevelTheme::from_dungeon_level(level);
+    let mut grid = Grid::new_copy(size, None);
+    let mut room_centers = Vec::new();
+    let mut rooms = Vec::new();
+
+    let mut npc_probability_distribution = spawn_tables.npc_probability_distribution(level);
+    apply_npc_theme_weights(theme, &mut npc_probability_distribution);
+    let mut item_probability_distribution = spawn_tables.item_probability_distribution(level);
+    apply_item_theme_weights(theme, &mut item_probability_distribution);
 
-        if room.only_intersects_empty(&grid) {
+    // Occasionally one room is given over to a single monster from
+    // deeper in the dungeon guarding some good loot
+    const DANGER_ROOM_CHANCE: f64 = 0.15;
+    const DANGER_ROOM_DEPTH_OFFSET: u32 = 2;
+    const DANGER_ROOM_INDEX: usize = 2;
+    let wants_danger_room = rng.gen_bool(DANGER_ROOM_CHANCE);
+    let mut level_stats = LevelStats::default();
+    let mut danger_npc_probability_distribution =
+        spawn_tables.npc_probability_distribution(level + DANGER_ROOM_DEPTH_OFFSET);
+    apply_npc_theme_weights(theme, &mut danger_npc_probability_distribution);
+
+    // attempt to add a room a number of times depending on the map's
+    // size. Nothing but rooms has been carved yet, so a room only needs
+    // checking against the others.
+    let (room_width_range, room_height_range) = theme.room_size_ranges();
+    for _ in 0..theme.num_room_attempts(size) {
+        let room = match Room::choose_in_size_range(
+            size,
+            room_width_range.clone(),
+            room_height_range.clone(),
+            rng,
+        ) {
+            Some(room) => room,
+            None => break,
+        };
+
+        if !rooms.iter().any(|other| room.intersects(other)) {
             room.carve_out(&mut grid);
+            room.maybe_add_interior_structure(&mut grid, rng);
+            if theme.has_rough_rooms() {
+                room.roughen(&mut grid, rng);
+            }
 
             let room_center = room.center();
 
+            // The player arrives in the first room. Below the first
+            // level they arrive on a staircase leading back up.
             if room_centers.is_empty() {
-                *grid.get_checked_mut(room_center) = Some(TerrainTile::Player);
+                let arrival = if level == 1 {
+                    TerrainTile::Player
+                } else {
+                    TerrainTile::StairsUp
+                };
+                *grid.get_checked_mut(room_center) = Some(arrival);
+            }
+
+            // The danger room is never the arrival room. It gets no
+            // liquids so its guardian can always reach the player.
+            if wants_danger_room && room_centers.len() == DANGER_ROOM_INDEX {
+                room_centers.push(room_center);
+                room.place_npcs(1, &danger_npc_probability_distribution, &mut grid, rng);
+                room.place_danger_room_loot(&mut grid, rng);
+                level_stats.out_of_depth_npcs += 1;
+                level_stats.has_danger_room = true;
+                rooms.push(room);
+                continue;
             }
 
             room_centers.push(room_center);
 
+            // Liquids are placed before anything else so nothing is
+            // generated standing in them
+            const WATER_PER_ROOM_CHANCE: f64 = 0.15;
+            if rng.gen_bool(WATER_PER_ROOM_CHANCE) {
+                room.place_water_pool(&mut grid, rng);
+            }
+            const LAVA_MIN_LEVEL: u32 = 3;
+            const LAVA_PER_ROOM_CHANCE: f64 = 0.1;
+            if level >= LAVA_MIN_LEVEL && rng.gen_bool(LAVA_PER_ROOM_CHANCE) {
+                room.place_lava_streak(&mut grid, rng);
+            }
+            if rng.gen_bool(theme.foliage_per_room_chance()) {
+                room.place_foliage_patch(&mut grid, rng);
+            }
+
             // add NPCs to the room
-            let &num_npcs = NPCS_PER_ROOM_DISTRIBUTION.choose(rng).unwrap();
+            let &num_npcs = spawn_tables.npcs_per_room.choose(rng).unwrap();
             room.place_npcs(num_npcs, &npc_probability_distribution, &mut grid, rng);
+            level_stats.npcs += num_npcs;
+            level_stats.expected_npcs += mean(&spawn_tables.npcs_per_room);
 
             // Add items to the room
-            let &num_items = ITEMS_PER_ROOM_DISTRIBUTION.choose(rng).unwrap();
+            let &num_items = spawn_tables.items_per_room.choose(rng).unwrap();
             room.place_items(num_items, &item_probability_distribution, &mut grid, rng);
+            level_stats.items += num_items;
+            level_stats.expected_items += mean(&spawn_tables.items_per_room);
+
+            // Occasionally leave some gold lying around
+            const GOLD_PER_ROOM_CHANCE: f64 = 0.3;
+            if rng.gen_bool(GOLD_PER_ROOM_CHANCE) {
+                room.place_tile(TerrainTile::Gold, &mut grid, rng);
+            }
+
+            // Altars are rare
+            const ALTAR_PER_ROOM_CHANCE: f64 = 0.05;
+            if rng.gen_bool(ALTAR_PER_ROOM_CHANCE) {
+                room.place_tile(TerrainTile::Altar, &mut grid, rng);
+            }
+
+            rooms.push(room);
         }
     }
 
-    for window in room_centers.windows(2) {
-        carve_corridor(window[0], window[1], &mut grid);
+    if room_centers.len() < constraints.min_rooms.max(1) {
+        return None;
+    }
+
+    // Each room is joined to the nearest of the rooms placed before it
+    let mut corridor_cells = Vec::new();
+    for (i, &room_center) in room_centers.iter().enumerate().skip(1) {
+        let &nearest = room_centers[..i]
+            .iter()
+            .min_by_key(|&&other| (other - room_center).magnitude2())
+            .unwrap();
+        let style = CorridorStyle::choose(rng);
+        corridor_cells.extend(carve_corridor_in_style(
+            nearest,
+            room_center,
+            style,
+            &mut grid,
+        ));
     }
 
-    *grid.get_checked_mut(*room_centers.last().unwrap()) = Some(TerrainTile::Stairs);
-    
-    grid.map(|t| t.unwrap_or(TerrainTile::Wall))
+    place_doors(&corridor_cells, &mut grid);
+    place_traps(level, &corridor_cells, &mut grid, rng);
+
+    const CLOSET_PER_ROOM_CHANCE: f64 = 0.1;
+    for room in &rooms {
+        if rng.gen_bool(CLOSET_PER_ROOM_CHANCE) {
+            room.try_add_closet(&item_probability_distribution, &mut grid, rng);
+        }
+    }
+
+    // The shop is added after the corridors so that no corridor can
+    // cut through its walls, which would give it a second entrance.
+    const SHOP_CHANCE: f64 = 0.25;
+    if rng.gen_bool(SHOP_CHANCE) {
+        try_add_shop(
+            &room_centers,
+            &item_probability_distribution,
+            &mut grid,
+            rng,
+        );
+    }
+
+    // The stairs go in the middle of the last room unless something is
+    // already there, such as an NPC, or the player on a level with only
+    // one room. A room packed full gives up the spawn nearest its middle,
+    // which is still counted in the level's stats.
+    let last_room = rooms.last().unwrap();
+    let stairs = last_room
+        .free_cell_nearest_center(&grid)
+        .or_else(|| last_room.spawn_nearest_center(&grid))?;
+    *grid.get_checked_mut(stairs) = Some(TerrainTile::Stairs);
+
+    connect_orphaned_cells(room_centers[0], &mut grid);
+
+    // Torches go in last so no corridor is carved through them
+    for room in &rooms {
+        if rng.gen_bool(theme.torch_per_room_chance()) {
+            room.try_add_torch(&mut grid, rng);
+        }
+    }
+
+    const CHASM_MIN_LEVEL: u32 = 2;
+    const CHASM_CHANCE: f64 = 0.2;
+    if level >= CHASM_MIN_LEVEL && rng.gen_bool(CHASM_CHANCE) {
+        try_add_chasm(room_centers[0], &mut grid, rng);
+    }
+
+    let grid = grid.map(|t| t.unwrap_or(TerrainTile::Wall));
+    debug_assert!(
+        unreachable_cells(&grid).is_empty(),
+        "generated level is not connected"
+    );
+    let stairs_distance = walking_distance(&grid, room_centers[0], stairs)?;
+    if stairs_distance < constraints.min_stairs_distance {
+        return None;
+    }
+    Some(Dungeon {
+        grid,
+        room_count: rooms.len(),
+        level_stats,
+    })
+}
+
+// The number chosen from the list on average, as each is equally likely
+fn mean(counts: &[usize]) -> f64 {
+    counts.iter().sum::<usize>() as f64 / counts.len() as f64
 }
 
-fn carve_corridor(start: Coord, end: Coord, grid: &mut Grid<Option<TerrainTile>>) {
+impl TerrainTile {
+    pub fn is_passable(self) -> bool {
+        !matches!(
+            self,
+            TerrainTile::Wall | TerrainTile::Chasm | TerrainTile::Torch
+        )
+    }
+
+    // Cells which are pointless or harmful if the player can't get to them
+    fn must_be_reachable(self) -> bool {
+        match self {
+            TerrainTile::Stairs
+            | TerrainTile::StairsUp
+            | TerrainTile::Npc(_)
+            | TerrainTile::Item(_)
+            | TerrainTile::ShopItem(_)
+            | TerrainTile::Gold
+            | TerrainTile::Altar
+            | TerrainTile::Rune
+            | TerrainTile::Portal => true,
+            _ => false,
+        }
+    }
+}
+
+// Returns a grid where each cell is `true` if it can be reached by
+// walking from `start` in cardinal directions.
+fn reachable_cells<F: Fn(Coord) -> bool>(size: Size, start: Coord, is_passable: F) -> Grid<bool> {
+    let mut reachable = Grid::new_copy(size, false);
+    let mut queue = VecDeque::new();
+    *reachable.get_checked_mut(start) = true;
+    queue.push_back(start);
+    while let Some(coord) = queue.pop_front() {
+        for &delta in &[
+            Coord::new(1, 0),
+            Coord::new(-1, 0),
+            Coord::new(0, 1),
+            Coord::new(0, -1),
+        ] {
+            let neighbour = coord + delta;
+            if let Some(cell) = reachable.get_mut(neighbour) {
+                if !*cell && is_passable(neighbour) {
+                    *cell = true;
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+    reachable
+}
+
+// Returns the number of cardinal steps it takes to walk from `start`
+// to `end`, or `None` if `end` can't be reached.
+fn walking_distance(grid: &Grid<TerrainTile>, start: Coord, end: Coord) -> Option<u32> {
+    let mut distances = Grid::new_copy(grid.size(), None);
+    let mut queue = VecDeque::new();
+    *distances.get_checked_mut(start) = Some(0);
+    queue.push_back(start);
+    while let Some(coord) = queue.pop_front() {
+        let distance = distances.get_checked(coord).unwrap();
+        if coord == end {
+            return Some(distance);
+        }
+        for &delta in &[
+            Coord::new(1, 0),
+            Coord::new(-1, 0),
+            Coord::new(0, 1),
+            Coord::new(0, -1),
+        ] {
+            let neighbour = coord + delta;
+            if let Some(cell) = distances.get_mut(neighbour) {
+                if cell.is_none() && grid.get_checked(neighbour).is_passable() {
+                    *cell = Some(distance + 1);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+    None
+}
+
+// Returns the coordinates of all the stairs, NPCs and items in a
+// generated level which can't be reached from the player's arrival
+// point.
+pub(crate) fn unreachable_cells(grid: &Grid<TerrainTile>) -> Vec<Coord> {
+    let start = match grid
+        .enumerate()
+        .find(|&(_, &tile)| tile == TerrainTile::Player || tile == TerrainTile::StairsUp)
+    {
+        Some((coord, _)) => coord,
+        None => return Vec::new(),
+    };
+    let reachable = reachable_cells(grid.size(), start, |coord| {
+        grid.get_checked(coord).is_passable()
+    });
+    grid.enumerate()
+        .filter(|(coord, tile)| tile.must_be_reachable() && !*reachable.get_checked(*coord))
+        .map(|(coord, _)| coord)
+        .collect()
+}
+
+// Carves corridors from cells which must be reachable, but aren't, to
+// the nearest reachable cell. The number of corridors added is bounded
+// in case something prevents a corridor from joining the two regions.
+fn connect_orphaned_cells(start: Coord, grid: &mut Grid<Option<TerrainTile>>) {
+    const MAX_EXTRA_CORRIDORS: usize = 20;
+    for _ in 0..MAX_EXTRA_CORRIDORS {
+        let reachable = reachable_cells(grid.size(), start, |coord| {
+            grid.get_checked(coord)
+                .map(|tile| tile.is_passable())
+                .unwrap_or(false)
+        });
+        let orphan = grid.enumerate().find_map(|(coord, cell)| match cell {
+            Some(tile) if tile.must_be_reachable() && !*reachable.get_checked(coord) => Some(coord),
+            _ => None,
+        });
+        let orphan = match orphan {
+            Some(orphan) => orphan,
+            None => return,
+        };
+        let nearest_reachable = reachable
+            .enumerate()
+            .filter(|&(_, &is_reachable)| is_reachable)
+            .map(|(coord, _)| coord)
+            .min_by_key(|&coord| (coord - orphan).magnitude2())
+            .expect("start cell is always reachable");
+        carve_corridor(orphan, nearest_reachable, grid);
+    }
+}
+
+// Carves along the row of `start` to the column of `end`, then along
+// that column to `end`. Both legs include their ends, so they share the
+// corner cell and the corridor has no gaps whichever way round the two
+// points are. Returns the coordinates of the cells which were carved out
+// by the corridor, excluding those that were already part of a room.
+fn carve_corridor(start: Coord, end: Coord, grid: &mut Grid<Option<TerrainTile>>) -> Vec<Coord> {
+    let mut carved = Vec::new();
     for i in start.x.min(end.x)..=start.x.max(end.x) {
-        let cell = grid.get_checked_mut(Coord { x:i, ..start });
+        let coord = Coord { x: i, ..start };
+        let cell = grid.get_checked_mut(coord);
         if *cell == None || *cell == Some(TerrainTile::Wall) {
             *cell = Some(TerrainTile::Floor);
+            carved.push(coord);
         }
     }
 
-    for i in start.y.min(end.y)..start.y.max(end.y) {
-        let cell = grid.get_checked_mut(Coord { y:i, ..end });
+    for i in start.y.min(end.y)..=start.y.max(end.y) {
+        let coord = Coord { y: i, ..end };
+        let cell = grid.get_checked_mut(coord);
         if *cell == None || *cell == Some(TerrainTile::Wall) {
             *cell = Some(TerrainTile::Floor);
+            carved.push(coord);
+        }
+    }
+    carved
+}
+
+// Cuts a chasm across the level along a single row or column, then
+// adds bridges wherever they're needed to keep everything which must
+// be reachable connected to `start`. The chasm is filled back in if it
+// would need more than MAX_BRIDGES bridges.
+fn try_add_chasm<R: Rng>(start: Coord, grid: &mut Grid<Option<TerrainTile>>, rng: &mut R) {
+    const MAX_BRIDGES: usize = 2;
+    const MIN_CHASM_LENGTH: usize = 4;
+    let size = grid.size();
+    let (line, across) = if rng.gen() {
+        let x = rng.gen_range((size.width() / 4)..(size.width() * 3 / 4)) as i32;
+        let line = (0..size.height() as i32)
+            .map(|y| Coord::new(x, y))
+            .collect::<Vec<_>>();
+        (line, Coord::new(1, 0))
+    } else {
+        let y = rng.gen_range((size.height() / 4)..(size.height() * 3 / 4)) as i32;
+        let line = (0..size.width() as i32)
+            .map(|x| Coord::new(x, y))
+            .collect::<Vec<_>>();
+        (line, Coord::new(0, 1))
+    };
+    let chasm = line
+        .into_iter()
+        .filter(|&coord| coord != start && *grid.get_checked(coord) == Some(TerrainTile::Floor))
+        .collect::<Vec<_>>();
+    if chasm.len() < MIN_CHASM_LENGTH {
+        return;
+    }
+    for &coord in &chasm {
+        *grid.get_checked_mut(coord) = Some(TerrainTile::Chasm);
+    }
+    let is_open = |grid: &Grid<Option<TerrainTile>>, coord: Coord| {
+        grid.get(coord)
+            .and_then(|&cell| cell)
+            .map(TerrainTile::is_passable)
+            .unwrap_or(false)
+    };
+    let mut num_bridges = 0;
+    loop {
+        let reachable = reachable_cells(size, start, |coord| is_open(grid, coord));
+        let disconnected = grid.enumerate().any(|(coord, cell)| {
+            cell.map(TerrainTile::must_be_reachable).unwrap_or(false)
+                && !*reachable.get_checked(coord)
+        });
+        if !disconnected {
+            break;
+        }
+        // a bridge is needed from the reachable side of the chasm to
+        // some part of the level beyond it
+        let bridge = chasm
+            .iter()
+            .cloned()
+            .filter(|&coord| {
+                let (before, after) = (coord - across, coord + across);
+                *grid.get_checked(coord) == Some(TerrainTile::Chasm)
+                    && is_open(grid, before)
+                    && is_open(grid, after)
+                    && reachable.get(before) != reachable.get(after)
+            })
+            .choose(rng);
+        match bridge {
+            Some(bridge) if num_bridges < MAX_BRIDGES => {
+                *grid.get_checked_mut(bridge) = Some(TerrainTile::Floor);
+                num_bridges += 1;
+            }
+            _ => {
+                for &coord in &chasm {
+                    *grid.get_checked_mut(coord) = Some(TerrainTile::Floor);
+                }
+                return;
+            }
+        }
+    }
+    // there's always at least one way across
+    if num_bridges == 0 {
+        if let Some(bridge) = chasm
+            .iter()
+            .cloned()
+            .filter(|&coord| is_open(grid, coord - across) && is_open(grid, coord + across))
+            .choose(rng)
+        {
+            *grid.get_checked_mut(bridge) = Some(TerrainTile::Floor);
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum CorridorStyle {
+    HorizontalFirst,
+    VerticalFirst,
+    Staircase,
+}
+
+impl CorridorStyle {
+    fn choose<R: Rng>(rng: &mut R) -> Self {
+        match rng.gen_range(0..3) {
+            0 => Self::HorizontalFirst,
+            1 => Self::VerticalFirst,
+            _ => Self::Staircase,
+        }
+    }
+}
+
+fn carve_corridor_in_style(
+    start: Coord,
+    end: Coord,
+    style: CorridorStyle,
+    grid: &mut Grid<Option<TerrainTile>>,
+) -> Vec<Coord> {
+    match style {
+        CorridorStyle::HorizontalFirst => carve_corridor(start, end, grid),
+        CorridorStyle::VerticalFirst => carve_corridor(end, start, grid),
+        CorridorStyle::Staircase => carve_staircase_corridor(start, end, grid),
+    }
+}
+
+// Carves a corridor which alternates between horizontal and vertical
+// steps, giving a rough diagonal, until it lines up with `end` and
+// finishes in a straight line.
+fn carve_staircase_corridor(
+    start: Coord,
+    end: Coord,
+    grid: &mut Grid<Option<TerrainTile>>,
+) -> Vec<Coord> {
+    let mut carved = Vec::new();
+    let mut coord = start;
+    let mut step_horizontally = true;
+    loop {
+        let cell = grid.get_checked_mut(coord);
+        if cell.is_none() || *cell == Some(TerrainTile::Wall) {
+            *cell = Some(TerrainTile::Floor);
+            carved.push(coord);
+        }
+        if coord == end {
+            break;
+        }
+        let delta = end - coord;
+        if delta.y == 0 || (step_horizontally && delta.x != 0) {
+            coord.x += delta.x.signum();
+        } else {
+            coord.y += delta.y.signum();
         }
+        step_horizontally = !step_horizontally;
     }
+    carved
 }
 
+// Places a closed door at each point where a corridor enters a
+// room. This is a corridor cell with walls on two opposite sides and
+// open cells on the other two sides, at least one of which belongs to
+// a room rather than a corridor. Doors are never placed next to one
+// another.
+fn place_doors(corridor_cells: &[Coord], grid: &mut Grid<Option<TerrainTile>>) {
+    let corridor_set = corridor_cells.iter().cloned().collect::<HashSet<_>>();
+    let is_wall = |grid: &Grid<Option<TerrainTile>>, coord: Coord| {
+        matches!(
+            grid.get(coord),
+            None | Some(None) | Some(Some(TerrainTile::Wall))
+        )
+    };
+    for &coord in corridor_cells {
+        let north = coord + Coord::new(0, -1);
+        let south = coord + Coord::new(0, 1);
+        let east = coord + Coord::new(1, 0);
+        let west = coord + Coord::new(-1, 0);
+        let open_pair = if is_wall(grid, north) && is_wall(grid, south) {
+            [east, west]
+        } else if is_wall(grid, east) && is_wall(grid, west) {
+            [north, south]
+        } else {
+            continue;
+        };
+        if open_pair.iter().any(|&c| is_wall(grid, c)) {
+            continue;
+        }
+        if !open_pair.iter().any(|c| !corridor_set.contains(c)) {
+            continue;
+        }
+        if [north, south, east, west]
+            .iter()
+            .any(|&c| grid.get(c) == Some(&Some(TerrainTile::DoorClosed)))
+        {
+            continue;
+        }
+        *grid.get_checked_mut(coord) = Some(TerrainTile::DoorClosed);
+    }
+}
+
+// Hides traps on floor cells. Traps are more likely to be placed in
+// corridors than in rooms.
+fn place_traps<R: Rng>(
+    level: u32,
+    corridor_cells: &[Coord],
+    grid: &mut Grid<Option<TerrainTile>>,
+    rng: &mut R,
+) {
+    const BASE_NUM_TRAPS: usize = 2;
+    const CORRIDOR_TRAP_CHANCE: f64 = 0.75;
+    let num_traps = BASE_NUM_TRAPS + level as usize / 2;
+    let trap_probability_distribution = make_trap_probability_distribution(level);
+    let corridor_set = corridor_cells.iter().cloned().collect::<HashSet<_>>();
+    for _ in 0..num_traps {
+        let is_free_floor = |coord: &Coord| grid.get_checked(*coord).unwrap() == TerrainTile::Floor;
+        let coord = if rng.gen_bool(CORRIDOR_TRAP_CHANCE) {
+            corridor_cells
+                .iter()
+                .cloned()
+                .filter(is_free_floor)
+                .choose(rng)
+        } else {
+            grid.coord_iter()
+                .filter(|coord| !corridor_set.contains(coord))
+                .filter(is_free_floor)
+                .choose(rng)
+        };
+        if let Some(coord) = coord {
+            let &trap_type =
+                choose_from_probability_distribution(&trap_probability_distribution, rng);
+            *grid.get_checked_mut(coord) = Some(TerrainTile::Trap(trap_type));
+        }
+    }
+}
+
+// Attempts to place a small room, completely surrounded by walls, in
+// an unused part of the grid. The room has a single entrance, on the
+// side facing the nearest existing room, which is connected to that
+// room by a corridor leading away from the shop. A shopkeeper stands
+// just inside the entrance and the rest of the floor is stocked with
+// items for sale.
+fn try_add_shop<R: Rng>(
+    room_centers: &[Coord],
+    item_probability_distribution: &[(ItemType, u32)],
+    grid: &mut Grid<Option<TerrainTile>>,
+    rng: &mut R,
+) {
+    const NUM_ATTEMPTS: usize = 50;
+    const MIN_SIZE: u32 = 5;
+    for _ in 0..NUM_ATTEMPTS {
+        // a shop shrunk to fit a small map has no room for its keeper
+        let room = match Room::choose_in_size_range(grid.size(), MIN_SIZE..7, MIN_SIZE..7, rng) {
+            Some(room) if room.size.width() >= MIN_SIZE && room.size.height() >= MIN_SIZE => room,
+            _ => return,
+        };
+        if !room.only_intersects_empty(grid) {
+            continue;
+        }
+        let center = room.center();
+        let target = match room_centers
+            .iter()
+            .min_by_key(|&&c| (c - center).magnitude2())
+        {
+            Some(&target) => target,
+            None => return,
+        };
+        let left = room.top_left.x;
+        let top = room.top_left.y;
+        let right = left + room.size.width() as i32 - 1;
+        let bottom = top + room.size.height() as i32 - 1;
+        let clamp_x = |x: i32| x.max(left + 1).min(right - 1);
+        let clamp_y = |y: i32| y.max(top + 1).min(bottom - 1);
+
+        // (entrance, cell outside the entrance, step into the shop)
+        let (entrance, outside, inward) = if target.x < left {
+            let y = clamp_y(target.y);
+            (
+                Coord::new(left, y),
+                Coord::new(left - 1, y),
+                Coord::new(1, 0),
+            )
+        } else if target.x > right {
+            let y = clamp_y(target.y);
+            (
+                Coord::new(right, y),
+                Coord::new(right + 1, y),
+                Coord::new(-1, 0),
+            )
+        } else if target.y < top {
+            let x = clamp_x(target.x);
+            (Coord::new(x, top), Coord::new(x, top - 1), Coord::new(0, 1))
+        } else {
+            let x = clamp_x(target.x);
+            (
+                Coord::new(x, bottom),
+                Coord::new(x, bottom + 1),
+                Coord::new(0, -1),
+            )
+        };
+
+        for coord in room.coords() {
+            let on_edge =
+                coord.x == left || coord.x == right || coord.y == top || coord.y == bottom;
+            *grid.get_checked_mut(coord) = Some(if on_edge {
+                TerrainTile::Wall
+            } else {
+                TerrainTile::Floor
+            });
+        }
+        *grid.get_checked_mut(entrance) = Some(TerrainTile::ShopExit);
+
+        // The corridor starts outside the entrance and heads away from
+        // the shop, so it never crosses the shop's walls.
+        carve_corridor(outside, target, grid);
+
+        // The shopkeeper stands beside the cell just inside the entrance,
+        // leaving the way in clear.
+        let inside = entrance + inward;
+        let beside = Coord::new(inward.y, inward.x);
+        let shopkeeper_coord =
+            if matches!(grid.get(inside + beside), Some(Some(TerrainTile::Floor))) {
+                inside + beside
+            } else {
+                inside - beside
+            };
+        *grid.get_checked_mut(shopkeeper_coord) = Some(TerrainTile::Npc(NpcType::Shopkeeper));
+
+        const MIN_ITEMS: usize = 3;
+        const MAX_ITEMS: usize = 5;
+        let num_items = rng.gen_range(MIN_ITEMS..=MAX_ITEMS);
+        for coord in room
+            .coords()
+            .filter(|&coord| coord != inside)
+            .filter(|&coord| grid.get_checked(coord).unwrap() == TerrainTile::Floor)
+            .choose_multiple(rng, num_items)
+        {
+            let &item = choose_from_probability_distribution(item_probability_distribution, rng);
+            *grid.get_checked_mut(coord) = Some(TerrainTile::ShopItem(item));
+        }
+        return;
+    }
+}
 
 struct Room {
     top_left: Coord,
@@ -86,15 +1373,42 @@ struct Room {
 }
 
 impl Room {
-    fn choose<R: Rng>(bounds: Size, rng: &mut R) -> Self {
-        let width = rng.gen_range(5..11);
-        let height = rng.gen_range(5..9);
+    // The smallest room with floor to stand on apart from its walls
+    const MIN_SIZE: u32 = 3;
+
+    // Rooms are shrunk to fit maps smaller than the range, leaving the
+    // bottom row and right column clear so no room's floor touches the
+    // edge of the map. None if the map is too small for any room.
+    fn choose_in_size_range<R: Rng>(
+        bounds: Size,
+        width_range: Range<u32>,
+        height_range: Range<u32>,
+        rng: &mut R,
+    ) -> Option<Self> {
+        let width = rng
+            .gen_range(width_range)
+            .min(bounds.width().saturating_sub(1));
+        let height = rng
+            .gen_range(height_range)
+            .min(bounds.height().saturating_sub(1));
+        if width < Self::MIN_SIZE || height < Self::MIN_SIZE {
+            return None;
+        }
         let size = Size::new(width, height);
         let top_left_bounds = bounds - size;
         let left = rng.gen_range(0..top_left_bounds.width());
         let top = rng.gen_range(0..top_left_bounds.height());
         let top_left = Coord::new(left as i32, top as i32);
-        Self { top_left, size}
+        Some(Self { top_left, size })
+    }
+
+    fn intersects(&self, other: &Room) -> bool {
+        let bottom_right = self.top_left + self.size.to_coord().unwrap();
+        let other_bottom_right = other.top_left + other.size.to_coord().unwrap();
+        self.top_left.x < other_bottom_right.x
+            && other.top_left.x < bottom_right.x
+            && self.top_left.y < other_bottom_right.y
+            && other.top_left.y < bottom_right.y
     }
 
     fn center(&self) -> Coord {
@@ -131,7 +1445,7 @@ impl Room {
         n: usize,
         probability_distribution: &[(NpcType, u32)],
         grid: &mut Grid<Option<TerrainTile>>,
-        rng: &mut R
+        rng: &mut R,
     ) {
         for coord in self
             .coords()
@@ -159,7 +1473,338 @@ impl Room {
             *grid.get_checked_mut(coord) = Some(TerrainTile::Item(item));
         }
     }
-    
+
+    // Attempts to add a single-cell closet on the far side of one of
+    // the room's walls, reachable only through a secret door. Most
+    // closets contain an item.
+    // Fills in some of the floor around the edge of the room, to make
+    // it look more like a natural cave
+    fn roughen<R: Rng>(&self, grid: &mut Grid<Option<TerrainTile>>, rng: &mut R) {
+        const ROUGHEN_CHANCE: f64 = 0.3;
+        let left = self.top_left.x + 1;
+        let top = self.top_left.y + 1;
+        let right = self.top_left.x + self.size.width() as i32 - 1;
+        let bottom = self.top_left.y + self.size.height() as i32 - 1;
+        for coord in self.coords() {
+            let on_edge =
+                coord.x == left || coord.x == right || coord.y == top || coord.y == bottom;
+            let cell = grid.get_checked_mut(coord);
+            if on_edge && *cell == Some(TerrainTile::Floor) && rng.gen_bool(ROUGHEN_CHANCE) {
+                *cell = Some(TerrainTile::Wall);
+            }
+        }
+    }
+
+    // Large rooms get some interior walls to break up lines of
+    // sight. The centre row and column are left clear as that's where
+    // corridors leave the room, and the structure is discarded if it
+    // would cut off any part of the room.
+    fn maybe_add_interior_structure<R: Rng>(
+        &self,
+        grid: &mut Grid<Option<TerrainTile>>,
+        rng: &mut R,
+    ) {
+        const MIN_INTERIOR_SIZE: u32 = 7;
+        let left = self.top_left.x + 1;
+        let top = self.top_left.y + 1;
+        let right = self.top_left.x + self.size.width() as i32 - 1;
+        let bottom = self.top_left.y + self.size.height() as i32 - 1;
+        if self.size.width() - 1 < MIN_INTERIOR_SIZE || self.size.height() - 1 < MIN_INTERIOR_SIZE {
+            return;
+        }
+        let center = self.center();
+        let walls = match rng.gen_range(0..3) {
+            // A regular grid of pillars
+            0 => self
+                .coords()
+                .filter(|coord| {
+                    let offset = *coord - Coord::new(left, top);
+                    offset.x % 2 == 1 && offset.y % 2 == 1 && coord.x < right && coord.y < bottom
+                })
+                .collect::<Vec<_>>(),
+            // A 2x2 block in each quadrant around the centre
+            1 => [-2, -1, 1, 2]
+                .iter()
+                .flat_map(|&dx| {
+                    [-2, -1, 1, 2]
+                        .iter()
+                        .map(move |&dy| center + Coord::new(dx, dy))
+                })
+                .collect::<Vec<_>>(),
+            // A partial wall across the room with a gap in it
+            _ => {
+                if rng.gen() {
+                    let x = if rng.gen() {
+                        (left + center.x) / 2
+                    } else {
+                        (center.x + right + 1) / 2
+                    };
+                    let gap = rng.gen_range(top..=bottom);
+                    ((top + 1)..bottom)
+                        .filter(|&y| y != gap)
+                        .map(|y| Coord::new(x, y))
+                        .collect::<Vec<_>>()
+                } else {
+                    let y = if rng.gen() {
+                        (top + center.y) / 2
+                    } else {
+                        (center.y + bottom + 1) / 2
+                    };
+                    let gap = rng.gen_range(left..=right);
+                    ((left + 1)..right)
+                        .filter(|&x| x != gap)
+                        .map(|x| Coord::new(x, y))
+                        .collect::<Vec<_>>()
+                }
+            }
+        };
+        let walls = walls
+            .into_iter()
+            .filter(|&coord| coord.x != center.x && coord.y != center.y)
+            .filter(|&coord| grid.get_checked(coord) == &Some(TerrainTile::Floor))
+            .collect::<Vec<_>>();
+        for &coord in &walls {
+            *grid.get_checked_mut(coord) = Some(TerrainTile::Wall);
+        }
+        let reachable = reachable_cells(grid.size(), center, |coord| {
+            grid.get_checked(coord)
+                .map(TerrainTile::is_passable)
+                .unwrap_or(false)
+        });
+        let disconnected = self.coords().any(|coord| {
+            grid.get_checked(coord)
+                .map(TerrainTile::is_passable)
+                .unwrap_or(false)
+                && !*reachable.get_checked(coord)
+        });
+        if disconnected {
+            for coord in walls {
+                *grid.get_checked_mut(coord) = Some(TerrainTile::Floor);
+            }
+        }
+    }
+
+    // Replaces a section of the room's top wall with a torch
+    fn try_add_torch<R: Rng>(&self, grid: &mut Grid<Option<TerrainTile>>, rng: &mut R) {
+        let y = self.top_left.y;
+        let left = self.top_left.x + 1;
+        let right = self.top_left.x + self.size.width() as i32 - 1;
+        if let Some(coord) = (left..=right)
+            .map(|x| Coord::new(x, y))
+            .filter(|&coord| {
+                *grid.get_checked(coord) == Some(TerrainTile::Wall)
+                    && *grid.get_checked(coord + Coord::new(0, 1)) == Some(TerrainTile::Floor)
+            })
+            .choose(rng)
+        {
+            *grid.get_checked_mut(coord) = Some(TerrainTile::Torch);
+        }
+    }
+
+    fn try_add_closet<R: Rng>(
+        &self,
+        item_probability_distribution: &[(ItemType, u32)],
+        grid: &mut Grid<Option<TerrainTile>>,
+        rng: &mut R,
+    ) {
+        let is_solid = |grid: &Grid<Option<TerrainTile>>, coord: Coord| {
+            matches!(grid.get(coord), Some(None) | Some(Some(TerrainTile::Wall)))
+        };
+        let left = self.top_left.x + 1;
+        let top = self.top_left.y + 1;
+        let right = self.top_left.x + self.size.width() as i32 - 1;
+        let bottom = self.top_left.y + self.size.height() as i32 - 1;
+        let (inside, step) = match rng.gen_range(0..4) {
+            0 => (
+                Coord::new(rng.gen_range(left..=right), top),
+                Coord::new(0, -1),
+            ),
+            1 => (
+                Coord::new(rng.gen_range(left..=right), bottom),
+                Coord::new(0, 1),
+            ),
+            2 => (
+                Coord::new(left, rng.gen_range(top..=bottom)),
+                Coord::new(-1, 0),
+            ),
+            _ => (
+                Coord::new(right, rng.gen_range(top..=bottom)),
+                Coord::new(1, 0),
+            ),
+        };
+        let secret_door = inside + step;
+        let closet = secret_door + step;
+        let across = Coord::new(step.y, step.x);
+        if !grid
+            .get(inside)
+            .map(|cell| cell.map(TerrainTile::is_passable).unwrap_or(false))
+            .unwrap_or(false)
+            || !is_solid(grid, secret_door)
+            || grid.get(closet) != Some(&None)
+            || !is_solid(grid, secret_door + across)
+            || !is_solid(grid, secret_door - across)
+        {
+            return;
+        }
+        // the closet must be completely enclosed apart from the secret door
+        let enclosed = Direction::all()
+            .map(|direction| closet + direction.coord())
+            .filter(|&neighbour| neighbour != secret_door)
+            .all(|neighbour| is_solid(grid, neighbour));
+        if !enclosed {
+            return;
+        }
+        *grid.get_checked_mut(secret_door) = Some(TerrainTile::SecretDoor);
+        const CLOSET_ITEM_CHANCE: f64 = 0.7;
+        *grid.get_checked_mut(closet) = Some(if rng.gen_bool(CLOSET_ITEM_CHANCE) {
+            let &item = choose_from_probability_distribution(item_probability_distribution, rng);
+            TerrainTile::Item(item)
+        } else {
+            TerrainTile::Floor
+        });
+    }
+
+    fn place_water_pool<R: Rng>(&self, grid: &mut Grid<Option<TerrainTile>>, rng: &mut R) {
+        const POOL_RADIUS_SQUARED: u32 = 2;
+        let pool_center = match self
+            .coords()
+            .filter(|&coord| grid.get_checked(coord).unwrap() == TerrainTile::Floor)
+            .choose(rng)
+        {
+            Some(pool_center) => pool_center,
+            None => return,
+        };
+        for coord in self.coords() {
+            let cell = grid.get_checked_mut(coord);
+            if *cell == Some(TerrainTile::Floor)
+                && (coord - pool_center).magnitude2() <= POOL_RADIUS_SQUARED
+            {
+                *cell = Some(TerrainTile::Water);
+            }
+        }
+    }
+
+    // Scatters foliage around a random point in the room. Unlike
+    // water, not every cell near the centre of the patch is covered.
+    fn place_foliage_patch<R: Rng>(&self, grid: &mut Grid<Option<TerrainTile>>, rng: &mut R) {
+        const PATCH_RADIUS_SQUARED: u32 = 5;
+        const FOLIAGE_CHANCE: f64 = 0.7;
+        let patch_center = match self
+            .coords()
+            .filter(|&coord| grid.get_checked(coord).unwrap() == TerrainTile::Floor)
+            .choose(rng)
+        {
+            Some(patch_center) => patch_center,
+            None => return,
+        };
+        for coord in self.coords() {
+            let cell = grid.get_checked_mut(coord);
+            if *cell == Some(TerrainTile::Floor)
+                && (coord - patch_center).magnitude2() <= PATCH_RADIUS_SQUARED
+                && rng.gen_bool(FOLIAGE_CHANCE)
+            {
+                *cell = Some(TerrainTile::Foliage);
+            }
+        }
+    }
+
+    // Places a line of lava in one half of a row or column of the
+    // room. The lava never crosses the room's centre row or column, as
+    // these are where corridors between rooms run.
+    fn place_lava_streak<R: Rng>(&self, grid: &mut Grid<Option<TerrainTile>>, rng: &mut R) {
+        let left = self.top_left.x + 1;
+        let top = self.top_left.y + 1;
+        let right = self.top_left.x + self.size.width() as i32 - 1;
+        let bottom = self.top_left.y + self.size.height() as i32 - 1;
+        let center = self.center();
+        let streak = if rng.gen() {
+            let y = (top..=bottom)
+                .filter(|&y| y != center.y)
+                .choose(rng)
+                .unwrap();
+            let xs = if rng.gen() {
+                left..=(center.x - 1)
+            } else {
+                (center.x + 1)..=right
+            };
+            xs.map(|x| Coord::new(x, y)).collect::<Vec<_>>()
+        } else {
+            let x = (left..=right)
+                .filter(|&x| x != center.x)
+                .choose(rng)
+                .unwrap();
+            let ys = if rng.gen() {
+                top..=(center.y - 1)
+            } else {
+                (center.y + 1)..=bottom
+            };
+            ys.map(|y| Coord::new(x, y)).collect::<Vec<_>>()
+        };
+        for coord in streak {
+            let cell = grid.get_checked_mut(coord);
+            if *cell == Some(TerrainTile::Floor) {
+                *cell = Some(TerrainTile::Lava);
+            }
+        }
+    }
+
+    // Either a single piece of good equipment or a handful of potions
+    fn place_danger_room_loot<R: Rng>(&self, grid: &mut Grid<Option<TerrainTile>>, rng: &mut R) {
+        use ItemType::*;
+        const NUM_POTIONS: usize = 3;
+        if rng.gen_bool(0.5) {
+            let &item = [Sword, Armor, Staff, Robe].choose(rng).unwrap();
+            self.place_tile(TerrainTile::Item(item), grid, rng);
+        } else {
+            for _ in 0..NUM_POTIONS {
+                self.place_tile(TerrainTile::Item(HealthPotion), grid, rng);
+            }
+        }
+    }
+
+    // The centre if nothing stands there, which liquid and foliage don't
+    // count as, or else the nearest bare floor. None if the room has no
+    // bare floor left.
+    fn free_cell_nearest_center(&self, grid: &Grid<Option<TerrainTile>>) -> Option<Coord> {
+        let center = self.center();
+        if matches!(
+            grid.get_checked(center),
+            Some(TerrainTile::Floor | TerrainTile::Water | TerrainTile::Foliage)
+        ) {
+            return Some(center);
+        }
+        self.coords()
+            .filter(|&coord| *grid.get_checked(coord) == Some(TerrainTile::Floor))
+            .min_by_key(|&coord| (coord - center).magnitude2())
+    }
+
+    // The NPC or item nearest the centre, for when there's no floor left
+    fn spawn_nearest_center(&self, grid: &Grid<Option<TerrainTile>>) -> Option<Coord> {
+        let center = self.center();
+        self.coords()
+            .filter(|&coord| {
+                matches!(
+                    grid.get_checked(coord),
+                    Some(TerrainTile::Npc(_) | TerrainTile::Item(_))
+                )
+            })
+            .min_by_key(|&coord| (coord - center).magnitude2())
+    }
+
+    fn place_tile<R: Rng>(
+        &self,
+        tile: TerrainTile,
+        grid: &mut Grid<Option<TerrainTile>>,
+        rng: &mut R,
+    ) {
+        if let Some(coord) = self
+            .coords()
+            .filter(|&coord| grid.get_checked(coord).unwrap() == TerrainTile::Floor)
+            .choose(rng)
+        {
+            *grid.get_checked_mut(coord) = Some(tile);
+        }
+    }
 }
 
 fn choose_from_probability_distribution<'a, T, R: Rng>(
@@ -177,39 +1822,335 @@ fn choose_from_probability_distribution<'a, T, R: Rng>(
     }
     unreachable!()
 }
-                                   
-fn make_npc_probability_distribution(level: u32) -> Vec<(NpcType, u32)> {
-    use NpcType::*;
-    vec![(Orc, 20), (Troll, level)]
+
+fn apply_npc_theme_weights(theme: LevelTheme, distribution: &mut [(NpcType, u32)]) {
+    for (npc_type, weight) in distribution.iter_mut() {
+        *weight = match (theme, *npc_type) {
+            (LevelTheme::Caves, NpcType::Troll) => *weight * 2,
+            (LevelTheme::Crypt, NpcType::Orc) => *weight / 2,
+            (LevelTheme::Lair, NpcType::Troll) => *weight * 3,
+            _ => *weight,
+        };
+    }
 }
 
-fn make_item_probability_distribution(level: u32) -> Vec<(ItemType, u32)> {
+fn apply_item_theme_weights(theme: LevelTheme, distribution: &mut [(ItemType, u32)]) {
     use ItemType::*;
-    let item_chance = match level {
-        0..=1 => 5,
-        2..=3 => 10,
-        _ => 20,
-    };
-    
-    vec![
-        (HealthPotion, 200),
-        (FireballScroll,
-         match level {
-             0..=1 => 10,
-             2..=4 => 50,
-             _ => 100,
-         },
-        ),
-        (ConfusionScroll,
-         match level {
-             0..=1 => 10,
-             2..=4 => 30,
-             _ => 50,
-         },
-        ),
-        (Sword, item_chance),
-        (Staff, item_chance),
-        (Armor, item_chance),
-        (Robe, item_chance),
-    ]
+    for (item_type, weight) in distribution.iter_mut() {
+        *weight = match (theme, *item_type) {
+            (LevelTheme::Caves, HealthPotion) => *weight * 3 / 2,
+            (
+                LevelTheme::Crypt,
+                FireballScroll | ConfusionScroll | ClairvoyanceScroll | Staff | Robe,
+            ) => *weight * 2,
+            (LevelTheme::Lair, Sword | Warhammer | Armor) => *weight * 2,
+            // torches matter more where it's darker
+            (LevelTheme::Crypt | LevelTheme::Lair, Torch) => *weight * 2,
+            _ => *weight,
+        };
+    }
+}
+
+fn make_trap_probability_distribution(level: u32) -> Vec<(TrapType, u32)> {
+    use TrapType::*;
+    vec![(Spike, 50), (Teleport, 20 + level), (Alarm, 25)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::difficulty::Difficulty;
+    use std::time::{Duration, Instant};
+
+    const MIN_MAP_SIZE: Size = Size::new_u16(20, 15);
+    const MAX_MAP_SIZE: Size = Size::new_u16(200, 120);
+    // generous, as tests are built without optimisations
+    const GENERATION_TIME_BUDGET: Duration = Duration::from_secs(5);
+
+    fn generate(size: Size, level: u32, seed: u64) -> Dungeon {
+        generate_dungeon(
+            size,
+            level,
+            &GenerationConstraints::default(),
+            &SpawnTables::built_in(),
+            &mut Isaac64Rng::seed_from_u64(seed),
+        )
+    }
+
+    #[test]
+    fn arena_is_open_floor_walled_around_the_edge() {
+        let size = Size::new(20, 15);
+        let dungeon = arena(size);
+        for (coord, &tile) in dungeon.grid.enumerate() {
+            let on_edge = coord.x == 0
+                || coord.y == 0
+                || coord.x == size.width() as i32 - 1
+                || coord.y == size.height() as i32 - 1;
+            match tile {
+                TerrainTile::Wall => assert!(on_edge, "wall inside the arena at {:?}", coord),
+                TerrainTile::Floor | TerrainTile::Player => {
+                    assert!(!on_edge, "gap in the arena's wall at {:?}", coord)
+                }
+                other => panic!("unexpected {:?} in the arena at {:?}", other, coord),
+            }
+        }
+        let players = dungeon
+            .grid
+            .iter()
+            .filter(|&&tile| tile == TerrainTile::Player);
+        assert_eq!(players.count(), 1);
+    }
+
+    #[test]
+    fn every_level_generates_on_the_smallest_and_largest_maps_in_time() {
+        for size in [MIN_MAP_SIZE, MAX_MAP_SIZE] {
+            for level in 1..=FINAL_DUNGEON_LEVEL {
+                let start = Instant::now();
+                let dungeon = generate(size, level, 0);
+                let elapsed = start.elapsed();
+                assert_eq!(dungeon.grid.size(), size);
+                assert!(
+                    elapsed < GENERATION_TIME_BUDGET,
+                    "level {} at {:?} took {:?}",
+                    level,
+                    size,
+                    elapsed
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn nothing_but_wall_on_the_edge_of_the_map() {
+        for size in [MIN_MAP_SIZE, Size::new(40, 23), Size::new(80, 50)] {
+            for seed in 0..20 {
+                for level in 1..=FINAL_DUNGEON_LEVEL {
+                    let dungeon = generate(size, level, seed);
+                    for (coord, &tile) in dungeon.grid.enumerate() {
+                        let on_edge = coord.x == 0
+                            || coord.y == 0
+                            || coord.x == size.width() as i32 - 1
+                            || coord.y == size.height() as i32 - 1;
+                        assert!(
+                            !on_edge || tile == TerrainTile::Wall,
+                            "{:?} on the edge at {:?} of level {} at {:?}, seed {}",
+                            tile,
+                            coord,
+                            level,
+                            size,
+                            seed
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn corridors_join_points_in_every_quadrant() {
+        let size = Size::new(11, 11);
+        let center = Coord::new(5, 5);
+        let others = [
+            Coord::new(9, 1),
+            Coord::new(1, 1),
+            Coord::new(1, 9),
+            Coord::new(9, 9),
+            // and in line, where one leg of the corridor has no length
+            Coord::new(9, 5),
+            Coord::new(5, 1),
+        ];
+        let styles = [
+            CorridorStyle::HorizontalFirst,
+            CorridorStyle::VerticalFirst,
+            CorridorStyle::Staircase,
+        ];
+        for (style_index, &style) in styles.iter().enumerate() {
+            for &other in &others {
+                for (start, end) in [(center, other), (other, center)] {
+                    let mut grid = Grid::new_copy(size, None);
+                    let carved = carve_corridor_in_style(start, end, style, &mut grid);
+                    let reachable = reachable_cells(size, start, |coord| {
+                        *grid.get_checked(coord) == Some(TerrainTile::Floor)
+                    });
+                    assert!(
+                        *reachable.get_checked(end),
+                        "style {} leaves a gap from {:?} to {:?}",
+                        style_index,
+                        start,
+                        end
+                    );
+                    // the shortest path, with no cell carved twice
+                    let delta = end - start;
+                    assert_eq!(
+                        carved.len(),
+                        (delta.x.abs() + delta.y.abs() + 1) as usize,
+                        "style {} from {:?} to {:?}",
+                        style_index,
+                        start,
+                        end
+                    );
+                }
+            }
+        }
+    }
+
+    fn count(dungeon: &Dungeon, tile: TerrainTile) -> usize {
+        dungeon.grid.iter().filter(|&&other| other == tile).count()
+    }
+
+    #[test]
+    fn a_level_with_one_room_keeps_its_player_start() {
+        // too narrow for a second room beside the first
+        let size = Size::new(8, 7);
+        let constraints = GenerationConstraints {
+            min_rooms: 1,
+            min_stairs_distance: 0,
+            ..GenerationConstraints::default()
+        };
+        for seed in 0..100 {
+            let dungeon = generate_dungeon(
+                size,
+                1,
+                &constraints,
+                &SpawnTables::built_in(),
+                &mut Isaac64Rng::seed_from_u64(seed),
+            );
+            assert_eq!(dungeon.room_count, 1);
+            assert_eq!(count(&dungeon, TerrainTile::Player), 1, "seed {}", seed);
+            assert_eq!(count(&dungeon, TerrainTile::Stairs), 1, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn rooms_packed_with_spawns_still_get_stairs() {
+        let mut spawn_tables = SpawnTables::built_in();
+        // more than any room has floor for
+        spawn_tables.npcs_per_room = vec![100];
+        spawn_tables.items_per_room = vec![100];
+        for seed in 0..20 {
+            for level in 1..FINAL_DUNGEON_LEVEL {
+                let dungeon = generate_dungeon(
+                    Size::new(40, 23),
+                    level,
+                    &GenerationConstraints::default(),
+                    &spawn_tables,
+                    &mut Isaac64Rng::seed_from_u64(seed),
+                );
+                let arrival = if level == 1 {
+                    TerrainTile::Player
+                } else {
+                    TerrainTile::StairsUp
+                };
+                assert_eq!(
+                    count(&dungeon, arrival),
+                    1,
+                    "level {}, seed {}",
+                    level,
+                    seed
+                );
+                assert_eq!(
+                    count(&dungeon, TerrainTile::Stairs),
+                    1,
+                    "level {}, seed {}",
+                    level,
+                    seed
+                );
+                assert!(unreachable_cells(&dungeon.grid).is_empty());
+            }
+        }
+    }
+
+    const DIFFICULTIES: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+
+    #[test]
+    fn harder_difficulties_put_more_npcs_in_each_room() {
+        let means = DIFFICULTIES.map(|difficulty| {
+            let spawn_tables = SpawnTables::built_in().with_difficulty(&difficulty.profile());
+            mean(&spawn_tables.npcs_per_room)
+        });
+        assert!(means[0] < means[1], "{:?}", means);
+        assert!(means[1] < means[2], "{:?}", means);
+    }
+
+    #[test]
+    fn harder_difficulties_spawn_more_npcs_on_the_same_seeds() {
+        let num_npcs = DIFFICULTIES.map(|difficulty| {
+            let spawn_tables = SpawnTables::built_in().with_difficulty(&difficulty.profile());
+            let mut num_npcs = 0;
+            for seed in 0..20 {
+                for level in 1..=5 {
+                    let dungeon = generate_dungeon(
+                        Size::new(40, 23),
+                        level,
+                        &GenerationConstraints::default(),
+                        &spawn_tables,
+                        &mut Isaac64Rng::seed_from_u64(seed),
+                    );
+                    num_npcs += dungeon
+                        .grid
+                        .iter()
+                        .filter(|tile| matches!(tile, TerrainTile::Npc(_)))
+                        .count();
+                }
+            }
+            num_npcs
+        });
+        assert!(num_npcs[0] < num_npcs[1], "{:?}", num_npcs);
+        assert!(num_npcs[1] < num_npcs[2], "{:?}", num_npcs);
+    }
+
+    fn level_stats(npcs: usize, items: usize) -> LevelStats {
+        LevelStats {
+            npcs,
+            expected_npcs: 4.0,
+            items,
+            expected_items: 4.0,
+            ..LevelStats::default()
+        }
+    }
+
+    #[test]
+    fn an_average_level_has_no_feeling() {
+        assert!(level_stats(4, 4).feelings().is_empty());
+        // just short of each threshold
+        assert!(level_stats(5, 5).feelings().is_empty());
+        assert!(level_stats(3, 3).feelings().is_empty());
+        // nothing was rolled for a hand-made level
+        assert!(LevelStats::default().feelings().is_empty());
+    }
+
+    #[test]
+    fn half_as_many_npcs_again_feels_dangerous() {
+        assert_eq!(level_stats(6, 4).feelings(), vec![LevelFeeling::Danger]);
+        let out_of_depth = LevelStats {
+            out_of_depth_npcs: 1,
+            ..level_stats(4, 4)
+        };
+        assert_eq!(out_of_depth.feelings(), vec![LevelFeeling::Danger]);
+    }
+
+    #[test]
+    fn half_as_many_items_again_feels_like_treasure() {
+        assert_eq!(level_stats(4, 6).feelings(), vec![LevelFeeling::Treasure]);
+        let danger_room = LevelStats {
+            has_danger_room: true,
+            ..level_stats(4, 4)
+        };
+        assert_eq!(danger_room.feelings(), vec![LevelFeeling::Treasure]);
+        assert_eq!(
+            level_stats(6, 6).feelings(),
+            vec![LevelFeeling::Danger, LevelFeeling::Treasure]
+        );
+    }
+
+    #[test]
+    fn half_as_many_npcs_feels_quiet_unless_its_dangerous() {
+        assert_eq!(level_stats(2, 4).feelings(), vec![LevelFeeling::Quiet]);
+        assert_eq!(level_stats(0, 4).feelings(), vec![LevelFeeling::Quiet]);
+        let out_of_depth = LevelStats {
+            out_of_depth_npcs: 1,
+            ..level_stats(2, 4)
+        };
+        assert_eq!(out_of_depth.feelings(), vec![LevelFeeling::Danger]);
+    }
 }
@@ -7,33 +7,66 @@ use chargrid::{
 };
 use coord_2d::{Coord, Size};
 use rgb24::Rgb24;
+use std::collections::vec_deque;
 
-use crate::app::colors;
-use crate::game::{ExamineCell, LogMessage};
-use crate::world::HitPoints;
-
+use crate::app::colors::ColorTheme;
+use crate::combat::CombatForecast;
+use crate::difficulty::Difficulty;
+use crate::game::{
+    Background, ExamineCell, LevelUp, LogEntry, LogMessage, MessageLog, NpcAwareness, RunStats,
+    WizardCommand, NUM_QUICK_SLOTS,
+};
+use crate::i18n;
+use crate::scores::{HighScoreOrder, HighScores};
+use crate::terrain::{self, NUM_RUNES};
+use crate::world::{HitPoints, ItemType, NpcType, StatusEffect};
 
 const HEALTH_WIDTH: u32 = 10;
-const HEALTH_FILL_COLOR: Rgb24 = Rgb24::new(200, 0, 0);
-const HEALTH_EMPTY_COLOR: Rgb24 = Rgb24::new(100, 0, 0);
-
+pub const NUM_MESSAGES: usize = 3;
+// the health and stats row, the messages, the status line, the equipment
+// row and the quickbar
+pub const UI_NUM_ROWS: u32 = NUM_MESSAGES as u32 + 4;
+// the width of everything to the right of the health bar
+const RIGHT_PANEL_WIDTH: u32 = 29;
 
 pub struct UiData<'a> {
+    pub colors: &'a ColorTheme,
     pub player_hit_points: HitPoints,
-    pub messages: &'a [LogMessage],
+    // the hit point text flashes briefly when the player is hurt
+    pub player_hit_flash: bool,
+    // how bright the pulsing low health warning is, while the player is
+    // badly wounded
+    pub player_low_health_pulse: Option<u8>,
+    pub messages: &'a MessageLog,
+    // the first message of the page being read while the log is paused
+    pub message_page_start: Option<usize>,
+    // shown in place of the status line once the player has died
+    pub is_player_dead: bool,
+    // shown in place of the status line while the player is resting
+    pub is_resting: bool,
+    pub quick_slots: [Option<ItemType>; NUM_QUICK_SLOTS],
     pub name: Option<&'static str>,
+    // shown in the same row as the name of an open screen, when there
+    // isn't one
+    pub character_name: &'a str,
     pub examine_cell: Option<ExamineCell>,
+    pub npc_awareness: Option<NpcAwareness>,
+    // the player's attack on the examined NPC, then the NPC's on the player
+    pub combat_forecast: Option<(CombatForecast, CombatForecast)>,
     pub stats_data: StatsData,
-    pub dungeon_level: u32,
+    pub equipment_data: EquipmentData,
+    pub status_data: StatusData,
 }
 
-
 #[derive(Default)]
 pub struct UiView {
     health_view: HealthView,
     messages_view: MessagesView,
     stats_view: StatsView,
-    dungeon_level_view: DungeonLevelView,
+    status_view: StatusView,
+    equipment_view: EquipmentView,
+    quickbar_view: QuickbarView,
+    examine_buf: String,
 }
 
 fn center_health_width<T: Clone>(view: impl View<T>, height: u32) -> impl View<T> {
@@ -56,24 +89,107 @@ impl<'a> View<UiData<'a>> for UiView {
         context: ViewContext<C>,
         frame: &mut F,
     ) {
-        self.health_view
-            .view(data.player_hit_points, context, frame);
-        self.stats_view
+        let colors = data.colors;
+        self.health_view.view(
+            HealthData {
+                hit_points: data.player_hit_points,
+                flash: data.player_hit_flash,
+                low_health_pulse: data.player_low_health_pulse,
+                colors,
+            },
+            context,
+            frame,
+        );
+        self.stats_view.view(
+            &data.stats_data,
+            context.add_offset(Coord::new(HEALTH_WIDTH as i32 + 1, 0)),
+            frame,
+        );
+        // the equipment row runs the full width of the screen, below the
+        // examine text
+        self.equipment_view.view(
+            (&data.equipment_data, colors),
+            context.add_offset(Coord::new(0, NUM_MESSAGES as i32 + 2)),
+            frame,
+        );
+        self.quickbar_view.view(
+            (&data.quick_slots, colors),
+            context.add_offset(Coord::new(0, NUM_MESSAGES as i32 + 3)),
+            frame,
+        );
+        let status_offset = Coord::new(HEALTH_WIDTH as i32 + 1, NUM_MESSAGES as i32 + 1);
+        // the --More-- prompt takes the place of the status line, as does
+        // the reminder of how to stop resting
+        let messages = if let Some(start) = data.message_page_start {
+            StringViewSingleLine::new(
+                Style::new()
+                    .with_bold(true)
+                    .with_foreground(Rgb24::new_grey(255)),
+            )
             .view(
-                &data.stats_data,
-                context.add_offset(Coord::new(HEALTH_WIDTH as i32 + 1, 0)),
+                i18n::text("ui.more"),
+                context.add_offset(status_offset),
+                frame,
+            );
+            data.messages.entries_from(start)
+        } else if data.is_player_dead {
+            StringViewSingleLine::new(Style::new().with_foreground(Rgb24::new_grey(255))).view(
+                i18n::text("ui.dead"),
+                context.add_offset(status_offset),
+                frame,
+            );
+            data.messages
+                .entries_from(data.messages.end().saturating_sub(NUM_MESSAGES))
+        } else if data.is_resting {
+            StringViewSingleLine::new(Style::new().with_foreground(Rgb24::new_grey(255))).view(
+                i18n::text("ui.resting"),
+                context.add_offset(status_offset),
+                frame,
+            );
+            data.messages
+                .entries_from(data.messages.end().saturating_sub(NUM_MESSAGES))
+        } else if let Some((player_attack, npc_attack)) = data.combat_forecast {
+            // examining an NPC shows how a fight with it would go instead
+            let forecast = i18n::format(
+                "examine.forecast",
+                &[
+                    ("player_hit", &player_attack.hit_percent),
+                    ("player_min", &player_attack.min_damage),
+                    ("player_max", &player_attack.max_damage),
+                    ("npc_hit", &npc_attack.hit_percent),
+                    ("npc_min", &npc_attack.min_damage),
+                    ("npc_max", &npc_attack.max_damage),
+                ],
+            );
+            StringViewSingleLine::new(Style::new().with_foreground(Rgb24::new_grey(187))).view(
+                forecast.as_str(),
+                context.add_offset(status_offset),
                 frame,
             );
-        center_health_width(&mut self.dungeon_level_view, 1).view(
-            data.dungeon_level,
-            context.add_offset(Coord::new(0, 1)),
+            data.messages
+                .entries_from(data.messages.end().saturating_sub(NUM_MESSAGES))
+        } else {
+            self.status_view.view(
+                (&data.status_data, colors),
+                context.add_offset(status_offset),
+                frame,
+            );
+            data.messages
+                .entries_from(data.messages.end().saturating_sub(NUM_MESSAGES))
+        };
+        let message_log_offset = Coord::new(HEALTH_WIDTH as i32 + 1, 1);
+        self.messages_view.view(
+            (messages, colors),
+            context.add_offset(message_log_offset),
             frame,
         );
-        let message_log_offset = Coord::new(HEALTH_WIDTH as i32 + 1, 1);
-        self.messages_view
-            .view(data.messages, context.add_offset(message_log_offset), frame);
 
-        if let Some(name) = data.name {
+        let title = match data.name {
+            Some(name) => Some(name),
+            None if !data.character_name.is_empty() => Some(data.character_name),
+            None => None,
+        };
+        if let Some(title) = title {
             BoundView {
                 size: Size::new(HEALTH_WIDTH, 1),
                 view: AlignView {
@@ -83,72 +199,192 @@ impl<'a> View<UiData<'a>> for UiView {
                     ),
                 },
             }
-            .view(name, context.add_offset(Coord::new(0, 2)), frame);
+            .view(title, context.add_offset(Coord::new(0, 1)), frame);
         }
         if let Some(examine_cell) = data.examine_cell {
+            use std::fmt::Write;
+            self.examine_buf.clear();
+            self.examine_buf.push_str(examine_cell_str(examine_cell));
+            if let ExamineCell::Npc { hit_points, .. } = examine_cell {
+                write!(&mut self.examine_buf, " {}", health_word(hit_points)).unwrap();
+            }
+            // lets the player learn how close they can get unnoticed
+            match data.npc_awareness {
+                Some(NpcAwareness { noticed: true, .. }) => {
+                    write!(&mut self.examine_buf, " {}", i18n::text("examine.alert")).unwrap();
+                }
+                Some(NpcAwareness {
+                    noticed: false,
+                    detection_range,
+                }) => {
+                    let unaware = i18n::format("examine.unaware", &[("range", &detection_range)]);
+                    write!(&mut self.examine_buf, " {}", unaware).unwrap();
+                }
+                None => (),
+            }
             center_health_width(
                 StringView::new(
                     Style::new().with_foreground(Rgb24::new_grey(187)),
                     wrap::Word::new(),
                 ),
-                2,
-            ).view(
-                examine_cell_str(examine_cell),
-                context.add_offset(Coord::new(0, 3)),
+                3,
+            )
+            .view(
+                self.examine_buf.as_str(),
+                context.add_offset(Coord::new(0, 2)),
                 frame,
             );
         }
     }
 }
 
-#[derive(Default)]
-struct HealthView {
+// A horizontal bar whose filled portion shows how close a value is to
+// its maximum, with the numbers written over the top. Used for hit points
+// and reusable for any other value with a maximum.
+struct GaugeView {
+    width: u32,
+    color_fn: fn(&ColorTheme, u32, u32) -> Rgb24,
     buf: String,
 }
 
-impl View<HitPoints> for HealthView {
+#[derive(Clone, Copy)]
+struct Gauge<'a> {
+    value: u32,
+    max: u32,
+    // draws the gauge in a bright flash colour
+    highlight: bool,
+    // replaces the usual fill colour of the gauge
+    fill_override: Option<Rgb24>,
+    colors: &'a ColorTheme,
+}
+
+impl GaugeView {
+    fn new(width: u32, color_fn: fn(&ColorTheme, u32, u32) -> Rgb24) -> Self {
+        Self {
+            width,
+            color_fn,
+            buf: String::new(),
+        }
+    }
+}
+
+// The number of cells of a gauge to fill. A gauge is never drawn empty
+// unless its value is 0, and never drawn full unless it's at its maximum.
+fn gauge_fill_width(value: u32, max: u32, width: u32) -> u32 {
+    if max == 0 || value == 0 {
+        return 0;
+    }
+    if value >= max {
+        return width;
+    }
+    (value * width / max).max(1).min(width.saturating_sub(1))
+}
+
+impl<'a> View<Gauge<'a>> for GaugeView {
     fn view<F: Frame, C: ColModify>(
         &mut self,
-        hit_points: HitPoints,
+        gauge: Gauge<'a>,
         context: ViewContext<C>,
         frame: &mut F,
     ) {
         use std::fmt::Write;
         self.buf.clear();
-        write!(&mut self.buf, "{}/{}", hit_points.current, hit_points.max).unwrap();
-
-        let mut hit_points_text_view = BoundView {
-            size: Size::new(HEALTH_WIDTH, 1),
+        write!(&mut self.buf, "{}/{}", gauge.value, gauge.max).unwrap();
+        let (text_color, fill_color) = if gauge.highlight {
+            (gauge.colors.danger, Rgb24::new_grey(255))
+        } else {
+            let fill_color = gauge
+                .fill_override
+                .unwrap_or_else(|| (self.color_fn)(gauge.colors, gauge.value, gauge.max));
+            (Rgb24::new_grey(255), fill_color)
+        };
+        let empty_color = fill_color.saturating_scalar_mul_div(1, 3);
+        let mut text_view = BoundView {
+            size: Size::new(self.width, 1),
             view: AlignView {
                 alignment: Alignment::centre(),
-                view: StringViewSingleLine::new(Style::new().with_foreground(Rgb24::new_grey(255))),
+                view: StringViewSingleLine::new(
+                    Style::new().with_bold(true).with_foreground(text_color),
+                ),
             },
         };
-        hit_points_text_view.view(&self.buf, context.add_depth(1), frame);
-        let mut health_fill_width =
-            (hit_points.current * HEALTH_WIDTH) / hit_points.max;
-        if hit_points.current > 0 {
-            health_fill_width = health_fill_width.max(1);
-        }
-        for i in 0..health_fill_width {
+        text_view.view(&self.buf, context.add_depth(1), frame);
+        let fill_width = gauge_fill_width(gauge.value, gauge.max, self.width);
+        for i in 0..self.width {
+            let background = if i < fill_width {
+                fill_color
+            } else {
+                empty_color
+            };
             frame.set_cell_relative(
                 Coord::new(i as i32, 0),
                 0,
-                ViewCell::new().with_background(HEALTH_FILL_COLOR),
+                ViewCell::new()
+                    .with_character(' ')
+                    .with_background(background),
                 context,
             );
         }
-        for i in health_fill_width..HEALTH_WIDTH {
-            frame.set_cell_relative(
-                Coord::new(i as i32, 0),
-                0,
-                ViewCell::new().with_background(HEALTH_EMPTY_COLOR),
-                context,
-            );
+    }
+}
+
+// Green when healthy, yellow below two thirds and red below one third
+fn health_color(colors: &ColorTheme, current: u32, max: u32) -> Rgb24 {
+    if current * 3 > max * 2 {
+        colors.health_high
+    } else if current * 3 > max {
+        colors.health_mid
+    } else {
+        colors.health_low
+    }
+}
+
+struct HealthView {
+    gauge_view: GaugeView,
+}
+
+impl Default for HealthView {
+    fn default() -> Self {
+        Self {
+            gauge_view: GaugeView::new(HEALTH_WIDTH, health_color),
         }
     }
 }
 
+struct HealthData<'a> {
+    hit_points: HitPoints,
+    flash: bool,
+    low_health_pulse: Option<u8>,
+    colors: &'a ColorTheme,
+}
+
+impl<'a> View<HealthData<'a>> for HealthView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        data: HealthData<'a>,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        // the bar turns white under red text while the player is being hit,
+        // and pulses between dark and bright red while they're badly wounded
+        let fill_override = data.low_health_pulse.map(|pulse| {
+            data.colors
+                .danger
+                .saturating_scalar_mul_div(255 + pulse as u32, 2 * 255)
+        });
+        self.gauge_view.view(
+            Gauge {
+                value: data.hit_points.current(),
+                max: data.hit_points.max(),
+                highlight: data.flash,
+                fill_override,
+                colors: data.colors,
+            },
+            context,
+            frame,
+        );
+    }
+}
 
 struct MessagesView {
     buf: Vec<RichTextPartOwned>,
@@ -163,127 +399,381 @@ impl Default for MessagesView {
     }
 }
 
-impl<'a> View<&'a [LogMessage]> for MessagesView {
+// The colour of a message's text, by what kind of event it's about
+fn message_category_color(message: LogMessage, colors: &ColorTheme) -> Rgb24 {
+    use LogMessage::*;
+    match message {
+        // the player being hurt
+        NpcAttacksPlayer(_)
+        | NpcKillsPlayer(_)
+        | TrapTriggered(..)
+        | LavaBurns(_)
+        | SlamsIntoWall(None)
+        | SlamsInto(None, _)
+        | KnockedIntoChasm(None)
+        | PlayerIsBlinded(_)
+        | PlayerFallsIntoChasm
+        | PlayerIsBadlyWounded => colors.log_harm,
+        // other combat
+        PlayerAttacksNpc(_)
+        | PlayerKillsNpc(_)
+        | PlayerLaunchesProjectile(_)
+        | NpcDies(_)
+        | NpcBecomesConfused(_)
+        | NpcIsNoLongerConfused(_)
+        | PlayerDodges(_)
+        | NpcDodges(_)
+        | NpcBecomesHostile(_)
+        | PlayerSwingsAtNothing
+        | FireballFizzles => colors.log_combat,
+        // items and money
+        PlayerGets(_)
+        | PlayerInventoryIsFull
+        | PlayerPutsOnBackpack(_)
+        | InventoryCannotGrow
+        | NoItemUnderPlayer
+        | NoItemInInventorySlot
+        | PlayerReadsClairvoyance
+        | TorchGoesOut
+        | PlayerThrowsSmokeBomb
+        | TorchIsAlreadyLit
+        | PlayerDrops(_)
+        | NoSpaceToDropItem
+        | PlayerEquips(_)
+        | PlayerUnequips(_)
+        | CannotWield(_)
+        | CannotWear(_)
+        | ItemIsAlreadyEquipped(_)
+        | ItemIsNotEquipped(_)
+        | PlayerGetsGold(_)
+        | PlayerOwes(_)
+        | PlayerPays(_)
+        | CannotAffordDebt(_)
+        | NothingToPayFor
+        | PlayerGetsRune(_) => colors.log_items,
+        _ => Rgb24::new_grey(187),
+    }
+}
+
+// Splits a message's text into the parts before, inside and after its
+// [highlighted] part
+fn split_message(buf: &mut [RichTextPartOwned], text: &str) {
+    for part in buf.iter_mut() {
+        part.text.clear();
+    }
+    let parts = text.split_once('[').and_then(|(before, rest)| {
+        rest.split_once(']')
+            .map(|(highlighted, after)| (before, highlighted, after))
+    });
+    match parts {
+        Some((before, highlighted, after)) => {
+            buf[0].text.push_str(before);
+            buf[1].text.push_str(highlighted);
+            buf[2].text.push_str(after);
+        }
+        None => buf[0].text.push_str(text),
+    }
+}
+
+// Fills the parts before, inside and after the highlight with a
+// message's text and colours
+fn format_message(buf: &mut [RichTextPartOwned], message: LogMessage, colors: &ColorTheme) {
+    use LogMessage::*;
+    let category_color = message_category_color(message, colors);
+    let npc = |key: &str, npc_type: NpcType| {
+        (
+            i18n::format(key, &[("npc", &i18n::npc_name(npc_type))]),
+            colors.npc_color(npc_type),
+        )
+    };
+    let item = |key: &str, item_type: ItemType| {
+        (
+            i18n::format(key, &[("item", &i18n::item_name(item_type))]),
+            colors.item_color(item_type),
+        )
+    };
+    let gold = |key: &str, amount: u32| (i18n::format(key, &[("amount", &amount)]), colors.gold);
+    let plain = |key: &str| (i18n::format(key, &[]), category_color);
+    // the text, and the colour of its highlighted part
+    let (text, highlight_color) = match message {
+        PlayerAttacksNpc(npc_type) => npc("message.player-attacks-npc", npc_type),
+        NpcAttacksPlayer(npc_type) => npc("message.npc-attacks-player", npc_type),
+        PlayerKillsNpc(npc_type) => npc("message.player-kills-npc", npc_type),
+        NpcKillsPlayer(npc_type) => npc("message.npc-kills-player", npc_type),
+        PlayerGets(item_type) => item("message.player-gets", item_type),
+        PlayerInventoryIsFull => plain("message.player-inventory-is-full"),
+        PlayerPutsOnBackpack(num_slots) => (
+            i18n::format("message.player-puts-on-backpack", &[("slots", &num_slots)]),
+            colors.item_color(ItemType::Backpack),
+        ),
+        InventoryCannotGrow => plain("message.inventory-cannot-grow"),
+        ScreenshotSaved => plain("message.screenshot-saved"),
+        ScreenshotFailed => plain("message.screenshot-failed"),
+        NoItemUnderPlayer => plain("message.no-item-under-player"),
+        NoItemInInventorySlot => plain("message.no-item-in-inventory-slot"),
+        PlayerThrowsSmokeBomb => (
+            i18n::format("message.player-throws-smoke-bomb", &[]),
+            colors.fog,
+        ),
+        TorchGoesOut => (i18n::format("message.torch-goes-out", &[]), colors.torch),
+        TorchIsAlreadyLit => (
+            i18n::format("message.torch-is-already-lit", &[]),
+            colors.torch,
+        ),
+        PlayerReadsClairvoyance => (
+            i18n::format("message.player-reads-clairvoyance", &[]),
+            colors.sensed,
+        ),
+        PlayerClairvoyanceEnds => (
+            i18n::format("message.player-clairvoyance-ends", &[]),
+            colors.sensed,
+        ),
+        PlayerHeals => plain("message.player-heals"),
+        PlayerDrops(item_type) => item("message.player-drops", item_type),
+        NoSpaceToDropItem => plain("message.no-space-to-drop-item"),
+        PlayerLaunchesProjectile(projectile) => (
+            i18n::format(
+                "message.player-launches-projectile",
+                &[("projectile", &i18n::projectile_name(projectile))],
+            ),
+            colors.projectile_color(projectile),
+        ),
+        NpcDies(npc_type) => npc("message.npc-dies", npc_type),
+        NpcBecomesConfused(npc_type) => npc("message.npc-becomes-confused", npc_type),
+        NpcIsNoLongerConfused(npc_type) => npc("message.npc-is-no-longer-confused", npc_type),
+        PlayerDodges(npc_type) => npc("message.player-dodges", npc_type),
+        NpcDodges(npc_type) => npc("message.npc-dodges", npc_type),
+        PlayerEquips(item_type) => item("message.player-equips", item_type),
+        PlayerUnequips(item_type) => item("message.player-unequips", item_type),
+        CannotWield(item_type) => item("message.cannot-wield", item_type),
+        CannotWear(item_type) => item("message.cannot-wear", item_type),
+        ItemIsAlreadyEquipped(item_type) => item("message.item-is-already-equipped", item_type),
+        ItemIsNotEquipped(item_type) => item("message.item-is-not-equipped", item_type),
+        PlayerGetsGold(amount) => gold("message.player-gets-gold", amount),
+        PlayerOwes(price) => gold("message.player-owes", price),
+        PlayerPays(amount) => gold("message.player-pays", amount),
+        CannotAffordDebt(debt) => gold("message.cannot-afford-debt", debt),
+        NothingToPayFor => plain("message.nothing-to-pay-for"),
+        NoShopkeeperNearby => plain("message.no-shopkeeper-nearby"),
+        PlayerSwapsWith(npc_type) => npc("message.player-swaps-with", npc_type),
+        PlayerSwingsAtNothing => plain("message.player-swings-at-nothing"),
+        PlayerAssignsQuickSlot(quick_slot, item_type) => (
+            i18n::format(
+                "message.player-assigns-quick-slot",
+                &[("slot", &quick_slot), ("item", &i18n::item_name(item_type))],
+            ),
+            colors.item_color(item_type),
+        ),
+        NothingBoundToQuickSlot(quick_slot) => (
+            i18n::format(
+                "message.nothing-bound-to-quick-slot",
+                &[("slot", &quick_slot)],
+            ),
+            category_color,
+        ),
+        PrayerHeals => plain("message.prayer-heals"),
+        PrayerIncreasesStat(level_up) => {
+            let key = match level_up {
+                LevelUp::Strength => "message.prayer-increases-strength",
+                LevelUp::Dexterity => "message.prayer-increases-dexterity",
+                LevelUp::Intelligence => "message.prayer-increases-intelligence",
+                LevelUp::Health => "message.prayer-increases-health",
+            };
+            (i18n::format(key, &[]), colors.altar)
+        }
+        PrayerIdentifiesInventory => plain("message.prayer-identifies-inventory"),
+        PrayerAngersGods => plain("message.prayer-angers-gods"),
+        PlayerClosesDoor => plain("message.player-closes-door"),
+        NoDoorToClose => plain("message.no-door-to-close"),
+        DoorIsBlocked => plain("message.door-is-blocked"),
+        TrapTriggered(None, trap_type) => (
+            i18n::format(
+                "message.player-triggers-trap",
+                &[("trap", &i18n::trap_name(trap_type))],
+            ),
+            colors.trap_color(trap_type),
+        ),
+        TrapTriggered(Some(npc_type), trap_type) => (
+            i18n::format(
+                "message.npc-triggers-trap",
+                &[
+                    ("npc", &i18n::npc_name(npc_type)),
+                    ("trap", &i18n::trap_name(trap_type)),
+                ],
+            ),
+            colors.trap_color(trap_type),
+        ),
+        PlayerNoticesTrap(trap_type) => (
+            i18n::format(
+                "message.player-notices-trap",
+                &[("trap", &i18n::trap_name(trap_type))],
+            ),
+            colors.trap_color(trap_type),
+        ),
+        LavaBurns(None) => (i18n::format("message.lava-burns-player", &[]), colors.lava),
+        LavaBurns(Some(npc_type)) => npc("message.lava-burns-npc", npc_type),
+        SlamsIntoWall(None) => (
+            i18n::format("message.player-slams-into-wall", &[]),
+            colors.danger,
+        ),
+        SlamsIntoWall(Some(npc_type)) => npc("message.npc-slams-into-wall", npc_type),
+        SlamsInto(None, other_npc) => npc("message.player-slams-into-npc", other_npc),
+        SlamsInto(Some(npc_type), other_npc) => (
+            i18n::format(
+                "message.npc-slams-into-npc",
+                &[
+                    ("npc", &i18n::npc_name(npc_type)),
+                    ("other", &i18n::npc_name(other_npc)),
+                ],
+            ),
+            colors.npc_color(npc_type),
+        ),
+        KnockedIntoChasm(None) => plain("message.player-knocked-into-chasm"),
+        KnockedIntoChasm(Some(npc_type)) => npc("message.npc-knocked-into-chasm", npc_type),
+        PlayerEntersLevel(theme) => (
+            i18n::format(
+                "message.player-enters-level",
+                &[("theme", &i18n::theme_name(theme))],
+            ),
+            colors.palette(theme).wall_background,
+        ),
+        PlayerIsBlinded(npc_type) => (
+            i18n::format(
+                "message.player-is-blinded",
+                &[("npc", &i18n::npc_name(npc_type))],
+            ),
+            colors.danger,
+        ),
+        PlayerCanSeeAgain => plain("message.player-can-see-again"),
+        CannotSeeTarget => plain("message.cannot-see-target"),
+        ConfirmJumpIntoChasm => (
+            i18n::format("message.confirm-jump-into-chasm", &[]),
+            colors.chasm,
+        ),
+        PlayerStepsBackFromChasm => (
+            i18n::format("message.player-steps-back-from-chasm", &[]),
+            colors.chasm,
+        ),
+        PlayerIsBadlyWounded => (
+            i18n::format("message.player-is-badly-wounded", &[]),
+            colors.danger,
+        ),
+        StairsNotKnown => (i18n::format("message.stairs-not-known", &[]), colors.stairs),
+        NoKnownPath => plain("message.no-known-path"),
+        NoTravelTargets => plain("message.no-travel-targets"),
+        PlayerRests(turns) => (
+            i18n::format_count("message.player-rests", turns, &[("turns", &turns)]),
+            colors.healing,
+        ),
+        NoNeedToRest => plain("message.no-need-to-rest"),
+        CannotRestNearEnemies => (
+            i18n::format("message.cannot-rest-near-enemies", &[]),
+            colors.danger,
+        ),
+        PlayerFallsIntoChasm => (
+            i18n::format("message.player-falls-into-chasm", &[]),
+            colors.danger,
+        ),
+        PlayerSeesHere(floor_things) => {
+            let things = floor_things.iter().collect::<Vec<_>>();
+            let key = if things.len() == 1 && things[0].is_feature() {
+                "message.there-is-here"
+            } else {
+                "message.player-sees-here"
+            };
+            let descriptions = things
+                .into_iter()
+                .map(i18n::floor_thing_description)
+                .collect::<Vec<_>>();
+            let list = i18n::list(&descriptions, floor_things.more());
+            (i18n::format(key, &[("things", &list)]), category_color)
+        }
+        PlayerGetsRune(runes) => (
+            i18n::format(
+                "message.player-gets-rune",
+                &[("runes", &runes), ("total", &NUM_RUNES)],
+            ),
+            colors.rune,
+        ),
+        PortalOpens => (i18n::format("message.portal-opens", &[]), colors.portal),
+        PortalIsSealed(runes_needed) => (
+            i18n::format_count(
+                "message.portal-is-sealed",
+                runes_needed,
+                &[("runes", &runes_needed)],
+            ),
+            colors.portal,
+        ),
+        HeavyBreathing => (i18n::format("message.heavy-breathing", &[]), colors.danger),
+        LevelFeeling(feeling) => match feeling {
+            terrain::LevelFeeling::Danger => (
+                i18n::format("message.level-feels-dangerous", &[]),
+                colors.danger,
+            ),
+            terrain::LevelFeeling::Treasure => {
+                (i18n::format("message.level-feels-rich", &[]), colors.gold)
+            }
+            terrain::LevelFeeling::Quiet => plain("message.level-feels-quiet"),
+        },
+        PlayerFindsSecretDoor => (
+            i18n::format("message.player-finds-secret-door", &[]),
+            colors.door,
+        ),
+        FireballFizzles => (
+            i18n::format("message.fireball-fizzles", &[]),
+            colors.fireball_scroll,
+        ),
+        NpcBecomesHostile(npc_type) => npc("message.npc-becomes-hostile", npc_type),
+        Wizard(command) => match command {
+            WizardCommand::RevealMap => plain("message.wizard-reveal-map"),
+            WizardCommand::Heal => plain("message.wizard-heal"),
+            WizardCommand::SpawnNpc(npc_type) => npc("message.wizard-spawn-npc", npc_type),
+            WizardCommand::GrantItem(item_type) => item("message.wizard-grant-item", item_type),
+            WizardCommand::Descend => plain("message.wizard-descend"),
+            WizardCommand::ToggleOmniscience => plain("message.wizard-toggle-omniscience"),
+            WizardCommand::RegenerateLevel => plain("message.wizard-regenerate-level"),
+            WizardCommand::ToggleNpcDistances => plain("message.wizard-toggle-npc-distances"),
+        },
+    };
+    split_message(buf, &text);
+    buf[0].style.foreground = Some(category_color);
+    buf[1].style.bold = Some(true);
+    buf[1].style.foreground = Some(highlight_color);
+    buf[2].style.foreground = Some(category_color);
+    match message {
+        NpcKillsPlayer(_) => {
+            for part in buf.iter_mut() {
+                part.text = part.text.to_uppercase();
+            }
+            buf[0].style.foreground = Some(colors.danger);
+            buf[2].style.foreground = Some(colors.danger);
+        }
+        PlayerHeals | PrayerHeals => buf[0].style.foreground = Some(colors.healing),
+        PrayerIdentifiesInventory => buf[0].style.foreground = Some(colors.altar),
+        PrayerAngersGods => buf[0].style.foreground = Some(colors.danger),
+        LavaBurns(None) | NpcBecomesHostile(_) => buf[2].style.foreground = Some(colors.danger),
+        // the prefix is put in as it is, brackets and all
+        Wizard(_) => buf[0]
+            .text
+            .insert_str(0, i18n::text("message.wizard-prefix")),
+        _ => (),
+    }
+}
+
+// Shows up to NUM_MESSAGES entries from the start of the iterator
+impl<'a> View<(vec_deque::Iter<'a, LogEntry>, &'a ColorTheme)> for MessagesView {
     fn view<F: Frame, C: ColModify>(
         &mut self,
-        messages: &'a [LogMessage],
+        (messages, colors): (vec_deque::Iter<'a, LogEntry>, &'a ColorTheme),
         context: ViewContext<C>,
         frame: &mut F,
     ) {
-        fn format_message(buf: &mut [RichTextPartOwned], message: LogMessage) {
-            use std::fmt::Write;
-            use LogMessage::*;
-            buf[0].text.clear();
-            buf[1].text.clear();
-            buf[2].text.clear();
-            buf[0].style.foreground = Some(Rgb24::new_grey(255));
-            buf[1].style.bold = Some(true);
-            buf[2].style.foreground = Some(Rgb24::new_grey(255));
-            match message {
-                PlayerAttacksNpc(npc_type) => {
-                    write!(&mut buf[0].text, "You attack the ").unwrap();
-                    write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colors::npc_color(npc_type));
-                    write!(&mut buf[2].text, ".").unwrap();
-                }
-                NpcAttacksPlayer(npc_type) => {
-                    write!(&mut buf[0].text, "The ").unwrap();
-                    write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colors::npc_color(npc_type));
-                    write!(&mut buf[2].text, " attacks you.").unwrap();
-                }
-                PlayerKillsNpc(npc_type) => {
-                    write!(&mut buf[0].text, "You kill the ").unwrap();
-                    write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colors::npc_color(npc_type));
-                    write!(&mut buf[2].text, ".").unwrap();
-                }
-                NpcKillsPlayer(npc_type) => {
-                    write!(&mut buf[0].text, "THE ").unwrap();
-                    buf[0].style.foreground = Some(Rgb24::new(255, 0, 0));
-                    write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
-                    buf[1].text.make_ascii_uppercase();
-                    buf[1].style.foreground = Some(colors::npc_color(npc_type));
-                    write!(&mut buf[2].text, " KILLS YOU.").unwrap();
-                    buf[2].style.foreground = Some(Rgb24::new(255, 0, 0));
-                }
-                PlayerGets(item_type) => {
-                    write!(&mut buf[0].text, "You get the ").unwrap();
-                    write!(&mut buf[1].text, "{}", item_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colors::item_color(item_type));
-                    write!(&mut buf[2].text, ".").unwrap();
-                }
-                PlayerInventoryIsFull => {
-                    write!(&mut buf[0].text, "Inventory is full!").unwrap();
-                }
-                NoItemUnderPlayer => {
-                    write!(&mut buf[0].text, "Nothing to get!").unwrap();
-                }
-                NoItemInInventorySlot => {
-                    write!(&mut buf[0].text, "No item in inventory slot!").unwrap();
-                }
-                PlayerHeals => {
-                    write!(&mut buf[0].text, "You feel slightly better.").unwrap();
-                    buf[0].style.foreground = Some(Rgb24::new(0, 187, 0));
-                }
-                PlayerDrops(item_type) => {
-                    write!(&mut buf[0].text, "You drop the ").unwrap();
-                    write!(&mut buf[1].text, "{}", item_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colors::item_color(item_type));
-                    write!(&mut buf[2].text, ".").unwrap();
-                }
-                NoSpaceToDropItem => {
-                    write!(&mut buf[0].text, "No space to drop item!").unwrap();
-                }
-                PlayerLaunchesProjectile(projectile) => {
-                    write!(&mut buf[0].text, "You launch a ").unwrap();
-                    write!(&mut buf[1].text, "{}", projectile.name()).unwrap();
-                    buf[1].style.foreground = Some(colors::projectile_color(projectile));
-                    write!(&mut buf[2].text, "!").unwrap();
-                }
-                NpcDies(npc_type) => {
-                    write!(&mut buf[0].text, "The ").unwrap();
-                    write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colors::npc_color(npc_type));
-                    write!(&mut buf[2].text, " dies.").unwrap();
-                }
-                NpcBecomesConfused(npc_type) => {
-                    write!(&mut buf[0].text, "The ").unwrap();
-                    write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colors::npc_color(npc_type));
-                    write!(&mut buf[2].text, " is confused.").unwrap();
-                }
-                NpcIsNoLongerConfused(npc_type) => {
-                    write!(&mut buf[0].text, "The ").unwrap();
-                    write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colors::npc_color(npc_type));
-                    write!(&mut buf[2].text, "'s confusion passes.").unwrap();
-                }
-                PlayerDodges(npc_type) => {
-                    write!(&mut buf[0].text, "You dodge the ").unwrap();
-                    write!(&mut buf[1].text, "{}'s", npc_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colors::npc_color(npc_type));
-                    write!(&mut buf[2].text, " attack.").unwrap();
-                }
-                NpcDodges(npc_type) => {
-                    write!(&mut buf[0].text, "The ").unwrap();
-                    write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colors::npc_color(npc_type));
-                    write!(&mut buf[2].text, " dodges your attack.").unwrap();
-                }
-                PlayerEquips(item_type) => {
-                    write!(&mut buf[0].text, "You equip the ").unwrap();
-                    write!(&mut buf[1].text, "{}", item_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colors::item_color(item_type));
-                    write!(&mut buf[2].text, ".").unwrap();
-                }
+        for (i, &entry) in messages.take(NUM_MESSAGES).enumerate() {
+            format_message(&mut self.buf, entry.message, colors);
+            if entry.count > 1 {
+                let repeated = i18n::format("message.repeated", &[("count", &entry.count)]);
+                self.buf[2].text.push_str(&repeated);
             }
-        }
-
-        const NUM_MESSAGES: usize = 4;
-        let start_index = messages.len().saturating_sub(NUM_MESSAGES);
-        for (i, &message) in (&messages[start_index..]).iter().enumerate() {
-            format_message(&mut self.buf, message);
             let offset = Coord::new(0, i as i32);
             RichTextViewSingleLine.view(
                 self.buf.iter().map(|part| part.as_rich_text_part()),
@@ -294,11 +784,31 @@ impl<'a> View<&'a [LogMessage]> for MessagesView {
     }
 }
 
+fn health_word(hit_points: HitPoints) -> &'static str {
+    i18n::text(if hit_points.is_full() {
+        "examine.unharmed"
+    } else if hit_points.current() * 3 > hit_points.max() {
+        "examine.wounded"
+    } else {
+        "examine.near-death"
+    })
+}
+
 fn examine_cell_str(examine_cell: ExamineCell) -> &'static str {
     match examine_cell {
-        ExamineCell::Npc(npc_type) | ExamineCell::NpcCorpse(npc_type) => npc_type.name(),
-        ExamineCell::Item(item_type) => item_type.name(),
-        ExamineCell::Player => "yourself",
+        ExamineCell::Npc { npc_type, .. } | ExamineCell::NpcCorpse(npc_type) => {
+            i18n::npc_name(npc_type)
+        }
+        ExamineCell::Item(item_type) => i18n::item_name(item_type),
+        ExamineCell::Gold => i18n::text("examine.gold"),
+        ExamineCell::Altar => i18n::text("examine.altar"),
+        ExamineCell::Trap(trap_type) => i18n::trap_name(trap_type),
+        ExamineCell::Liquid(liquid) => i18n::liquid_name(liquid),
+        ExamineCell::Chasm => i18n::text("examine.chasm"),
+        ExamineCell::Rune => i18n::text("examine.rune"),
+        ExamineCell::Portal => i18n::text("examine.portal"),
+        ExamineCell::Fog => i18n::text("examine.fog"),
+        ExamineCell::Player => i18n::text("examine.player"),
     }
 }
 
@@ -311,6 +821,10 @@ pub struct StatsData {
     pub strength: i32,
     pub dexterity: i32,
     pub intelligence: i32,
+    pub gold: u32,
+    pub debt: u32,
+    // only shown on the final level
+    pub runes: Option<u32>,
 }
 
 impl<'a> View<&'a StatsData> for StatsView {
@@ -320,35 +834,751 @@ impl<'a> View<&'a StatsData> for StatsView {
         context: ViewContext<C>,
         frame: &mut F,
     ) {
-        use std::fmt::Write;
-        self.buf.clear();
-        write!(
-            &mut self.buf,
-            "str: {} dex: {} int: {}",
-            data.strength, data.dexterity, data.intelligence
-        ).unwrap();
+        self.buf = i18n::format(
+            "ui.stats",
+            &[
+                ("str", &data.strength),
+                ("dex", &data.dexterity),
+                ("int", &data.intelligence),
+                ("gold", &data.gold),
+            ],
+        );
+        if data.debt > 0 {
+            self.buf
+                .push_str(&i18n::format("ui.debt", &[("debt", &data.debt)]));
+        }
+        if let Some(runes) = data.runes {
+            self.buf.push_str(&i18n::format(
+                "ui.runes",
+                &[("runes", &runes), ("total", &NUM_RUNES)],
+            ));
+        }
         StringViewSingleLine::new(Style::new().with_foreground(Rgb24::new_grey(187)))
             .view(&self.buf, context, frame);
     }
 }
 
+pub struct StatusData {
+    pub depth: u32,
+    pub turn: u64,
+    pub player_coord: Coord,
+    // in order of danger, with the turns each has left
+    pub status_effects: Vec<(StatusEffect, u32)>,
+}
 
 #[derive(Default)]
-struct DungeonLevelView {
-    buf: String,
+struct StatusView {
+    parts: Vec<RichTextPartOwned>,
 }
 
-impl View<u32> for DungeonLevelView {
+fn status_effect_color(status_effect: StatusEffect, colors: &ColorTheme) -> Rgb24 {
+    match status_effect {
+        StatusEffect::Blind => colors.log_harm,
+        StatusEffect::Confused => colors.confusion_scroll,
+        StatusEffect::Clairvoyant => colors.sensed,
+    }
+}
+
+// How many of the status tags with the given lengths fit in `width`
+// columns, separated by spaces. The tags come most dangerous first, so
+// they are dropped from the end, leaving room for a "+n" counting them.
+fn num_status_tags_that_fit(tag_lens: &[usize], width: usize) -> usize {
+    (0..=tag_lens.len())
+        .rev()
+        .find(|&num_shown| {
+            let num_hidden = tag_lens.len() - num_shown;
+            let hidden_len = if num_hidden > 0 {
+                format!("+{}", num_hidden).len() + 1
+            } else {
+                0
+            };
+            let shown_len = tag_lens[..num_shown]
+                .iter()
+                .map(|len| len + 1)
+                .sum::<usize>();
+            shown_len + hidden_len <= width + 1
+        })
+        .unwrap_or(0)
+}
+
+impl<'a> View<(&'a StatusData, &'a ColorTheme)> for StatusView {
     fn view<F: Frame, C: ColModify>(
         &mut self,
-        dungeon_level: u32,
+        (data, colors): (&'a StatusData, &'a ColorTheme),
         context: ViewContext<C>,
         frame: &mut F,
     ) {
-        use std::fmt::Write;
-        self.buf.clear();
-        write!(&mut self.buf, "Level: {}", dungeon_level).unwrap();
-        StringViewSingleLine::new(Style::new().with_foreground(Rgb24::new_grey(187)))
-            .view(&self.buf, context, frame);
+        let mut statuses = data
+            .status_effects
+            .iter()
+            .map(|&(status_effect, turns)| {
+                (
+                    format!("{}:{}", i18n::status_tag(status_effect), turns),
+                    status_effect_color(status_effect, colors),
+                )
+            })
+            .collect::<Vec<_>>();
+        let tag_lens = statuses
+            .iter()
+            .map(|(tag, _)| tag.len())
+            .collect::<Vec<_>>();
+        let num_shown = num_status_tags_that_fit(&tag_lens, RIGHT_PANEL_WIDTH as usize);
+        let num_hidden = statuses.len() - num_shown;
+        statuses.truncate(num_shown);
+        if num_hidden > 0 {
+            statuses.push((format!("+{}", num_hidden), colors.log_harm));
+        }
+        let mut info = vec![
+            i18n::format("ui.depth", &[("depth", &data.depth)]),
+            i18n::format("ui.turn", &[("turn", &data.turn)]),
+            format!("({},{})", data.player_coord.x, data.player_coord.y),
+        ];
+        // statuses are always shown, and the other fields are dropped from
+        // the end until everything fits
+        let statuses_len = statuses.iter().map(|(tag, _)| tag.len() + 1).sum::<usize>();
+        let info_len = |info: &[String]| info.iter().map(|s| s.len() + 1).sum::<usize>();
+        while !info.is_empty() && info_len(&info) + statuses_len > RIGHT_PANEL_WIDTH as usize + 1 {
+            info.pop();
+        }
+        self.parts.clear();
+        let info_style = Style::new().with_foreground(Rgb24::new_grey(187));
+        let mut text = info.join(" ");
+        for (tag, color) in statuses {
+            if !text.is_empty() || !self.parts.is_empty() {
+                text.push(' ');
+            }
+            self.parts.push(RichTextPartOwned::new(text, info_style));
+            text = String::new();
+            self.parts.push(RichTextPartOwned::new(
+                tag,
+                Style::new().with_bold(true).with_foreground(color),
+            ));
+        }
+        self.parts.push(RichTextPartOwned::new(text, info_style));
+        RichTextViewSingleLine.view(
+            self.parts.iter().map(|part| part.as_rich_text_part()),
+            context,
+            frame,
+        );
+    }
+}
+
+pub struct EquipmentData {
+    pub main_hand: Option<ItemType>,
+    pub off_hand: Option<ItemType>,
+    pub body: Option<ItemType>,
+    // the least and most damage dealt and blocked per hit
+    pub attack: (i32, i32),
+    pub defense: (i32, i32),
+}
+
+#[derive(Default)]
+struct EquipmentView {
+    parts: Vec<RichTextPartOwned>,
+}
+
+impl<'a> View<(&'a EquipmentData, &'a ColorTheme)> for EquipmentView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        (data, colors): (&'a EquipmentData, &'a ColorTheme),
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        let label_style = Style::new().with_foreground(Rgb24::new_grey(187));
+        let item_part = |item_type: Option<ItemType>| match item_type {
+            Some(item_type) => RichTextPartOwned::new(
+                i18n::item_name(item_type).to_string(),
+                Style::new()
+                    .with_bold(true)
+                    .with_foreground(colors.item_color(item_type)),
+            ),
+            None => RichTextPartOwned::new("-".to_string(), label_style),
+        };
+        self.parts.clear();
+        self.parts.push(RichTextPartOwned::new(
+            i18n::text("ui.wield").to_string(),
+            label_style,
+        ));
+        self.parts.push(item_part(data.main_hand));
+        if data.off_hand.is_some() {
+            self.parts.push(RichTextPartOwned::new(
+                i18n::text("ui.and").to_string(),
+                label_style,
+            ));
+            self.parts.push(item_part(data.off_hand));
+        }
+        self.parts.push(RichTextPartOwned::new(
+            i18n::text("ui.wear").to_string(),
+            label_style,
+        ));
+        self.parts.push(item_part(data.body));
+        self.parts.push(RichTextPartOwned::new(
+            i18n::format(
+                "ui.attack-defense",
+                &[
+                    ("attack_min", &data.attack.0),
+                    ("attack_max", &data.attack.1),
+                    ("defense_min", &data.defense.0),
+                    ("defense_max", &data.defense.1),
+                ],
+            ),
+            label_style,
+        ));
+        RichTextViewSingleLine.view(
+            self.parts.iter().map(|part| part.as_rich_text_part()),
+            context,
+            frame,
+        );
+    }
+}
+
+// The items bound to each quick slot, by the slot's number
+#[derive(Default)]
+struct QuickbarView {
+    parts: Vec<RichTextPartOwned>,
+}
+
+impl<'a> View<(&'a [Option<ItemType>; NUM_QUICK_SLOTS], &'a ColorTheme)> for QuickbarView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        (quick_slots, colors): (&'a [Option<ItemType>; NUM_QUICK_SLOTS], &'a ColorTheme),
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        let label_style = Style::new().with_foreground(Rgb24::new_grey(187));
+        self.parts.clear();
+        for (i, &item_type) in quick_slots.iter().enumerate() {
+            let separator = if i == 0 { "" } else { " " };
+            self.parts.push(RichTextPartOwned::new(
+                format!("{}{}:", separator, i + 1),
+                label_style,
+            ));
+            self.parts.push(match item_type {
+                Some(item_type) => RichTextPartOwned::new(
+                    i18n::item_name(item_type).to_string(),
+                    Style::new().with_foreground(colors.item_color(item_type)),
+                ),
+                None => RichTextPartOwned::new("-".to_string(), label_style),
+            });
+        }
+        RichTextViewSingleLine.view(
+            self.parts.iter().map(|part| part.as_rich_text_part()),
+            context,
+            frame,
+        );
+    }
+}
+
+pub struct RunStatsData<'a> {
+    pub title: &'a str,
+    pub title_color: Rgb24,
+    pub run_stats: &'a RunStats,
+    // the date, if the run is a daily challenge
+    pub daily_date: Option<&'a str>,
+    pub difficulty: Difficulty,
+    pub colors: &'a ColorTheme,
+}
+
+// The run's statistics under a title, with a line for each type of NPC
+// the player has killed
+#[derive(Default)]
+pub struct RunStatsView {
+    lines: Vec<(String, Style)>,
+}
+
+impl<'a> View<RunStatsData<'a>> for RunStatsView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        data: RunStatsData<'a>,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        let run_stats = data.run_stats;
+        let text_style = Style::new().with_foreground(Rgb24::new_grey(187));
+        self.lines.clear();
+        self.lines.push((
+            data.title.to_string(),
+            Style::new()
+                .with_bold(true)
+                .with_foreground(data.title_color),
+        ));
+        if let Some(daily_date) = data.daily_date {
+            self.lines.push((
+                i18n::format("run-stats.daily", &[("date", &daily_date)]),
+                text_style,
+            ));
+        }
+        let difficulty = i18n::difficulty_name(data.difficulty);
+        self.lines.push((
+            i18n::format("run-stats.difficulty", &[("difficulty", &difficulty)]),
+            text_style,
+        ));
+        self.lines.push((String::new(), text_style));
+        self.lines.push((
+            i18n::format("run-stats.kills", &[("kills", &run_stats.total_kills())]),
+            text_style,
+        ));
+        for &(npc_type, count) in run_stats.kills() {
+            self.lines.push((
+                format!("  {} x{}", i18n::npc_name(npc_type), count),
+                Style::new().with_foreground(data.colors.npc_color(npc_type)),
+            ));
+        }
+        let counts: [(&str, &str, u32); 5] = [
+            ("run-stats.damage-dealt", "damage", run_stats.damage_dealt),
+            ("run-stats.damage-taken", "damage", run_stats.damage_taken),
+            (
+                "run-stats.potions-drunk",
+                "potions",
+                run_stats.potions_drunk,
+            ),
+            ("run-stats.scrolls-read", "scrolls", run_stats.scrolls_read),
+            (
+                "run-stats.items-picked-up",
+                "items",
+                run_stats.items_picked_up,
+            ),
+        ];
+        for (key, name, count) in counts {
+            self.lines
+                .push((i18n::format(key, &[(name, &count)]), text_style));
+        }
+        for (i, (line, style)) in self.lines.iter().enumerate() {
+            StringViewSingleLine::new(*style).view(
+                line,
+                context.add_offset(Coord::new(0, i as i32)),
+                frame,
+            );
+        }
+    }
+}
+
+pub struct CharacterCreationData<'a> {
+    pub name: &'a str,
+    pub background: Background,
+}
+
+// The name being typed and the background chosen for a new character,
+// with what the background starts with
+#[derive(Default)]
+pub struct CharacterCreationView {
+    lines: Vec<(String, Style)>,
+}
+
+impl<'a> View<CharacterCreationData<'a>> for CharacterCreationView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        data: CharacterCreationData<'a>,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        let text_style = Style::new().with_foreground(Rgb24::new_grey(187));
+        let chosen_style = Style::new()
+            .with_bold(true)
+            .with_foreground(Rgb24::new_grey(255));
+        self.lines.clear();
+        self.lines.push((
+            i18n::text("character.title").to_string(),
+            Style::new()
+                .with_bold(true)
+                .with_foreground(Rgb24::new_grey(255)),
+        ));
+        self.lines.push((String::new(), text_style));
+        // the underscore stands in for a text cursor
+        let name = format!("{}_", data.name);
+        self.lines.push((
+            i18n::format("character.name", &[("name", &name)]),
+            chosen_style,
+        ));
+        let background = i18n::background_name(data.background);
+        self.lines.push((
+            i18n::format("character.background", &[("background", &background)]),
+            chosen_style,
+        ));
+        self.lines.push((
+            i18n::background_description(data.background).to_string(),
+            text_style,
+        ));
+        self.lines.push((String::new(), text_style));
+        self.lines
+            .push((i18n::text("character.help").to_string(), text_style));
+        for (i, (line, style)) in self.lines.iter().enumerate() {
+            StringViewSingleLine::new(*style).view(
+                line,
+                context.add_offset(Coord::new(0, i as i32)),
+                frame,
+            );
+        }
+    }
+}
+
+pub struct HighScoresData<'a> {
+    pub high_scores: &'a HighScores,
+    pub order: HighScoreOrder,
+    // entries past this many aren't shown
+    pub max_entries: usize,
+}
+
+// The best runs in a table, one per line. Daily challenge runs are marked
+// with a D, runs where the player cheated with a C, and easy and hard runs
+// with an E and an H.
+#[derive(Default)]
+pub struct HighScoresView {
+    lines: Vec<(String, Style)>,
+}
+
+impl<'a> View<HighScoresData<'a>> for HighScoresView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        data: HighScoresData<'a>,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        let text_style = Style::new().with_foreground(Rgb24::new_grey(187));
+        let latest_style = Style::new()
+            .with_bold(true)
+            .with_foreground(Rgb24::new(255, 255, 0));
+        self.lines.clear();
+        self.lines.push((
+            i18n::format("high-scores.title", &[("order", &data.order.name())]),
+            Style::new()
+                .with_bold(true)
+                .with_foreground(Rgb24::new_grey(255)),
+        ));
+        self.lines.push((String::new(), text_style));
+        let entries = data.high_scores.sorted(data.order);
+        if entries.is_empty() {
+            self.lines
+                .push((i18n::text("high-scores.empty").to_string(), text_style));
+        } else {
+            self.lines.push((
+                format!(
+                    "{:>3} {:>5} {:>3} {:<10} {}",
+                    i18n::text("high-scores.rank"),
+                    i18n::text("high-scores.score"),
+                    i18n::text("high-scores.depth"),
+                    i18n::text("high-scores.date"),
+                    i18n::text("high-scores.outcome"),
+                ),
+                text_style,
+            ));
+        }
+        for (i, high_score) in entries.into_iter().take(data.max_entries).enumerate() {
+            let tags = format!(
+                "{}{}{}",
+                if high_score.daily { "D" } else { "" },
+                if high_score.cheated { "C" } else { "" },
+                match high_score.difficulty {
+                    Difficulty::Easy => "E",
+                    Difficulty::Normal => "",
+                    Difficulty::Hard => "H",
+                },
+            );
+            self.lines.push((
+                format!(
+                    "{:>3} {:>5} {:>3} {:<10} {} {} {}",
+                    i + 1,
+                    high_score.score,
+                    high_score.dungeon_level,
+                    high_score
+                        .date
+                        .as_deref()
+                        .unwrap_or(i18n::text("high-scores.unknown-date")),
+                    high_score.outcome(),
+                    tags,
+                    high_score.name,
+                ),
+                if data.high_scores.is_latest(high_score) {
+                    latest_style
+                } else {
+                    text_style
+                },
+            ));
+        }
+        self.lines.push((String::new(), text_style));
+        self.lines
+            .push((i18n::text("high-scores.help").to_string(), text_style));
+        for (i, (line, style)) in self.lines.iter().enumerate() {
+            StringViewSingleLine::new(*style).view(
+                line,
+                context.add_offset(Coord::new(0, i as i32)),
+                frame,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::colors::CLASSIC;
+    use crate::terrain::LevelTheme;
+    use crate::world::{FloorThing, FloorThings, ProjectileType, TrapType};
+
+    const TRAP_TYPES: [TrapType; 3] = [TrapType::Spike, TrapType::Teleport, TrapType::Alarm];
+
+    // Every variant with a field is listed with every value that picks
+    // different text
+    fn every_message() -> Vec<LogMessage> {
+        use LogMessage::*;
+        let mut messages = vec![
+            PlayerInventoryIsFull,
+            PlayerPutsOnBackpack(8),
+            InventoryCannotGrow,
+            ScreenshotSaved,
+            ScreenshotFailed,
+            NoItemUnderPlayer,
+            NoItemInInventorySlot,
+            PlayerHeals,
+            PlayerReadsClairvoyance,
+            TorchGoesOut,
+            PlayerThrowsSmokeBomb,
+            TorchIsAlreadyLit,
+            PlayerClairvoyanceEnds,
+            NoSpaceToDropItem,
+            PlayerLaunchesProjectile(ProjectileType::Fireball { damage: 5 }),
+            PlayerLaunchesProjectile(ProjectileType::Confusion { duration: 5 }),
+            PlayerGetsGold(10),
+            PlayerOwes(10),
+            PlayerPays(10),
+            CannotAffordDebt(10),
+            NothingToPayFor,
+            NoShopkeeperNearby,
+            PlayerSwingsAtNothing,
+            NothingBoundToQuickSlot(1),
+            PrayerHeals,
+            PrayerIdentifiesInventory,
+            PrayerAngersGods,
+            PlayerClosesDoor,
+            NoDoorToClose,
+            DoorIsBlocked,
+            LavaBurns(None),
+            SlamsIntoWall(None),
+            KnockedIntoChasm(None),
+            PlayerFindsSecretDoor,
+            FireballFizzles,
+            HeavyBreathing,
+            LevelFeeling(terrain::LevelFeeling::Danger),
+            LevelFeeling(terrain::LevelFeeling::Treasure),
+            LevelFeeling(terrain::LevelFeeling::Quiet),
+            PlayerGetsRune(1),
+            PortalOpens,
+            PortalIsSealed(1),
+            PortalIsSealed(2),
+            PlayerCanSeeAgain,
+            PlayerIsBadlyWounded,
+            StairsNotKnown,
+            NoKnownPath,
+            NoTravelTargets,
+            PlayerRests(1),
+            PlayerRests(2),
+            NoNeedToRest,
+            CannotRestNearEnemies,
+            CannotSeeTarget,
+            ConfirmJumpIntoChasm,
+            PlayerStepsBackFromChasm,
+            PlayerFallsIntoChasm,
+            Wizard(WizardCommand::RevealMap),
+            Wizard(WizardCommand::Heal),
+            Wizard(WizardCommand::Descend),
+            Wizard(WizardCommand::ToggleOmniscience),
+            Wizard(WizardCommand::RegenerateLevel),
+            Wizard(WizardCommand::ToggleNpcDistances),
+        ];
+        for npc_type in NpcType::ALL {
+            messages.extend([
+                PlayerAttacksNpc(npc_type),
+                NpcAttacksPlayer(npc_type),
+                PlayerKillsNpc(npc_type),
+                NpcKillsPlayer(npc_type),
+                NpcDies(npc_type),
+                NpcBecomesConfused(npc_type),
+                NpcIsNoLongerConfused(npc_type),
+                PlayerDodges(npc_type),
+                NpcDodges(npc_type),
+                PlayerSwapsWith(npc_type),
+                NpcBecomesHostile(npc_type),
+                LavaBurns(Some(npc_type)),
+                SlamsIntoWall(Some(npc_type)),
+                SlamsInto(None, npc_type),
+                SlamsInto(Some(npc_type), NpcType::Orc),
+                KnockedIntoChasm(Some(npc_type)),
+                PlayerIsBlinded(npc_type),
+                Wizard(WizardCommand::SpawnNpc(npc_type)),
+            ]);
+            for trap_type in TRAP_TYPES {
+                messages.push(TrapTriggered(Some(npc_type), trap_type));
+            }
+        }
+        for item_type in ItemType::ALL {
+            messages.extend([
+                PlayerGets(item_type),
+                PlayerDrops(item_type),
+                PlayerEquips(item_type),
+                PlayerUnequips(item_type),
+                CannotWield(item_type),
+                CannotWear(item_type),
+                ItemIsAlreadyEquipped(item_type),
+                ItemIsNotEquipped(item_type),
+                PlayerAssignsQuickSlot(1, item_type),
+                Wizard(WizardCommand::GrantItem(item_type)),
+            ]);
+        }
+        for trap_type in TRAP_TYPES {
+            messages.extend([TrapTriggered(None, trap_type), PlayerNoticesTrap(trap_type)]);
+        }
+        for level_up in LevelUp::ALL {
+            messages.push(PrayerIncreasesStat(level_up));
+        }
+        for theme in [
+            LevelTheme::Dungeon,
+            LevelTheme::Caves,
+            LevelTheme::Crypt,
+            LevelTheme::Lair,
+        ] {
+            messages.push(PlayerEntersLevel(theme));
+        }
+        let floor_things = [
+            FloorThing::Stairs,
+            FloorThing::StairsUp,
+            FloorThing::Altar,
+            FloorThing::Portal,
+            FloorThing::Item(ItemType::Sword),
+            FloorThing::Gold,
+            FloorThing::Corpse(NpcType::Orc),
+            FloorThing::Rune,
+        ];
+        for &floor_thing in &floor_things {
+            messages.push(PlayerSeesHere(FloorThings::new(&[floor_thing]).unwrap()));
+        }
+        // more than fit in one message
+        messages.push(PlayerSeesHere(FloorThings::new(&floor_things).unwrap()));
+        messages
+    }
+
+    // A new variant fails to compile here until it's given the next
+    // number, and then fails the test until it's added to every_message
+    const NUM_VARIANTS: usize = 79;
+
+    fn variant_index(message: LogMessage) -> usize {
+        use LogMessage::*;
+        match message {
+            PlayerAttacksNpc(_) => 0,
+            NpcAttacksPlayer(_) => 1,
+            PlayerKillsNpc(_) => 2,
+            NpcKillsPlayer(_) => 3,
+            PlayerGets(_) => 4,
+            PlayerInventoryIsFull => 5,
+            PlayerPutsOnBackpack(_) => 6,
+            InventoryCannotGrow => 7,
+            ScreenshotSaved => 8,
+            ScreenshotFailed => 9,
+            NoItemUnderPlayer => 10,
+            NoItemInInventorySlot => 11,
+            PlayerHeals => 12,
+            PlayerReadsClairvoyance => 13,
+            TorchGoesOut => 14,
+            PlayerThrowsSmokeBomb => 15,
+            TorchIsAlreadyLit => 16,
+            PlayerClairvoyanceEnds => 17,
+            PlayerDrops(_) => 18,
+            NoSpaceToDropItem => 19,
+            PlayerLaunchesProjectile(_) => 20,
+            NpcDies(_) => 21,
+            NpcBecomesConfused(_) => 22,
+            NpcIsNoLongerConfused(_) => 23,
+            PlayerDodges(_) => 24,
+            NpcDodges(_) => 25,
+            PlayerEquips(_) => 26,
+            PlayerUnequips(_) => 27,
+            CannotWield(_) => 28,
+            CannotWear(_) => 29,
+            ItemIsAlreadyEquipped(_) => 30,
+            ItemIsNotEquipped(_) => 31,
+            PlayerGetsGold(_) => 32,
+            PlayerOwes(_) => 33,
+            PlayerPays(_) => 34,
+            CannotAffordDebt(_) => 35,
+            NothingToPayFor => 36,
+            NoShopkeeperNearby => 37,
+            PlayerSwapsWith(_) => 38,
+            PlayerSwingsAtNothing => 39,
+            PlayerAssignsQuickSlot(..) => 40,
+            NothingBoundToQuickSlot(_) => 41,
+            NpcBecomesHostile(_) => 42,
+            PrayerHeals => 43,
+            PrayerIncreasesStat(_) => 44,
+            PrayerIdentifiesInventory => 45,
+            PrayerAngersGods => 46,
+            PlayerClosesDoor => 47,
+            NoDoorToClose => 48,
+            DoorIsBlocked => 49,
+            TrapTriggered(..) => 50,
+            PlayerNoticesTrap(_) => 51,
+            LavaBurns(_) => 52,
+            SlamsIntoWall(_) => 53,
+            SlamsInto(..) => 54,
+            KnockedIntoChasm(_) => 55,
+            PlayerFindsSecretDoor => 56,
+            PlayerEntersLevel(_) => 57,
+            FireballFizzles => 58,
+            HeavyBreathing => 59,
+            LevelFeeling(_) => 60,
+            PlayerGetsRune(_) => 61,
+            PortalOpens => 62,
+            PortalIsSealed(_) => 63,
+            PlayerIsBlinded(_) => 64,
+            PlayerCanSeeAgain => 65,
+            PlayerIsBadlyWounded => 66,
+            StairsNotKnown => 67,
+            NoKnownPath => 68,
+            NoTravelTargets => 69,
+            PlayerRests(_) => 70,
+            NoNeedToRest => 71,
+            CannotRestNearEnemies => 72,
+            CannotSeeTarget => 73,
+            ConfirmJumpIntoChasm => 74,
+            PlayerStepsBackFromChasm => 75,
+            PlayerFallsIntoChasm => 76,
+            PlayerSeesHere(_) => 77,
+            Wizard(_) => 78,
+        }
+    }
+
+    #[test]
+    fn every_message_renders_in_english() {
+        let mut buf = MessagesView::default().buf;
+        let mut listed = [false; NUM_VARIANTS];
+        for message in every_message() {
+            listed[variant_index(message)] = true;
+            format_message(&mut buf, message, &CLASSIC);
+            let text = buf
+                .iter()
+                .map(|part| part.text.as_str())
+                .collect::<String>();
+            assert!(!text.trim().is_empty(), "{:?} has no text", message);
+            assert!(
+                !text.contains(i18n::MISSING_TEXT),
+                "{:?} is missing text: {}",
+                message,
+                text
+            );
+            assert!(
+                !text.contains('{'),
+                "{:?} has an argument left in: {}",
+                message,
+                text
+            );
+        }
+        for (index, listed) in listed.iter().enumerate() {
+            assert!(*listed, "variant {} isn't in every_message", index);
+        }
+    }
+
+    #[test]
+    fn repeated_messages_render_their_count() {
+        let repeated = i18n::format("message.repeated", &[("count", &3)]);
+        assert!(repeated.contains('3'));
+        assert!(!repeated.contains(i18n::MISSING_TEXT));
     }
 }
@@ -1,12 +1,19 @@
-use crate::world::World;
+use crate::world::{Layer, Tile, World};
 use coord_2d::{Coord, Size};
 use grid_2d::Grid;
-use serde::{Serialize, Deserialize};
+use serde::{Deserialize, Serialize};
 
+// Brightness of a visible cell indexed by its distance from the player in
+// tenths of the sight radius, so things towards the edge of vision are
+// drawn dimmer
+const DISTANCE_FALLOFF: [u8; 11] = [255, 255, 255, 255, 255, 230, 204, 179, 153, 128, 102];
 
-const VISION_DISTANCE_SQUARED: u32 = 100;
-const VISION_DISTANCE: shadowcast::vision_distance::Circle =
-    shadowcast::vision_distance::Circle::new_squared(VISION_DISTANCE_SQUARED);
+fn distance_brightness(player_coord: Coord, coord: Coord, sight_radius: u32) -> u8 {
+    let distance = ((coord - player_coord).magnitude2() as f64).sqrt();
+    let max_index = DISTANCE_FALLOFF.len() - 1;
+    let index = (distance * max_index as f64 / sight_radius.max(1) as f64).round() as usize;
+    DISTANCE_FALLOFF[index.min(max_index)]
+}
 
 struct Visibility;
 
@@ -23,16 +30,41 @@ impl shadowcast::InputGrid for Visibility {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum VisibilityAlgorithm {
     Shadowcast,
     Omniscient,
 }
 
+// Everything which decides which cells an update finds visible and how
+// they're lit. While it stays the same, only what's in the cells needs
+// looking at again.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct ViewKey {
+    player_coord: Coord,
+    player_light_radius: u32,
+    sight_radius: u32,
+    omniscient: bool,
+    vision_changes: u64,
+}
+
+// The number of updates on this thread which worked out what's visible
+// rather than reusing the last answer, for tests of when that's skipped
+#[cfg(test)]
+thread_local! {
+    pub(crate) static NUM_FULL_UPDATES: std::cell::Cell<usize> = std::cell::Cell::new(0);
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct VisibilityGrid {
     grid: Grid<VisibilityCell>,
     count: u64,
+    // the cells found visible by the last full update, and what it
+    // depended on. A loaded game starts with a full update.
+    #[serde(skip)]
+    visible: Vec<Coord>,
+    #[serde(skip)]
+    last_view: Option<ViewKey>,
 }
 
 impl VisibilityGrid {
@@ -40,13 +72,8 @@ impl VisibilityGrid {
         Self {
             grid: Grid::new_default(size),
             count: 1,
-        }
-    }
-
-    pub fn clear(&mut self) {
-        self.count = 1;
-        for cell in self.grid.iter_mut() {
-            *cell = Default::default();
+            visible: Vec::new(),
+            last_view: None,
         }
     }
 
@@ -64,50 +91,250 @@ impl VisibilityGrid {
         }
     }
 
+    // The tiles the player last saw in each cell which isn't currently
+    // visible. These may no longer match what's really there.
+    pub fn remembered_tiles<'a>(&'a self) -> impl 'a + Iterator<Item = (Coord, Layer, Tile)> {
+        let count = self.count;
+        self.grid
+            .enumerate()
+            .filter(move |(_, cell)| cell.last_seen != count && cell.last_seen != 0)
+            .flat_map(|(coord, cell)| cell_remembered_tiles(coord, cell))
+    }
+
+    // The tiles the player last saw in every cell they have ever seen,
+    // including those they can see now
+    pub fn known_tiles<'a>(&'a self) -> impl 'a + Iterator<Item = (Coord, Layer, Tile)> {
+        self.grid
+            .enumerate()
+            .filter(|(_, cell)| cell.last_seen != 0)
+            .flat_map(|(coord, cell)| cell_remembered_tiles(coord, cell))
+    }
+
+    // Where the player remembers seeing the stairs down, if they've ever
+    // seen them
+    pub fn known_stairs_coord(&self) -> Option<Coord> {
+        self.grid
+            .enumerate()
+            .find(|(_, cell)| matches!(cell.remembered.floor, Some(Tile::Stairs)))
+            .map(|(coord, _)| coord)
+    }
+
+    // How brightly lit a currently visible cell is, from 0 (no light
+    // reaches it) to 255
+    pub fn light(&self, coord: Coord) -> u8 {
+        self.grid
+            .get(coord)
+            .map(|cell| {
+                if cell.last_lit == self.count {
+                    cell.light
+                } else {
+                    0
+                }
+            })
+            .unwrap_or(0)
+    }
+
+    // How bright a currently visible cell appears based on its distance
+    // from the player, from 0 to 255
+    pub fn distance_brightness(&self, coord: Coord) -> u8 {
+        self.grid
+            .get(coord)
+            .map(|cell| {
+                if cell.last_seen == self.count {
+                    cell.distance_brightness
+                } else {
+                    0
+                }
+            })
+            .unwrap_or(0)
+    }
+
+    // Remembers every cell that isn't currently visible as it is now, as
+    // if it had been seen on the previous update, for wizard mode
+    pub fn reveal_all(&mut self, world: &World) {
+        let previous = self.count - 1;
+        for (coord, cell) in self.grid.enumerate_mut() {
+            if cell.last_seen != self.count {
+                cell.last_seen = previous;
+                cell.remembered = RememberedCell::from_world(coord, world);
+            }
+        }
+    }
+
+    // Skips working out what's visible when nothing it depends on has
+    // changed since the last update, and only remembers what the visible
+    // cells now contain. True if it was worked out.
     pub fn update(
         &mut self,
         player_coord: Coord,
+        player_light_radius: u32,
+        sight_radius: u32,
+        max_radius: Option<u32>,
         world: &World,
         shadowcast_context: &mut shadowcast::Context<u8>,
         algorithm: VisibilityAlgorithm,
-    ) {
-        self.count +=1;
+    ) -> bool {
+        // a limit on how far the player can see overrides the algorithm
+        let algorithm = if max_radius.is_some() {
+            VisibilityAlgorithm::Shadowcast
+        } else {
+            algorithm
+        };
+        let sight_radius =
+            max_radius.map_or(sight_radius, |max_radius| sight_radius.min(max_radius));
+        let view = ViewKey {
+            player_coord,
+            player_light_radius,
+            sight_radius,
+            omniscient: matches!(algorithm, VisibilityAlgorithm::Omniscient),
+            vision_changes: world.vision_changes(),
+        };
+        if self.last_view == Some(view) {
+            for &coord in &self.visible {
+                self.grid.get_checked_mut(coord).remembered =
+                    RememberedCell::from_world(coord, world);
+            }
+            return false;
+        }
+        self.last_view = Some(view);
+        self.visible.clear();
+        self.count += 1;
+        #[cfg(test)]
+        NUM_FULL_UPDATES.with(|num_full_updates| num_full_updates.set(num_full_updates.get() + 1));
+        // the extra radius rounds the circle out so it includes diagonal
+        // neighbours at small radii
+        let vision_distance = shadowcast::vision_distance::Circle::new_squared(
+            sight_radius * sight_radius + sight_radius,
+        );
         match algorithm {
             VisibilityAlgorithm::Omniscient => {
-                for cell in self.grid.iter_mut() {
+                for (coord, cell) in self.grid.enumerate_mut() {
                     cell.last_seen = self.count;
+                    cell.last_lit = self.count;
+                    cell.light = 255;
+                    cell.distance_brightness = 255;
+                    cell.remembered = RememberedCell::from_world(coord, world);
+                    self.visible.push(coord);
                 }
             }
             VisibilityAlgorithm::Shadowcast => {
                 let count = self.count;
                 let grid = &mut self.grid;
+                let visible = &mut self.visible;
                 shadowcast_context.for_each_visible(
                     player_coord,
                     &Visibility,
                     world,
-                    VISION_DISTANCE,
+                    vision_distance,
                     255,
                     |coord, _visible_directions, _visibility| {
                         let cell = grid.get_checked_mut(coord);
                         cell.last_seen = count;
+                        cell.distance_brightness =
+                            distance_brightness(player_coord, coord, sight_radius);
+                        cell.remembered = RememberedCell::from_world(coord, world);
+                        visible.push(coord);
                     },
                 );
+                let light_sources = std::iter::once((player_coord, player_light_radius))
+                    .chain(world.light_sources());
+                for (source_coord, radius) in light_sources {
+                    self.add_light(source_coord, radius, world, shadowcast_context);
+                }
             }
         }
+        true
+    }
+
+    // Light falls off linearly with distance from its source, and each
+    // cell keeps the brightest light that reaches it
+    fn add_light(
+        &mut self,
+        source_coord: Coord,
+        radius: u32,
+        world: &World,
+        shadowcast_context: &mut shadowcast::Context<u8>,
+    ) {
+        if radius == 0 {
+            return;
+        }
+        let count = self.count;
+        let grid = &mut self.grid;
+        let radius_squared = radius * radius;
+        shadowcast_context.for_each_visible(
+            source_coord,
+            &Visibility,
+            world,
+            shadowcast::vision_distance::Circle::new_squared(radius_squared),
+            255,
+            |coord, _visible_directions, _visibility| {
+                let distance = ((coord - source_coord).magnitude2() as f64).sqrt();
+                let light = (255.0 * (1.0 - distance / radius as f64)).max(0.0) as u8;
+                let cell = grid.get_checked_mut(coord);
+                if cell.last_lit != count {
+                    cell.last_lit = count;
+                    cell.light = light;
+                } else {
+                    cell.light = cell.light.max(light);
+                }
+            },
+        );
     }
 }
 
 #[derive(Serialize, Deserialize)]
 struct VisibilityCell {
     last_seen: u64,
+    last_lit: u64,
+    light: u8,
+    distance_brightness: u8,
+    remembered: RememberedCell,
 }
 
 impl Default for VisibilityCell {
     fn default() -> Self {
-        Self { last_seen: 0 }
+        Self {
+            last_seen: 0,
+            last_lit: 0,
+            light: 0,
+            distance_brightness: 0,
+            remembered: RememberedCell::default(),
+        }
+    }
+}
+
+// Characters are deliberately not remembered, since they move around
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+struct RememberedCell {
+    floor: Option<Tile>,
+    feature: Option<Tile>,
+    object: Option<Tile>,
+}
+
+impl RememberedCell {
+    fn from_world(coord: Coord, world: &World) -> Self {
+        Self {
+            floor: world.tile_at(coord, Layer::Floor),
+            feature: world.tile_at(coord, Layer::Feature),
+            object: world.tile_at(coord, Layer::Object),
+        }
     }
 }
 
+fn cell_remembered_tiles(
+    coord: Coord,
+    cell: &VisibilityCell,
+) -> impl Iterator<Item = (Coord, Layer, Tile)> {
+    let remembered = cell.remembered;
+    [
+        (Layer::Floor, remembered.floor),
+        (Layer::Feature, remembered.feature),
+        (Layer::Object, remembered.object),
+    ]
+    .into_iter()
+    .filter_map(move |(layer, tile)| tile.map(|tile| (coord, layer, tile)))
+}
+
 pub enum CellVisibility {
     Currently,
     Previously,
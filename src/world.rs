@@ -1,15 +1,22 @@
 // world.rs
 
 use coord_2d::{Coord, Size};
-use direction::CardinalDirection;
+use direction::{CardinalDirection, Direction};
 use entity_table::{ComponentTable, Entity, EntityAllocator};
 use line_2d::CardinalStepIter;
-use rand::Rng;
+use rand::{seq::SliceRandom, Rng};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::mem;
+use std::str::FromStr;
 
 use crate::behavior::Agent;
-use crate::game::{ExamineCell, LevelUp, LogMessage};
-use crate::terrain::{self, TerrainTile};
+use crate::combat::{self, CombatForecast};
+use crate::difficulty::{self, DifficultyProfile};
+use crate::game::{ExamineCell, GameEvent, LevelUp, LogMessage, MessageLog};
+use crate::terrain::{
+    self, Dungeon, DungeonStats, GenerationConstraints, LevelStats, SpawnTables, TerrainTile,
+};
 
 pub use components::EntityData;
 
@@ -24,6 +31,58 @@ pub enum Tile {
     Item(ItemType),
     Projectile(ProjectileType),
     Stairs,
+    Gold,
+    Altar,
+    DoorClosed,
+    DoorOpen,
+    Trap(TrapType),
+    StairsUp,
+    Water,
+    Lava,
+    Foliage,
+    Chasm,
+    Rune,
+    PortalSealed,
+    PortalOpen,
+    Torch,
+    Fog,
+}
+
+impl Tile {
+    // The character a tile is drawn as, both in the game and in map dumps
+    pub fn character(self) -> char {
+        match self {
+            Self::Player => '@',
+            Self::PlayerCorpse | Self::NpcCorpse(_) => '%',
+            Self::Floor => '.',
+            Self::Wall | Self::Torch => '#',
+            Self::Npc(NpcType::Orc) => 'o',
+            Self::Npc(NpcType::Troll) => 'T',
+            Self::Npc(NpcType::Shopkeeper) => '@',
+            Self::Gold => '$',
+            Self::Item(ItemType::HealthPotion) | Self::Item(ItemType::SmokeBomb) => '!',
+            Self::Item(ItemType::FireballScroll)
+            | Self::Item(ItemType::ConfusionScroll)
+            | Self::Item(ItemType::ClairvoyanceScroll) => '?',
+            Self::Item(ItemType::Torch) | Self::Item(ItemType::Sword) => '/',
+            Self::Item(ItemType::Staff) => '\\',
+            Self::Item(ItemType::Armor) => ']',
+            Self::Item(ItemType::Robe) => '}',
+            Self::Item(ItemType::Backpack) => '(',
+            Self::Item(ItemType::Warhammer) => '&',
+            Self::Fog | Self::Chasm => ':',
+            Self::Projectile(_) | Self::Rune => '*',
+            Self::Stairs => '>',
+            Self::StairsUp => '<',
+            Self::DoorClosed => '+',
+            Self::DoorOpen => '/',
+            Self::Trap(_) => '^',
+            Self::Altar => '_',
+            Self::Water | Self::Lava => '~',
+            Self::Foliage => '"',
+            Self::PortalSealed | Self::PortalOpen => '0',
+        }
+    }
 }
 
 entity_table::declare_entity_module! {
@@ -37,12 +96,40 @@ entity_table::declare_entity_module! {
         projectile: ProjectileType,
         confusion_countdown: u32,
         stairs: (),
+        stairs_up: (),
         base_damage: i32,
         strength: i32,
         dexterity: i32,
         intelligence: i32,
         equipment_worn_inventory_index: usize,
+        // the main hand
         equipment_held_inventory_index: usize,
+        // only ever a one-handed weapon, beside another in the main hand
+        equipment_off_hand_inventory_index: usize,
+        gold: u32,
+        debt: u32,
+        price: u32,
+        neutral: (),
+        shop_exit: (),
+        altar_use_count: u32,
+        door: DoorState,
+        trap: Trap,
+        alarmed: (),
+        liquid: Liquid,
+        secret_door: (),
+        foliage: (),
+        opacity: u8,
+        chasm: (),
+        rune: (),
+        runes: u32,
+        portal: (),
+        light_radius: u32,
+        blind_countdown: u32,
+        clairvoyance_countdown: u32,
+        fuel: u32,
+        fog: Fog,
+        wall_connections: u8,
+        visual_effect: VisualEffect,
     }
 }
 
@@ -55,6 +142,7 @@ spatial_table::declare_layers_module! {
         object: Object,
         feature: Feature,
         projectile: Projectile,
+        gas: Gas,
     }
 }
 
@@ -62,17 +150,67 @@ pub use layers::Layer;
 type SpatialTable = spatial_table::SpatialTable<layers::Layers>;
 pub type Location = spatial_table::Location<Layer>;
 
+// The number of levels populated on this thread, for tests that no level
+// is populated more than once
+#[cfg(test)]
+thread_local! {
+    pub(crate) static NUM_POPULATED: std::cell::Cell<usize> = std::cell::Cell::new(0);
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct World {
     pub entity_allocator: EntityAllocator,
     pub components: Components,
     pub spatial_table: SpatialTable,
+    // waiting to be taken by the game and added to the run's statistics
+    #[serde(skip)]
+    stat_events: Vec<StatEvent>,
+    // waiting to be taken by the game and passed on to the app
+    #[serde(skip)]
+    events: Vec<GameEvent>,
+    // counts changes to what blocks sight or gives off light, so the
+    // player's view is only worked out again when it could be different
+    #[serde(default)]
+    vision_changes: u64,
+}
+
+// Things the player did which count towards the run's statistics
+#[derive(Clone, Copy, Debug)]
+pub enum StatEvent {
+    PlayerKills(NpcType),
+    PlayerDealsDamage(u32),
+    PlayerTakesDamage(u32),
+    PlayerUses(ItemType),
+    PlayerPicksUp(ItemType),
+    PlayerDies(DamageSource),
+}
+
+// Where damage to a character came from. The player's own fireballs can
+// hurt the player.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DamageSource {
+    Player,
+    Npc(NpcType),
+    Trap(TrapType),
+    Lava,
+    Fall,
+}
+
+// What a new player carries on top of what every character starts with
+#[derive(Clone, Debug, Default)]
+pub struct StartingKit {
+    // equipped from the start
+    pub held: Option<ItemType>,
+    pub worn: Option<ItemType>,
+    pub items: Vec<ItemType>,
+    pub extra_dexterity: i32,
 }
 
 pub struct Populate {
     pub player_entity: Entity,
     pub ai_state: ComponentTable<Agent>,
+    pub stats: DungeonStats,
+    pub level_stats: LevelStats,
 }
 
 impl World {
@@ -84,13 +222,25 @@ impl World {
             entity_allocator,
             components,
             spatial_table,
+            stat_events: Vec::new(),
+            events: Vec::new(),
+            vision_changes: 0,
         }
     }
 
-    pub fn clear(&mut self) {
-        self.entity_allocator.clear();
-        self.components.clear();
-        self.spatial_table.clear();
+    pub fn vision_changes(&self) -> u64 {
+        self.vision_changes
+    }
+
+    // Returns what counts towards the run's statistics since this was
+    // last called
+    pub fn take_stat_events(&mut self) -> Vec<StatEvent> {
+        mem::take(&mut self.stat_events)
+    }
+
+    // Returns what has happened since this was last called, in order
+    pub fn take_events(&mut self) -> Vec<GameEvent> {
+        mem::take(&mut self.events)
     }
 
     fn remove_entity_data(&mut self, entity: Entity) -> EntityData {
@@ -151,6 +301,45 @@ impl World {
         self.components.update_entity_data(entity, entity_data);
     }
 
+    // Inserts a character removed from another level at the nearest
+    // free cell to `coord`, returning its new entity.
+    pub fn insert_character_near(&mut self, coord: Coord, character_data: CharacterData) -> Entity {
+        let coord = self
+            .nearest_free_coord(coord)
+            .expect("no space to insert character");
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Character),
+                },
+            )
+            .unwrap();
+        self.replace_character(entity, character_data);
+        entity
+    }
+
+    fn nearest_free_coord(&self, coord: Coord) -> Option<Coord> {
+        let size = self.size();
+        let max_radius = size.width().max(size.height()) as i32;
+        (0..max_radius).find_map(|radius| {
+            (-radius..=radius)
+                .flat_map(|dy| (-radius..=radius).map(move |dx| Coord::new(dx, dy)))
+                .filter(|delta| delta.x.abs() == radius || delta.y.abs() == radius)
+                .map(|delta| coord + delta)
+                .find(|&candidate| {
+                    self.spatial_table
+                        .layers_at(candidate)
+                        .map(|layers| layers.floor.is_some())
+                        .unwrap_or(false)
+                        && !self.coord_contains_chasm(candidate)
+                        && self.can_spawn_character_at(candidate)
+                })
+        })
+    }
+
     pub fn coord_contains_stairs(&self, coord: Coord) -> bool {
         self.spatial_table
             .layers_at_checked(coord)
@@ -159,23 +348,148 @@ impl World {
             .unwrap_or(false)
     }
 
+    pub fn coord_contains_stairs_up(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at_checked(coord)
+            .floor
+            .map(|floor_entity| self.components.stairs_up.contains(floor_entity))
+            .unwrap_or(false)
+    }
+
+    pub fn stairs_coord(&self) -> Option<Coord> {
+        self.components
+            .stairs
+            .entities()
+            .next()
+            .and_then(|entity| self.spatial_table.coord_of(entity))
+    }
+
+    pub fn stairs_up_coord(&self) -> Option<Coord> {
+        self.components
+            .stairs_up
+            .entities()
+            .next()
+            .and_then(|entity| self.spatial_table.coord_of(entity))
+    }
+
+    fn altar_at(&self, coord: Coord) -> Option<Entity> {
+        self.spatial_table
+            .layers_at_checked(coord)
+            .floor
+            .filter(|&floor_entity| self.components.altar_use_count.contains(floor_entity))
+    }
+
+    pub fn coord_contains_altar(&self, coord: Coord) -> bool {
+        self.altar_at(coord).is_some()
+    }
+
     pub fn size(&self) -> Size {
         self.spatial_table.grid_size()
     }
 
     pub fn opacity_at(&self, coord: Coord) -> u8 {
-        if self
-            .spatial_table
-            .layers_at_checked(coord)
+        let layers = self.spatial_table.layers_at_checked(coord);
+        if layers.gas.is_some() {
+            return 255;
+        }
+        layers
             .feature
-            .is_some()
-        {
+            .map(|feature| self.feature_opacity(feature))
+            .unwrap_or(0)
+    }
+
+    // Solid features are always opaque. Other features can have their
+    // own opacity, such as foliage which hides what's behind it.
+    fn feature_opacity(&self, feature: Entity) -> u8 {
+        if self.is_solid_feature(feature) {
             255
         } else {
-            0
+            self.components.opacity.get(feature).cloned().unwrap_or(0)
         }
     }
 
+    // Walls and closed doors block movement and vision. Open doors
+    // and foliage are features which don't block movement.
+    fn is_solid_feature(&self, feature: Entity) -> bool {
+        !self.components.foliage.contains(feature)
+            && self.components.door.get(feature) != Some(&DoorState::Open)
+    }
+
+    fn door_at(&self, coord: Coord) -> Option<(Entity, DoorState)> {
+        self.spatial_table
+            .layers_at(coord)
+            .and_then(|layers| layers.feature)
+            .and_then(|feature| {
+                self.components
+                    .door
+                    .get(feature)
+                    .map(|&door_state| (feature, door_state))
+            })
+    }
+
+    fn set_door_state(&mut self, door: Entity, door_state: DoorState) {
+        let tile = match door_state {
+            DoorState::Open => Tile::DoorOpen,
+            DoorState::Closed => Tile::DoorClosed,
+        };
+        self.components.door.insert(door, door_state);
+        self.components.tile.insert(door, tile);
+        self.vision_changes += 1;
+    }
+
+    // open or closed, but not secret doors which haven't been found
+    pub fn is_door_at(&self, coord: Coord) -> bool {
+        self.door_at(coord).is_some()
+    }
+
+    pub fn is_closed_door_at(&self, coord: Coord) -> bool {
+        matches!(self.door_at(coord), Some((_, DoorState::Closed)))
+    }
+
+    pub fn maybe_open_door(&mut self, coord: Coord) -> Result<(), ()> {
+        match self.door_at(coord) {
+            Some((door, DoorState::Closed)) => {
+                self.set_door_state(door, DoorState::Open);
+                self.events.push(GameEvent::DoorOpened(coord));
+                Ok(())
+            }
+            _ => Err(()),
+        }
+    }
+
+    pub fn maybe_close_door(
+        &mut self,
+        character: Entity,
+        message_log: &mut MessageLog,
+    ) -> Result<(), ()> {
+        let coord = self
+            .spatial_table
+            .coord_of(character)
+            .expect("character has no coord");
+        let open_doors = Direction::all()
+            .map(|direction| coord + direction.coord())
+            .filter_map(|neighbour| match self.door_at(neighbour) {
+                Some((door, DoorState::Open)) => Some((door, neighbour)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if open_doors.is_empty() {
+            message_log.push(LogMessage::NoDoorToClose);
+            return Err(());
+        }
+        for (door, door_coord) in open_doors {
+            let layers = self.spatial_table.layers_at_checked(door_coord);
+            if layers.character.is_none() && layers.object.is_none() {
+                self.set_door_state(door, DoorState::Closed);
+                self.events.push(GameEvent::DoorClosed(door_coord));
+                message_log.push(LogMessage::PlayerClosesDoor);
+                return Ok(());
+            }
+        }
+        message_log.push(LogMessage::DoorIsBlocked);
+        Err(())
+    }
+
     fn spawn_player(&mut self, coord: Coord) -> Entity {
         let entity = self.entity_allocator.alloc();
         self.spatial_table
@@ -192,12 +506,15 @@ impl World {
             .hit_points
             .insert(entity, HitPoints::new_full(20));
         self.components
-            .inventory.
-            insert(entity, Inventory::new(10));
+            .inventory
+            .insert(entity, Inventory::new(PLAYER_INVENTORY_SLOTS));
         self.components.base_damage.insert(entity, 1);
         self.components.strength.insert(entity, 1);
         self.components.dexterity.insert(entity, 1);
         self.components.intelligence.insert(entity, 1);
+        self.components.gold.insert(entity, 0);
+        self.components.debt.insert(entity, 0);
+        self.components.runes.insert(entity, 0);
 
         entity
     }
@@ -230,7 +547,7 @@ impl World {
         self.components.tile.insert(entity, Tile::Floor);
     }
 
-    fn spawn_item(&mut self, coord: Coord, item_type: ItemType) {
+    fn spawn_item(&mut self, coord: Coord, item_type: ItemType) -> Entity {
         let entity = self.entity_allocator.alloc();
         self.spatial_table
             .update(
@@ -243,181 +560,1159 @@ impl World {
             .unwrap();
         self.components.tile.insert(entity, Tile::Item(item_type));
         self.components.item.insert(entity, item_type);
+        if item_type == ItemType::Torch {
+            const TORCH_FUEL: u32 = 300;
+            self.components.fuel.insert(entity, TORCH_FUEL);
+        }
+
+        entity
     }
 
-    fn spawn_npc(&mut self, coord: Coord, npc_type: NpcType) -> Entity {
+    fn spawn_shop_item(&mut self, coord: Coord, item_type: ItemType) {
+        let entity = self.spawn_item(coord, item_type);
+        self.components.price.insert(entity, item_type.price());
+    }
+
+    fn spawn_gold(&mut self, coord: Coord, amount: u32) {
         let entity = self.entity_allocator.alloc();
         self.spatial_table
             .update(
                 entity,
                 Location {
                     coord,
-                    layer: Some(Layer::Character),
+                    layer: Some(Layer::Object),
                 },
             )
             .unwrap();
-        let hit_points = match npc_type {
-            NpcType::Orc => HitPoints::new_full(2),
-            NpcType::Troll => HitPoints::new_full(6),
-        };
-        self.components.tile.insert(entity, Tile::Npc(npc_type));
-        self.components.npc_type.insert(entity, npc_type);
-        self.components.hit_points.insert(entity, hit_points);
-        self.components.base_damage.insert(entity, 1);
-        let (strength, dexterity) = match npc_type {
-            NpcType::Orc => (1, 1),
-            NpcType::Troll => (2, 0),
-        };
-        self.components.strength.insert(entity, strength);
-        self.components.dexterity.insert(entity, dexterity);
-
-        entity
+        self.components.tile.insert(entity, Tile::Gold);
+        self.components.gold.insert(entity, amount);
     }
 
-    fn spawn_projectile(&mut self, from: Coord, to: Coord, projectile_type: ProjectileType) {
+    fn spawn_fog(&mut self, coord: Coord, fog: Fog) {
         let entity = self.entity_allocator.alloc();
         self.spatial_table
             .update(
                 entity,
                 Location {
-                    coord: from,
-                    layer: Some(Layer::Projectile),
+                    coord,
+                    layer: Some(Layer::Gas),
                 },
-            ).unwrap();
-        self.components
-            .tile
-            .insert(entity, Tile::Projectile(projectile_type));
-        self.components.projectile.insert(entity, projectile_type);
-        self.components
-            .trajectory
-            .insert(entity, CardinalStepIter::new(to - from));
+            )
+            .unwrap();
+        self.components.tile.insert(entity, Tile::Fog);
+        self.components.fog.insert(entity, fog);
+        self.vision_changes += 1;
     }
 
-    fn spawn_stairs(&mut self, coord: Coord) {
-        let entity = self.entity_allocator.alloc();
-        self.spatial_table
-            .update(
-                entity,
-                Location {
-                    coord,
-                    layer: Some(Layer::Floor),
+    fn release_smoke(&mut self, coord: Coord) {
+        const SMOKE_SPREAD: u32 = 3;
+        const SMOKE_DURATION: u32 = 12;
+        if !self.coord_contains_fog(coord) {
+            self.spawn_fog(
+                coord,
+                Fog {
+                    turns_left: SMOKE_DURATION,
+                    spread: SMOKE_SPREAD,
                 },
-            ).unwrap();
-        self.components.tile.insert(entity, Tile::Stairs);
-        self.components.stairs.insert(entity, ());
+            );
+        }
     }
 
-    pub fn move_projectiles(&mut self, message_log: &mut Vec<LogMessage>) {
-        let mut entities_to_remove = Vec::new();
-        let mut fireball_hit = Vec::new();
-        let mut confusion_hit = Vec::new();
+    pub fn coord_contains_fog(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at(coord)
+            .map(|layers| layers.gas.is_some())
+            .unwrap_or(false)
+    }
 
-        for (entity, trajectory) in self.components.trajectory.iter_mut() {
-            if let Some(direction) = trajectory.next() {
-                let current_coord = self.spatial_table.coord_of(entity).unwrap();
-                let new_coord = current_coord + direction.coord();
-                let dest_layers = self.spatial_table.layers_at_checked(new_coord);
-                if dest_layers.feature.is_some() {
-                    entities_to_remove.push(entity);
-                } else if let Some(character) = dest_layers.character {
-                    entities_to_remove.push(entity);
-                    if let Some(&projectile_type) = self.components.projectile.get(entity) {
-                        match projectile_type {
-                            ProjectileType::Fireball { damage } => {
-                                fireball_hit.push((character, damage));
-                            }
-                            ProjectileType::Confusion { duration } => {
-                                confusion_hit.push((character, duration));
-                            }
-                        }
-                    }
+    // Fog spreads one cell per turn into open neighbouring cells until it
+    // has spread as far as it can, and clears once its time runs out
+    pub fn update_fog(&mut self) {
+        let mut to_spawn = Vec::new();
+        let mut to_remove = Vec::new();
+        for (entity, fog) in self.components.fog.iter_mut() {
+            let coord = match self.spatial_table.coord_of(entity) {
+                Some(coord) => coord,
+                None => continue,
+            };
+            if fog.spread > 0 {
+                for direction in CardinalDirection::all() {
+                    to_spawn.push((
+                        coord + direction.coord(),
+                        Fog {
+                            turns_left: fog.turns_left,
+                            spread: fog.spread - 1,
+                        },
+                    ));
                 }
-
-                // ignore porjectiles hitting projectiles
-                let _ = self.spatial_table.update_coord(entity, new_coord);
+                fog.spread = 0;
+            }
+            if fog.turns_left == 0 {
+                to_remove.push(entity);
             } else {
-                entities_to_remove.push(entity);
+                fog.turns_left -= 1;
             }
         }
-        for entity in entities_to_remove {
+        for entity in to_remove {
             self.remove_entity(entity);
         }
-        for (entity, damage) in fireball_hit {
-            let maybe_npc = self.components.npc_type.get(entity).cloned();
-            if let Some(VictimDies) = self.character_damage(entity, damage) {
-                if let Some(npc) = maybe_npc {
-                    message_log.push(LogMessage::NpcDies(npc));
-                }
+        for (coord, fog) in to_spawn {
+            let can_spread = self
+                .spatial_table
+                .layers_at(coord)
+                .map(|layers| {
+                    layers.gas.is_none()
+                        && layers
+                            .feature
+                            .map(|feature| !self.is_solid_feature(feature))
+                            .unwrap_or(true)
+                })
+                .unwrap_or(false);
+            if can_spread {
+                self.spawn_fog(coord, fog);
             }
         }
-        for (entity, duration) in confusion_hit {
-            self.components.confusion_countdown.insert(entity, duration);
-            if let Some(&npc_type) = self.components.npc_type.get(entity) {
-                message_log.push(LogMessage::NpcBecomesConfused(npc_type));
-            }
+    }
+
+    // Torches are mounted in walls, so they block movement and sight
+    // like the walls they replace
+    fn spawn_torch(&mut self, coord: Coord) {
+        const TORCH_LIGHT_RADIUS: u32 = 5;
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Feature),
+                },
+            )
+            .unwrap();
+        self.components.tile.insert(entity, Tile::Torch);
+        self.components
+            .light_radius
+            .insert(entity, TORCH_LIGHT_RADIUS);
+    }
+
+    pub fn tile_at(&self, coord: Coord, layer: Layer) -> Option<Tile> {
+        let entity = self.entity_at(coord, layer)?;
+        self.components.tile.get(entity).cloned()
+    }
+
+    fn entity_at(&self, coord: Coord, layer: Layer) -> Option<Entity> {
+        let layers = self.spatial_table.layers_at(coord)?;
+        match layer {
+            Layer::Floor => layers.floor,
+            Layer::Character => layers.character,
+            Layer::Object => layers.object,
+            Layer::Feature => layers.feature,
+            Layer::Projectile => layers.projectile,
+            Layer::Gas => layers.gas,
         }
     }
 
-    pub fn strength(&self, entity: Entity) -> Option<i32> {
-        self.components.strength.get(entity).cloned()
+    // The entities on the layer in the rectangle's cells which are on the
+    // map, in row-major order. Each cell holds at most one entity per
+    // layer, so the order is the same every time and callers drawing
+    // from an RNG for each entity stay reproducible.
+    pub fn entities_within_rect(&self, top_left: Coord, size: Size, layer: Layer) -> Vec<Entity> {
+        size.coord_iter_row_major()
+            .filter_map(|offset| self.entity_at(top_left + offset, layer))
+            .collect()
     }
 
-    pub fn dexterity(&self, entity: Entity) -> Option<i32> {
-        self.components.dexterity.get(entity).cloned()
+    // The entities on the layer within `radius` cells of `centre` in a
+    // straight line, in row-major order like entities_within_rect. Only
+    // the cells around the centre are looked at, however many entities
+    // the level holds.
+    pub fn entities_within_layer(&self, centre: Coord, radius: u32, layer: Layer) -> Vec<Entity> {
+        let radius_squared = radius * radius;
+        let top_left = centre - Coord::new(radius as i32, radius as i32);
+        let size = Size::new(radius * 2 + 1, radius * 2 + 1);
+        size.coord_iter_row_major()
+            .map(|offset| top_left + offset)
+            .filter(|&coord| (coord - centre).magnitude2() <= radius_squared)
+            .filter_map(|coord| self.entity_at(coord, layer))
+            .collect()
     }
 
-    pub fn intelligence(&self, entity: Entity) -> Option<i32> {
-        self.components.intelligence.get(entity).cloned()
+    // Living characters, including the player, within `radius` of `centre`
+    pub fn characters_within(&self, centre: Coord, radius: u32) -> Vec<Entity> {
+        self.entities_within_layer(centre, radius, Layer::Character)
     }
 
+    pub fn light_sources<'a>(&'a self) -> impl 'a + Iterator<Item = (Coord, u32)> {
+        self.components
+            .light_radius
+            .iter()
+            .filter_map(move |(entity, &radius)| {
+                self.spatial_table
+                    .coord_of(entity)
+                    .map(|coord| (coord, radius))
+            })
+    }
 
-    pub fn populate<R: Rng>(&mut self, level: u32, rng: &mut R) -> Populate {
-        let terrain = terrain::generate_dungeon(self.spatial_table.grid_size(), level, rng);
-        let mut player_entity = None;
-        let mut ai_state = ComponentTable::default();
+    fn spawn_rune(&mut self, coord: Coord) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Object),
+                },
+            )
+            .unwrap();
+        self.components.tile.insert(entity, Tile::Rune);
+        self.components.rune.insert(entity, ());
+    }
 
-        for (coord, &terrain_tile) in terrain.enumerate() {
-            match terrain_tile {
-                TerrainTile::Player => {
-                    self.spawn_floor(coord);
-                    player_entity = Some(self.spawn_player(coord));
-                }
-                TerrainTile::Floor => self.spawn_floor(coord),
-                TerrainTile::Wall => {
-                    self.spawn_floor(coord);
-                    self.spawn_wall(coord);
-                }
-                TerrainTile::Npc(npc_type) => {
-                    let entity = self.spawn_npc(coord, npc_type);
-                    self.spawn_floor(coord);
-                    ai_state.insert(entity, Agent::new());
-                }
-                TerrainTile::Item(item_type) => {
-                    self.spawn_item(coord, item_type);
-                    self.spawn_floor(coord);
-                }
-                TerrainTile::Stairs => self.spawn_stairs(coord),
-            }
-        }
-        Populate {
-            player_entity: player_entity.unwrap(),
-            ai_state,
-        }
+    // The portal out of the dungeon stays sealed until the player has
+    // collected every rune
+    fn spawn_portal(&mut self, coord: Coord) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Floor),
+                },
+            )
+            .unwrap();
+        self.components.tile.insert(entity, Tile::PortalSealed);
+        self.components.portal.insert(entity, ());
+    }
 
+    fn unseal_portals(&mut self) {
+        let portals = self.components.portal.entities().collect::<Vec<_>>();
+        for portal in portals {
+            self.components.tile.insert(portal, Tile::PortalOpen);
+        }
     }
 
-    pub fn maybe_move_character<R: Rng>(
+    pub fn coord_contains_portal(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at(coord)
+            .and_then(|layers| layers.floor)
+            .map(|floor_entity| self.components.portal.contains(floor_entity))
+            .unwrap_or(false)
+    }
+
+    pub fn runes(&self, entity: Entity) -> Option<u32> {
+        self.components.runes.get(entity).cloned()
+    }
+
+    // Traps are stored on the floor entity of the cell they occupy. A
+    // hidden trap looks like an ordinary floor tile until it is revealed.
+    fn spawn_trap(&mut self, coord: Coord, trap_type: TrapType) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Floor),
+                },
+            )
+            .unwrap();
+        self.components.tile.insert(entity, Tile::Floor);
+        self.components.trap.insert(
+            entity,
+            Trap {
+                trap_type,
+                revealed: false,
+            },
+        );
+    }
+
+    fn spawn_door(&mut self, coord: Coord) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Feature),
+                },
+            )
+            .unwrap();
+        self.components.tile.insert(entity, Tile::DoorClosed);
+        self.components.door.insert(entity, DoorState::Closed);
+    }
+
+    fn spawn_foliage(&mut self, coord: Coord) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Feature),
+                },
+            )
+            .unwrap();
+        self.components.tile.insert(entity, Tile::Foliage);
+        self.components.foliage.insert(entity, ());
+        self.components.opacity.insert(entity, 255);
+    }
+
+    // Secret doors look and behave like walls until they are found
+    fn spawn_secret_door(&mut self, coord: Coord) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Feature),
+                },
+            )
+            .unwrap();
+        self.components.tile.insert(entity, Tile::Wall);
+        self.components.secret_door.insert(entity, ());
+    }
+
+    fn spawn_altar(&mut self, coord: Coord) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Floor),
+                },
+            )
+            .unwrap();
+        self.components.tile.insert(entity, Tile::Altar);
+        self.components.altar_use_count.insert(entity, 0);
+    }
+
+    fn spawn_shop_exit(&mut self, coord: Coord) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Floor),
+                },
+            )
+            .unwrap();
+        self.components.tile.insert(entity, Tile::Floor);
+        self.components.shop_exit.insert(entity, ());
+    }
+
+    fn spawn_npc(&mut self, coord: Coord, npc_type: NpcType) -> Entity {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Character),
+                },
+            )
+            .unwrap();
+        let hit_points = match npc_type {
+            NpcType::Orc => HitPoints::new_full(2),
+            NpcType::Troll => HitPoints::new_full(6),
+            NpcType::Shopkeeper => HitPoints::new_full(30),
+        };
+        self.components.tile.insert(entity, Tile::Npc(npc_type));
+        self.components.npc_type.insert(entity, npc_type);
+        self.components.hit_points.insert(entity, hit_points);
+        self.components.base_damage.insert(entity, 1);
+        let (strength, dexterity) = match npc_type {
+            NpcType::Orc => (1, 1),
+            NpcType::Troll => (2, 0),
+            NpcType::Shopkeeper => (4, 3),
+        };
+        self.components.strength.insert(entity, strength);
+        self.components.dexterity.insert(entity, dexterity);
+        if npc_type == NpcType::Shopkeeper {
+            self.components.neutral.insert(entity, ());
+        }
+
+        entity
+    }
+
+    // Puts an NPC in the first free cell next to `coord`, for wizard mode
+    pub fn spawn_npc_next_to(&mut self, coord: Coord, npc_type: NpcType) -> Result<Entity, ()> {
+        let npc_coord = CardinalDirection::all()
+            .map(|direction| coord + direction.coord())
+            .find(|&candidate| {
+                self.spatial_table
+                    .layers_at(candidate)
+                    .map(|layers| layers.floor.is_some())
+                    .unwrap_or(false)
+                    && !self.coord_contains_chasm(candidate)
+                    && self.can_spawn_character_at(candidate)
+            })
+            .ok_or(())?;
+        Ok(self.spawn_npc(npc_coord, npc_type))
+    }
+
+    // Creates an item straight into a character's inventory, for wizard
+    // mode and starting kits. Nothing is created if the inventory is full.
+    // A backpack grows the inventory instead.
+    pub fn grant_item(&mut self, character: Entity, item_type: ItemType) -> Result<(), ()> {
+        let coord = self
+            .spatial_table
+            .coord_of(character)
+            .expect("character has no coord");
+        let item = self.spawn_item(coord, item_type);
+        self.spatial_table.remove(item);
+        let inventory = self
+            .components
+            .inventory
+            .get_mut(character)
+            .expect("character has no inventory");
+        if item_type == ItemType::Backpack {
+            let result = inventory
+                .grow(BACKPACK_SLOTS)
+                .map_err(|InventoryCannotGrow| ());
+            self.remove_entity(item);
+            return result;
+        }
+        if inventory.insert(item).is_ok() {
+            Ok(())
+        } else {
+            self.remove_entity(item);
+            Err(())
+        }
+    }
+
+    // Makes every NPC on the level as tough as the profile says. This is
+    // done once, after the level is populated.
+    pub fn apply_difficulty(&mut self, profile: &DifficultyProfile) {
+        let npcs = self.components.npc_type.entities().collect::<Vec<_>>();
+        for entity in npcs {
+            if let Some(hit_points) = self.components.hit_points.get_mut(entity) {
+                let max = difficulty::scale(hit_points.max(), profile.npc_hit_points_percent);
+                hit_points.set_max(max.max(1), true);
+            }
+            if let Some(base_damage) = self.components.base_damage.get_mut(entity) {
+                *base_damage += profile.npc_extra_damage as i32;
+            }
+        }
+    }
+
+    pub fn heal_fully(&mut self, character: Entity) {
+        if let Some(hit_points) = self.components.hit_points.get_mut(character) {
+            hit_points.fill();
+        }
+    }
+
+    fn spawn_projectile(&mut self, from: Coord, to: Coord, projectile_type: ProjectileType) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord: from,
+                    layer: Some(Layer::Projectile),
+                },
+            )
+            .unwrap();
+        self.components
+            .tile
+            .insert(entity, Tile::Projectile(projectile_type));
+        self.components.projectile.insert(entity, projectile_type);
+        self.components
+            .trajectory
+            .insert(entity, CardinalStepIter::new(to - from));
+    }
+
+    fn spawn_stairs(&mut self, coord: Coord) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Floor),
+                },
+            )
+            .unwrap();
+        self.components.tile.insert(entity, Tile::Stairs);
+        self.components.stairs.insert(entity, ());
+    }
+
+    // Liquids take the place of the floor in the cell they occupy
+    fn spawn_liquid(&mut self, coord: Coord, liquid: Liquid) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Floor),
+                },
+            )
+            .unwrap();
+        let tile = match liquid {
+            Liquid::Water => Tile::Water,
+            Liquid::Lava => Tile::Lava,
+        };
+        self.components.tile.insert(entity, tile);
+        self.components.liquid.insert(entity, liquid);
+    }
+
+    // The things in a cell worth telling the player about when they step
+    // into it, floor features first
+    pub fn floor_things_at(&self, coord: Coord) -> Vec<FloorThing> {
+        let layers = match self.spatial_table.layers_at(coord) {
+            Some(layers) => layers,
+            None => return Vec::new(),
+        };
+        let floor_thing = |entity: Entity| match *self.components.tile.get(entity)? {
+            Tile::Stairs => Some(FloorThing::Stairs),
+            Tile::StairsUp => Some(FloorThing::StairsUp),
+            Tile::Altar => Some(FloorThing::Altar),
+            Tile::PortalSealed | Tile::PortalOpen => Some(FloorThing::Portal),
+            Tile::Item(item_type) => Some(FloorThing::Item(item_type)),
+            Tile::Gold => Some(FloorThing::Gold),
+            Tile::NpcCorpse(npc_type) => Some(FloorThing::Corpse(npc_type)),
+            Tile::Rune => Some(FloorThing::Rune),
+            _ => None,
+        };
+        layers
+            .floor
+            .into_iter()
+            .chain(layers.object)
+            .filter_map(floor_thing)
+            .collect()
+    }
+
+    pub fn liquid_at(&self, coord: Coord) -> Option<Liquid> {
+        self.spatial_table
+            .layers_at(coord)
+            .and_then(|layers| layers.floor)
+            .and_then(|floor_entity| self.components.liquid.get(floor_entity).cloned())
+    }
+
+    // Chasms take the place of the floor, and can only be entered by
+    // jumping in
+    fn spawn_chasm(&mut self, coord: Coord) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Floor),
+                },
+            )
+            .unwrap();
+        self.components.tile.insert(entity, Tile::Chasm);
+        self.components.chasm.insert(entity, ());
+    }
+
+    pub fn coord_contains_chasm(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at(coord)
+            .and_then(|layers| layers.floor)
+            .map(|floor_entity| self.components.chasm.contains(floor_entity))
+            .unwrap_or(false)
+    }
+
+    pub fn damage_character_by_fall(&mut self, character: Entity) {
+        const FALL_DAMAGE: u32 = 4;
+        self.character_damage(character, FALL_DAMAGE, DamageSource::Fall);
+    }
+
+    pub fn move_character_to(&mut self, character: Entity, coord: Coord) {
+        self.spatial_table.update_coord(character, coord).unwrap();
+    }
+
+    // Chooses a random cell where a character could be placed
+    pub fn random_free_coord<R: Rng>(&self, rng: &mut R) -> Option<Coord> {
+        const NUM_ATTEMPTS: usize = 1000;
+        let size = self.size();
+        (0..NUM_ATTEMPTS)
+            .map(|_| {
+                Coord::new(
+                    rng.gen_range(0..size.width()) as i32,
+                    rng.gen_range(0..size.height()) as i32,
+                )
+            })
+            .find(|&coord| {
+                let has_floor = self.spatial_table.layers_at_checked(coord).floor.is_some();
+                has_floor
+                    && self.can_spawn_character_at(coord)
+                    && self.trap_at(coord).is_none()
+                    && !self.coord_contains_chasm(coord)
+                    && self.liquid_at(coord) != Some(Liquid::Lava)
+            })
+    }
+
+    pub fn is_blind(&self, entity: Entity) -> bool {
+        self.components.blind_countdown.contains(entity)
+    }
+
+    pub fn tick_blindness(&mut self, character: Entity, message_log: &mut MessageLog) {
+        if tick_status(&mut self.components.blind_countdown, character) {
+            message_log.push(LogMessage::PlayerCanSeeAgain);
+        }
+    }
+
+    pub fn is_clairvoyant(&self, entity: Entity) -> bool {
+        self.components.clairvoyance_countdown.contains(entity)
+    }
+
+    pub fn tick_clairvoyance(&mut self, character: Entity, message_log: &mut MessageLog) {
+        if tick_status(&mut self.components.clairvoyance_countdown, character) {
+            message_log.push(LogMessage::PlayerClairvoyanceEnds);
+        }
+    }
+
+    // The timed statuses affecting a character, and how many more turns
+    // each will last, in the order of StatusEffect
+    pub fn status_effects(&self, entity: Entity) -> impl '_ + Iterator<Item = (StatusEffect, u32)> {
+        [
+            (StatusEffect::Blind, &self.components.blind_countdown),
+            (StatusEffect::Confused, &self.components.confusion_countdown),
+            (
+                StatusEffect::Clairvoyant,
+                &self.components.clairvoyance_countdown,
+            ),
+        ]
+        .into_iter()
+        .filter_map(move |(status_effect, countdowns)| {
+            // a status is still in effect on the turn its countdown reaches 0
+            countdowns
+                .get(entity)
+                .map(|&countdown| (status_effect, countdown + 1))
+        })
+    }
+
+    // The torch a character carries which is currently burning. Only the
+    // first torch in the inventory burns.
+    pub fn carried_torch(&self, character: Entity) -> Option<Entity> {
+        self.components
+            .inventory
+            .get(character)?
+            .slots()
+            .iter()
+            .flatten()
+            .cloned()
+            .find(|&item| self.components.item.get(item) == Some(&ItemType::Torch))
+    }
+
+    pub fn burn_torch(&mut self, character: Entity, message_log: &mut MessageLog) {
+        let torch = match self.carried_torch(character) {
+            Some(torch) => torch,
+            None => return,
+        };
+        let fuel = self
+            .components
+            .fuel
+            .get_mut(torch)
+            .expect("torch has no fuel");
+        *fuel = fuel.saturating_sub(1);
+        if *fuel == 0 {
+            let inventory = self
+                .components
+                .inventory
+                .get_mut(character)
+                .expect("character has no inventory");
+            if let Some(index) = inventory
+                .slots()
+                .iter()
+                .position(|&slot| slot == Some(torch))
+            {
+                inventory.remove(index).unwrap();
+            }
+            self.remove_entity(torch);
+            message_log.push(LogMessage::TorchGoesOut);
+        }
+    }
+
+    pub fn npc_at(&self, coord: Coord) -> Option<Entity> {
+        self.spatial_table
+            .layers_at(coord)?
+            .character
+            .filter(|&entity| self.components.npc_type.contains(entity))
+    }
+
+    // Walls, and the doors and torches set into them, join up with their
+    // neighbours when drawn
+    fn is_wall_like(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at(coord)
+            .and_then(|layers| layers.feature)
+            .and_then(|feature| self.components.tile.get(feature))
+            .map(|tile| {
+                matches!(
+                    tile,
+                    Tile::Wall | Tile::Torch | Tile::DoorClosed | Tile::DoorOpen
+                )
+            })
+            .unwrap_or(false)
+    }
+
+    // Walls buried in solid rock are never seen, so they don't count as
+    // neighbours to join up with
+    fn is_exposed_wall(&self, coord: Coord) -> bool {
+        self.is_wall_like(coord)
+            && Direction::all().any(|direction| {
+                let neighbour = coord + direction.coord();
+                neighbour.is_valid(self.size()) && !self.is_wall_like(neighbour)
+            })
+    }
+
+    // Works out which of its orthogonal neighbours each wall joins up with.
+    // Walls never move, so this only needs doing once per level.
+    fn connect_walls(&mut self) {
+        let walls = self
+            .components
+            .tile
+            .iter()
+            .filter(|(_, tile)| matches!(tile, Tile::Wall))
+            .filter_map(|(entity, _)| {
+                let location = self.spatial_table.location_of(entity)?;
+                if location.layer == Some(Layer::Feature) {
+                    Some((entity, location.coord))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        for (entity, coord) in walls {
+            let connections = CardinalDirection::all()
+                .enumerate()
+                .filter(|(_, direction)| self.is_exposed_wall(coord + direction.coord()))
+                .fold(0, |connections, (i, _)| connections | (1 << i));
+            self.components.wall_connections.insert(entity, connections);
+        }
+    }
+
+    // A bitmask of which orthogonal neighbours the wall at the coordinate
+    // joins up with, in the order of CardinalDirection::all
+    pub fn wall_connections(&self, coord: Coord) -> Option<u8> {
+        let feature = self.spatial_table.layers_at(coord)?.feature?;
+        self.components.wall_connections.get(feature).cloned()
+    }
+
+    pub fn examine_npc_at(&self, coord: Coord) -> Option<ExamineCell> {
+        let npc = self.npc_at(coord)?;
+        Some(ExamineCell::Npc {
+            npc_type: self.components.npc_type.get(npc).cloned()?,
+            hit_points: self.hit_points(npc)?,
+        })
+    }
+
+    // Damages every character standing in lava
+    pub fn burn_characters_in_lava(&mut self, message_log: &mut MessageLog) {
+        const LAVA_DAMAGE: u32 = 5;
+        let burning = self
+            .components
+            .hit_points
+            .entities()
+            .filter(|&entity| self.is_living_character(entity))
+            .filter(|&entity| {
+                self.entity_coord(entity)
+                    .and_then(|coord| self.liquid_at(coord))
+                    == Some(Liquid::Lava)
+            })
+            .collect::<Vec<_>>();
+        for entity in burning {
+            let maybe_npc = self.components.npc_type.get(entity).cloned();
+            message_log.push(LogMessage::LavaBurns(maybe_npc));
+            if let Some(VictimDies) = self.character_damage(entity, LAVA_DAMAGE, DamageSource::Lava)
+            {
+                if let Some(npc) = maybe_npc {
+                    message_log.push(LogMessage::NpcDies(npc));
+                }
+            }
+        }
+    }
+
+    fn spawn_stairs_up(&mut self, coord: Coord) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Floor),
+                },
+            )
+            .unwrap();
+        self.components.tile.insert(entity, Tile::StairsUp);
+        self.components.stairs_up.insert(entity, ());
+    }
+
+    // What stops a projectile trying to move into a cell, if anything
+    fn projectile_stop_at(
+        &self,
+        coord: Coord,
+        projectile_type: ProjectileType,
+    ) -> Option<ProjectileStop> {
+        let dest_layers = match self.spatial_table.layers_at(coord) {
+            Some(dest_layers) => dest_layers,
+            None => return Some(ProjectileStop::Blocked),
+        };
+        let dest_is_water = dest_layers
+            .floor
+            .and_then(|floor_entity| self.components.liquid.get(floor_entity))
+            == Some(&Liquid::Water);
+        if dest_layers
+            .feature
+            .map(|feature| self.is_solid_feature(feature))
+            .unwrap_or(false)
+        {
+            Some(ProjectileStop::Blocked)
+        } else if dest_is_water
+            && dest_layers.character.is_none()
+            && matches!(projectile_type, ProjectileType::Fireball { .. })
+        {
+            Some(ProjectileStop::Fizzles)
+        } else {
+            dest_layers.character.map(ProjectileStop::HitsCharacter)
+        }
+    }
+
+    // Where a projectile launched from `from` towards `to` stops short of
+    // its target, and why. Projectiles and the aiming preview both use
+    // this so that they can't disagree.
+    pub fn first_blocking_cell_on_line(
+        &self,
+        from: Coord,
+        to: Coord,
+        projectile_type: ProjectileType,
+    ) -> Option<(Coord, ProjectileStop)> {
+        let mut coord = from;
+        for direction in CardinalStepIter::new(to - from) {
+            coord = coord + direction.coord();
+            if let Some(stop) = self.projectile_stop_at(coord, projectile_type) {
+                return Some((coord, stop));
+            }
+        }
+        None
+    }
+
+    // The projectile a character would launch by using an aimed item
+    pub fn aimed_projectile(
+        &self,
+        character: Entity,
+        item_type: ItemType,
+    ) -> Option<ProjectileType> {
+        let magic = self.magic(character).max(0) as u32;
+        match item_type {
+            ItemType::FireballScroll => Some(ProjectileType::Fireball { damage: magic }),
+            ItemType::ConfusionScroll => Some(ProjectileType::Confusion {
+                duration: magic * 3,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn move_projectiles(&mut self, message_log: &mut MessageLog) {
+        let mut entities_to_remove = Vec::new();
+        let mut fireball_hit = Vec::new();
+        let mut confusion_bursts = Vec::new();
+        let mut blast_coords = Vec::new();
+
+        let steps = self
+            .components
+            .trajectory
+            .iter_mut()
+            .map(|(entity, trajectory)| (entity, trajectory.next()))
+            .collect::<Vec<_>>();
+        for (entity, maybe_direction) in steps {
+            let current_coord = self.spatial_table.coord_of(entity).unwrap();
+            let &projectile_type = self.components.projectile.get(entity).unwrap();
+            if let Some(direction) = maybe_direction {
+                let new_coord = current_coord + direction.coord();
+                match self.projectile_stop_at(new_coord, projectile_type) {
+                    None => (),
+                    Some(ProjectileStop::Blocked) => {
+                        entities_to_remove.push(entity);
+                        if let ProjectileType::Confusion { duration } = projectile_type {
+                            confusion_bursts.push((current_coord, duration));
+                        }
+                    }
+                    Some(ProjectileStop::Fizzles) => {
+                        entities_to_remove.push(entity);
+                        message_log.push(LogMessage::FireballFizzles);
+                    }
+                    Some(ProjectileStop::HitsCharacter(character)) => {
+                        entities_to_remove.push(entity);
+                        match projectile_type {
+                            ProjectileType::Fireball { damage } => {
+                                fireball_hit.push((character, damage));
+                                blast_coords.push(new_coord);
+                            }
+                            ProjectileType::Confusion { duration } => {
+                                confusion_bursts.push((new_coord, duration));
+                            }
+                        }
+                    }
+                }
+
+                // ignore porjectiles hitting projectiles
+                let _ = self.spatial_table.update_coord(entity, new_coord);
+            } else {
+                entities_to_remove.push(entity);
+                // it reached the target without hitting anything
+                if let ProjectileType::Confusion { duration } = projectile_type {
+                    confusion_bursts.push((current_coord, duration));
+                }
+            }
+        }
+        for entity in entities_to_remove {
+            self.remove_entity(entity);
+        }
+        for coord in blast_coords {
+            self.spawn_visual_effect(coord, VisualEffectType::Blast);
+            self.events.push(GameEvent::ExplosionAt(coord));
+        }
+        for (entity, damage) in fireball_hit {
+            let maybe_npc = self.components.npc_type.get(entity).cloned();
+            // only the player launches projectiles
+            if let Some(VictimDies) = self.character_damage(entity, damage, DamageSource::Player) {
+                if let Some(npc) = maybe_npc {
+                    message_log.push(LogMessage::NpcDies(npc));
+                }
+            }
+        }
+        for (centre, duration) in confusion_bursts {
+            self.confuse_area(centre, duration, message_log);
+        }
+    }
+
+    // The square of cells a projectile bursting at `centre` affects, if it
+    // affects more than the character it hits
+    pub fn projectile_area(
+        centre: Coord,
+        projectile_type: ProjectileType,
+    ) -> Option<(Coord, Size)> {
+        let radius = projectile_type.area_radius()?;
+        let top_left = centre - Coord::new(radius as i32, radius as i32);
+        Some((top_left, Size::new(radius * 2 + 1, radius * 2 + 1)))
+    }
+
+    // Confuses every NPC in the square around `centre`. The player is
+    // spared, as the spell only clouds the minds of those it's aimed at.
+    pub fn confuse_area(&mut self, centre: Coord, duration: u32, message_log: &mut MessageLog) {
+        let confusion = ProjectileType::Confusion { duration };
+        let (top_left, size) = Self::projectile_area(centre, confusion).unwrap();
+        for entity in self.entities_within_rect(top_left, size, Layer::Character) {
+            if let Some(&npc_type) = self.components.npc_type.get(entity) {
+                self.components.confusion_countdown.insert(entity, duration);
+                message_log.push(LogMessage::NpcBecomesConfused(npc_type));
+            }
+        }
+    }
+
+    pub fn strength(&self, entity: Entity) -> Option<i32> {
+        self.components.strength.get(entity).cloned()
+    }
+
+    pub fn dexterity(&self, entity: Entity) -> Option<i32> {
+        self.components.dexterity.get(entity).cloned()
+    }
+
+    pub fn intelligence(&self, entity: Entity) -> Option<i32> {
+        self.components.intelligence.get(entity).cloned()
+    }
+
+    // The least and most damage a character's bump attack can do, before
+    // the victim's defense is taken off
+    pub fn attack_range(&self, entity: Entity) -> Option<(i32, i32)> {
+        let &base_damage = self.components.base_damage.get(entity)?;
+        let &strength = self.components.strength.get(entity)?;
+        let min = base_damage + self.damage_modifier(entity);
+        Some((min, min + strength + self.off_hand_damage_bonus(entity)))
+    }
+
+    // The least and most damage a character's defense takes off a hit
+    pub fn defense_range(&self, entity: Entity) -> Option<(i32, i32)> {
+        let &dexterity = self.components.dexterity.get(entity)?;
+        let min = self.defense_modifier(entity);
+        Some((min, min + dexterity))
+    }
+
+    // What to expect of the attacker bumping into the victim
+    pub fn combat_forecast(&self, attacker: Entity, victim: Entity) -> Option<CombatForecast> {
+        Some(combat::forecast(
+            self.attack_range(attacker)?,
+            self.defense_range(victim)?,
+        ))
+    }
+
+    pub fn populate<R: Rng>(
+        &mut self,
+        level: u32,
+        spawn_tables: &SpawnTables,
+        starting_kit: &StartingKit,
+        rng: &mut R,
+    ) -> Populate {
+        let terrain = terrain::generate_dungeon(
+            self.spatial_table.grid_size(),
+            level,
+            &GenerationConstraints::default(),
+            spawn_tables,
+            rng,
+        );
+        self.populate_from(terrain, level, starting_kit, rng)
+    }
+
+    // Fills the world with a level which has already been laid out, such
+    // as one read from a file. The world must be empty and the same size
+    // as the level.
+    pub fn populate_from<R: Rng>(
+        &mut self,
+        terrain: Dungeon,
+        level: u32,
+        starting_kit: &StartingKit,
+        rng: &mut R,
+    ) -> Populate {
+        #[cfg(test)]
+        NUM_POPULATED.with(|num_populated| num_populated.set(num_populated.get() + 1));
+        let mut player_entity = None;
+        let mut ai_state = ComponentTable::default();
+
+        for (coord, &terrain_tile) in terrain.grid.enumerate() {
+            match terrain_tile {
+                TerrainTile::Player => {
+                    self.spawn_floor(coord);
+                    player_entity = Some(self.spawn_player(coord));
+                }
+                TerrainTile::Floor => self.spawn_floor(coord),
+                TerrainTile::Wall => {
+                    self.spawn_floor(coord);
+                    self.spawn_wall(coord);
+                }
+                TerrainTile::Npc(npc_type) => {
+                    let entity = self.spawn_npc(coord, npc_type);
+                    self.spawn_floor(coord);
+                    ai_state.insert(entity, Agent::new(rng.gen()));
+                }
+                TerrainTile::Item(item_type) => {
+                    self.spawn_item(coord, item_type);
+                    self.spawn_floor(coord);
+                }
+                TerrainTile::Stairs => self.spawn_stairs(coord),
+                TerrainTile::StairsUp => {
+                    self.spawn_stairs_up(coord);
+                    player_entity = Some(self.spawn_player(coord));
+                }
+                TerrainTile::SecretDoor => {
+                    self.spawn_floor(coord);
+                    self.spawn_secret_door(coord);
+                }
+                TerrainTile::Water => self.spawn_liquid(coord, Liquid::Water),
+                TerrainTile::Lava => self.spawn_liquid(coord, Liquid::Lava),
+                TerrainTile::Chasm => self.spawn_chasm(coord),
+                TerrainTile::Rune => {
+                    self.spawn_rune(coord);
+                    self.spawn_floor(coord);
+                }
+                TerrainTile::Portal => self.spawn_portal(coord),
+                TerrainTile::Torch => {
+                    self.spawn_floor(coord);
+                    self.spawn_torch(coord);
+                }
+                TerrainTile::Foliage => {
+                    self.spawn_floor(coord);
+                    self.spawn_foliage(coord);
+                }
+                TerrainTile::Gold => {
+                    let amount = rng.gen_range(1..=(level * 10));
+                    self.spawn_gold(coord, amount);
+                    self.spawn_floor(coord);
+                }
+                TerrainTile::ShopItem(item_type) => {
+                    self.spawn_shop_item(coord, item_type);
+                    self.spawn_floor(coord);
+                }
+                TerrainTile::ShopExit => self.spawn_shop_exit(coord),
+                TerrainTile::Altar => self.spawn_altar(coord),
+                TerrainTile::Trap(trap_type) => self.spawn_trap(coord, trap_type),
+                TerrainTile::DoorClosed => {
+                    self.spawn_floor(coord);
+                    self.spawn_door(coord);
+                }
+            }
+        }
+        self.connect_walls();
+        let player_entity = player_entity.unwrap();
+        self.give_starting_kit(player_entity, starting_kit);
+        Populate {
+            player_entity,
+            ai_state,
+            stats: terrain.stats(),
+            level_stats: terrain.level_stats,
+        }
+    }
+
+    // The player's inventory is still empty, so the kit fills it in order
+    // with the equipment first
+    fn give_starting_kit(&mut self, player: Entity, starting_kit: &StartingKit) {
+        let equipment = starting_kit.held.iter().chain(starting_kit.worn.iter());
+        for &item_type in equipment.chain(starting_kit.items.iter()) {
+            self.grant_item(player, item_type)
+                .expect("starting kit doesn't fit in the inventory");
+        }
+        let mut inventory_index = 0;
+        if starting_kit.held.is_some() {
+            self.components
+                .equipment_held_inventory_index
+                .insert(player, inventory_index);
+            inventory_index += 1;
+        }
+        if starting_kit.worn.is_some() {
+            self.components
+                .equipment_worn_inventory_index
+                .insert(player, inventory_index);
+        }
+        *self
+            .components
+            .dexterity
+            .get_mut(player)
+            .expect("player lacks dexterity") += starting_kit.extra_dexterity;
+    }
+
+    // Puts an NPC in a free cell chosen at random
+    pub fn spawn_npc_anywhere<R: Rng>(
+        &mut self,
+        npc_type: NpcType,
+        rng: &mut R,
+    ) -> Result<Entity, ()> {
+        let coord = self.random_free_coord(rng).ok_or(())?;
+        Ok(self.spawn_npc(coord, npc_type))
+    }
+
+    // The tile drawn on top at a cell
+    pub fn top_tile_at(&self, coord: Coord) -> Option<Tile> {
+        let layers = self.spatial_table.layers_at(coord)?;
+        [
+            layers.projectile,
+            layers.character,
+            layers.gas,
+            layers.object,
+            layers.feature,
+            layers.floor,
+        ]
+        .iter()
+        .filter_map(|&entity| entity)
+        .find_map(|entity| self.components.tile.get(entity).cloned())
+    }
+
+    // The way a character goes when it means to go `direction`. A
+    // confused character stumbles a random way instead, until its
+    // confusion wears off.
+    pub fn stumble_direction<R: Rng>(
         &mut self,
         character_entity: Entity,
         direction: CardinalDirection,
-        message_log: &mut Vec<LogMessage>,
+        message_log: &mut MessageLog,
         rng: &mut R,
-    ) {
-        let player_coord = self
-            .spatial_table
-            .coord_of(character_entity)
-            .expect("player has no coord");
-        let direction = if let Some(confusion_countdown) = self
+    ) -> CardinalDirection {
+        if let Some(confusion_countdown) = self
             .components
             .confusion_countdown
             .get_mut(character_entity)
@@ -433,63 +1728,574 @@ impl World {
             rng.gen()
         } else {
             direction
-        };
+        }
+    }
+
+    // With `force_attack`, the character attacks whatever is in the way,
+    // neutral or not, and swings at the air if nothing is.
+    pub fn maybe_move_character<R: Rng>(
+        &mut self,
+        character_entity: Entity,
+        direction: CardinalDirection,
+        force_attack: bool,
+        message_log: &mut MessageLog,
+        rng: &mut R,
+    ) {
+        let player_coord = self
+            .spatial_table
+            .coord_of(character_entity)
+            .expect("player has no coord");
         let new_player_coord = player_coord + direction.coord();
         if new_player_coord.is_valid(self.spatial_table.grid_size()) {
             let dest_layers = self.spatial_table.layers_at_checked(new_player_coord);
             if let Some(dest_character_entity) = dest_layers.character {
+                if self.components.neutral.contains(dest_character_entity) {
+                    let npc_type = self.components.npc_type.get(dest_character_entity).cloned();
+                    let character_is_player = !self.components.npc_type.contains(character_entity);
+                    match npc_type {
+                        Some(npc_type) if character_is_player && force_attack => {
+                            self.components.neutral.remove(dest_character_entity);
+                            message_log.push(LogMessage::NpcBecomesHostile(npc_type));
+                        }
+                        // the player trades places with neutral characters
+                        // rather than being blocked by them
+                        Some(npc_type) if character_is_player => {
+                            self.swap_characters(character_entity, dest_character_entity);
+                            message_log.push(LogMessage::PlayerSwapsWith(npc_type));
+                            self.character_arrives(
+                                character_entity,
+                                new_player_coord,
+                                message_log,
+                                rng,
+                            );
+                            return;
+                        }
+                        _ => return,
+                    }
+                }
                 let character_is_npc = self.components.npc_type.get(character_entity).cloned();
                 let dest_character_is_npc =
                     self.components.npc_type.get(dest_character_entity).cloned();
                 if character_is_npc.is_some() != dest_character_is_npc.is_some() {
                     let outcome =
-                        self.character_bump_attack(dest_character_entity,
-                                                   character_entity,
-                                                   rng);
+                        self.character_bump_attack(dest_character_entity, character_entity, rng);
                     let npc_type = character_is_npc.or(dest_character_is_npc).unwrap();
+                    let player_was_hit =
+                        character_is_npc.is_some() && matches!(outcome, BumpAttackOutcome::Hit);
+                    self.events.push(match outcome {
+                        BumpAttackOutcome::Dodge => GameEvent::MeleeMiss,
+                        BumpAttackOutcome::Hit | BumpAttackOutcome::Kill => GameEvent::MeleeHit,
+                    });
+                    if character_is_npc.is_none() && matches!(outcome, BumpAttackOutcome::Kill) {
+                        self.events.push(GameEvent::KillingBlow(new_player_coord));
+                    }
                     Self::write_combat_log_messages(
                         character_is_npc.is_none(),
                         outcome,
                         npc_type,
                         message_log,
                     );
+                    if player_was_hit && rng.gen_bool(npc_type.blinding_chance()) {
+                        const BLINDNESS_DURATION: u32 = 8;
+                        self.components
+                            .blind_countdown
+                            .insert(dest_character_entity, BLINDNESS_DURATION);
+                        message_log.push(LogMessage::PlayerIsBlinded(npc_type));
+                    }
+                    if matches!(outcome, BumpAttackOutcome::Hit)
+                        && self.does_knock_back(character_entity, rng)
+                    {
+                        let source =
+                            character_is_npc.map_or(DamageSource::Player, DamageSource::Npc);
+                        self.push_character(
+                            dest_character_entity,
+                            direction,
+                            source,
+                            message_log,
+                            rng,
+                        );
+                    }
                 }
-            } else if dest_layers.feature.is_none() {
+            } else if force_attack {
+                message_log.push(LogMessage::PlayerSwingsAtNothing);
+            } else if self.coord_contains_chasm(new_player_coord) {
+                // Characters never stumble into a chasm. The player can
+                // only enter one by deliberately jumping in.
+            } else if let Some((door, DoorState::Closed)) = self.door_at(new_player_coord) {
+                // Opening a door takes the character's move, so NPCs can
+                // follow the player through doors at the cost of a turn.
+                self.set_door_state(door, DoorState::Open);
+                self.events.push(GameEvent::DoorOpened(new_player_coord));
+            } else if dest_layers
+                .feature
+                .map(|feature| !self.is_solid_feature(feature))
+                .unwrap_or(true)
+            {
                 self.spatial_table
                     .update_coord(character_entity, new_player_coord)
                     .unwrap();
+                self.character_arrives(character_entity, new_player_coord, message_log, rng);
+            }
+        }
+    }
+
+    // A warhammer knocks back whoever it hits, and some NPCs sometimes
+    // knock back the player
+    fn does_knock_back<R: Rng>(&self, attacker: Entity, rng: &mut R) -> bool {
+        match self.components.npc_type.get(attacker) {
+            // only rolled for NPCs which can, so other fights use the rng
+            // as they always have
+            Some(npc_type) => {
+                let chance = npc_type.knockback_chance();
+                chance > 0.0 && rng.gen_bool(chance)
+            }
+            None => self
+                .components
+                .equipment_held_inventory_index
+                .get(attacker)
+                .and_then(|&held_index| self.inventory_item_type(attacker, held_index))
+                .map_or(false, ItemType::knocks_back),
+        }
+    }
+
+    // Knocks a character one cell in the direction. Being knocked into a
+    // wall or another character hurts, and landing on a trap or in a
+    // chasm works as if the character had stepped there. The player falls
+    // once the turn is over, while an NPC is gone for good. Any damage
+    // from a collision is blamed on `source`, whoever did the knocking.
+    fn push_character<R: Rng>(
+        &mut self,
+        character: Entity,
+        direction: CardinalDirection,
+        source: DamageSource,
+        message_log: &mut MessageLog,
+        rng: &mut R,
+    ) {
+        const COLLISION_DAMAGE: u32 = 2;
+        // for the character knocked into
+        const COLLIDED_DAMAGE: u32 = 1;
+        let coord = self
+            .spatial_table
+            .coord_of(character)
+            .expect("character has no coord");
+        let dest = coord + direction.coord();
+        let maybe_npc = self.components.npc_type.get(character).cloned();
+        let dest_layers = self.spatial_table.layers_at(dest);
+        let dest_character = dest_layers.and_then(|layers| layers.character);
+        let is_blocked = dest_layers.map_or(true, |layers| {
+            layers
+                .feature
+                .map_or(false, |feature| self.is_solid_feature(feature))
+        });
+        if let Some(other) = dest_character {
+            if let Some(&other_npc) = self.components.npc_type.get(other) {
+                message_log.push(LogMessage::SlamsInto(maybe_npc, other_npc));
+            }
+            self.collision_damage(character, COLLISION_DAMAGE, source, message_log);
+            self.collision_damage(other, COLLIDED_DAMAGE, source, message_log);
+        } else if is_blocked {
+            message_log.push(LogMessage::SlamsIntoWall(maybe_npc));
+            self.collision_damage(character, COLLISION_DAMAGE, source, message_log);
+        } else {
+            self.spatial_table.update_coord(character, dest).unwrap();
+            if self.coord_contains_chasm(dest) {
+                message_log.push(LogMessage::KnockedIntoChasm(maybe_npc));
+                if maybe_npc.is_some() {
+                    self.remove_entity(character);
+                }
+                return;
+            }
+            self.character_arrives(character, dest, message_log, rng);
+        }
+    }
+
+    fn collision_damage(
+        &mut self,
+        character: Entity,
+        damage: u32,
+        source: DamageSource,
+        message_log: &mut MessageLog,
+    ) {
+        let maybe_npc = self.components.npc_type.get(character).cloned();
+        if let Some(VictimDies) = self.character_damage(character, damage, source) {
+            if let Some(npc_type) = maybe_npc {
+                message_log.push(LogMessage::NpcDies(npc_type));
+            }
+        }
+    }
+
+    // What happens when a character steps into a cell
+    fn character_arrives<R: Rng>(
+        &mut self,
+        character_entity: Entity,
+        coord: Coord,
+        message_log: &mut MessageLog,
+        rng: &mut R,
+    ) {
+        if self.coord_contains_shop_exit(coord)
+            && self
+                .components
+                .debt
+                .get(character_entity)
+                .cloned()
+                .unwrap_or(0)
+                > 0
+        {
+            self.shopkeepers_become_hostile(message_log);
+        }
+        if let Some(trap_entity) = self.trap_at(coord) {
+            self.trigger_trap(character_entity, trap_entity, message_log, rng);
+        }
+    }
+
+    // The spatial table never holds two characters in one cell, so one
+    // is taken out while the other moves into its place.
+    fn swap_characters(&mut self, a: Entity, b: Entity) {
+        let a_coord = self
+            .spatial_table
+            .coord_of(a)
+            .expect("character has no coord");
+        let b_coord = self
+            .spatial_table
+            .coord_of(b)
+            .expect("character has no coord");
+        self.spatial_table.remove(a);
+        self.spatial_table.update_coord(b, a_coord).unwrap();
+        self.spatial_table
+            .update(
+                a,
+                Location {
+                    coord: b_coord,
+                    layer: Some(Layer::Character),
+                },
+            )
+            .unwrap();
+    }
+
+    fn trap_at(&self, coord: Coord) -> Option<Entity> {
+        self.spatial_table
+            .layers_at(coord)
+            .and_then(|layers| layers.floor)
+            .filter(|&floor_entity| self.components.trap.contains(floor_entity))
+    }
+
+    fn coord_contains_revealed_trap(&self, coord: Coord) -> bool {
+        self.trap_at(coord)
+            .and_then(|trap_entity| self.components.trap.get(trap_entity))
+            .map(|trap| trap.revealed)
+            .unwrap_or(false)
+    }
+
+    fn reveal_trap(&mut self, trap_entity: Entity) {
+        if let Some(trap) = self.components.trap.get_mut(trap_entity) {
+            trap.revealed = true;
+            let trap_type = trap.trap_type;
+            self.components
+                .tile
+                .insert(trap_entity, Tile::Trap(trap_type));
+        }
+    }
+
+    fn trigger_trap<R: Rng>(
+        &mut self,
+        character: Entity,
+        trap_entity: Entity,
+        message_log: &mut MessageLog,
+        rng: &mut R,
+    ) {
+        let trap_type = match self.components.trap.get(trap_entity) {
+            Some(trap) => trap.trap_type,
+            None => return,
+        };
+        self.reveal_trap(trap_entity);
+        let maybe_npc = self.components.npc_type.get(character).cloned();
+        message_log.push(LogMessage::TrapTriggered(maybe_npc, trap_type));
+        match trap_type {
+            TrapType::Spike => {
+                const MIN_DAMAGE: u32 = 1;
+                const MAX_DAMAGE: u32 = 3;
+                let damage = rng.gen_range(MIN_DAMAGE..=MAX_DAMAGE);
+                let source = DamageSource::Trap(trap_type);
+                if let Some(VictimDies) = self.character_damage(character, damage, source) {
+                    if let Some(npc) = maybe_npc {
+                        message_log.push(LogMessage::NpcDies(npc));
+                    }
+                }
+            }
+            TrapType::Teleport => {
+                if let Some(coord) = self.random_free_coord(rng) {
+                    self.spatial_table.update_coord(character, coord).unwrap();
+                }
+            }
+            TrapType::Alarm => {
+                const ALARM_DISTANCE: u32 = 15;
+                let trap_coord = self
+                    .spatial_table
+                    .coord_of(trap_entity)
+                    .expect("trap has no coord");
+                let alarmed = self
+                    .characters_within(trap_coord, ALARM_DISTANCE)
+                    .into_iter()
+                    .filter(|&entity| self.components.npc_type.contains(entity))
+                    .collect::<Vec<_>>();
+                for entity in alarmed {
+                    self.components.alarmed.insert(entity, ());
+                }
+            }
+        }
+    }
+
+    // Each turn the character has a chance, based on their dexterity,
+    // of noticing each hidden trap adjacent to them.
+    pub fn passive_search<R: Rng>(
+        &mut self,
+        character: Entity,
+        message_log: &mut MessageLog,
+        rng: &mut R,
+    ) {
+        let dexterity = self.dexterity(character).unwrap_or(0).max(0) as u32;
+        const PERCENT_PER_DEXTERITY: u32 = 10;
+        const MIN_PERCENT: u32 = 5;
+        let percent = (dexterity * PERCENT_PER_DEXTERITY)
+            .max(MIN_PERCENT)
+            .min(100);
+        self.search(character, percent, message_log, rng);
+    }
+
+    // Deliberately searching is much more likely to find things than
+    // noticing them in passing, and benefits from intelligence as well
+    // as dexterity.
+    pub fn active_search<R: Rng>(
+        &mut self,
+        character: Entity,
+        message_log: &mut MessageLog,
+        rng: &mut R,
+    ) {
+        let dexterity = self.dexterity(character).unwrap_or(0).max(0) as u32;
+        let intelligence = self.intelligence(character).unwrap_or(0).max(0) as u32;
+        const BASE_PERCENT: u32 = 20;
+        const PERCENT_PER_STAT: u32 = 15;
+        let percent = (BASE_PERCENT + (dexterity + intelligence) * PERCENT_PER_STAT).min(100);
+        self.search(character, percent, message_log, rng);
+    }
+
+    // Each hidden trap and secret door adjacent to the character is
+    // found with the given percentage chance.
+    fn search<R: Rng>(
+        &mut self,
+        character: Entity,
+        percent: u32,
+        message_log: &mut MessageLog,
+        rng: &mut R,
+    ) {
+        let coord = match self.spatial_table.coord_of(character) {
+            Some(coord) => coord,
+            None => return,
+        };
+        let secret_doors = Direction::all()
+            .filter_map(|direction| {
+                self.spatial_table
+                    .layers_at(coord + direction.coord())
+                    .and_then(|layers| layers.feature)
+            })
+            .filter(|&feature| self.components.secret_door.contains(feature))
+            .collect::<Vec<_>>();
+        for secret_door in secret_doors {
+            if rng.gen_ratio(percent, 100) {
+                self.components.secret_door.remove(secret_door);
+                self.set_door_state(secret_door, DoorState::Closed);
+                message_log.push(LogMessage::PlayerFindsSecretDoor);
+            }
+        }
+        let hidden_traps = Direction::all()
+            .filter_map(|direction| self.trap_at(coord + direction.coord()))
+            .filter(|&trap_entity| {
+                self.components
+                    .trap
+                    .get(trap_entity)
+                    .map(|trap| !trap.revealed)
+                    .unwrap_or(false)
+            })
+            .collect::<Vec<_>>();
+        for trap_entity in hidden_traps {
+            if rng.gen_ratio(percent, 100) {
+                self.reveal_trap(trap_entity);
+                let trap_type = self.components.trap.get(trap_entity).unwrap().trap_type;
+                message_log.push(LogMessage::PlayerNoticesTrap(trap_type));
             }
         }
     }
 
+    // Returns the NPCs which heard an alarm since the last call.
+    pub fn take_alarmed(&mut self) -> Vec<Entity> {
+        let alarmed = self.components.alarmed.entities().collect::<Vec<_>>();
+        self.components.alarmed.clear();
+        alarmed
+    }
+
+    fn coord_contains_shop_exit(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at_checked(coord)
+            .floor
+            .map(|floor_entity| self.components.shop_exit.contains(floor_entity))
+            .unwrap_or(false)
+    }
+
+    fn shopkeepers_become_hostile(&mut self, message_log: &mut MessageLog) {
+        let shopkeepers = self
+            .components
+            .neutral
+            .entities()
+            .filter(|&entity| self.components.npc_type.get(entity) == Some(&NpcType::Shopkeeper))
+            .collect::<Vec<_>>();
+        for entity in shopkeepers {
+            self.components.neutral.remove(entity);
+            message_log.push(LogMessage::NpcBecomesHostile(NpcType::Shopkeeper));
+        }
+    }
+
+    // True if the thing lying at `coord` belongs to a shop
+    pub fn is_for_sale_at(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at(coord)
+            .and_then(|layers| layers.object)
+            .map(|object| self.components.price.contains(object))
+            .unwrap_or(false)
+    }
+
+    pub fn is_neutral(&self, entity: Entity) -> bool {
+        self.components.neutral.contains(entity)
+    }
+
+    pub fn maybe_pay(&mut self, character: Entity, message_log: &mut MessageLog) -> Result<(), ()> {
+        let coord = self
+            .spatial_table
+            .coord_of(character)
+            .expect("character has no coord");
+        let shopkeeper_is_adjacent = self.components.neutral.entities().any(|entity| {
+            self.components.npc_type.get(entity) == Some(&NpcType::Shopkeeper)
+                && self
+                    .spatial_table
+                    .coord_of(entity)
+                    .map(|shopkeeper_coord| {
+                        let delta = shopkeeper_coord - coord;
+                        delta.x.abs() <= 1 && delta.y.abs() <= 1
+                    })
+                    .unwrap_or(false)
+        });
+        if !shopkeeper_is_adjacent {
+            message_log.push(LogMessage::NoShopkeeperNearby);
+            return Err(());
+        }
+        let debt = self.components.debt.get(character).cloned().unwrap_or(0);
+        if debt == 0 {
+            message_log.push(LogMessage::NothingToPayFor);
+            return Err(());
+        }
+        let gold = self
+            .components
+            .gold
+            .get_mut(character)
+            .expect("character has no gold");
+        if *gold < debt {
+            message_log.push(LogMessage::CannotAffordDebt(debt));
+            return Err(());
+        }
+        *gold -= debt;
+        self.components.debt.insert(character, 0);
+        let inventory = self
+            .components
+            .inventory
+            .get(character)
+            .expect("character has no inventory");
+        for &item in inventory.slots().iter().flatten() {
+            self.components.price.remove(item);
+        }
+        message_log.push(LogMessage::PlayerPays(debt));
+        Ok(())
+    }
+
     pub fn maybe_get_item(
         &mut self,
         character: Entity,
-        message_log: &mut Vec<LogMessage>,
+        message_log: &mut MessageLog,
     ) -> Result<(), ()> {
         let coord = self
             .spatial_table
             .coord_of(character)
             .expect("character has no coord");
-        if let Some(object_entity) =
-            self.spatial_table.layers_at_checked(coord).object {
-                if let Some(&item_type) = self.components.item.get(object_entity) {
-                    // assumes only player characters can get items
-                    let inventory = self
-                        .components
-                        .inventory
-                        .get_mut(character)
-                        .expect("character has no inventory");
-                    if inventory.insert(object_entity).is_ok() {
-                        self.spatial_table.remove(object_entity);
-                        message_log.push(LogMessage::PlayerGets(item_type));
-                        return Ok(());
-                    } else {
-                        message_log.push(LogMessage::PlayerInventoryIsFull);
+        if let Some(object_entity) = self.spatial_table.layers_at_checked(coord).object {
+            if let Some(&amount) = self.components.gold.get(object_entity) {
+                *self
+                    .components
+                    .gold
+                    .get_mut(character)
+                    .expect("character has no gold") += amount;
+                self.remove_entity(object_entity);
+                message_log.push(LogMessage::PlayerGetsGold(amount));
+                return Ok(());
+            }
+            if self.components.rune.contains(object_entity) {
+                let runes = self
+                    .components
+                    .runes
+                    .get_mut(character)
+                    .expect("character has no runes");
+                *runes += 1;
+                let runes = *runes;
+                self.remove_entity(object_entity);
+                message_log.push(LogMessage::PlayerGetsRune(runes));
+                if runes == terrain::NUM_RUNES {
+                    self.unseal_portals();
+                    message_log.push(LogMessage::PortalOpens);
+                }
+                return Ok(());
+            }
+            if let Some(&item_type) = self.components.item.get(object_entity) {
+                // assumes only player characters can get items
+                let inventory = self
+                    .components
+                    .inventory
+                    .get_mut(character)
+                    .expect("character has no inventory");
+                // a backpack is put on straight away, and adds slots
+                // rather than taking one
+                if item_type == ItemType::Backpack {
+                    if inventory.grow(BACKPACK_SLOTS).is_err() {
+                        message_log.push(LogMessage::InventoryCannotGrow);
                         return Err(());
                     }
+                    let num_slots = inventory.slots().len() as u32;
+                    message_log.push(LogMessage::PlayerPutsOnBackpack(num_slots));
+                    self.stat_events.push(StatEvent::PlayerPicksUp(item_type));
+                    self.events.push(GameEvent::ItemPickedUp(item_type));
+                    if let Some(&price) = self.components.price.get(object_entity) {
+                        if let Some(debt) = self.components.debt.get_mut(character) {
+                            *debt += price;
+                        }
+                        message_log.push(LogMessage::PlayerOwes(price));
+                    }
+                    self.remove_entity(object_entity);
+                    return Ok(());
+                }
+                if inventory.insert(object_entity).is_ok() {
+                    self.spatial_table.remove(object_entity);
+                    message_log.push(LogMessage::PlayerGets(item_type));
+                    self.stat_events.push(StatEvent::PlayerPicksUp(item_type));
+                    self.events.push(GameEvent::ItemPickedUp(item_type));
+                    if let Some(&price) = self.components.price.get(object_entity) {
+                        if let Some(debt) = self.components.debt.get_mut(character) {
+                            *debt += price;
+                        }
+                        message_log.push(LogMessage::PlayerOwes(price));
+                    }
+                    return Ok(());
+                } else {
+                    message_log.push(LogMessage::PlayerInventoryIsFull);
+                    return Err(());
                 }
             }
+        }
         message_log.push(LogMessage::NoItemUnderPlayer);
         Err(())
     }
@@ -498,7 +2304,7 @@ impl World {
         &mut self,
         character: Entity,
         inventory_index: usize,
-        message_log: &mut Vec<LogMessage>,
+        message_log: &mut MessageLog,
     ) -> Result<ItemUsage, ()> {
         let inventory = self
             .components
@@ -519,33 +2325,58 @@ impl World {
             .expect("non-item in inventory");
         let usage = match item_type {
             ItemType::HealthPotion => {
-                let mut hit_points = self
-                    .components
+                const HEALTH_TO_HEAL: u32 = 5;
+                self.components
                     .hit_points
                     .get_mut(character)
-                    .expect("character has no hit points");
-                const HEALTH_TO_HEAL: u32 = 5;
-                hit_points.current = hit_points.max.min(hit_points.current + HEALTH_TO_HEAL);
+                    .expect("character has no hit points")
+                    .give(HEALTH_TO_HEAL);
                 inventory.remove(inventory_index).unwrap();
                 message_log.push(LogMessage::PlayerHeals);
+                self.spawn_visual_effect_on(character, VisualEffectType::Sparkle);
                 ItemUsage::Immediate
             }
-            ItemType::FireballScroll | ItemType::ConfusionScroll => ItemUsage::Aim,
-            ItemType::Sword | ItemType::Staff => {
+            ItemType::ClairvoyanceScroll => {
+                const CLAIRVOYANCE_DURATION: u32 = 20;
                 self.components
-                    .equipment_held_inventory_index
-                    .insert(character, inventory_index);
-                message_log.push(LogMessage::PlayerEquips(item_type));
+                    .clairvoyance_countdown
+                    .insert(character, CLAIRVOYANCE_DURATION);
+                inventory.remove(inventory_index).unwrap();
+                message_log.push(LogMessage::PlayerReadsClairvoyance);
                 ItemUsage::Immediate
             }
-            ItemType::Armor | ItemType::Robe => {
-                self.components
-                    .equipment_worn_inventory_index
-                    .insert(character, inventory_index);
-                message_log.push(LogMessage::PlayerEquips(item_type));
+            ItemType::SmokeBomb => {
+                let coord = self
+                    .spatial_table
+                    .coord_of(character)
+                    .expect("character has no coord");
+                inventory.remove(inventory_index).unwrap();
+                message_log.push(LogMessage::PlayerThrowsSmokeBomb);
+                self.release_smoke(coord);
+                ItemUsage::Immediate
+            }
+            ItemType::Torch => {
+                // carrying a torch is enough to light it
+                message_log.push(LogMessage::TorchIsAlreadyLit);
+                return Err(());
+            }
+            ItemType::FireballScroll | ItemType::ConfusionScroll => ItemUsage::Aim,
+            ItemType::Sword
+            | ItemType::Staff
+            | ItemType::Warhammer
+            | ItemType::Armor
+            | ItemType::Robe => {
+                self.equip(character, inventory_index, item_type, message_log)?;
                 ItemUsage::Immediate
             }
+            // backpacks never go in the inventory
+            ItemType::Backpack => return Err(()),
         };
+        // aimed items are counted once they are launched
+        if let ItemUsage::Immediate = usage {
+            self.stat_events.push(StatEvent::PlayerUses(item_type));
+            self.events.push(GameEvent::ItemUsed(item_type));
+        }
         Ok(usage)
     }
 
@@ -554,7 +2385,7 @@ impl World {
         character: Entity,
         inventory_index: usize,
         target: Coord,
-        message_log: &mut Vec<LogMessage>,
+        message_log: &mut MessageLog,
     ) -> Result<(), ()> {
         let character_coord = self.spatial_table.coord_of(character).unwrap();
         if character_coord == target {
@@ -567,36 +2398,170 @@ impl World {
             .expect("character has no inventory");
         let item_entity = inventory.remove(inventory_index).unwrap();
         let &item_type = self.components.item.get(item_entity).unwrap();
-        match item_type {
-            ItemType::HealthPotion
-                | ItemType::Sword
-                | ItemType::Staff
-                | ItemType::Armor
-                | ItemType::Robe => panic!("invalid item for aim"),
-            ItemType::FireballScroll => {
-                let fireball = ProjectileType::Fireball {
-                    damage: self.magic(character).max(0) as u32,
-                };
-                message_log.push(LogMessage::PlayerLaunchesProjectile(fireball));
-                self.spawn_projectile(character_coord, target, fireball);
+        let projectile = self
+            .aimed_projectile(character, item_type)
+            .expect("invalid item for aim");
+        message_log.push(LogMessage::PlayerLaunchesProjectile(projectile));
+        self.stat_events.push(StatEvent::PlayerUses(item_type));
+        self.events.push(GameEvent::ItemUsed(item_type));
+        self.spawn_projectile(character_coord, target, projectile);
+        Ok(())
+    }
+
+    // Puts the weapon in the inventory slot in the character's hands
+    pub fn maybe_wield_item(
+        &mut self,
+        character: Entity,
+        inventory_index: usize,
+        message_log: &mut MessageLog,
+    ) -> Result<(), ()> {
+        let item_type = self.item_type_in_slot(character, inventory_index, message_log)?;
+        if !item_type
+            .equipment_class()
+            .map_or(false, EquipmentClass::is_weapon)
+        {
+            message_log.push(LogMessage::CannotWield(item_type));
+            return Err(());
+        }
+        self.equip(character, inventory_index, item_type, message_log)?;
+        self.events.push(GameEvent::ItemUsed(item_type));
+        Ok(())
+    }
+
+    pub fn maybe_wear_item(
+        &mut self,
+        character: Entity,
+        inventory_index: usize,
+        message_log: &mut MessageLog,
+    ) -> Result<(), ()> {
+        let item_type = self.item_type_in_slot(character, inventory_index, message_log)?;
+        if item_type.equipment_class() != Some(EquipmentClass::Body) {
+            message_log.push(LogMessage::CannotWear(item_type));
+            return Err(());
+        }
+        self.equip(character, inventory_index, item_type, message_log)?;
+        self.events.push(GameEvent::ItemUsed(item_type));
+        Ok(())
+    }
+
+    pub fn maybe_unequip_item(
+        &mut self,
+        character: Entity,
+        inventory_index: usize,
+        message_log: &mut MessageLog,
+    ) -> Result<(), ()> {
+        let item_type = self.item_type_in_slot(character, inventory_index, message_log)?;
+        if !self.unequip(character, inventory_index) {
+            message_log.push(LogMessage::ItemIsNotEquipped(item_type));
+            return Err(());
+        }
+        message_log.push(LogMessage::PlayerUnequips(item_type));
+        Ok(())
+    }
+
+    fn item_type_in_slot(
+        &self,
+        character: Entity,
+        inventory_index: usize,
+        message_log: &mut MessageLog,
+    ) -> Result<ItemType, ()> {
+        match self.inventory_item_type(character, inventory_index) {
+            Some(item_type) => Ok(item_type),
+            None => {
+                message_log.push(LogMessage::NoItemInInventorySlot);
+                Err(())
             }
-            ItemType::ConfusionScroll => {
-                let confusion = ProjectileType::Confusion {
-                    duration: self.magic(character).max(0) as u32
-                        * 3,
-                };
-                message_log.push(LogMessage::PlayerLaunchesProjectile(confusion));
-                self.spawn_projectile(character_coord, target, confusion);
+        }
+    }
+
+    // A two-handed weapon takes both hands. A one-handed weapon goes in
+    // the main hand, or in the off hand if the main hand already holds a
+    // one-handed weapon.
+    fn equip(
+        &mut self,
+        character: Entity,
+        inventory_index: usize,
+        item_type: ItemType,
+        message_log: &mut MessageLog,
+    ) -> Result<(), ()> {
+        let equipped = self.equipped_inventory_indices(character);
+        if equipped.contains(inventory_index) {
+            message_log.push(LogMessage::ItemIsAlreadyEquipped(item_type));
+            return Err(());
+        }
+        let main_hand_class = equipped
+            .main_hand
+            .and_then(|index| self.inventory_item_type(character, index))
+            .and_then(ItemType::equipment_class);
+        match item_type.equipment_class() {
+            Some(EquipmentClass::TwoHanded) => {
+                self.components
+                    .equipment_held_inventory_index
+                    .insert(character, inventory_index);
+                self.components
+                    .equipment_off_hand_inventory_index
+                    .remove(character);
+            }
+            Some(EquipmentClass::OneHanded)
+                if main_hand_class == Some(EquipmentClass::OneHanded) =>
+            {
+                self.components
+                    .equipment_off_hand_inventory_index
+                    .insert(character, inventory_index);
+            }
+            Some(EquipmentClass::OneHanded) => {
+                self.components
+                    .equipment_held_inventory_index
+                    .insert(character, inventory_index);
             }
+            Some(EquipmentClass::Body) => {
+                self.components
+                    .equipment_worn_inventory_index
+                    .insert(character, inventory_index);
+            }
+            None => return Err(()),
         }
+        message_log.push(LogMessage::PlayerEquips(item_type));
         Ok(())
     }
 
+    // Takes the item in the inventory slot out of whichever equipment
+    // slot it's in. A weapon left in the off hand moves to the main hand.
+    // False if the item wasn't equipped.
+    fn unequip(&mut self, character: Entity, inventory_index: usize) -> bool {
+        let mut unequipped = false;
+        let components = &mut self.components;
+        for equipment in [
+            &mut components.equipment_held_inventory_index,
+            &mut components.equipment_off_hand_inventory_index,
+            &mut components.equipment_worn_inventory_index,
+        ] {
+            if equipment.get(character) == Some(&inventory_index) {
+                equipment.remove(character);
+                unequipped = true;
+            }
+        }
+        if !components
+            .equipment_held_inventory_index
+            .contains(character)
+        {
+            let off_hand = components
+                .equipment_off_hand_inventory_index
+                .remove(character);
+            if let Some(off_hand) = off_hand {
+                components
+                    .equipment_held_inventory_index
+                    .insert(character, off_hand);
+            }
+        }
+        unequipped
+    }
+
     pub fn maybe_drop_item(
         &mut self,
         character: Entity,
         inventory_index: usize,
-        message_log: &mut Vec<LogMessage>,
+        message_log: &mut MessageLog,
     ) -> Result<(), ()> {
         let coord = self
             .spatial_table
@@ -606,28 +2571,7 @@ impl World {
             message_log.push(LogMessage::NoSpaceToDropItem);
             return Err(());
         }
-        if self
-            .components
-            .equipment_held_inventory_index
-            .get(character)
-            .cloned()
-            == Some(inventory_index)
-        {
-            self.components
-                .equipment_held_inventory_index
-                .remove(character);
-        }
-        if self
-            .components
-            .equipment_worn_inventory_index
-            .get(character)
-            .cloned()
-            == Some(inventory_index)
-        {
-            self.components
-                .equipment_worn_inventory_index
-                .remove(character);
-        }
+        self.unequip(character, inventory_index);
         let inventory = self
             .components
             .inventory
@@ -647,7 +2591,13 @@ impl World {
                     coord,
                     layer: Some(Layer::Object),
                 },
-            ).unwrap();
+            )
+            .unwrap();
+        if let Some(&price) = self.components.price.get(item) {
+            if let Some(debt) = self.components.debt.get_mut(character) {
+                *debt = debt.saturating_sub(price);
+            }
+        }
         let &item_type = self
             .components
             .item
@@ -657,6 +2607,98 @@ impl World {
         Ok(())
     }
 
+    // Prays at the altar under the character. Returns the entities of
+    // any NPCs summoned as punishment so the caller can give them AI.
+    pub fn maybe_pray<R: Rng>(
+        &mut self,
+        character: Entity,
+        message_log: &mut MessageLog,
+        rng: &mut R,
+    ) -> Result<Vec<Entity>, ()> {
+        let coord = self
+            .spatial_table
+            .coord_of(character)
+            .expect("character has no coord");
+        let altar = match self.altar_at(coord) {
+            Some(altar) => altar,
+            None => return Err(()),
+        };
+        let use_count = self
+            .components
+            .altar_use_count
+            .get_mut(altar)
+            .expect("altar has no use count");
+        // Each prayer at the same altar makes the gods less patient
+        const PUNISHMENT_WEIGHT_BASE: u32 = 10;
+        const PUNISHMENT_WEIGHT_PER_USE: u32 = 25;
+        let punishment_weight = PUNISHMENT_WEIGHT_BASE + PUNISHMENT_WEIGHT_PER_USE * *use_count;
+        *use_count += 1;
+        let outcomes = [
+            (PrayerOutcome::Heal, 30),
+            (PrayerOutcome::Stat, 30),
+            (PrayerOutcome::Identify, 20),
+            (PrayerOutcome::Punish, punishment_weight),
+        ];
+        let total = outcomes.iter().map(|&(_, weight)| weight).sum::<u32>();
+        let mut choice = rng.gen_range(0..total);
+        let mut outcome = PrayerOutcome::Punish;
+        for &(candidate, weight) in outcomes.iter() {
+            if choice < weight {
+                outcome = candidate;
+                break;
+            }
+            choice -= weight;
+        }
+        let mut summoned = Vec::new();
+        match outcome {
+            PrayerOutcome::Heal => {
+                self.components
+                    .hit_points
+                    .get_mut(character)
+                    .expect("character has no hit points")
+                    .fill();
+                message_log.push(LogMessage::PrayerHeals);
+                self.spawn_visual_effect_on(character, VisualEffectType::Sparkle);
+            }
+            PrayerOutcome::Stat => {
+                let level_up = match rng.gen_range(0..3) {
+                    0 => LevelUp::Strength,
+                    1 => LevelUp::Dexterity,
+                    _ => LevelUp::Intelligence,
+                };
+                self.level_up_character(character, level_up);
+                message_log.push(LogMessage::PrayerIncreasesStat(level_up));
+            }
+            PrayerOutcome::Identify => {
+                message_log.push(LogMessage::PrayerIdentifiesInventory);
+            }
+            PrayerOutcome::Punish => {
+                const NUM_SUMMONED: usize = 2;
+                let free_coords = Direction::all()
+                    .map(|direction| coord + direction.coord())
+                    .filter(|&neighbour| self.can_spawn_character_at(neighbour))
+                    .collect::<Vec<_>>();
+                for neighbour in free_coords.choose_multiple(rng, NUM_SUMMONED) {
+                    let npc_type = if rng.gen_ratio(1, 3) {
+                        NpcType::Troll
+                    } else {
+                        NpcType::Orc
+                    };
+                    summoned.push(self.spawn_npc(*neighbour, npc_type));
+                }
+                message_log.push(LogMessage::PrayerAngersGods);
+            }
+        }
+        Ok(summoned)
+    }
+
+    fn can_spawn_character_at(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at(coord)
+            .map(|layers| layers.character.is_none() && layers.feature.is_none())
+            .unwrap_or(false)
+    }
+
     pub fn level_up_character(&mut self, character_entity: Entity, level_up: LevelUp) {
         match level_up {
             LevelUp::Strength => {
@@ -687,8 +2729,7 @@ impl World {
                     .get_mut(character_entity)
                     .expect("character lacks hit points");
                 const INCREASE: u32 = 5;
-                hit_points.current += INCREASE;
-                hit_points.max += INCREASE;
+                hit_points.set_max(hit_points.max() + INCREASE, true);
             }
         }
     }
@@ -699,21 +2740,19 @@ impl World {
         attacker: Entity,
         rng: &mut R,
     ) -> BumpAttackOutcome {
-        let &attacker_base_damage = self.components.base_damage.get(attacker).unwrap();
-        let &attacker_strength = self.components.strength.get(attacker).unwrap();
-        let attacker_damage_modifier = self.damage_modifier(attacker);
-        let &victim_dexterity = self.components.dexterity.get(victim).unwrap();
-        let victim_defense_modifier = self.defense_modifier(victim);
-        let gross_damage = attacker_base_damage
-            + rng.gen_range(0..(attacker_strength + 1))
-            + attacker_damage_modifier;
-        let damage_reduction = rng.gen_range(0..(victim_dexterity + 1))
-            + victim_defense_modifier;
-        let net_damage = gross_damage.saturating_sub(damage_reduction).max(0) as u32;
+        let attack = self.attack_range(attacker).unwrap();
+        let defense = self.defense_range(victim).unwrap();
+        let attack_roll = rng.gen_range(0..combat::num_rolls(attack));
+        let defense_roll = rng.gen_range(0..combat::num_rolls(defense));
+        let net_damage = combat::net_damage(attack, defense, attack_roll, defense_roll);
         if net_damage == 0 {
             BumpAttackOutcome::Dodge
         } else {
-            if self.character_damage(victim, net_damage).is_some() {
+            let source = match self.components.npc_type.get(attacker) {
+                Some(&npc_type) => DamageSource::Npc(npc_type),
+                None => DamageSource::Player,
+            };
+            if self.character_damage(victim, net_damage, source).is_some() {
                 BumpAttackOutcome::Kill
             } else {
                 BumpAttackOutcome::Hit
@@ -721,10 +2760,42 @@ impl World {
         }
     }
 
-    fn character_damage(&mut self, victim: Entity, damage: u32) -> Option<VictimDies> {
+    fn character_damage(
+        &mut self,
+        victim: Entity,
+        damage: u32,
+        source: DamageSource,
+    ) -> Option<VictimDies> {
         if let Some(hit_points) = self.components.hit_points.get_mut(victim) {
-            hit_points.current = hit_points.current.saturating_sub(damage);
-            if hit_points.current == 0 {
+            let hit_points_lost = hit_points.take(damage);
+            let victim_dies = hit_points.is_empty();
+            let maybe_npc = self.components.npc_type.get(victim).cloned();
+            match maybe_npc {
+                None => {
+                    self.stat_events
+                        .push(StatEvent::PlayerTakesDamage(hit_points_lost));
+                    if damage > 0 {
+                        self.events.push(GameEvent::PlayerDamaged);
+                    }
+                    if victim_dies {
+                        self.stat_events.push(StatEvent::PlayerDies(source));
+                        self.events.push(GameEvent::PlayerDied);
+                    }
+                }
+                Some(npc_type) => {
+                    if source == DamageSource::Player {
+                        self.stat_events
+                            .push(StatEvent::PlayerDealsDamage(hit_points_lost));
+                        if victim_dies {
+                            self.stat_events.push(StatEvent::PlayerKills(npc_type));
+                        }
+                    }
+                    if victim_dies {
+                        self.events.push(GameEvent::NpcDied(npc_type));
+                    }
+                }
+            }
+            if victim_dies {
                 self.character_die(victim);
                 return Some(VictimDies);
             }
@@ -732,12 +2803,42 @@ impl World {
         None
     }
 
+    // Doors are considered passable when NPCs plan their paths, since
+    // an NPC can open a closed door by walking into it.
+    fn can_npc_pass_feature(&self, feature: Option<Entity>) -> bool {
+        feature
+            .map(|feature| {
+                self.components.door.contains(feature) || !self.is_solid_feature(feature)
+            })
+            .unwrap_or(true)
+    }
 
     pub fn can_npc_enter_ignoring_other_npcs(&self, coord: Coord) -> bool {
         self.spatial_table
             .layers_at(coord)
-            .map(|layers| layers.feature.is_none())
+            .map(|layers| self.can_npc_pass_feature(layers.feature))
             .unwrap_or(false)
+            && !self.coord_contains_revealed_trap(coord)
+            && self.liquid_at(coord) != Some(Liquid::Lava)
+            && !self.coord_contains_chasm(coord)
+    }
+
+    // What it costs an NPC to step into the cell when planning a route,
+    // ignoring other NPCs. None if it can't. A closed door takes a turn
+    // to open, and NPCs would rather keep out of water, so they go around
+    // either when the way around is short.
+    pub fn npc_step_cost(&self, coord: Coord) -> Option<u32> {
+        if !self.can_npc_enter_ignoring_other_npcs(coord) {
+            return None;
+        }
+        let mut cost = 1;
+        if matches!(self.door_at(coord), Some((_, DoorState::Closed))) {
+            cost += 1;
+        }
+        if self.liquid_at(coord) == Some(Liquid::Water) {
+            cost += 1;
+        }
+        Some(cost)
     }
 
     pub fn can_npc_enter(&self, coord: Coord) -> bool {
@@ -748,10 +2849,12 @@ impl World {
                     .character
                     .map(|entity| self.components.npc_type.contains(entity))
                     .unwrap_or(false);
-                let contains_feature = layers.feature.is_some();
-                !(contains_npc || contains_feature)
+                !contains_npc && self.can_npc_pass_feature(layers.feature)
             })
             .unwrap_or(false)
+            && !self.coord_contains_revealed_trap(coord)
+            && self.liquid_at(coord) != Some(Liquid::Lava)
+            && !self.coord_contains_chasm(coord)
     }
 
     pub fn entity_coord(&self, entity: Entity) -> Option<Coord> {
@@ -761,7 +2864,13 @@ impl World {
     pub fn can_npc_see_through_cell(&self, coord: Coord) -> bool {
         self.spatial_table
             .layers_at(coord)
-            .map(|layers| layers.feature.is_none())
+            .map(|layers| {
+                layers.gas.is_none()
+                    && layers
+                        .feature
+                        .map(|feature| self.feature_opacity(feature) == 0)
+                        .unwrap_or(true)
+            })
             .unwrap_or(false)
     }
 
@@ -789,6 +2898,13 @@ impl World {
     }
 
     pub fn remove_entity(&mut self, entity: Entity) {
+        if matches!(
+            self.spatial_table.layer_of(entity),
+            Some(Layer::Feature | Layer::Gas)
+        ) || self.components.light_radius.contains(entity)
+        {
+            self.vision_changes += 1;
+        }
         self.components.remove_entity(entity);
         self.spatial_table.remove(entity);
         self.entity_allocator.free(entity);
@@ -806,7 +2922,7 @@ impl World {
         attacker_is_player: bool,
         outcome: BumpAttackOutcome,
         npc_type: NpcType,
-        message_log: &mut Vec<LogMessage>,
+        message_log: &mut MessageLog,
     ) {
         if attacker_is_player {
             match outcome {
@@ -833,17 +2949,37 @@ impl World {
 
     pub fn examine_cell(&self, coord: Coord) -> Option<ExamineCell> {
         let layers = self.spatial_table.layers_at(coord)?;
+        // fog hides whatever is in it, other than the player
+        let contains_player = layers
+            .character
+            .map(|character| matches!(self.components.tile.get(character), Some(Tile::Player)))
+            .unwrap_or(false);
+        if layers.gas.is_some() && !contains_player {
+            return Some(ExamineCell::Fog);
+        }
         layers
             .character
             .or_else(|| layers.object)
+            .or_else(|| layers.floor)
             .and_then(|entity| {
                 self.components
                     .tile
                     .get(entity)
                     .and_then(|&tile| match tile {
-                        Tile::Npc(npc_type) => Some(ExamineCell::Npc(npc_type)),
+                        Tile::Npc(npc_type) => Some(ExamineCell::Npc {
+                            npc_type,
+                            hit_points: self.hit_points(entity)?,
+                        }),
                         Tile::NpcCorpse(npc_type) => Some(ExamineCell::NpcCorpse(npc_type)),
                         Tile::Item(item_type) => Some(ExamineCell::Item(item_type)),
+                        Tile::Gold => Some(ExamineCell::Gold),
+                        Tile::Altar => Some(ExamineCell::Altar),
+                        Tile::Trap(trap_type) => Some(ExamineCell::Trap(trap_type)),
+                        Tile::Water => Some(ExamineCell::Liquid(Liquid::Water)),
+                        Tile::Lava => Some(ExamineCell::Liquid(Liquid::Lava)),
+                        Tile::Chasm => Some(ExamineCell::Chasm),
+                        Tile::Rune => Some(ExamineCell::Rune),
+                        Tile::PortalSealed | Tile::PortalOpen => Some(ExamineCell::Portal),
                         Tile::Player => Some(ExamineCell::Player),
                         _ => None,
                     })
@@ -854,18 +2990,71 @@ impl World {
         !self.components.trajectory.is_empty()
     }
 
+    // Visual effects aren't placed in the spatial table, so they never
+    // get in the way of anything
+    fn spawn_visual_effect(&mut self, coord: Coord, effect_type: VisualEffectType) {
+        let entity = self.entity_allocator.alloc();
+        self.components.visual_effect.insert(
+            entity,
+            VisualEffect {
+                coord,
+                effect_type,
+                frame: 0,
+            },
+        );
+    }
+
+    fn spawn_visual_effect_on(&mut self, entity: Entity, effect_type: VisualEffectType) {
+        if let Some(coord) = self.spatial_table.coord_of(entity) {
+            self.spawn_visual_effect(coord, effect_type);
+        }
+    }
+
+    pub fn tick_visual_effects(&mut self) {
+        let mut finished = Vec::new();
+        for (entity, visual_effect) in self.components.visual_effect.iter_mut() {
+            visual_effect.frame += 1;
+            if visual_effect.frame >= visual_effect.effect_type.num_frames() {
+                finished.push(entity);
+            }
+        }
+        for entity in finished {
+            self.remove_entity(entity);
+        }
+    }
+
+    pub fn has_visual_effects(&self) -> bool {
+        !self.components.visual_effect.is_empty()
+    }
+
+    pub fn visual_effects<'a>(&'a self) -> impl 'a + Iterator<Item = VisualEffect> {
+        self.components
+            .visual_effect
+            .iter()
+            .map(|(_, &visual_effect)| visual_effect)
+    }
+
     pub fn equipped_inventory_indices(&self, entity: Entity) -> EquippedInventoryIndices {
-        let held = self
+        let main_hand = self
             .components
             .equipment_held_inventory_index
             .get(entity)
             .cloned();
-        let worn = self
+        let off_hand = self
+            .components
+            .equipment_off_hand_inventory_index
+            .get(entity)
+            .cloned();
+        let body = self
             .components
             .equipment_worn_inventory_index
             .get(entity)
             .cloned();
-        EquippedInventoryIndices { held, worn }
+        EquippedInventoryIndices {
+            main_hand,
+            off_hand,
+            body,
+        }
     }
 
     fn inventory_item_type(&self, entity: Entity, index: usize) -> Option<ItemType> {
@@ -881,14 +3070,48 @@ impl World {
         self.components
             .equipment_held_inventory_index
             .get(entity)
-            .and_then(|&held_index| {
-                self.inventory_item_type(entity, held_index)
-                    .map(|item_type| match item_type {
-                        ItemType::Sword => 1,
-                        _ => 0,
-                    })
+            .and_then(|&held_index| self.inventory_item_type(entity, held_index))
+            .map(ItemType::damage_bonus)
+            .unwrap_or(0)
+    }
+
+    // A weapon in the off hand is swung less surely than one in the main
+    // hand, so its bonus only raises the most damage a hit can do
+    fn off_hand_damage_bonus(&self, entity: Entity) -> i32 {
+        self.components
+            .equipment_off_hand_inventory_index
+            .get(entity)
+            .and_then(|&off_hand_index| self.inventory_item_type(entity, off_hand_index))
+            .map(ItemType::damage_bonus)
+            .unwrap_or(0)
+    }
+
+    // How much closer NPCs need to be to spot a character. Nimble
+    // characters who aren't clanking around in armor are harder to see.
+    fn stealth(&self, entity: Entity) -> u32 {
+        let from_dexterity = self.dexterity(entity).unwrap_or(0).max(0) as u32 / 3;
+        let from_armor = self
+            .components
+            .equipment_worn_inventory_index
+            .get(entity)
+            .and_then(|&worn_index| self.inventory_item_type(entity, worn_index))
+            .map(|item_type| match item_type {
+                ItemType::Armor => 0,
+                _ => 1,
             })
-            .unwrap_or(0)
+            .unwrap_or(1);
+        from_dexterity + from_armor
+    }
+
+    // How close the target must be before the NPC can spot it
+    pub fn npc_detection_range(&self, npc: Entity, target: Entity) -> u32 {
+        let sight_radius = self
+            .components
+            .npc_type
+            .get(npc)
+            .map(|npc_type| npc_type.sight_radius())
+            .unwrap_or(0);
+        sight_radius.saturating_sub(self.stealth(target)).max(1)
     }
 
     fn defense_modifier(&self, entity: Entity) -> i32 {
@@ -933,6 +3156,14 @@ impl World {
         held + worn
     }
 
+    pub fn gold(&self, entity: Entity) -> Option<u32> {
+        self.components.gold.get(entity).cloned()
+    }
+
+    pub fn debt(&self, entity: Entity) -> Option<u32> {
+        self.components.debt.get(entity).cloned()
+    }
+
     fn magic(&self, entity: Entity) -> i32 {
         self.components
             .intelligence
@@ -941,69 +3172,421 @@ impl World {
             .unwrap_or(0)
             + self.magic_modifier(entity)
     }
-
 }
 
-
 // END(impl world)
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum NpcType {
     Orc,
     Troll,
+    Shopkeeper,
 }
 
 impl NpcType {
+    pub const ALL: [NpcType; 3] = [NpcType::Orc, NpcType::Troll, NpcType::Shopkeeper];
+
     pub fn name(self) -> &'static str {
         match self {
             Self::Orc => "orc",
             Self::Troll => "troll",
+            Self::Shopkeeper => "shopkeeper",
+        }
+    }
+
+    pub fn sight_radius(self) -> u32 {
+        match self {
+            Self::Orc | Self::Shopkeeper => 7,
+            Self::Troll => 5,
+        }
+    }
+
+    // chance that a hit from this NPC leaves the player blinded
+    fn blinding_chance(self) -> f64 {
+        match self {
+            Self::Troll => 0.1,
+            Self::Orc | Self::Shopkeeper => 0.0,
+        }
+    }
+
+    // of knocking the player back a cell with a hit
+    fn knockback_chance(self) -> f64 {
+        match self {
+            Self::Troll => 0.25,
+            Self::Orc | Self::Shopkeeper => 0.0,
         }
     }
 }
 
 // TODO add more NpcTypes
 
-
+// An amount which is used up and restored, up to a maximum. It's only
+// changed through its methods, which never let the current amount go
+// above the maximum or below zero.
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-pub struct HitPoints {
-    pub current: u32,
-    pub max: u32,
+pub struct Pool {
+    current: u32,
+    max: u32,
 }
 
-impl HitPoints {
-    fn new_full(max: u32) -> Self {
+pub type HitPoints = Pool;
+
+impl Pool {
+    pub fn new_full(max: u32) -> Self {
         Self { current: max, max }
     }
+
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.current >= self.max
+    }
+
+    // Returns how much was taken, which is less than `amount` if there
+    // wasn't that much left
+    pub fn take(&mut self, amount: u32) -> u32 {
+        let taken = amount.min(self.current);
+        self.current -= taken;
+        taken
+    }
+
+    // Returns how much was given, which is less than `amount` if that
+    // would have gone over the maximum
+    pub fn give(&mut self, amount: u32) -> u32 {
+        let given = amount.min(self.max.saturating_sub(self.current));
+        self.current += given;
+        given
+    }
+
+    pub fn fill(&mut self) {
+        self.current = self.max;
+    }
+
+    // Raising the maximum also adds the difference to the current amount
+    // if `fill_difference` is set. Lowering it brings the current amount
+    // down to the new maximum if it was over.
+    pub fn set_max(&mut self, max: u32, fill_difference: bool) {
+        if fill_difference && max > self.max {
+            self.current = self.current.saturating_add(max - self.max);
+        }
+        self.max = max;
+        self.current = self.current.min(max);
+    }
 }
 
 struct VictimDies;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProjectileStop {
+    // walls and closed doors stop a projectile before it enters their cell
+    Blocked,
+    // a fireball going out in water
+    Fizzles,
+    HitsCharacter(Entity),
+}
+
+// A short-lived effect drawn over the map. Input is locked while effects
+// play, so none of them last more than a few frames.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct VisualEffect {
+    pub coord: Coord,
+    pub effect_type: VisualEffectType,
+    pub frame: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VisualEffectType {
+    // an expanding ring where a fireball lands
+    Blast,
+    // glitter over a character being healed
+    Sparkle,
+}
+
+impl VisualEffectType {
+    pub fn num_frames(self) -> u32 {
+        match self {
+            Self::Blast => 3,
+            Self::Sparkle => 4,
+        }
+    }
+}
+
+// Something on or built into the floor that the player notices when they
+// step onto it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FloorThing {
+    Stairs,
+    StairsUp,
+    Altar,
+    Portal,
+    Item(ItemType),
+    Gold,
+    Corpse(NpcType),
+    Rune,
+}
+
+impl FloorThing {
+    // features are part of the cell ("there is ... here") rather than
+    // lying in it ("you see ... here")
+    pub fn is_feature(self) -> bool {
+        matches!(
+            self,
+            Self::Stairs | Self::StairsUp | Self::Altar | Self::Portal
+        )
+    }
+}
+
+// Up to three floor things, combined into a single log message
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FloorThings {
+    things: [Option<FloorThing>; 3],
+    more: bool,
+}
+
+impl FloorThings {
+    pub fn new(all: &[FloorThing]) -> Option<Self> {
+        if all.is_empty() {
+            return None;
+        }
+        let mut things = [None; 3];
+        for (slot, &thing) in things.iter_mut().zip(all) {
+            *slot = Some(thing);
+        }
+        Some(Self {
+            things,
+            more: all.len() > things.len(),
+        })
+    }
+
+    pub fn iter(&self) -> impl '_ + Iterator<Item = FloorThing> {
+        self.things.iter().filter_map(|&thing| thing)
+    }
+
+    // whether there were more things than could be listed
+    pub fn more(&self) -> bool {
+        self.more
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Liquid {
+    Water,
+    Lava,
+}
+
+impl Liquid {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Water => "shallow water",
+            Self::Lava => "lava",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrapType {
+    Spike,
+    Teleport,
+    Alarm,
+}
+
+impl TrapType {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Spike => "spike trap",
+            Self::Teleport => "teleport trap",
+            Self::Alarm => "alarm trap",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Trap {
+    trap_type: TrapType,
+    revealed: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DoorState {
+    Open,
+    Closed,
+}
+
+#[derive(Clone, Copy)]
+enum PrayerOutcome {
+    Heal,
+    Stat,
+    Identify,
+    Punish,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Fog {
+    turns_left: u32,
+    // how many more cells the fog will spread outwards
+    spread: u32,
+}
+
+// Timed statuses a character can be under, from the most to the least
+// dangerous
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StatusEffect {
+    Blind,
+    Confused,
+    Clairvoyant,
+}
+
+// Counts down a timed status, removing it once it runs out. Returns true
+// if the status ended this turn.
+fn tick_status(countdowns: &mut ComponentTable<u32>, entity: Entity) -> bool {
+    if let Some(countdown) = countdowns.get_mut(entity) {
+        if *countdown == 0 {
+            countdowns.remove(entity);
+            return true;
+        }
+        *countdown -= 1;
+    }
+    false
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ItemType {
     HealthPotion,
     FireballScroll,
     ConfusionScroll,
+    ClairvoyanceScroll,
+    Torch,
+    SmokeBomb,
     Sword,
     Staff,
     Armor,
-    Robe
+    Robe,
+    Backpack,
+    Warhammer,
 }
 
 impl ItemType {
+    pub const ALL: [ItemType; 12] = [
+        ItemType::HealthPotion,
+        ItemType::FireballScroll,
+        ItemType::ConfusionScroll,
+        ItemType::ClairvoyanceScroll,
+        ItemType::Torch,
+        ItemType::SmokeBomb,
+        ItemType::Sword,
+        ItemType::Staff,
+        ItemType::Armor,
+        ItemType::Robe,
+        ItemType::Backpack,
+        ItemType::Warhammer,
+    ];
+
     pub fn name(self) -> &'static str {
         match self {
             Self::HealthPotion => "health potion",
             Self::FireballScroll => "fireball scroll",
             Self::ConfusionScroll => "confusion scroll",
+            Self::ClairvoyanceScroll => "clairvoyance scroll",
+            Self::Torch => "torch",
+            Self::SmokeBomb => "smoke bomb",
+            Self::Sword => "sword",
+            Self::Staff => "staff",
+            Self::Armor => "armor",
+            Self::Robe => "robe",
+            Self::Backpack => "backpack",
+            Self::Warhammer => "warhammer",
+        }
+    }
+
+    pub fn equipment_class(self) -> Option<EquipmentClass> {
+        match self {
+            Self::Sword => Some(EquipmentClass::OneHanded),
+            Self::Staff | Self::Warhammer => Some(EquipmentClass::TwoHanded),
+            Self::Armor | Self::Robe => Some(EquipmentClass::Body),
+            _ => None,
+        }
+    }
+
+    // Added to a hit by a weapon held in the main hand
+    fn damage_bonus(self) -> i32 {
+        match self {
+            Self::Sword | Self::Warhammer => 1,
+            _ => 0,
+        }
+    }
+
+    // Whether a hit with it held in the main hand knocks the victim back
+    fn knocks_back(self) -> bool {
+        self == Self::Warhammer
+    }
+
+    // As written on the command line, such as health-potion
+    fn id(self) -> &'static str {
+        match self {
+            Self::HealthPotion => "health-potion",
+            Self::FireballScroll => "fireball-scroll",
+            Self::ConfusionScroll => "confusion-scroll",
+            Self::ClairvoyanceScroll => "clairvoyance-scroll",
+            Self::Torch => "torch",
+            Self::SmokeBomb => "smoke-bomb",
             Self::Sword => "sword",
             Self::Staff => "staff",
             Self::Armor => "armor",
             Self::Robe => "robe",
+            Self::Backpack => "backpack",
+            Self::Warhammer => "warhammer",
+        }
+    }
+
+    pub fn price(self) -> u32 {
+        match self {
+            Self::HealthPotion => 10,
+            Self::FireballScroll => 25,
+            Self::ConfusionScroll => 20,
+            Self::ClairvoyanceScroll => 30,
+            Self::Torch => 15,
+            Self::SmokeBomb => 20,
+            Self::Sword | Self::Staff => 40,
+            Self::Warhammer => 50,
+            Self::Armor | Self::Robe => 50,
+            Self::Backpack => 60,
         }
     }
 }
 
+impl fmt::Display for ItemType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+impl FromStr for ItemType {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .cloned()
+            .find(|item_type| item_type.id() == s)
+            .ok_or_else(|| {
+                let ids = Self::ALL
+                    .iter()
+                    .map(|item_type| item_type.id())
+                    .collect::<Vec<_>>();
+                format!("unknown item {:?} (expected one of {})", s, ids.join(", "))
+            })
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Inventory {
     slots: Vec<Option<Entity>>,
@@ -1011,10 +3594,17 @@ pub struct Inventory {
 
 pub struct InventoryIsFull;
 
+pub struct InventoryCannotGrow;
+
+pub const PLAYER_INVENTORY_SLOTS: usize = 10;
+// added to the player's inventory by each backpack
+pub const BACKPACK_SLOTS: usize = 4;
+// as many as the inventory menu can list on the smallest map
+pub const MAX_INVENTORY_SLOTS: usize = 18;
+
 #[derive(Debug)]
 pub struct InventorySlotIsEmpty;
 
-
 impl Inventory {
     pub fn new(capacity: usize) -> Self {
         let slots = vec![None; capacity];
@@ -1033,6 +3623,16 @@ impl Inventory {
         &self.slots
     }
 
+    // Adds empty slots, up to MAX_INVENTORY_SLOTS
+    pub fn grow(&mut self, num_slots: usize) -> Result<(), InventoryCannotGrow> {
+        if self.slots.len() >= MAX_INVENTORY_SLOTS {
+            return Err(InventoryCannotGrow);
+        }
+        let capacity = (self.slots.len() + num_slots).min(MAX_INVENTORY_SLOTS);
+        self.slots.resize(capacity, None);
+        Ok(())
+    }
+
     pub fn insert(&mut self, item: Entity) -> Result<(), InventoryIsFull> {
         if let Some(slot) = self.slots.iter_mut().find(|s| s.is_none()) {
             *slot = Some(item);
@@ -1057,7 +3657,7 @@ pub enum ItemUsage {
     Aim,
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ProjectileType {
     Fireball { damage: u32 },
     Confusion { duration: u32 },
@@ -1070,6 +3670,16 @@ impl ProjectileType {
             Self::Confusion { .. } => "confusion spell",
         }
     }
+
+    // How far from where it bursts the projectile reaches, for those which
+    // affect more than the character they hit
+    pub fn area_radius(self) -> Option<u32> {
+        const CONFUSION_RADIUS: u32 = 1;
+        match self {
+            Self::Fireball { .. } => None,
+            Self::Confusion { .. } => Some(CONFUSION_RADIUS),
+        }
+    }
 }
 
 pub struct CharacterData {
@@ -1077,15 +3687,501 @@ pub struct CharacterData {
     inventory_entity_data: Vec<Option<EntityData>>,
 }
 
-
 enum BumpAttackOutcome {
     Hit,
     Dodge,
     Kill,
 }
 
-
 pub struct EquippedInventoryIndices {
-    pub worn: Option<usize>,
-    pub held: Option<usize>,
+    pub main_hand: Option<usize>,
+    pub off_hand: Option<usize>,
+    pub body: Option<usize>,
+}
+
+impl EquippedInventoryIndices {
+    pub fn contains(&self, inventory_index: usize) -> bool {
+        [self.main_hand, self.off_hand, self.body].contains(&Some(inventory_index))
+    }
+}
+
+// Where an item goes when it's equipped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EquipmentClass {
+    OneHanded,
+    TwoHanded,
+    Body,
+}
+
+impl EquipmentClass {
+    pub fn is_weapon(self) -> bool {
+        matches!(self, Self::OneHanded | Self::TwoHanded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_isaac::Isaac64Rng;
+
+    const SIZE: Size = Size::new_u16(11, 11);
+    const CENTRE: Coord = Coord::new(5, 5);
+
+    #[test]
+    fn characters_on_the_edge_of_the_radius_are_within_it() {
+        let mut world = World::new(SIZE);
+        let player = world.spawn_player(CENTRE);
+        let straight = world.spawn_npc(Coord::new(8, 5), NpcType::Orc);
+        let diagonal = world.spawn_npc(Coord::new(7, 7), NpcType::Orc);
+        // just outside: 3 across and 1 down is further than 3 away
+        world.spawn_npc(Coord::new(8, 6), NpcType::Orc);
+        world.spawn_npc(Coord::new(9, 5), NpcType::Orc);
+        assert_eq!(
+            world.characters_within(CENTRE, 3),
+            vec![player, straight, diagonal]
+        );
+        assert_eq!(world.characters_within(CENTRE, 0), vec![player]);
+    }
+
+    #[test]
+    fn area_queries_only_look_at_their_own_layer() {
+        let mut world = World::new(SIZE);
+        let orc = world.spawn_npc(CENTRE, NpcType::Orc);
+        let item = world.spawn_item(CENTRE, ItemType::HealthPotion);
+        assert_eq!(world.characters_within(CENTRE, 1), vec![orc]);
+        assert_eq!(
+            world.entities_within_layer(CENTRE, 1, Layer::Object),
+            vec![item]
+        );
+        assert!(world
+            .entities_within_layer(CENTRE, 1, Layer::Feature)
+            .is_empty());
+    }
+
+    #[test]
+    fn area_queries_return_entities_in_row_major_order() {
+        let mut world = World::new(SIZE);
+        // spawned bottom to top and right to left, so neither the order
+        // they were made in nor their entity ids match the order wanted
+        let coords = [
+            Coord::new(6, 6),
+            Coord::new(4, 6),
+            Coord::new(6, 5),
+            Coord::new(4, 5),
+            Coord::new(6, 4),
+            Coord::new(4, 4),
+        ];
+        let mut orcs = coords
+            .iter()
+            .map(|&coord| (coord, world.spawn_npc(coord, NpcType::Orc)))
+            .collect::<Vec<_>>();
+        orcs.sort_by_key(|&(coord, _)| (coord.y, coord.x));
+        let expected = orcs.iter().map(|&(_, orc)| orc).collect::<Vec<_>>();
+        assert_eq!(
+            world.entities_within_rect(Coord::new(4, 4), Size::new(3, 3), Layer::Character),
+            expected
+        );
+        assert_eq!(world.characters_within(CENTRE, 2), expected);
+    }
+
+    #[test]
+    fn area_queries_ignore_the_parts_off_the_map() {
+        let mut world = World::new(SIZE);
+        let corner = world.spawn_npc(Coord::new(0, 0), NpcType::Orc);
+        let edge = world.spawn_npc(Coord::new(10, 1), NpcType::Orc);
+        assert_eq!(
+            world.entities_within_rect(Coord::new(-5, -5), Size::new(7, 7), Layer::Character),
+            vec![corner]
+        );
+        assert_eq!(world.characters_within(Coord::new(10, 0), 1), vec![edge]);
+        assert_eq!(
+            world.entities_within_rect(Coord::new(0, 0), SIZE, Layer::Character),
+            vec![corner, edge]
+        );
+    }
+
+    // Knocks the character east, returning what was logged
+    fn push_east(world: &mut World, character: Entity, source: DamageSource) -> Vec<LogMessage> {
+        let mut message_log = MessageLog::default();
+        let mut rng = Isaac64Rng::seed_from_u64(0);
+        world.push_character(
+            character,
+            CardinalDirection::East,
+            source,
+            &mut message_log,
+            &mut rng,
+        );
+        message_log
+            .entries_from(0)
+            .map(|entry| entry.message)
+            .collect()
+    }
+
+    fn current_hit_points(world: &World, character: Entity) -> u32 {
+        world
+            .components
+            .hit_points
+            .get(character)
+            .unwrap()
+            .current()
+    }
+
+    #[test]
+    fn knocked_into_a_wall() {
+        let mut world = World::new(SIZE);
+        // a troll survives what would kill an orc
+        let troll = world.spawn_npc(CENTRE, NpcType::Troll);
+        world.spawn_wall(CENTRE + Coord::new(1, 0));
+        let messages = push_east(&mut world, troll, DamageSource::Player);
+        assert_eq!(
+            messages,
+            vec![LogMessage::SlamsIntoWall(Some(NpcType::Troll))]
+        );
+        assert_eq!(world.spatial_table.coord_of(troll), Some(CENTRE));
+        assert_eq!(current_hit_points(&world, troll), 4);
+    }
+
+    #[test]
+    fn knocked_into_another_character() {
+        let mut world = World::new(SIZE);
+        let troll = world.spawn_npc(CENTRE, NpcType::Troll);
+        let other_troll = world.spawn_npc(CENTRE + Coord::new(1, 0), NpcType::Troll);
+        let messages = push_east(&mut world, troll, DamageSource::Player);
+        assert_eq!(
+            messages,
+            vec![LogMessage::SlamsInto(Some(NpcType::Troll), NpcType::Troll)]
+        );
+        assert_eq!(world.spatial_table.coord_of(troll), Some(CENTRE));
+        assert_eq!(current_hit_points(&world, troll), 4);
+        assert_eq!(current_hit_points(&world, other_troll), 5);
+        // the player did the knocking, so it's theirs
+        let damage_dealt = world
+            .take_stat_events()
+            .into_iter()
+            .filter(|stat_event| matches!(stat_event, StatEvent::PlayerDealsDamage(_)))
+            .count();
+        assert_eq!(damage_dealt, 2);
+    }
+
+    #[test]
+    fn knocked_into_another_character_by_an_npc() {
+        let mut world = World::new(SIZE);
+        let player = world.spawn_player(CENTRE);
+        world
+            .components
+            .hit_points
+            .get_mut(player)
+            .unwrap()
+            .take(19);
+        world.spawn_npc(CENTRE + Coord::new(1, 0), NpcType::Orc);
+        push_east(&mut world, player, DamageSource::Npc(NpcType::Troll));
+        let stat_events = world.take_stat_events();
+        assert!(stat_events.iter().any(|stat_event| matches!(
+            stat_event,
+            StatEvent::PlayerDies(DamageSource::Npc(NpcType::Troll))
+        )));
+        // and the orc knocked into isn't the player's doing
+        assert!(!stat_events
+            .iter()
+            .any(|stat_event| matches!(stat_event, StatEvent::PlayerDealsDamage(_))));
+    }
+
+    #[test]
+    fn knocked_onto_a_trap() {
+        let mut world = World::new(SIZE);
+        let dest = CENTRE + Coord::new(1, 0);
+        let troll = world.spawn_npc(CENTRE, NpcType::Troll);
+        world.spawn_trap(dest, TrapType::Spike);
+        let messages = push_east(&mut world, troll, DamageSource::Player);
+        assert_eq!(
+            messages,
+            vec![LogMessage::TrapTriggered(
+                Some(NpcType::Troll),
+                TrapType::Spike
+            )]
+        );
+        assert_eq!(world.spatial_table.coord_of(troll), Some(dest));
+        assert!(current_hit_points(&world, troll) < 6);
+    }
+
+    #[test]
+    fn knocked_into_a_chasm() {
+        let mut world = World::new(SIZE);
+        let dest = CENTRE + Coord::new(1, 0);
+        let troll = world.spawn_npc(CENTRE, NpcType::Troll);
+        world.spawn_chasm(dest);
+        let messages = push_east(&mut world, troll, DamageSource::Player);
+        assert_eq!(
+            messages,
+            vec![LogMessage::KnockedIntoChasm(Some(NpcType::Troll))]
+        );
+        assert_eq!(world.spatial_table.coord_of(troll), None);
+        // the player is left over the chasm, to fall once the turn is over
+        let player = world.spawn_player(CENTRE);
+        let messages = push_east(&mut world, player, DamageSource::Npc(NpcType::Troll));
+        assert_eq!(messages, vec![LogMessage::KnockedIntoChasm(None)]);
+        assert_eq!(world.spatial_table.coord_of(player), Some(dest));
+    }
+
+    // Moves the projectiles until they've all landed, returning what was
+    // logged
+    fn fly(world: &mut World) -> Vec<LogMessage> {
+        const MAX_STEPS: usize = 20;
+        let mut message_log = MessageLog::default();
+        for _ in 0..MAX_STEPS {
+            if !world.has_projectiles() {
+                return message_log
+                    .entries_from(0)
+                    .map(|entry| entry.message)
+                    .collect();
+            }
+            world.move_projectiles(&mut message_log);
+        }
+        panic!("the projectile never landed");
+    }
+
+    #[test]
+    fn a_projectile_hits_a_bystander_in_its_path() {
+        let mut world = World::new(SIZE);
+        let from = Coord::new(1, 5);
+        let bystander_coord = Coord::new(4, 5);
+        world.spawn_player(from);
+        world.spawn_npc(bystander_coord, NpcType::Orc);
+        let target = world.spawn_npc(Coord::new(8, 5), NpcType::Troll);
+        world.spawn_projectile(
+            from,
+            Coord::new(8, 5),
+            ProjectileType::Fireball { damage: 5 },
+        );
+        let messages = fly(&mut world);
+        assert_eq!(messages, vec![LogMessage::NpcDies(NpcType::Orc)]);
+        assert_eq!(current_hit_points(&world, target), 6);
+        assert!(world
+            .events
+            .iter()
+            .any(|&event| event == GameEvent::ExplosionAt(bystander_coord)));
+    }
+
+    #[test]
+    fn a_projectile_hits_a_character_who_steps_into_its_path() {
+        let mut world = World::new(SIZE);
+        let from = Coord::new(1, 5);
+        world.spawn_player(from);
+        let bystander = world.spawn_npc(Coord::new(6, 4), NpcType::Troll);
+        let target = world.spawn_npc(Coord::new(8, 5), NpcType::Troll);
+        world.spawn_projectile(
+            from,
+            Coord::new(8, 5),
+            ProjectileType::Fireball { damage: 2 },
+        );
+        let mut message_log = MessageLog::default();
+        world.move_projectiles(&mut message_log);
+        // while the projectile is still on its way
+        world
+            .spatial_table
+            .update_coord(bystander, Coord::new(6, 5))
+            .unwrap();
+        fly(&mut world);
+        assert_eq!(current_hit_points(&world, bystander), 4);
+        assert_eq!(current_hit_points(&world, target), 6);
+    }
+
+    #[test]
+    fn a_confusion_spell_confuses_every_npc_next_to_where_it_bursts() {
+        let mut world = World::new(SIZE);
+        let from = Coord::new(1, 5);
+        let player = world.spawn_player(from);
+        let adjacent = [Coord::new(6, 4), Coord::new(6, 5), Coord::new(6, 6)]
+            .map(|coord| world.spawn_npc(coord, NpcType::Orc));
+        // two cells from where it bursts
+        let out_of_reach = world.spawn_npc(Coord::new(8, 5), NpcType::Orc);
+        world.spawn_projectile(
+            from,
+            Coord::new(6, 5),
+            ProjectileType::Confusion { duration: 6 },
+        );
+        let mut message_log = MessageLog::default();
+        while world.has_projectiles() {
+            world.move_projectiles(&mut message_log);
+        }
+        for orc in adjacent {
+            assert_eq!(world.components.confusion_countdown.get(orc), Some(&6));
+        }
+        assert!(!world.components.confusion_countdown.contains(out_of_reach));
+        assert!(!world.components.confusion_countdown.contains(player));
+        // one message for each, which the log counts together
+        let messages = message_log
+            .entries_from(0)
+            .map(|entry| (entry.message, entry.count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![(LogMessage::NpcBecomesConfused(NpcType::Orc), 3)]
+        );
+    }
+
+    // A player carrying the items, in inventory slots in the same order
+    fn player_carrying(items: &[ItemType]) -> (World, Entity) {
+        let mut world = World::new(SIZE);
+        let player = world.spawn_player(CENTRE);
+        for &item_type in items {
+            world.grant_item(player, item_type).unwrap();
+        }
+        (world, player)
+    }
+
+    fn wield(world: &mut World, player: Entity, inventory_index: usize) {
+        world
+            .maybe_wield_item(player, inventory_index, &mut MessageLog::default())
+            .unwrap();
+    }
+
+    fn hands(world: &World, player: Entity) -> (Option<usize>, Option<usize>) {
+        let equipped = world.equipped_inventory_indices(player);
+        (equipped.main_hand, equipped.off_hand)
+    }
+
+    #[test]
+    fn a_second_sword_goes_in_the_off_hand() {
+        let (mut world, player) = player_carrying(&[ItemType::Sword, ItemType::Sword]);
+        wield(&mut world, player, 0);
+        assert_eq!(hands(&world, player), (Some(0), None));
+        wield(&mut world, player, 1);
+        assert_eq!(hands(&world, player), (Some(0), Some(1)));
+    }
+
+    #[test]
+    fn a_staff_takes_both_hands() {
+        let (mut world, player) =
+            player_carrying(&[ItemType::Sword, ItemType::Sword, ItemType::Staff]);
+        wield(&mut world, player, 0);
+        wield(&mut world, player, 1);
+        wield(&mut world, player, 2);
+        assert_eq!(hands(&world, player), (Some(2), None));
+    }
+
+    #[test]
+    fn a_weapon_wielded_with_a_staff_held_replaces_it() {
+        let (mut world, player) = player_carrying(&[ItemType::Staff, ItemType::Sword]);
+        wield(&mut world, player, 0);
+        wield(&mut world, player, 1);
+        assert_eq!(hands(&world, player), (Some(1), None));
+    }
+
+    #[test]
+    fn dropping_the_main_hand_weapon_moves_the_off_hand_one_across() {
+        let (mut world, player) = player_carrying(&[ItemType::Sword, ItemType::Sword]);
+        wield(&mut world, player, 0);
+        wield(&mut world, player, 1);
+        world
+            .maybe_drop_item(player, 0, &mut MessageLog::default())
+            .unwrap();
+        assert_eq!(hands(&world, player), (Some(1), None));
+        assert!(matches!(
+            world.tile_at(CENTRE, Layer::Object),
+            Some(Tile::Item(ItemType::Sword))
+        ));
+    }
+
+    #[test]
+    fn a_pool_never_goes_below_zero_or_above_its_maximum() {
+        let mut pool = Pool::new_full(10);
+        assert!(pool.is_full());
+        assert_eq!(pool.give(5), 0);
+        assert_eq!(pool.take(4), 4);
+        assert_eq!(pool.current(), 6);
+        assert_eq!(pool.give(u32::MAX), 4);
+        assert!(pool.is_full());
+        assert_eq!(pool.take(u32::MAX), 10);
+        assert!(pool.is_empty());
+        assert_eq!(pool.take(1), 0);
+        assert_eq!(pool.current(), 0);
+    }
+
+    #[test]
+    fn raising_a_pools_maximum_fills_the_difference_if_asked() {
+        let mut pool = Pool::new_full(10);
+        pool.take(3);
+        pool.set_max(15, true);
+        assert_eq!((pool.current(), pool.max()), (12, 15));
+        pool.set_max(20, false);
+        assert_eq!((pool.current(), pool.max()), (12, 20));
+        pool.set_max(u32::MAX, true);
+        assert_eq!(pool.max(), u32::MAX);
+        assert!(pool.current() <= pool.max());
+    }
+
+    #[test]
+    fn lowering_a_pools_maximum_brings_the_current_amount_down() {
+        let mut pool = Pool::new_full(10);
+        pool.set_max(6, true);
+        assert_eq!((pool.current(), pool.max()), (6, 6));
+        assert!(pool.is_full());
+        pool.take(4);
+        pool.set_max(4, false);
+        assert_eq!((pool.current(), pool.max()), (2, 4));
+        pool.set_max(0, true);
+        assert!(pool.is_empty());
+        assert!(pool.is_full());
+    }
+
+    #[test]
+    fn hit_points_load_from_saves_made_before_pools() {
+        let hit_points: HitPoints = serde_json::from_str(r#"{"current":7,"max":20}"#).unwrap();
+        assert_eq!((hit_points.current(), hit_points.max()), (7, 20));
+        let saved = serde_json::to_string(&hit_points).unwrap();
+        assert_eq!(saved, r#"{"current":7,"max":20}"#);
+    }
+
+    // A player with an 8-slot inventory full of potions who has put on a
+    // backpack from the floor
+    fn player_with_backpack_on() -> (World, Entity) {
+        const NUM_SLOTS: usize = 8;
+        let mut world = World::new(SIZE);
+        let player = world.spawn_player(CENTRE);
+        world
+            .components
+            .inventory
+            .insert(player, Inventory::new(NUM_SLOTS));
+        for _ in 0..NUM_SLOTS {
+            world.grant_item(player, ItemType::HealthPotion).unwrap();
+        }
+        world.spawn_item(CENTRE, ItemType::Backpack);
+        assert!(world
+            .maybe_get_item(player, &mut MessageLog::default())
+            .is_ok());
+        (world, player)
+    }
+
+    fn num_items(inventory: &Inventory) -> usize {
+        inventory
+            .slots()
+            .iter()
+            .filter(|slot| slot.is_some())
+            .count()
+    }
+
+    #[test]
+    fn a_backpack_makes_room_in_a_full_inventory() {
+        let (mut world, player) = player_with_backpack_on();
+        let inventory = world.components.inventory.get(player).unwrap();
+        assert_eq!(inventory.slots().len(), 8 + BACKPACK_SLOTS);
+        world.spawn_item(CENTRE, ItemType::Torch);
+        assert!(world
+            .maybe_get_item(player, &mut MessageLog::default())
+            .is_ok());
+        let inventory = world.components.inventory.get(player).unwrap();
+        assert_eq!(num_items(inventory), 9);
+    }
+
+    #[test]
+    fn a_grown_inventory_keeps_its_capacity_when_saved() {
+        let (world, player) = player_with_backpack_on();
+        let inventory = world.components.inventory.get(player).unwrap();
+        let saved = serde_json::to_string(inventory).unwrap();
+        let loaded: Inventory = serde_json::from_str(&saved).unwrap();
+        assert_eq!(loaded.slots().len(), 8 + BACKPACK_SLOTS);
+        assert_eq!(num_items(&loaded), 8);
+    }
 }
@@ -0,0 +1,119 @@
+// tests/determinism.rs
+
+// Checks a seeded run plays out the same everywhere. The final checksum
+// of a fixed script is recorded in determinism.checksum beside this file,
+// and must match on every platform. A change meant to alter how the game
+// plays records the new value by running these tests with
+// DETERMINISM_BLESS=1 set, and commits it.
+
+use coord_2d::Size;
+use direction::CardinalDirection;
+use rand::{Rng, SeedableRng};
+use rand_isaac::Isaac64Rng;
+use std::{env, fs};
+
+use chargrid_roguelike_tutorial_2020::difficulty::Difficulty;
+use chargrid_roguelike_tutorial_2020::game::{
+    ActionResult, GameState, LevelUp, NewCharacter, PlayerAction,
+};
+use chargrid_roguelike_tutorial_2020::terrain::SpawnTables;
+use chargrid_roguelike_tutorial_2020::visibility::VisibilityAlgorithm;
+
+const GAME_SEED: u64 = 0;
+const SCRIPT_SEED: u64 = 2;
+const MAP_SIZE: Size = Size::new_u16(40, 23);
+const NUM_ACTIONS: usize = 500;
+// where the game is saved and loaded again
+const SAVE_AFTER: usize = 250;
+const VISIBILITY_ALGORITHM: VisibilityAlgorithm = VisibilityAlgorithm::Shadowcast;
+const CHECKSUM_FILE: &str = "tests/determinism.checksum";
+
+fn new_game() -> GameState {
+    GameState::new(
+        MAP_SIZE,
+        GAME_SEED,
+        VISIBILITY_ALGORITHM,
+        SpawnTables::built_in(),
+        Difficulty::Normal,
+        NewCharacter::default(),
+    )
+}
+
+// The script is drawn up front, so it's the same whatever the game does
+fn script() -> Vec<PlayerAction> {
+    let mut script_rng = Isaac64Rng::seed_from_u64(SCRIPT_SEED);
+    (0..NUM_ACTIONS)
+        .map(|_| match script_rng.gen_range(0..10) {
+            0 => PlayerAction::Wait,
+            1 => PlayerAction::Get,
+            _ => PlayerAction::Move(script_rng.gen::<CardinalDirection>()),
+        })
+        .collect()
+}
+
+// Plays the actions, going down any stairs the player ends up on. Stops
+// early, returning false, if the player dies.
+fn play(game_state: &mut GameState, actions: &[PlayerAction]) -> bool {
+    for &action in actions {
+        let action = if game_state.is_player_on_stairs() {
+            PlayerAction::Descend(LevelUp::Health)
+        } else {
+            action
+        };
+        if game_state.act(action, VISIBILITY_ALGORITHM) == ActionResult::PlayerDied {
+            return false;
+        }
+    }
+    true
+}
+
+fn save_and_load(game_state: &GameState) -> GameState {
+    let saved = serde_json::to_string(game_state).unwrap();
+    serde_json::from_str(&saved).unwrap()
+}
+
+fn final_checksum() -> u64 {
+    let mut game_state = new_game();
+    play(&mut game_state, &script());
+    game_state.checksum()
+}
+
+#[test]
+fn the_script_ends_with_the_recorded_checksum() {
+    let checksum = final_checksum();
+    let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), CHECKSUM_FILE);
+    if env::var_os("DETERMINISM_BLESS").is_some() {
+        fs::write(&path, format!("{}\n", checksum)).unwrap();
+        return;
+    }
+    let recorded = match fs::read_to_string(&path) {
+        Ok(recorded) => recorded,
+        Err(error) => panic!(
+            "{}: {}; run with DETERMINISM_BLESS=1 to record {}",
+            CHECKSUM_FILE, error, checksum
+        ),
+    };
+    let recorded = recorded.trim().parse::<u64>().unwrap();
+    assert_eq!(
+        checksum, recorded,
+        "the run has changed; if that's intended, run with DETERMINISM_BLESS=1"
+    );
+}
+
+#[test]
+fn the_script_ends_the_same_every_time() {
+    assert_eq!(final_checksum(), final_checksum());
+}
+
+#[test]
+fn saving_and_loading_part_way_through_changes_nothing() {
+    let script = script();
+    let mut game_state = new_game();
+    let alive = play(&mut game_state, &script[..SAVE_AFTER]);
+    let mut loaded = save_and_load(&game_state);
+    assert_eq!(loaded.checksum(), game_state.checksum());
+    if alive {
+        play(&mut loaded, &script[SAVE_AFTER..]);
+    }
+    assert_eq!(loaded.checksum(), final_checksum());
+}
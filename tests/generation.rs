@@ -0,0 +1,86 @@
+// tests/generation.rs
+
+// Sweeps level generation over many seeds, checking that every level can
+// be finished.
+
+use coord_2d::{Coord, Size};
+use grid_2d::Grid;
+use rand::SeedableRng;
+use rand_isaac::Isaac64Rng;
+use std::collections::VecDeque;
+
+use chargrid_roguelike_tutorial_2020::terrain::{
+    self, GenerationConstraints, SpawnTables, TerrainTile,
+};
+
+const MAP_SIZE: Size = Size::new_u16(40, 23);
+const NUM_SEEDS: u64 = 5000;
+
+// Whether `end` can be walked to from `start` in cardinal steps
+fn is_reachable(grid: &Grid<TerrainTile>, start: Coord, end: Coord) -> bool {
+    let mut visited = Grid::new_copy(grid.size(), false);
+    let mut queue = VecDeque::new();
+    *visited.get_checked_mut(start) = true;
+    queue.push_back(start);
+    while let Some(coord) = queue.pop_front() {
+        if coord == end {
+            return true;
+        }
+        for delta in [
+            Coord::new(1, 0),
+            Coord::new(-1, 0),
+            Coord::new(0, 1),
+            Coord::new(0, -1),
+        ] {
+            let neighbour = coord + delta;
+            if let Some(cell) = visited.get_mut(neighbour) {
+                if !*cell && grid.get_checked(neighbour).is_passable() {
+                    *cell = true;
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+    false
+}
+
+fn find(grid: &Grid<TerrainTile>, tile: TerrainTile) -> Option<Coord> {
+    grid.enumerate()
+        .find(|&(_, &other)| other == tile)
+        .map(|(coord, _)| coord)
+}
+
+#[test]
+fn the_way_down_is_always_reachable() {
+    let spawn_tables = SpawnTables::built_in();
+    for seed in 0..NUM_SEEDS {
+        // every level in turn, so each theme gets as many seeds
+        let level = 1 + (seed % terrain::FINAL_DUNGEON_LEVEL as u64) as u32;
+        let dungeon = terrain::generate_dungeon(
+            MAP_SIZE,
+            level,
+            &GenerationConstraints::default(),
+            &spawn_tables,
+            &mut Isaac64Rng::seed_from_u64(seed),
+        );
+        let grid = &dungeon.grid;
+        let start = find(grid, TerrainTile::Player)
+            .or_else(|| find(grid, TerrainTile::StairsUp))
+            .unwrap_or_else(|| panic!("nowhere to arrive on level {}, seed {}", level, seed));
+        // the final level has the portal out in place of stairs
+        let goal = if level == terrain::FINAL_DUNGEON_LEVEL {
+            TerrainTile::Portal
+        } else {
+            TerrainTile::Stairs
+        };
+        let end = find(grid, goal)
+            .unwrap_or_else(|| panic!("no {:?} on level {}, seed {}", goal, level, seed));
+        assert!(
+            is_reachable(grid, start, end),
+            "{:?} unreachable on level {}, seed {}",
+            goal,
+            level,
+            seed
+        );
+    }
+}
@@ -0,0 +1,92 @@
+// tests/level_file.rs
+
+// Plays on the example levels under maps/, as --level-file would.
+
+use coord_2d::{Coord, Size};
+use direction::CardinalDirection;
+
+use chargrid_roguelike_tutorial_2020::difficulty::Difficulty;
+use chargrid_roguelike_tutorial_2020::game::{
+    ActionResult, GameState, NewCharacter, PlayerAction, StartOptions,
+};
+use chargrid_roguelike_tutorial_2020::terrain::{self, SpawnTables};
+use chargrid_roguelike_tutorial_2020::visibility::VisibilityAlgorithm;
+
+const RNG_SEED: u64 = 0;
+const MAP_SIZE: Size = Size::new_u16(40, 23);
+const VISIBILITY_ALGORITHM: VisibilityAlgorithm = VisibilityAlgorithm::Shadowcast;
+
+fn game_on_level_file(name: &str) -> GameState {
+    let path = format!("{}/maps/{}", env!("CARGO_MANIFEST_DIR"), name);
+    let level = terrain::load_level(&path).unwrap();
+    let mut game_state = GameState::new(
+        MAP_SIZE,
+        RNG_SEED,
+        VISIBILITY_ALGORITHM,
+        SpawnTables::built_in(),
+        Difficulty::Normal,
+        NewCharacter::default(),
+    );
+    game_state.apply_start_options(
+        &StartOptions {
+            dungeon_level: 1,
+            level_ups: Vec::new(),
+            items: Vec::new(),
+            first_level: Some(level),
+        },
+        VISIBILITY_ALGORITHM,
+    );
+    game_state
+}
+
+// Takes a step, opening any door in the way first
+fn step(game_state: &mut GameState, direction: CardinalDirection) {
+    let start = game_state.player_coord();
+    for _ in 0..2 {
+        assert_eq!(
+            game_state.act(PlayerAction::Move(direction), VISIBILITY_ALGORITHM),
+            ActionResult::Done
+        );
+        if game_state.player_coord() != start {
+            return;
+        }
+    }
+    panic!("couldn't step {:?} from {:?}", direction, start);
+}
+
+#[test]
+fn every_level_file_loads() {
+    for name in ["arena.txt", "rooms.txt"] {
+        let path = format!("{}/maps/{}", env!("CARGO_MANIFEST_DIR"), name);
+        if let Err(error) = terrain::load_level(&path) {
+            panic!("{}: {}", name, error);
+        }
+    }
+}
+
+#[test]
+fn walking_to_the_gold_in_rooms() {
+    use CardinalDirection::*;
+    let mut game_state = game_on_level_file("rooms.txt");
+    assert_eq!(game_state.player_coord(), Coord::new(1, 1));
+    // two orcs and a troll
+    assert_eq!(game_state.diagnostics().ai_state_size, 3);
+    let entity_count = game_state.diagnostics().entity_count;
+    // through the doors at the bottom of the first two rooms, keeping
+    // clear of the spike trap
+    let route = [
+        East, East, South, South, South, South, South, West, South, South, South, South, South,
+        East, East, South, South, South, South, South, West,
+    ];
+    for direction in route {
+        step(&mut game_state, direction);
+    }
+    assert_eq!(game_state.player_coord(), Coord::new(3, 16));
+    assert_eq!(
+        game_state.act(PlayerAction::Get, VISIBILITY_ALGORITHM),
+        ActionResult::Done
+    );
+    assert!(game_state.player_gold() > 0);
+    assert_eq!(game_state.diagnostics().entity_count, entity_count - 1);
+    assert_eq!(game_state.diagnostics().ai_state_size, 3);
+}
@@ -0,0 +1,90 @@
+// tests/scripted_game.rs
+
+// Plays whole games through the headless API, with no window or terminal,
+// the way a bot or a replay would.
+
+use coord_2d::Size;
+use direction::CardinalDirection;
+use rand::{Rng, SeedableRng};
+use rand_isaac::Isaac64Rng;
+
+use chargrid_roguelike_tutorial_2020::difficulty::Difficulty;
+use chargrid_roguelike_tutorial_2020::game::{
+    ActionResult, GameState, LevelUp, NewCharacter, PlayerAction,
+};
+use chargrid_roguelike_tutorial_2020::terrain::SpawnTables;
+use chargrid_roguelike_tutorial_2020::visibility::VisibilityAlgorithm;
+
+const GAME_SEED: u64 = 0;
+const SCRIPT_SEED: u64 = 1;
+const MAP_SIZE: Size = Size::new_u16(40, 23);
+const NUM_ACTIONS: usize = 200;
+const VISIBILITY_ALGORITHM: VisibilityAlgorithm = VisibilityAlgorithm::Shadowcast;
+
+fn new_game() -> GameState {
+    GameState::new(
+        MAP_SIZE,
+        GAME_SEED,
+        VISIBILITY_ALGORITHM,
+        SpawnTables::built_in(),
+        Difficulty::Normal,
+        NewCharacter::default(),
+    )
+}
+
+// Mostly wandering, picking things up and going down any stairs found.
+// The script has its own RNG so it's the same whatever the game does.
+fn next_action(game_state: &GameState, script_rng: &mut Isaac64Rng) -> PlayerAction {
+    if game_state.is_player_on_stairs() {
+        return PlayerAction::Descend(LevelUp::Health);
+    }
+    match script_rng.gen_range(0..10) {
+        0 => PlayerAction::Wait,
+        1 => PlayerAction::Get,
+        _ => PlayerAction::Move(script_rng.gen::<CardinalDirection>()),
+    }
+}
+
+// Plays the script, checking after every action that the game is still in
+// a sensible state. Stops early if the player dies.
+fn play_script() -> GameState {
+    let mut game_state = new_game();
+    let mut script_rng = Isaac64Rng::seed_from_u64(SCRIPT_SEED);
+    for _ in 0..NUM_ACTIONS {
+        let action = next_action(&game_state, &mut script_rng);
+        let turn = game_state.turn();
+        let dungeon_level = game_state.dungeon_level();
+        let result = game_state.act(action, VISIBILITY_ALGORITHM);
+        let hit_points = game_state.player_hit_points();
+        assert!(hit_points.current() <= hit_points.max());
+        assert!(game_state.turn() >= turn);
+        assert!(game_state.dungeon_level() >= dungeon_level);
+        assert!(!game_state.has_animations());
+        match result {
+            ActionResult::Refused => {
+                assert_eq!(game_state.turn(), turn);
+                assert_eq!(game_state.dungeon_level(), dungeon_level);
+            }
+            ActionResult::Done => assert!(game_state.is_player_alive()),
+            ActionResult::PlayerDied => break,
+        }
+    }
+    game_state
+}
+
+#[test]
+fn scripted_game_keeps_the_game_consistent() {
+    let start_checksum = new_game().checksum();
+    let game_state = play_script();
+    assert!(game_state.turn() > 0);
+    assert_ne!(game_state.checksum(), start_checksum);
+}
+
+#[test]
+fn scripted_game_ends_in_the_same_state_every_time() {
+    let first = play_script();
+    let second = play_script();
+    assert_eq!(first.turn(), second.turn());
+    assert_eq!(first.player_coord(), second.player_coord());
+    assert_eq!(first.checksum(), second.checksum());
+}